@@ -16,6 +16,9 @@ pub mod modules{
         pub mod elevator_queue_handling;
         pub mod elevator_light_function;
         pub mod elevator_status_functions;
+        pub mod elevator_load;
+        pub mod elevator_door;
+        pub mod elevator_command;
     }
 
     pub mod system_status;
@@ -23,6 +26,10 @@ pub mod modules{
     pub mod master_functions{
         pub mod master;
         pub mod master_test;
+        pub mod parking;
+        pub mod traffic;
+        pub mod lease;
+        pub mod worldview_delta;
     }
 
     pub mod slave_functions{
@@ -30,11 +37,21 @@ pub mod modules{
         pub mod slave_test;
     }
 
+    pub mod peer_functions{
+        pub mod peer;
+    }
+
     pub mod udp_functions{
         pub mod udp;
         pub mod udp_test;
         pub mod message_handlers;
         pub mod udp_handler_init;
+        pub mod virtual_network;
+        pub mod fault_injection;
+        pub mod dispatcher;
+        pub mod transport;
+        pub mod tcp_transport;
+        pub mod codec;
 
     }
     
@@ -44,4 +61,14 @@ pub mod modules{
         pub mod io_init;
     }
 
+    pub mod system_clock;
+    pub mod node;
+    pub mod shutdown;
+    pub mod isolation;
+
+    pub mod simulation {
+        pub mod sim_elevator;
+        pub mod sim_harness;
+    }
+
 }
\ No newline at end of file
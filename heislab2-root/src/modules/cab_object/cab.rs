@@ -6,7 +6,9 @@ use std::time::SystemTime;
 
 use std::net::{IpAddr, Ipv4Addr, SocketAddr}; // https://doc.rust-lang.org/std/net/enum.IpAddr.html
 use serde::{Deserialize, Serialize};
-use local_ip_address::local_ip;
+use local_ip_address::{local_ip, local_ipv6};
+use crate::modules::system_clock::system_now;
+use crate::modules::udp_functions::udp::{OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
 pub use crate::modules::system_status::SystemState;
 pub use crate::modules::master_functions::master::Role;
@@ -21,6 +23,7 @@ pub use alias_lib::{HALL_DOWN, HALL_UP,CAB, DIRN_DOWN, DIRN_UP, DIRN_STOP};
 //-------------- GLOBALS/and CONSTANTS
 
 
+/// Sent in most messages, the field order is the wire layout of protocol version 1, see "Wire format" in udp.rs
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Cab {
 
@@ -35,7 +38,15 @@ pub struct Cab {
     pub direction: u8,            // Current direction the elevator is headed
     pub role: Role,               // Current Role of this elevator
     pub last_lifesign: SystemTime,
-    pub alive: bool
+    pub alive: bool,
+    pub takes_hall_calls: bool,   // False while the door has been obstructed too long, see spawn_obstruction_watchdog
+    pub maintenance: bool,        // Out of service for hall calls, see set_maintenance
+    pub parking: Option<u8>,      // Floor to wait at while the queue is empty, see parking.rs
+    pub served_floors: u64,       // Bit n set if the cab can stop at floor n, see floor_mask
+    pub load: Option<u16>,        // Last reading of the load sensor, None without a sensor, see elevator_load.rs
+    pub full_load: bool,          // Too full to pick up more people, skips hall stops on the way
+    pub accessible_stops: Vec<u8>, // Floors of cab calls made with the accessibility button, the door stays open longer
+    pub protocol_versions: (u8, u8), // Oldest and newest protocol version the node speaks, see udp.rs
 }
 
 
@@ -46,7 +57,7 @@ impl Cab {
         let outport = 3800;
 
         let (inn, out) = if set_id == state.me_id {
-            match local_ip() {
+            match local_ip().or_else(|_| local_ipv6()) {
                 Ok(ip) => {
                     let inn = *inn_addr;
                     let out = *out_addr;
@@ -71,14 +82,62 @@ impl Cab {
                 out_address: out,
                 num_floors,
                 id: set_id,
-                current_floor: 1,
-                last_served_floor: 8,
+                current_floor: 0,             // Not known before the first floor is reached, see Status::Init
+                last_served_floor: 0,
                 queue: Vec::new(),
-                status: Status::Idle,
+                status: Status::Init,
                 direction: 0,
                 role: Role::Slave,
-                last_lifesign: SystemTime::now(),
+                last_lifesign: system_now(),
                 alive: true,
+                takes_hall_calls: true,
+                maintenance: false,
+                parking: None,
+                served_floors: floor_mask(&(0..num_floors).collect::<Vec<u8>>()),
+                load: None,
+                full_load: false,
+                accessible_stops: Vec::new(),
+                protocol_versions: (OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION),
             });
     }
+
+    /// True if the cab can stop at `floor`
+    pub fn serves_floor(&self, floor: u8) -> bool {
+        return floor < self.num_floors && floor < 64 && self.served_floors & (1 << floor) != 0;
+    }
+}
+
+/// The served_floors mask of a cab stopping at `floors`
+pub fn floor_mask(floors: &[u8]) -> u64 {
+    return floors.iter().filter(|floor| **floor < 64).fold(0, |mask, floor| mask | (1 << floor));
+}
+
+/// parse_floors
+/// Read a list of floors like "0,2-5"
+///
+/// # Arguments:
+///
+/// * `text` - &str - floors and ranges of floors separated by ','.
+/// * `num_floors` - u8 - floors in the shaft, floors above it are an error.
+///
+/// # Returns:
+///
+/// Returns - Option<Vec<u8>> - the floors, None if the text can't be read.
+///
+pub fn parse_floors(text: &str, num_floors: u8) -> Option<Vec<u8>> {
+    let mut floors = Vec::new();
+    for part in text.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim().parse::<u8>().ok()?, last.trim().parse::<u8>().ok()?),
+            None => {
+                let floor = part.trim().parse::<u8>().ok()?;
+                (floor, floor)
+            }
+        };
+        if first > last || last >= num_floors {
+            return None;
+        }
+        floors.extend(first..=last);
+    }
+    return Some(floors);
 }
@@ -0,0 +1,60 @@
+//! ## Elevator Command Module
+//! The state machine of the cab runs on the state thread and must not wait on the elevator, so it returns
+//! what the elevator and the door timer must do as a list of `ElevatorCommand`s. The main loop or the
+//! queue finisher runs the list afterwards, in order, see node.rs.
+//!
+//! ## The structs includes:
+//! - **ElevatorCommand**: One thing to do at the elevator or the door timer.
+//!
+//! ## The functions includes:
+//! - 'run'                      runs one command against the elevator and the door timer.
+//! - 'run_elevator_commands'    runs a list of commands in order.
+//!
+//! Readings the state machine needs, like the floor sensor during an emergency stop,
+//! are taken by the caller before the state is changed.
+
+use crate::modules::cab_object::elevator_door::DoorTimer;
+use crate::modules::elevator_object::elevator_init::Elevator;
+
+/// One thing the elevator or the door timer must do after the cab has changed
+#[derive(Clone, Debug, PartialEq)]
+pub enum ElevatorCommand {
+    Motor(u8),          // Motor direction, DIRN_UP, DIRN_DOWN or DIRN_STOP
+    DoorLight(bool),
+    StopLight(bool),
+    OpenDoor(bool),     // Arm the door timer, true for the open time of an accessible cab call
+    HoldDoor,           // Keep the open door open for another open time
+    CloseDoor,          // Close the open door now
+}
+
+impl ElevatorCommand {
+    /// run
+    /// Carry out the command, outside the state thread
+    ///
+    /// # Arguments:
+    ///
+    /// * `elevator` - &Elevator - the elevator of this node.
+    /// * `door` - &DoorTimer - the door timer of this node.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - None - .
+    ///
+    pub fn run(&self, elevator: &Elevator, door: &DoorTimer) {
+        match self {
+            ElevatorCommand::Motor(direction) => elevator.motor_direction(*direction),
+            ElevatorCommand::DoorLight(on) => elevator.door_light(*on),
+            ElevatorCommand::StopLight(on) => elevator.stop_button_light(*on),
+            ElevatorCommand::OpenDoor(accessible) => door.open(*accessible),
+            ElevatorCommand::HoldDoor => door.hold(),
+            ElevatorCommand::CloseDoor => door.close(),
+        }
+    }
+}
+
+/// Run the commands returned by the state machine of the cab, in the order they were given
+pub fn run_elevator_commands(commands: &[ElevatorCommand], elevator: &Elevator, door: &DoorTimer) {
+    for command in commands {
+        command.run(elevator, door);
+    }
+}
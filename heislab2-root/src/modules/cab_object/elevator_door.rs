@@ -0,0 +1,178 @@
+//! ## Elevator Door Module
+//! This module times the door of the cab. The state machine of the cab arms the timer when the door opens
+//! and the main loop gets `NodeEvent::Door(true)` when it is time to close, so no thread waits on the door.
+//!
+//! ## The structs includes:
+//! - **DoorConfig**: How long the door stays open, also for accessibility cab calls, and how long it can be held.
+//! - **DoorRequest**: The door-open and door-close buttons inside the cab.
+//! - **DoorTimer**: Handle to the timer of one node, cloned into everything that can open the door.
+//!
+//! ## The functions includes:
+//! - 'parse'    reads one "key: value" line from "boot.txt" into a `DoorConfig`.
+//! - 'start'    starts the timer thread of a node.
+//! - 'open'     the door has opened, close it after the open time.
+//! - 'hold'     the door-open button, keeps the door open for another open time, never past the max hold.
+//! - 'close'    the door-close button, closes the door now.
+//!
+//! An obstructed door is never closed by the timer, see the DoorClosed command in node.rs.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! door_open_time: 2               seconds the door is open at a stop
+//! door_accessible_time: 6         seconds the door is open for a cab call made with the accessibility button
+//! door_max_hold: 15               most seconds the door-open button can keep the door open
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel as cbc;
+
+use crate::modules::system_clock::{clock_stopped, now, sleep, track};
+
+// How often the timer looks at the clock
+const TIMER_PERIOD: Duration = Duration::from_millis(10);
+
+/// How long the door stays open, see the keys above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoorConfig {
+    pub open_time: Duration,
+    pub accessible_open_time: Duration,
+    pub max_hold: Duration,
+}
+
+impl Default for DoorConfig {
+    fn default() -> Self {
+        DoorConfig {
+            open_time: Duration::from_secs(2),
+            accessible_open_time: Duration::from_secs(6),
+            max_hold: Duration::from_secs(15),
+        }
+    }
+}
+
+/// A door button inside the cab
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DoorRequest {
+    Open,
+    Close,
+}
+
+// What the state machine asks the timer thread to do
+#[derive(Debug)]
+enum DoorCommand {
+    Open(Duration),
+    Hold,
+    Close,
+}
+
+/// Handle to the door timer of a node
+#[derive(Clone, Debug)]
+pub struct DoorTimer {
+    commands: cbc::Sender<DoorCommand>,
+    pub config: DoorConfig,
+}
+
+impl DoorConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "door_" are ignored.
+    /// * `value` - &str - the text after ':', in seconds.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a door key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let seconds = match value.trim().parse::<f64>().ok().filter(|s| s.is_finite()).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+            Some(seconds) => seconds,
+            None => return false,
+        };
+        match key.trim() {
+            "door_open_time" => self.open_time = seconds,
+            "door_accessible_time" => self.accessible_open_time = seconds,
+            "door_max_hold" => self.max_hold = seconds,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+impl DoorTimer {
+    /// start
+    /// Start the timer thread of a node
+    ///
+    /// # Arguments:
+    ///
+    /// * `door_tx` - cbc::Sender<bool> - gets `true` when the door should close.
+    /// * `config` - DoorConfig - the open times.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - DoorTimer - handle used to arm the timer, the thread stops when every handle is dropped or the clock stops.
+    ///
+    pub fn start(door_tx: cbc::Sender<bool>, config: DoorConfig) -> DoorTimer {
+        let (commands, command_rx) = cbc::unbounded();
+        track(thread::spawn(move || run_door_timer(command_rx, door_tx, config)));
+        return DoorTimer { commands, config };
+    }
+
+    /// The door has opened, close it after the open time, or the accessible open time if `accessible`
+    pub fn open(&self, accessible: bool) {
+        let open_time = if accessible { self.config.accessible_open_time } else { self.config.open_time };
+        let _ = self.commands.send(DoorCommand::Open(open_time));
+    }
+
+    /// Keep the open door open for another open time, the max hold counts from when it opened
+    pub fn hold(&self) {
+        let _ = self.commands.send(DoorCommand::Hold);
+    }
+
+    /// Close the open door now
+    pub fn close(&self) {
+        let _ = self.commands.send(DoorCommand::Close);
+    }
+}
+
+// The timer: at most one deadline at a time, a new open replaces it
+fn run_door_timer(command_rx: cbc::Receiver<DoorCommand>, door_tx: cbc::Sender<bool>, config: DoorConfig) {
+    let mut opened_at: Option<Instant> = None;
+    let mut close_at: Option<Instant> = None;
+    loop {
+        loop {
+            match command_rx.try_recv() {
+                Ok(DoorCommand::Open(open_time)) => {
+                    opened_at = Some(now());
+                    close_at = Some(now() + open_time);
+                }
+                Ok(DoorCommand::Hold) => {
+                    if let (Some(opened), Some(deadline)) = (opened_at, close_at) {
+                        let held = (now() + config.open_time).min(opened + config.max_hold);
+                        close_at = Some(deadline.max(held));
+                    }
+                }
+                Ok(DoorCommand::Close) => {
+                    if close_at.is_some() {
+                        close_at = Some(now());
+                    }
+                }
+                Err(cbc::TryRecvError::Empty) => break,
+                Err(cbc::TryRecvError::Disconnected) => return,
+            }
+        }
+
+        if close_at.is_some_and(|deadline| now() >= deadline) {
+            opened_at = None;
+            close_at = None;
+            if door_tx.send(true).is_err() {
+                return;
+            }
+        }
+        sleep(TIMER_PERIOD);
+        if clock_stopped() {
+            return;
+        }
+    }
+}
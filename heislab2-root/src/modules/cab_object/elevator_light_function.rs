@@ -36,10 +36,10 @@ impl Cab{
     }
     pub fn lights(&mut self, state: &Arc<SystemState>, elevator:Elevator){
         // Turn off lights for orders that are no longer in the new order vector.
+        let known_elevators_clone = state.known_elevators();
         for floor in 0..self.num_floors {
             for call_type in 0..3 {
                 let order = Order::init(floor, call_type);
-                let known_elevators_clone = state.known_elevators.lock().unwrap().clone();
                 let mut should_light = false;
     
                 // For hall orders, check if any known elevator has the order in its queue.
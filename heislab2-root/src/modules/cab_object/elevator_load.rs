@@ -0,0 +1,99 @@
+//! ## Elevator Load Module
+//! This module keeps track of how loaded the cab is, read from an optional load sensor on the elevator.
+//! The load is a part of the Cab struct, so every node sees it in the worldview and in best_to_worst_elevator.
+//!
+//! ## The structs includes:
+//! - **LoadConfig**: If there is a load sensor, the capacity of the cab and when it counts as full.
+//!
+//! ## The functions includes:
+//! - 'parse'      reads one "key: value" line from "boot.txt" into a `LoadConfig`.
+//! - 'is_full'    true if a load reading is a full cab.
+//! - 'set_load'   stores a new reading in the cab and updates the full-load flag.
+//!
+//! - A full cab does not stop for hall orders on the way, only for cab orders and the order it is driving to.
+//! - A full cab is put far down the list for new hall orders, see best_to_worst_elevator.
+//!
+//! The load is read with an extra command (10) on the elevator connection, which the simulated elevator answers.
+//! The stock elevator server does not know it, so the sensor must be turned on in "boot.txt".
+//! A node that gets no answer within LOAD_TIMEOUT at startup runs without the sensor, see elevator_init.rs.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! load_sensor: false              true if the elevator answers the load command
+//! load_capacity: 630              capacity of the cab, in the unit of the sensor (kg or persons)
+//! load_full: 80                   percent of the capacity that makes the cab full
+//! ```
+
+use super::cab::Cab;
+
+/// How the load of the cab is read and when it is full, see the keys above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadConfig {
+    pub sensor: bool,
+    pub capacity: u16,
+    pub full_percent: u8,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        LoadConfig {
+            sensor: false,
+            capacity: 630,
+            full_percent: 80,
+        }
+    }
+}
+
+impl LoadConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "load_" are ignored.
+    /// * `value` - &str - the text after ':'.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a load key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        match key.trim() {
+            "load_sensor" => value.parse().ok().map(|sensor| self.sensor = sensor).is_some(),
+            "load_capacity" => value.parse().ok().filter(|capacity| *capacity > 0).map(|capacity| self.capacity = capacity).is_some(),
+            "load_full" => value.parse::<u8>().ok().map(|percent| self.full_percent = percent.min(100)).is_some(),
+            _ => false,
+        }
+    }
+
+    /// True if `load` is at or above the full share of the capacity
+    pub fn is_full(&self, load: u16) -> bool {
+        return load as u32 * 100 >= self.capacity as u32 * self.full_percent as u32;
+    }
+}
+
+impl Cab {
+    /// set_load
+    /// Store a new reading of the load sensor
+    ///
+    /// # Arguments:
+    ///
+    /// * `load` - u16 - the reading, in the unit of the sensor.
+    /// * `config` - &LoadConfig - when the cab is full.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the cab became full or stopped being full.
+    ///
+    pub fn set_load(&mut self, load: u16, config: &LoadConfig) -> bool {
+        let full = config.is_full(load);
+        let changed = full != self.full_load;
+        self.load = Some(load);
+        self.full_load = full;
+        if changed {
+            println!("Cab {} is {}, load {} of {}", self.id, if full { "full" } else { "no longer full" }, load, config.capacity);
+        }
+        return changed;
+    }
+}
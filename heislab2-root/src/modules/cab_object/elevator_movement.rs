@@ -1,68 +1,51 @@
 #![allow(dead_code)]
 #![warn(unused_variables)]
 
-use std::time::{Duration, SystemTime};
-use std::thread;
-use crossbeam_channel as cbc;
-
 use crate::modules::elevator_object::*;
 use alias_lib::{CAB,HALL_DOWN,HALL_UP,DIRN_DOWN, DIRN_UP, DIRN_STOP};
-use elevator_init::Elevator; 
 
 use super::elevator_status_functions::Status;
 use super::cab::Cab;
+use super::elevator_command::ElevatorCommand;
 
 impl Cab{
-    // Set initial status
-    pub fn try_close_door( &mut self, door_tx: cbc::Sender<bool>, obstruction_rx: cbc::Receiver<bool>, elevator: Elevator) -> bool {
-        elevator.door_light(true);
-        let mut cabclone = self.clone();
+    /// Open the door at this floor, the door timer closes it again, see elevator_door.rs.
+    /// Returns the commands that light the door and arm the timer
+    pub fn try_close_door(&mut self) -> Vec<ElevatorCommand> {
+        let mut commands = vec![ElevatorCommand::DoorLight(true)];
+        commands.extend(self.set_status(Status::DoorOpen));
+        println!("Doors opened");
 
-        self.set_status(Status::DoorOpen, elevator.clone());
-        thread::spawn(move || {
-            println!("Doors opened");           
-            let mut start_time = SystemTime::now();
-            loop {
-                // Wait 1 second before attempting to close
-                    
-                match obstruction_rx.try_recv() {
-                        
-                    Ok(true) => {
-                         // obstruction: start loop again
-                        println!("Obstruction detected, holding doors..");
-                        cabclone.status = Status::Obstruction;
-                        start_time = SystemTime::now();                            
-                        continue;
-                        }
-                    Ok(false) =>{
-                        cabclone.status = Status::DoorOpen;
-                        continue;
-                        }
-                    Err(cbc::TryRecvError::Empty) => {
-                        // No obstruction or nothing received: close door
-                        let now = SystemTime::now();
-                        if (now.duration_since(start_time).unwrap() > Duration::from_secs(2)) && (cabclone.status != Status::Obstruction) {
-                            println!("No obstruction, closing doors");
-                            break;
-                        }
-                        continue;
-                    }
-                    Err(e) => {
-                        println!("Error receiving obstruction: {:?}", e);
-                        break;
-                    }
-                }
-            }
-        
-            door_tx.send(true).unwrap();
-            println!("Doors closed");
-        });
-        true
+        // A cab call made with the accessibility button keeps the door open longer
+        let floor = self.current_floor;
+        let accessible = self.accessible_stops.contains(&floor);
+        self.accessible_stops.retain(|stop| *stop != floor);
+        commands.push(ElevatorCommand::OpenDoor(accessible));
+        return commands;
     }
     
          
      
-    pub fn go_next_floor(&mut self, door_tx: cbc::Sender<bool>, obstruction_rx: cbc::Receiver<bool>, elevator:Elevator) {
+    /// Decide the next move of the cab from its queue, runs on the state thread.
+    /// Returns what the elevator must do, the caller runs it afterwards, see elevator_command.rs
+    pub fn go_next_floor(&mut self) -> Vec<ElevatorCommand> {
+        let mut commands = Vec::new();
+        // The travel watchdog drives the motor while the cab is in error, and run_node while it looks for its first floor
+        if self.status == Status::Error || self.status == Status::Init {
+            return commands;
+        }
+
+        // Parking is below every real order, a new order cancels it
+        if !self.queue.is_empty() {
+            self.parking = None;
+        }
+        if let Some(park_floor) = self.parking {
+            if self.status == Status::Moving || self.status == Status::Idle {
+                commands.push(self.drive_to_parking(park_floor));
+            }
+            return commands;
+        }
+
         if self.status == Status::DoorOpen  {
             // Update last_served_floor here before starting to move.
             self.last_served_floor = self.current_floor;
@@ -78,14 +61,15 @@ impl Cab{
     
             if let Some(next_floor) = self.queue.first().map(|first_item| first_item.floor) {
                 
+                // A full cab passes hall orders on the way, it still stops at the floor it is driving to
                 let should_stop = self.queue.iter().any(|order| {
                     if self.status == Status::Moving && order.floor == self.last_served_floor {
                         false
                     } else {
                         order.floor == effective_floor &&
                         (
-                            (self.direction == DIRN_UP   && order.order_type == HALL_UP) ||
-                            (self.direction == DIRN_DOWN && order.order_type == HALL_DOWN) ||
+                            (!self.full_load && self.direction == DIRN_UP   && order.order_type == HALL_UP) ||
+                            (!self.full_load && self.direction == DIRN_DOWN && order.order_type == HALL_DOWN) ||
                             (order.order_type == CAB)
                         )
                     }
@@ -99,8 +83,8 @@ impl Cab{
                         } else {
                             order.floor == effective_floor &&
                             (
-                                (self.direction == DIRN_UP   && order.order_type == HALL_UP) ||
-                                (self.direction == DIRN_DOWN && order.order_type == HALL_DOWN) ||
+                                (!self.full_load && self.direction == DIRN_UP   && order.order_type == HALL_UP) ||
+                                (!self.full_load && self.direction == DIRN_DOWN && order.order_type == HALL_DOWN) ||
                                 (order.order_type == CAB)
                             )
                         }
@@ -111,32 +95,51 @@ impl Cab{
                             "Stopping at floor {} because order in queue matches effective floor {}.",
                             effective_floor, effective_floor
                         );
-                        elevator.motor_direction(DIRN_STOP);
-                        self.try_close_door(door_tx, obstruction_rx.clone(), elevator.clone());
+                        commands.push(ElevatorCommand::Motor(DIRN_STOP));
+                        commands.extend(self.try_close_door());
                         // Update current_floor now that we've stopped.
                         self.current_floor = effective_floor;
                     }
                 } else {
                     // If no order demands a stop at the effective floor, command movement.
                     if next_floor > self.current_floor {
-                        self.set_status(Status::Moving, elevator.clone());
-                        elevator.motor_direction(DIRN_UP);
+                        commands.extend(self.set_status(Status::Moving));
+                        commands.push(ElevatorCommand::Motor(DIRN_UP));
                     } else if next_floor < self.current_floor {
-                        self.set_status(Status::Moving, elevator.clone());
-                        elevator.motor_direction(DIRN_DOWN);
+                        commands.extend(self.set_status(Status::Moving));
+                        commands.push(ElevatorCommand::Motor(DIRN_DOWN));
                     } else if next_floor == self.current_floor {
                         // Should only occur if we have just arrived.
-                        elevator.motor_direction(DIRN_STOP);
-                        self.try_close_door(door_tx, obstruction_rx.clone(), elevator.clone());
+                        commands.push(ElevatorCommand::Motor(DIRN_STOP));
+                        commands.extend(self.try_close_door());
                         self.current_floor = next_floor;
                     }
                 }
             } else {
-                elevator.motor_direction(DIRN_STOP);
+                commands.push(ElevatorCommand::Motor(DIRN_STOP));
             }
         } else {
             // If there are no orders, ensure the elevator is stopped.
-            elevator.motor_direction(DIRN_STOP);
+            commands.push(ElevatorCommand::Motor(DIRN_STOP));
         }
+        return commands;
 }
+
+    /// Drive towards the parking floor without opening the door, stop and forget it when there
+    fn drive_to_parking(&mut self, park_floor: u8) -> ElevatorCommand {
+        if park_floor > self.current_floor {
+            self.status = Status::Moving;
+            self.direction = DIRN_UP;
+            return ElevatorCommand::Motor(DIRN_UP);
+        } else if park_floor < self.current_floor {
+            self.status = Status::Moving;
+            self.direction = DIRN_DOWN;
+            return ElevatorCommand::Motor(DIRN_DOWN);
+        }
+        println!("Parked at floor {}", park_floor);
+        self.status = Status::Idle;
+        self.direction = DIRN_STOP;
+        self.parking = None;
+        return ElevatorCommand::Motor(DIRN_STOP);
+    }
 }    
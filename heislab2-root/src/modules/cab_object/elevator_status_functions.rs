@@ -4,9 +4,9 @@
 
 use std::fmt;
 use crate::modules::elevator_object::*;
-use alias_lib::{DIRN_DOWN,DIRN_UP,DIRN_STOP};
-use elevator_init::Elevator;
+use alias_lib::{CAB,DIRN_DOWN,DIRN_UP,DIRN_STOP};
 use super::cab::Cab;
+use super::elevator_command::ElevatorCommand;
 use serde::{Deserialize, Serialize};
 
 
@@ -18,6 +18,7 @@ pub enum Status{
     Error,
     Stop,
     Obstruction,
+    Init,           // Driving to a known floor after start, not online yet
 }
 
 
@@ -29,7 +30,8 @@ impl Status{
             Status::DoorOpen => "DoorOpen",
             Status::Error => "Error",
             Status::Stop => "Stop",
-            Status::Obstruction => "Obstruction"
+            Status::Obstruction => "Obstruction",
+            Status::Init => "Init"
         }
         
     }
@@ -37,9 +39,11 @@ impl Status{
 
 impl Cab{
     pub fn print_status(&self){
-        println!("______________________STATUS : {}", self.status.as_str()); //This line got angry if i shortened the rest
+        println!("______________________STATUS : {}{}{}", self.status.as_str(), if self.maintenance { " (maintenance)" } else { "" }, if self.full_load { " (full)" } else { "" }); //This line got angry if i shortened the rest
     }
-    pub fn set_status(&mut self, status: Status, elevator: Elevator){
+    /// Change the status of the cab, runs on the state thread. Returns what the elevator must do, see elevator_command.rs
+    pub fn set_status(&mut self, status: Status) -> Vec<ElevatorCommand> {
+        let mut commands = Vec::new();
 
         match status{
             // Floors are read as u8 0 is hall up, 1 hall down, 2 cab
@@ -83,7 +87,10 @@ impl Cab{
                 
             }
             Status::Obstruction=>{
-                self.status = Status::Obstruction;
+                // The obstruction does not end an emergency stop
+                if self.status != Status::Stop {
+                    self.status = Status::Obstruction;
+                }
             }
 
             Status::Idle => {
@@ -101,21 +108,13 @@ impl Cab{
             }
 
             //From stop you can only swap out by calling stop again
+            // The stop button goes through toggle_stop, it knows if the cab is at a floor
             Status::Stop => {
-                match self.status{
-                    Status::Stop => {
-                        self.status = Status::Idle;
+                return self.toggle_stop(None);
+            }
 
-                    }
-                    _ => {
-                        // KILL ELEVATOR !?
-                        self.turn_off_lights(elevator.clone());
-                        elevator.motor_direction(DIRN_STOP);
-                        self.status = Status::Stop;
-                        self.queue.clear();
-                        self.print_status();
-                    }
-                } 
+            Status::Init => {
+                self.status = Status::Init;
             }
 
             Status::Error => {
@@ -126,7 +125,7 @@ impl Cab{
                     }
                     _ =>{
                         // KILL ELEVATOR !
-                        elevator.motor_direction(DIRN_STOP);
+                        commands.push(ElevatorCommand::Motor(DIRN_STOP));
                         self.status = Status::Error;
                         self.queue.clear();
                         self.print_status();
@@ -139,7 +138,38 @@ impl Cab{
                 
             }
         }
-        
+        return commands;
+    }
+
+    /// toggle_stop
+    /// The stop button was pressed or released, from Status::Stop only the stop button gets the cab out again
+    ///
+    /// # Arguments:
+    ///
+    /// * `at_floor` - Option<u8> - what the floor sensor reads, read by the caller before the state thread is asked.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Vec<ElevatorCommand> - what the elevator must do, see elevator_command.rs.
+    ///
+    pub fn toggle_stop(&mut self, at_floor: Option<u8>) -> Vec<ElevatorCommand> {
+        if self.status == Status::Stop {
+            // Released, the caller decides how the cab moves on
+            self.status = Status::Idle;
+            self.direction = DIRN_STOP;
+            return vec![ElevatorCommand::StopLight(false)];
+        }
+
+        // Emergency stop: motor off at once, door open if at a floor, hall orders go to the others
+        let mut commands = vec![ElevatorCommand::Motor(DIRN_STOP), ElevatorCommand::StopLight(true)];
+        if let Some(floor) = at_floor {
+            self.current_floor = floor;
+            commands.push(ElevatorCommand::DoorLight(true));
+        }
+        self.status = Status::Stop;
+        self.queue.retain(|order| order.order_type == CAB);
+        self.print_status();
+        return commands;
     }
 
     pub fn merge_with(&mut self, other: &Cab) {
@@ -147,7 +177,19 @@ impl Cab{
         self.current_floor = other.current_floor;
         self.status = other.status.clone();
         self.direction = other.direction;
+        self.takes_hall_calls = other.takes_hall_calls;
+        self.maintenance = other.maintenance;
+        self.parking = other.parking;
+        self.served_floors = other.served_floors;
+        self.load = other.load;
+        self.full_load = other.full_load;
+        self.protocol_versions = other.protocol_versions;
         //self.role = other.role.clone();
     }
+
+    // A cab with a blocked door, a cab in maintenance and a cab that has not found its first floor do not take hall orders
+    pub fn serves_hall_calls(&self) -> bool {
+        return self.takes_hall_calls && !self.maintenance && self.status != Status::Init;
+    }
     
 }
\ No newline at end of file
@@ -6,6 +6,7 @@ use std::io::*;
 use std::fmt;
 use std::net::TcpStream; // https://doc.rust-lang.org/std/net/enum.IpAddr.html
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub use crate::modules::system_status::SystemState;
 pub use crate::modules::elevator_object::*;
@@ -14,6 +15,9 @@ pub use crate::modules::order_object::order_init::Order;
 pub use super::alias_lib::{HALL_DOWN, HALL_UP,CAB, DIRN_DOWN, DIRN_UP, DIRN_STOP};
 
 
+// How long to wait for the answer to the load command, the elevator server never answers it
+pub const LOAD_TIMEOUT: Duration = Duration::from_millis(200);
+
 #[derive(Clone, Debug)]
 pub struct Elevator {
     pub socket: Arc<Mutex<TcpStream>>,
@@ -26,8 +30,11 @@ pub struct Elevator {
 impl Elevator {
     
     pub fn init(addr: &str, num_floors: u8) -> Result<Elevator> {
+        let stream = TcpStream::connect(addr)?;
+        // Commands are 4 bytes each, dont let them wait for each other
+        stream.set_nodelay(true)?;
         Ok(Self {
-            socket: Arc::new(Mutex::new(TcpStream::connect(addr)?)),
+            socket: Arc::new(Mutex::new(stream)),
             num_floors,
         })
     }
@@ -98,6 +105,31 @@ impl Elevator {
         buf[1] != 0
     }
 
+    // Only the simulator answers this one, see elevator_load.rs. None if there was no answer within LOAD_TIMEOUT
+    pub fn load(&self) -> Option<u16> {
+        let mut buf = [10, 0, 0, 0];
+        let mut sock = self.socket.lock().unwrap();
+        if let Err(e) = sock.write_all(&buf) {
+            println!("Could not send the load command: {}", e);
+            return None;
+        }
+        if let Err(e) = sock.set_read_timeout(Some(LOAD_TIMEOUT)) {
+            println!("Could not set a timeout for the load command: {}", e);
+            return None;
+        }
+        let answer = sock.read_exact(&mut buf);
+        // The other commands wait for their answer as before
+        let _ = sock.set_read_timeout(None);
+        match answer {
+            Ok(()) if buf[0] == 10 => Some(u16::from_be_bytes([buf[1], buf[2]])),
+            Ok(()) => None,
+            Err(e) => {
+                println!("No answer to the load command: {}", e);
+                None
+            }
+        }
+    }
+
 }
 
 impl fmt::Display for Elevator {
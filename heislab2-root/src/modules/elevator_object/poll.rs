@@ -1,9 +1,9 @@
 use crossbeam_channel as cbc;
-use std::thread;
 use std::time;
 
 
 use super::elevator_init::Elevator; 
+use crate::modules::system_clock::{clock_stopped, sleep};
 
 #[derive(Debug)]
 pub struct CallButton {
@@ -17,13 +17,16 @@ pub fn call_buttons(elev: Elevator, ch: cbc::Sender<CallButton>, period: time::D
         for f in 0..elev.num_floors {
             for c in 0..3 {
                 let v = elev.call_button(f, c);
-                if v && prev[f as usize][c as usize] != v {
-                    ch.send(CallButton { floor: f, call: c }).unwrap();
+                if v && prev[f as usize][c as usize] != v && ch.send(CallButton { floor: f, call: c }).is_err() {
+                    return;
                 }
                 prev[f as usize][c as usize] = v;
             }
         }
-        thread::sleep(period)
+        sleep(period);
+        if clock_stopped() {
+            return;
+        }
     }
 }
 
@@ -32,11 +35,16 @@ pub fn floor_sensor(elev: Elevator, ch: cbc::Sender<u8>, period: time::Duration)
     loop {
         if let Some(f) = elev.floor_sensor() {
             if f != prev {
-                ch.send(f).unwrap();
+                if ch.send(f).is_err() {
+                    return;
+                }
                 prev = f;
             }
         }
-        thread::sleep(period)
+        sleep(period);
+        if clock_stopped() {
+            return;
+        }
     }
 }
 
@@ -45,10 +53,15 @@ pub fn stop_button(elev: Elevator, ch: cbc::Sender<bool>, period: time::Duration
     loop {
         let v = elev.stop_button();
         if prev != v {
-            ch.send(v).unwrap();
+            if ch.send(v).is_err() {
+                return;
+            }
             prev = v;
         }
-        thread::sleep(period)
+        sleep(period);
+        if clock_stopped() {
+            return;
+        }
     }
 }
 
@@ -57,9 +70,39 @@ pub fn obstruction(elev: Elevator, ch: cbc::Sender<bool>, period: time::Duration
     loop {
         let v = elev.obstruction();
         if prev != v {
-            ch.send(v).unwrap();
+            if ch.send(v).is_err() {
+                return;
+            }
             prev = v;
         }
-        thread::sleep(period)
+        sleep(period);
+        if clock_stopped() {
+            return;
+        }
+    }
+}
+
+pub fn load(elev: Elevator, ch: cbc::Sender<u16>, period: time::Duration) {
+    let mut prev = None;
+    while let Some(v) = elev.load() {
+        if prev != Some(v) {
+            if ch.send(v).is_err() {
+                return;
+            }
+            prev = Some(v);
+        }
+        sleep(period);
+        if clock_stopped() {
+            return;
+        }
+    }
+
+    // Every poll without an answer would hold the elevator for LOAD_TIMEOUT, stop asking but keep the channel open
+    println!("The elevator stopped answering the load command, the load sensor is turned off");
+    loop {
+        sleep(time::Duration::from_secs(3600));
+        if clock_stopped() {
+            return;
+        }
     }
 }
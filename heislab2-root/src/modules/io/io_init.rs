@@ -2,7 +2,7 @@
 // Module description
 //--------------------
 //! This module contains the objects related to elevator input/output. 
-//! The input and output consists of the buttons, floor sensor, and obstruction
+//! The input and output consists of the buttons, floor sensor, and obstruction, and the load sensor if there is one
 
 
 //---------
@@ -19,6 +19,7 @@ use crossbeam_channel as cbc;
 use crate::modules::elevator_object::poll;
 use crate::modules::elevator_object::elevator_init::Elevator;
 use crate::modules::order_object::order_init;
+use crate::modules::system_clock::track;
 
 
 //-----------
@@ -42,6 +43,9 @@ pub type FloorSensorRx = cbc::Receiver<u8>;
 pub type ObstructionTx = cbc::Sender<bool>;
 pub type ObstructionRx = cbc::Receiver<bool>;
 
+pub type LoadSensorTx = cbc::Sender<u16>;
+pub type LoadSensorRx = cbc::Receiver<u16>;
+
 pub type DoorCh = bool;
 pub type DoorTx = cbc::Sender<DoorCh>;
 pub type DoorRx = cbc::Receiver<DoorCh>;
@@ -70,8 +74,8 @@ fn create_rx_channel<T, F>(elevator: &Elevator, poll_fn: F) -> cbc::Receiver<T>
     
     let elevator_clone = elevator.clone();
 
-    // initialize polling thread
-    spawn(move || poll_fn(elevator_clone, tx_channel, POLL_PERIOD));
+    // initialize polling thread, it stops when the receiver is dropped or the virtual clock stops
+    track(spawn(move || poll_fn(elevator_clone, tx_channel, POLL_PERIOD)));
 
     rx_channel
 }
@@ -106,6 +110,7 @@ pub struct IoChannels {
     pub stop_rx         : StopButtonRx,
     pub floor_rx        : FloorSensorRx,
     pub obstruction_rx  : ObstructionRx,
+    pub load_rx         : LoadSensorRx,
     pub door_rx         : DoorRx,
     pub order_update_rx : OrderUpdateRx,
     pub light_update_rx : LightUpdateRx,
@@ -139,6 +144,7 @@ impl IoChannels {
             stop_rx         : create_rx_channel(elevator, poll::stop_button),
             floor_rx        : create_rx_channel(elevator, poll::floor_sensor),
             obstruction_rx  : create_rx_channel(elevator, poll::obstruction),
+            load_rx         : cbc::never(),
             door_rx         : door_ch.rx,
             order_update_rx : order_update_ch.rx,
             light_update_rx : light_update_ch.rx,
@@ -150,4 +156,10 @@ impl IoChannels {
 
         io_channels
     }
+
+    /// Starts polling the load sensor, only for elevators that answer the load command
+    pub fn with_load_sensor(mut self, elevator: &Elevator) -> IoChannels {
+        self.load_rx = create_rx_channel(elevator, poll::load);
+        self
+    }
 }
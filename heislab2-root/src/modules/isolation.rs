@@ -0,0 +1,171 @@
+//! ## Isolation Module
+//! Finds out when this node has lost every other elevator, and serves the hall calls pressed on its own panel meanwhile.
+//! A slave normally leaves hall calls to the master, with no master to hear them the call would wait for an election.
+//! The node is isolated when no message from another elevator has come for `timeout`: slaves get a worldview and
+//! the master an ack for every worldview about every 2 s, peers gossip more often.
+//!
+//! ## The structs includes:
+//! - **IsolationConfig**: How long the node waits for a word from another elevator.
+//! - **Isolation**: When the node last heard another elevator, and if it is isolated.
+//!
+//! ## The functions includes:
+//! - 'parse'                   reads one "key: value" line from "boot.txt" into an `IsolationConfig`.
+//! - 'heard_from'              remembers a message from another elevator, called on the state thread.
+//! - 'spawn_isolation_watch'   goes in and out of isolation every half second, and sends the orders taken when it is back.
+//!
+//! - While isolated every hall call pressed here goes straight into the queue of this cab, see the Call event in node.rs,
+//!   and the hall calls waiting in `all_orders` that no cab has are taken when the node goes isolated.
+//! - When another elevator is heard again the node sends NewOnline so the others take it as alive, and broadcasts
+//!   its cab as ErrorWorldview so the master adds the hall orders it took, see send_own_orders. The master may have changed
+//!   meanwhile, so it is not sent to one address. If that message is lost, the next worldview lacks the orders and
+//!   update_from_worldview sends them again.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! isolation_timeout: 3            seconds without a message from another elevator before the node is isolated
+//! ```
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::modules::elevator_object::alias_lib::CAB;
+use crate::modules::order_object::order_init::Order;
+use crate::modules::slave_functions::slave::{send_new_online, send_own_orders};
+use crate::modules::system_clock::{clock_stopped, elapsed_since, now, sleep, track};
+use crate::modules::system_status::{NodeState, SystemState};
+use crate::modules::udp_functions::udp_handler_init::UdpHandler;
+
+/// How long the node waits for a word from another elevator, see the keys above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IsolationConfig {
+    pub timeout: Duration,
+}
+
+impl Default for IsolationConfig {
+    fn default() -> Self {
+        IsolationConfig { timeout: Duration::from_secs(3) }
+    }
+}
+
+/// When this node last heard another elevator
+#[derive(Clone, Debug, PartialEq)]
+pub struct Isolation {
+    pub last_heard: Instant,    // Last message from another elevator, or when the node started
+    pub isolated: bool,         // No message for the timeout, hall calls pressed here are served here
+}
+
+impl Isolation {
+    pub fn init() -> Isolation {
+        Isolation { last_heard: now(), isolated: false }
+    }
+}
+
+impl IsolationConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', "isolation_timeout".
+    /// * `value` - &str - the text after ':', seconds.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was an isolation key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        if key.trim() != "isolation_timeout" {
+            return false;
+        }
+        match value.trim().parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+            Some(seconds) => self.timeout = seconds,
+            None => return false,
+        }
+        return true;
+    }
+}
+
+/// heard_from
+/// A message from elevator `sender_id` came in, called by the receiver for every message
+///
+/// # Arguments:
+///
+/// * `s` - &mut NodeState - the state of this node.
+/// * `sender_id` - u8 - the elevator that sent the message, messages from this node do not count.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn heard_from(s: &mut NodeState, sender_id: u8) {
+    if sender_id != s.me_id {
+        s.isolation.last_heard = now();
+    }
+}
+
+/// spawn_isolation_watch
+/// Every half second, check if the node has gone isolated or has heard another elevator again, see the module description.
+///
+/// # Arguments:
+///
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - Arc<UdpHandler> - sends NewOnline and the orders of this cab when the node is back.
+/// * `config` - IsolationConfig - the timeout.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn spawn_isolation_watch(state: Arc<SystemState>, udp_handler: Arc<UdpHandler>, config: IsolationConfig) {
+    track(thread::spawn(move || {
+        loop {
+            sleep(Duration::from_millis(500));
+            if clock_stopped() {
+                return;
+            }
+
+            let timeout = config.timeout;
+            let back = state.update(move |s| {
+                let isolated = elapsed_since(s.isolation.last_heard) > timeout;
+                if isolated == s.isolation.isolated {
+                    return false;
+                }
+                s.isolation.isolated = isolated;
+
+                if isolated {
+                    println!("No word from any other elevator in {:?}, serving the hall calls pressed here", timeout);
+                    take_waiting_hall_orders(s);
+                } else {
+                    println!("Heard another elevator again, rejoining the system");
+                }
+                return !isolated;
+            });
+
+            if back {
+                send_new_online(&state, &udp_handler);
+                send_own_orders(&state, &udp_handler, None);
+            }
+        }
+    }));
+}
+
+// Put the hall orders no cab has in the queue of this cab, they were pressed while no one could take them
+fn take_waiting_hall_orders(s: &mut NodeState) {
+    let assigned: Vec<Order> = s.known_elevators.iter().flat_map(|e| e.queue.iter().cloned()).collect();
+    let waiting: Vec<Order> = s.all_orders.iter()
+        .filter(|order| order.order_type != CAB && !assigned.contains(order))
+        .cloned()
+        .collect();
+
+    let me = match s.known_elevators.get_mut(0) {
+        Some(me) => me,
+        None => return,
+    };
+    for order in waiting {
+        if me.serves_floor(order.floor) && !me.queue.contains(&order) {
+            println!("Isolated, taking waiting hall order {:?}", order);
+            me.queue.push(order);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! ## Lease Module
+//! A master only acts as master while it holds a lease. Every slave acks the worldviews of its master,
+//! and the lease holds as long as a quorum of the known elevators has acked within `duration`.
+//! A master that can't renew the lease is fenced: it has lost the network or most of the others,
+//! and the rest of the system is electing a new master without it.
+//!
+//! ## The structs includes:
+//! - **Quorum**: Whose acks renew the lease.
+//! - **LeaseConfig**: The quorum and how long a lease lasts.
+//! - **MasterLease**: When this node became master, the last ack from every elevator, and if it is fenced.
+//!
+//! ## The functions includes:
+//! - 'parse'           reads one "key: value" line from "boot.txt" into a `LeaseConfig`.
+//! - 'record_ack'      remembers an ack, called on the state thread.
+//! - 'lease_holds'     tells if the acks are enough to hold the lease.
+//! - 'renew_lease'     fences or unfences this master, called by the master monitor.
+//! - 'step_down_to'    a fenced master hears another master and becomes its slave.
+//!
+//! - A new master holds the lease for `duration` before any ack is needed.
+//! - A fenced master marks its own cab as Slave and takes every hall order it knows of and stops at,
+//!   it gives no orders to other cabs and parks none. It keeps its master id so it does not elect itself again.
+//! - It keeps sending worldviews so it can find its way back: if its slaves ack again it unfences,
+//!   if it hears a worldview from a master that is not fenced, or from a fenced one with a lower id, it becomes a slave.
+//! - Keep `duration` at most the 3 s the slaves wait for a worldview, so the old master is fenced by the time they elect a new one.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! lease_quorum: majority          "majority" (default) of the known elevators or "any" other elevator
+//! lease_duration: 3               seconds an ack counts for the lease
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::modules::cab_object::cab::Cab;
+use crate::modules::elevator_object::alias_lib::CAB;
+use crate::modules::master_functions::master::Role;
+use crate::modules::system_clock::now;
+use crate::modules::system_status::{NodeState, SystemState};
+
+/// Whose acks renew the lease of the master
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Quorum {
+    #[default]
+    Majority,   // More than half of the known elevators, the master counts itself
+    AnyPeer,    // Any other elevator, a master alone in the system always holds it
+}
+
+/// The quorum and how long an ack counts, see the keys above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LeaseConfig {
+    pub quorum: Quorum,
+    pub duration: Duration,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        LeaseConfig {
+            quorum: Quorum::Majority,
+            duration: Duration::from_secs(3),
+        }
+    }
+}
+
+/// The lease of this node, only used while it is master
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MasterLease {
+    pub since: Option<Instant>,     // When this node became master, None while it is a slave
+    pub acks: Vec<(u8, Instant)>,   // Last ack from every other elevator
+    pub fenced: bool,               // The lease ran out, this master serves only its own cab
+}
+
+impl LeaseConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', a key starting with "lease_".
+    /// * `value` - &str - the text after ':', "majority" or "any" for the quorum, seconds for the duration.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a lease key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        match key.trim() {
+            "lease_quorum" => match value {
+                "majority" => self.quorum = Quorum::Majority,
+                "any" => self.quorum = Quorum::AnyPeer,
+                _ => return false,
+            },
+            "lease_duration" => match value.parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+                Some(seconds) => self.duration = seconds,
+                None => return false,
+            },
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+/// record_ack
+/// Remember that elevator `id` acked at `at`, any ack shows it still hears this node
+///
+/// # Arguments:
+///
+/// * `lease` - &mut MasterLease - the lease of this node.
+/// * `id` - u8 - the elevator that acked.
+/// * `at` - Instant - when the ack came.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn record_ack(lease: &mut MasterLease, id: u8, at: Instant) {
+    match lease.acks.iter_mut().find(|(acked_id, _)| *acked_id == id) {
+        Some(ack) => ack.1 = at,
+        None => lease.acks.push((id, at)),
+    }
+}
+
+/// lease_holds
+/// Tell if the lease of master `me_id` holds at `at`
+///
+/// # Arguments:
+///
+/// * `lease` - &MasterLease - the lease of this node.
+/// * `me_id` - u8 - id of this node, it does not ack itself.
+/// * `cabs` - &[Cab] - the known elevators, dead ones count in the size of the system.
+/// * `config` - &LeaseConfig - the quorum and the duration.
+/// * `at` - Instant - now.
+///
+/// # Returns:
+///
+/// Returns - bool - true if this node became master less than `duration` ago or enough elevators acked within `duration`.
+///
+pub fn lease_holds(lease: &MasterLease, me_id: u8, cabs: &[Cab], config: &LeaseConfig, at: Instant) -> bool {
+    if lease.since.is_some_and(|since| at.saturating_duration_since(since) < config.duration) {
+        return true;
+    }
+
+    let acked = cabs
+        .iter()
+        .filter(|cab| cab.id != me_id)
+        .filter(|cab| lease.acks.iter().any(|(id, acked_at)| *id == cab.id && at.saturating_duration_since(*acked_at) < config.duration))
+        .count();
+
+    return match config.quorum {
+        Quorum::Majority => (acked + 1) * 2 > cabs.len(),
+        Quorum::AnyPeer => acked > 0 || cabs.len() <= 1,
+    };
+}
+
+/// renew_lease
+/// Check the lease of this node if it is master, fence it if the lease ran out and unfence it if the acks are back.
+/// Called by the master monitor before the worldview is sent.
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `config` - &LeaseConfig - the quorum and the duration.
+///
+/// # Returns:
+///
+/// Returns - bool - true if this node is master and holds the lease.
+///
+pub fn renew_lease(state: &Arc<SystemState>, config: &LeaseConfig) -> bool {
+    let config = *config;
+    return state.update(move |s| {
+        if s.master_id != s.me_id {
+            s.lease = MasterLease::default();
+            return false;
+        }
+
+        let at = now();
+        if s.lease.since.is_none() {
+            s.lease.since = Some(at);
+        }
+        let holds = lease_holds(&s.lease, s.me_id, &s.known_elevators, &config, at);
+
+        if !holds && !s.lease.fenced {
+            println!("Master lease lost, no {:?} of the elevators acked in {:?}. Serving this cab only", config.quorum, config.duration);
+            s.lease.fenced = true;
+            take_known_hall_orders(s);
+        } else if holds && s.lease.fenced {
+            println!("Master lease renewed, acting as master again");
+            s.lease.fenced = false;
+        }
+
+        if let Some(me) = s.known_elevators.get_mut(0) {
+            me.role = if s.lease.fenced { Role::Slave } else { Role::Master };
+        }
+        return holds;
+    });
+}
+
+/// step_down_to
+/// A worldview from elevator `sender_id` came to this node, if this is a fenced master it becomes a slave of the sender.
+/// Runs on the state thread, the caller sends NewOnline if it returns true so the others take this cab as alive.
+///
+/// # Arguments:
+///
+/// * `s` - &mut NodeState - the state of this node.
+/// * `sender_id` - u8 - the elevator that sent the worldview.
+/// * `sender_is_master` - bool - the sender has its own cab as Master in the worldview, it is not fenced.
+///
+/// # Returns:
+///
+/// Returns - bool - true if this node stepped down.
+///
+pub fn step_down_to(s: &mut NodeState, sender_id: u8, sender_is_master: bool) -> bool {
+    if !s.lease.fenced || s.master_id != s.me_id {
+        return false;
+    }
+    // Two fenced masters, the one with the higher id gives way
+    if !sender_is_master && sender_id > s.me_id {
+        return false;
+    }
+
+    println!("Fenced master hears elevator {}, stepping down to slave", sender_id);
+    let me_id = s.me_id;
+    s.master_id = sender_id;
+    s.lifesign_master = now();
+    s.lease = MasterLease::default();
+    for elevator in s.known_elevators.iter_mut() {
+        if elevator.id == sender_id {
+            elevator.role = Role::Master;
+        } else if elevator.id == me_id {
+            elevator.role = Role::Slave;
+        }
+    }
+    return true;
+}
+
+// Put every hall order this node knows of and stops at in its own queue, the others may be serving them too
+fn take_known_hall_orders(s: &mut NodeState) {
+    let mut hall_orders: Vec<_> = s.all_orders.iter().filter(|order| order.order_type != CAB).cloned().collect();
+    for elevator in s.known_elevators.iter().skip(1) {
+        hall_orders.extend(elevator.queue.iter().filter(|order| order.order_type != CAB).cloned());
+    }
+
+    let me = match s.known_elevators.get_mut(0) {
+        Some(me) => me,
+        None => return,
+    };
+    for order in hall_orders {
+        if me.serves_floor(order.floor) && !me.queue.contains(&order) {
+            println!("Fenced master takes hall order {:?}", order);
+            me.queue.push(order);
+        }
+    }
+}
@@ -12,6 +12,8 @@
 //! - 'handle_slave_failure'
 //! - 'reassign_orders'
 //! - 'best_to_worst_elevator'
+//! - 'unservable_orders'
+//! - 'set_aside_unservable'
 //! - 'handle_multiple_masters'
 //! 
 //! ## Dependencies
@@ -28,7 +30,7 @@ use crate::modules::master_functions::master;
 #[allow(unused_variables)]
 
 //-----------------------IMPORTS------------------------------------------------------------
-use crate::modules::udp_functions::udp::{UdpMsg, UdpData,MessageType,udp_broadcast,make_udp_msg};
+use crate::modules::udp_functions::udp::{UdpMsg, UdpData,MessageType,make_udp_msg};
 use crate::modules::udp_functions::udp_handler_init::UdpHandler;
 use crate::modules::cab_object::elevator_status_functions::Status;
 use crate::modules::cab_object::cab::Cab;
@@ -36,6 +38,8 @@ use crate::modules::slave_functions::slave::{reboot_program, set_new_master};
 use crate::modules::order_object::order_init::Order;
 use crate::modules::system_status::SystemState;
 use crate::modules::elevator_object::alias_lib::{CAB,DIRN_UP,DIRN_DOWN, DIRN_STOP};
+use crate::modules::master_functions::traffic::DispatchMode;
+use crate::modules::master_functions::worldview_delta::next_worldview_update;
 use crossbeam_channel as cbc;
 
 
@@ -70,7 +74,8 @@ pub enum Role{
 /// give_order
 /// Sends an order to a slave elevator and waits for an acknowledgment.
 /// Broadcast order and wait for responce from reciver, if not recived resend, if this fail. find return false
-/// The diffrence from just adding from worldview broadcast and from give_order() is that unlike regular udp_broadcast() give_order() requires an acknoledgement from the recivers
+/// The diffrence from just adding from worldview broadcast and from give_order() is that unlike a regular UdpHandler::broadcast() give_order() requires an acknoledgement from the recivers
+/// A master that has lost its lease only gives orders to itself, see lease.rs
 /// 
 /// # Arguments:
 /// 
@@ -88,52 +93,65 @@ pub fn give_order(elevator_id: u8, new_order: Vec<&Order>, state: &Arc<SystemSta
 
     println!("Give order entered");
 
-    // Lock known_elevators
-    let known_elevators_locked = state.known_elevators.lock().unwrap();
-    // Find the elevator and copy the needed data
-    let elevator_index = match known_elevators_locked.iter().position(|e| e.id == elevator_id && e.alive) {
-        Some(index) => index,
-        None => {
-            println!("ERROR: Elevator ID {} not found in active elevators.", elevator_id);
-            return false;
+    let new_order: Vec<Order> = new_order.into_iter().cloned().collect();
+
+    // Check and add the orders in one job, so the cab sent in NewOrder is the queue of the state and nothing added meanwhile is lost
+    let me_id = state.me_id;
+    let found = state.update(move |s| {
+        // A fenced master gives no orders to others and puts its own straight in the queue, see lease.rs
+        if s.lease.fenced {
+            if elevator_id != me_id {
+                println!("Master lease lost, not giving orders to elevator {}", elevator_id);
+                return Err(false);
+            }
+            let me = s.known_elevators.get_mut(0).unwrap();
+            for order in new_order {
+                if !me.queue.contains(&order) {
+                    me.queue.push(order);
+                }
+            }
+            return Err(true);
         }
-    };
-    let mut already_handeld=Vec::new();
-    let mut not_handeld=Vec::new();
 
-    // Clone necessary data before dropping mutex lock
-    let mut elevator = known_elevators_locked[elevator_index].clone();
+        if !s.known_elevators.iter().any(|e| e.id == elevator_id && e.alive) {
+            println!("ERROR: Elevator ID {} not found in active elevators.", elevator_id);
+            return Err(false);
+        }
 
-    //Check if order is already being handeld
-    for order in &new_order {
-        if order.order_type != CAB {
-            //For all alive elevators
-            let alive_elevators: Vec<&Cab> = known_elevators_locked.iter().filter(|e| e.alive).collect();
-            for possible_other_server in alive_elevators{
-                // Elevator is alive, and has a cabcall or similar order, then we assume the order will be handeld by this elevator
-                if possible_other_server.queue.iter().any(|o: &Order| {o.floor == order.floor && (o.order_type == order.order_type)|| order.order_type == CAB}) {
-                    already_handeld.push(order.clone());
+        //Check if order is already being handeld
+        let mut already_handeld = Vec::new();
+        for order in &new_order {
+            if order.order_type != CAB {
+                //For all alive elevators
+                for possible_other_server in s.known_elevators.iter().filter(|e| e.alive) {
+                    // Elevator is alive, and has a cabcall or similar order, then we assume the order will be handeld by this elevator
+                    if possible_other_server.queue.iter().any(|o: &Order| {o.floor == order.floor && (o.order_type == order.order_type)|| order.order_type == CAB}) {
+                        already_handeld.push(order.clone());
+                    }
                 }
             }
         }
-    }
-    
-    // Remove orders that are being handeld
-    if !already_handeld.is_empty() {
-        not_handeld = new_order.into_iter().filter(|o| !already_handeld.contains(o)).collect();
-        println!("Order is covered by other orders, ignoring");
-    }else{
-        not_handeld = new_order;
-    }
-    
-    // Release known_elevators
-    drop(known_elevators_locked);
-    
-    // Add new orders to elevator
-    for order in not_handeld {
-        elevator.queue.push(order.clone());
-        println!("Added order{} to ID:{}",order.floor,elevator.id);
-    }
+
+        // Remove orders that are being handeld
+        if !already_handeld.is_empty() {
+            println!("Order is covered by other orders, ignoring");
+        }
+
+        // Add new orders to elevator
+        let elevator = s.known_elevators.iter_mut().find(|e| e.id == elevator_id).unwrap();
+        for order in new_order.into_iter().filter(|o| !already_handeld.contains(o)) {
+            if !elevator.queue.contains(&order) {
+                println!("Added order{} to ID:{}",order.floor,elevator.id);
+                elevator.queue.push(order);
+            }
+        }
+        return Ok(elevator.clone());
+    });
+
+    let elevator = match found {
+        Ok(elevator) => elevator,
+        Err(given) => return given,
+    };
 
     // Inform rest of system that the order has been added
     let message = make_udp_msg(state.me_id,MessageType::NewOrder, UdpData::Cab(elevator.clone()));
@@ -158,32 +176,33 @@ pub fn give_order(elevator_id: u8, new_order: Vec<&Order>, state: &Arc<SystemSta
 pub fn correct_master_worldview(discrepancy_cabs:&Vec<Cab>, state: &Arc<SystemState>) -> bool {
     println!("Correcting worldview for master");
 
-    let mut changes_made = false;
-
     if discrepancy_cabs.is_empty(){
         println!("List of missing cabs is empty");
         return false;
     }
 
-    // Compare elevators to missing orders list, lock the known_elevators 
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-    for missing_elevator in discrepancy_cabs.iter() {
-        if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == missing_elevator.id) {
-            for order in &missing_elevator.queue {
-                if !elevator.queue.contains(order) {
-                   elevator.queue.push(order.clone());
-                   println!("Added missing order {:?} to elevator {}", order.floor, elevator.id);
-                   changes_made = true;
+    // Compare elevators to missing orders list
+    let discrepancy_cabs = discrepancy_cabs.clone();
+    let changes_made = state.update(move |s| {
+        let mut changes_made = false;
+        for missing_elevator in discrepancy_cabs.iter() {
+            if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == missing_elevator.id) {
+                for order in &missing_elevator.queue {
+                    if !elevator.queue.contains(order) {
+                       elevator.queue.push(order.clone());
+                       println!("Added missing order {:?} to elevator {}", order.floor, elevator.id);
+                       changes_made = true;
+                    }
                 }
+            } else {
+                println!(
+                    "Warning: Elevator ID {} from missing_orders not found in active elevators",
+                    missing_elevator.id
+                );
             }
-        } else {
-            println!(
-                "Warning: Elevator ID {} from missing_orders not found in active elevators",
-                missing_elevator.id
-            );
         }
-    }
-    drop(known_elevators_locked);
+        return changes_made;
+    });
 
     return changes_made;
 }
@@ -193,6 +212,7 @@ pub fn correct_master_worldview(discrepancy_cabs:&Vec<Cab>, state: &Arc<SystemSt
 
 /// master_worldview
 /// Compare message and send out the corrected worldview (union of the recived and current worldview)
+/// Sent as a delta or a snapshot with a sequence number, see worldview_delta.rs, or whole if a known node is on an older protocol
 /// 
 /// # Arguments:
 /// 
@@ -206,10 +226,17 @@ pub fn master_worldview(state:&Arc<SystemState>, udphandler: &Arc<UdpHandler>){
 
     println!("Starting worldview");
 
-    let known_cabs = state.known_elevators.lock().unwrap().clone();
+    if udphandler.broadcast_version() < MessageType::WorldviewUpdate.since_version() {
+        let known_cabs = state.known_elevators();
+        let worldview_msg = make_udp_msg(state.me_id, MessageType::Worldview, UdpData::Cabs(known_cabs.clone()));
+        udphandler.broadcast(&worldview_msg);
+        return;
+    }
 
-    let worldview_msg = make_udp_msg(state.me_id, MessageType::Worldview, UdpData::Cabs(known_cabs.clone()));
-    udp_broadcast(&worldview_msg);
+    for update in state.update(next_worldview_update) {
+        let update_msg = make_udp_msg(state.me_id, MessageType::WorldviewUpdate, UdpData::WorldviewUpdate(update));
+        udphandler.broadcast(&update_msg);
+    }
 }
 
 // Give away master role, NOT NEEDED, KILL INSTEAD
@@ -265,30 +292,32 @@ pub fn handle_slave_failure(slave_id: u8, elevators: &mut Vec<Cab>,state: &Arc<S
 ///
 pub fn reassign_orders(orders: &Vec<Order>, state: &Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) -> bool {
     
-    // Copy value in mutexes
-    let mut all_orders = state.all_orders.lock().unwrap().clone();
-    let mut known_elevators = state.known_elevators.lock().unwrap().clone();
+    // Copy the orders and elevators
+    let (all_orders, known_elevators) = state.read(|s| (s.all_orders.clone(), s.known_elevators.clone()));
 
     // Find all orders currently assigned to any elevator
     let all_assigned_orders: Vec<Order> = known_elevators.iter().flat_map(|e| e.queue.iter().cloned()).collect();
 
-    // Filter out orders that are already in any elevator's queue
-    let mut missing_orders = all_orders.iter().filter(|o| !all_assigned_orders.contains(o)).cloned().collect();
+    // Filter out orders that are already in any elevator's queue, or given to this function to reassign
+    let mut missing_orders = all_orders.iter().filter(|o| !all_assigned_orders.contains(o) && !orders.contains(o)).cloned().collect();
 
     let mut combined_orders = orders.clone();
     combined_orders.append(&mut missing_orders);
 
+    // Orders no alive elevator stops at wait in all_orders, they are not tried again until one does
+    let combined_orders = set_aside_unservable(combined_orders, state);
+
     for order in combined_orders {
         
         let mut assigned = false;
 
         if order.order_type != CAB { 
 
-            //Lock active elevators and copy, then release
-            let elevators= state.known_elevators.lock().unwrap().clone();
+            //Copy active elevators
+            let elevators = state.known_elevators();
 
             //Give order to best alternative
-            for best_alternative in best_to_worst_elevator(&order, &elevators) {
+            for best_alternative in best_to_worst_elevator(&order, &elevators, state.dispatch_mode()) {
                 println!("Assigning order {} to elevator {}", order.floor, best_alternative);
 
                 if give_order(best_alternative, vec![&order],state,udp_handler) {
@@ -301,19 +330,19 @@ pub fn reassign_orders(orders: &Vec<Order>, state: &Arc<SystemState>, udp_handle
             }
         }
         
-        let mut failed_orders_locked = state.all_orders.lock().unwrap();
-
         // If no elevator accepted the order, store it for retry
         if !assigned{
             println!("No available elevator for order {}. Storing to retry later.", order.floor);
-            failed_orders_locked.push(order.clone());
-            drop(failed_orders_locked);
+            state.update(move |s| {
+                // A missing order is still in all_orders, it must not go in twice
+                if !s.all_orders.contains(&order) {
+                    s.all_orders.push(order);
+                }
+            });
         }
     }
 
-    let failed_orders_locked = state.all_orders.lock().unwrap();
-
-    if failed_orders_locked.is_empty() {
+    if state.all_orders().is_empty() {
         println!("All failed orders are redistributed");
         return true;
     } else {
@@ -327,72 +356,55 @@ pub fn reassign_elevator_orders(error_cab_id: u8 , state: &Arc<SystemState>, udp
     
     //Changing elevator from active elevators to inactive
     // Remove from active queue
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-    if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == error_cab_id) {
-        elevator.alive = false;
-        println!("Set elevator ID:{} as offline.", error_cab_id);
-    } else {
+    let dead_queue = state.update(move |s| {
+        if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == error_cab_id) {
+            elevator.alive = false;
+            println!("Set elevator ID:{} as offline.", error_cab_id);
+            // Take the hall orders away before giving them out, an answer from the elevator could mark it alive again
+            let dead_queue = elevator.queue.clone();
+            elevator.queue.retain(|o| o.order_type == CAB);
+            return dead_queue;
+        }
         println!("Error: can't find elevator ID {}, in known list", error_cab_id);
-    }
-    
+        return Vec::new();
+    });
 
-    //Find this elevator
-    if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == error_cab_id) {
-        let mut assigned = true;
-
-        //For each order check if CAB order
-        for order in elevator.queue.clone(){
-
-            // Do not reassign CAB orders
-            if order.order_type != CAB { 
-
-                //Lock known elevators and copy the active ones, then release
-                let live_elevators: Vec<_> = state.known_elevators.lock().unwrap().clone().into_iter().filter(|e| e.alive).collect();
-
-                //Give order to best alternative
-                for best_alternative in best_to_worst_elevator(&order, &live_elevators) {
-                    println!("Assigning order {} to elevator {}", order.floor, best_alternative);
-
-                    // Give the order to the best alternative and remove the order from the dead elevator
-                    if give_order(best_alternative, vec![&order],state,udp_handler) {
-
-                        //lock mutex
-                        let mut known_elevators_locked=state.known_elevators.lock().unwrap();
-
-                        //Find only the one that match ID
-                        if let Some(real_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == error_cab_id) {
-                            real_elevator.queue.retain(|o| *o != order);
-                            println!("Order removed from ID:{} and succsesfully redistributed", error_cab_id);
-                        } else {
-                            println!("Could not find elevator with ID {} in dead_elevators", error_cab_id);
-                        }
-                        drop(known_elevators_locked);
-                        break; 
-                    
-                    } else {
-                        println!("Failed to assign order {} to elevator {}. Trying next option", order.floor, best_alternative);
-                        assigned = false;
-                    }
-                }
+    // Do not reassign CAB orders, and set aside the ones no alive elevator stops at
+    let hall_orders: Vec<Order> = dead_queue.into_iter().filter(|order| order.order_type != CAB).collect();
+
+    for order in set_aside_unservable(hall_orders, state) {
+
+        let mut assigned = false;
 
-                let mut failed_orders_locked = state.all_orders.lock().unwrap();
-                // If no elevator accepted the order, store it for retry
-                if !assigned{
-                    println!("No available elevator for order {}. Storing to retry later.", order.floor);
-                    failed_orders_locked.push(order.clone());
-                    drop(failed_orders_locked);
-                } 
+        //Copy the active elevators
+        let live_elevators: Vec<_> = state.known_elevators().into_iter().filter(|e| e.alive).collect();
+
+        //Give order to best alternative
+        for best_alternative in best_to_worst_elevator(&order, &live_elevators, state.dispatch_mode()) {
+            println!("Assigning order {} to elevator {}", order.floor, best_alternative);
+
+            if give_order(best_alternative, vec![&order],state,udp_handler) {
+                println!("Order {} succsesfully redistributed from ID:{}", order.floor, error_cab_id);
+                assigned = true;
+                break; 
+            } else {
+                println!("Failed to assign order {} to elevator {}. Trying next option", order.floor, best_alternative);
             }
         }
-    }else{
-        println!("Couldnt find ID:{}, in active elevators", error_cab_id);
-        }
-
 
-    
+        // If no elevator accepted the order, store it for retry
+        if !assigned{
+            println!("No available elevator for order {}. Storing to retry later.", order.floor);
+            state.update(move |s| {
+                // A missing order is still in all_orders, it must not go in twice
+                if !s.all_orders.contains(&order) {
+                    s.all_orders.push(order);
+                }
+            });
+        }
+    }
 
-    let failed_orders_locked = state.all_orders.lock().unwrap();
-    if failed_orders_locked.is_empty() {
+    if state.all_orders().is_empty() {
         println!("All failed orders are redistributed");
         return true;
     } else {
@@ -408,14 +420,29 @@ pub fn reassign_elevator_orders(error_cab_id: u8 , state: &Arc<SystemState>, udp
 /// 
 /// * `order` - &Order - refrence to the order.
 /// * `elevators` - &Vec<Cab> - refrence to list of active elevators that the functions will sort.
+/// * `mode` - DispatchMode - the traffic pattern right now, see traffic.rs.
 /// 
 /// # Returns:
 ///
-/// Retruns - Vec<u8> - a list of i IDs in decending order from best fit to worst fit.
+/// Retruns - Vec<u8> - a list of i IDs in decending order from best fit to worst fit, cabs not stopping at the floor are left out,
+/// and so are cabs not taking hall calls, in maintenance or still starting for hall orders. Full cabs are put far down for hall orders.
 ///
-pub fn best_to_worst_elevator(order: &Order, elevators: &Vec<Cab>) -> Vec<u8> {
+pub fn best_to_worst_elevator(order: &Order, elevators: &Vec<Cab>, mode: DispatchMode) -> Vec<u8> {
+    // In down-peak every cab that takes hall calls gets its own zone of the floors above the lobby
+    let mut zoned_ids: Vec<u8> = elevators.iter().filter(|e| e.alive && e.serves_hall_calls()).map(|e| e.id).collect();
+    zoned_ids.sort();
+
     let mut scores: Vec<(u8, i32)> = Vec::new();
     for elevator in elevators {
+        // Never give an order to a cab that can't stop there, see Cab::served_floors
+        if !elevator.serves_floor(order.floor) {
+            continue;
+        }
+        // A cab with a blocked door does not take hall orders, see spawn_obstruction_watchdog,
+        // nor does a cab in maintenance or a cab that has not found its first floor yet
+        if order.order_type != CAB && !elevator.serves_hall_calls() {
+            continue;
+        }
         let mut score = 0;
 
         // Distance: closer floors get a higher score.
@@ -445,6 +472,14 @@ pub fn best_to_worst_elevator(order: &Order, elevators: &Vec<Cab>) -> Vec<u8> {
         // Shorter queue gets priority.
         score -= 10 * elevator.queue.len() as i32;
 
+        if order.order_type != CAB {
+            // A full cab can't pick anyone up, it is only given hall orders if every other cab is worse off
+            if elevator.full_load {
+                score -= 200;
+            }
+            score += traffic_score(order, elevator, mode, &zoned_ids);
+        }
+
         println!("score: {}      for elevator.id:    {}",{score},{elevator.id});
 
         scores.push((elevator.id, score));
@@ -457,6 +492,89 @@ pub fn best_to_worst_elevator(order: &Order, elevators: &Vec<Cab>) -> Vec<u8> {
     scores.into_iter().map(|(id, _)| id).collect()
 }
 
+/// unservable_orders
+/// Find the hall orders no alive elevator can stop for
+///
+/// # Arguments:
+///
+/// * `orders` - &[Order] - the orders to check, cab orders are left out.
+/// * `elevators` - &[Cab] - the known elevators.
+///
+/// # Returns:
+///
+/// Returns - Vec<Order> - the orders at floors no alive elevator stops at.
+///
+pub fn unservable_orders(orders: &[Order], elevators: &[Cab]) -> Vec<Order> {
+    return orders
+        .iter()
+        .filter(|order| order.order_type != CAB)
+        .filter(|order| !elevators.iter().any(|e| e.alive && e.serves_floor(order.floor)))
+        .cloned()
+        .collect();
+}
+
+/// set_aside_unservable
+/// Keep the hall orders no alive elevator stops at in all_orders and in the unservable list of the state,
+/// they are only reported the first time. An order leaves the list when an alive elevator stops at its floor again.
+///
+/// # Arguments:
+///
+/// * `orders` - Vec<Order> - the orders about to be given out.
+/// * `state` - &Arc<SystemState> - the state of this node.
+///
+/// # Returns:
+///
+/// Returns - Vec<Order> - the orders that can be given out, in the same order.
+///
+pub fn set_aside_unservable(orders: Vec<Order>, state: &Arc<SystemState>) -> Vec<Order> {
+    return state.update(move |s| {
+        let unservable = unservable_orders(&orders, &s.known_elevators);
+
+        // Back in play once a cab stops there again, or gone if the order is
+        let still_unservable = unservable_orders(&s.unservable, &s.known_elevators);
+        let all_orders = &s.all_orders;
+        s.unservable.retain(|order| all_orders.contains(order) && still_unservable.contains(order));
+
+        for order in &unservable {
+            if !s.unservable.contains(order) {
+                println!("UNSERVABLE: no alive elevator stops at floor {}, order {:?} is waiting", order.floor, order);
+                s.unservable.push(order.clone());
+            }
+            if !s.all_orders.contains(order) {
+                s.all_orders.push(order.clone());
+            }
+        }
+        return orders.into_iter().filter(|order| !unservable.contains(order)).collect();
+    });
+}
+
+/// Extra score for a hall order from the dispatch mode
+fn traffic_score(order: &Order, elevator: &Cab, mode: DispatchMode, zoned_ids: &[u8]) -> i32 {
+    match mode {
+        DispatchMode::Balanced => return 0,
+        DispatchMode::UpPeak { lobby } => {
+            // Keep the cabs waiting at the lobby for the next lobby pickup
+            let waiting_at_lobby = elevator.status == Status::Idle && elevator.current_floor == lobby && elevator.queue.is_empty();
+            if order.floor == lobby {
+                return if waiting_at_lobby { 20 } else { 0 };
+            }
+            return if waiting_at_lobby { -25 } else { 0 };
+        }
+        DispatchMode::DownPeak { lobby } => {
+            let upper_floors = elevator.num_floors.saturating_sub(lobby + 1) as usize;
+            let zone_index = match zoned_ids.iter().position(|id| *id == elevator.id) {
+                Some(index) => index,
+                None => return 0,
+            };
+            if order.floor <= lobby || upper_floors == 0 {
+                return 0;
+            }
+            let order_zone = (order.floor - lobby - 1) as usize * zoned_ids.len() / upper_floors;
+            return if order_zone == zone_index { 40 } else { 0 };
+        }
+    }
+}
+
 /// handle_multiple_masters
 /// If for some reason more than master is active, forexample race during election or one didnt recive the first message from new master.
 /// master with lowest ID keeps the role, the rest become slaves.
@@ -471,16 +589,12 @@ pub fn best_to_worst_elevator(order: &Order, elevators: &Vec<Cab>) -> Vec<u8> {
 /// Returns - Some(bool) - returns false if the ID is its own, returns true if it keeps the master if the ID is higher than the sender, reboots if it is lower.
 ///
 pub fn fix_master_issues(state: &Arc<SystemState>, udp_handler: &UdpHandler) {
-    // Make lowest id alive the master id
-    {
-        // Lock master_id first.
-        let mut master_id_guard = state.master_id.lock().unwrap();
-        let old_master_id = master_id_guard.clone();
-        // Then lock known_elevators.
-        let mut known_elevators = state.known_elevators.lock().unwrap();
+    // Make lowest id alive the master id, master_id and known_elevators change together
+    let new_master = state.update(|s| {
+        let old_master_id = s.master_id;
 
         // Gather mutable references to all elevators marked as Master.
-        let mut masters: Vec<&mut Cab> = known_elevators
+        let mut masters: Vec<&mut Cab> = s.known_elevators
             .iter_mut()
             .filter(|cab| cab.role == Role::Master)
             .collect();
@@ -488,55 +602,55 @@ pub fn fix_master_issues(state: &Arc<SystemState>, udp_handler: &UdpHandler) {
         if masters.len() > 1 {
             // Multiple masters found.
             masters.sort_by_key(|cab| cab.id);
-            let chosen_master = &masters[0];
-            let chosen_master_id = chosen_master.id;
+            let chosen_master_id = masters[0].id;
             println!(
                 "Multiple masters detected. Keeping elevator {} as master.",
                 chosen_master_id
             );
 
-            // Set the shared master id.
-            *master_id_guard = chosen_master_id;
-
             // Reassign all other masters to slave.
             for cab in masters.iter_mut().skip(1) {
                 println!("Reassigning elevator {} from master to slave.", cab.id);
                 cab.role = Role::Slave;
             }
+
+            // Set the shared master id.
+            s.master_id = chosen_master_id;
         } else if masters.is_empty() {
             // No elevator is master.
             println!("No masters alive, setting new master.");
             // Find all alive elevators.
-            let mut alive_elevators: Vec<&mut Cab> = known_elevators
+            let mut alive_elevators: Vec<&mut Cab> = s.known_elevators
                 .iter_mut()
                 .filter(|cab| cab.alive)
                 .collect();
             alive_elevators.sort_by_key(|cab| cab.id);
             if let Some(new_master) = alive_elevators.first_mut() {
                 new_master.role = Role::Master;
-                *master_id_guard = new_master.id;
+                s.master_id = new_master.id;
             }
         } else {
             // Exactly one master exists.
             println!("No multiple-master conflict detected.");
         }
 
-        if !(old_master_id == *master_id_guard){
-            if let Some(master_elevator) = known_elevators.iter().find(|cab| cab.id == *master_id_guard)
-            {
-                let msg = make_udp_msg(
-                    state.me_id,
-                    MessageType::NewMaster,
-                    UdpData::Cab(master_elevator.clone()),
-                );
-                for elevator in known_elevators.iter() {
-                    udp_handler.send(&elevator.inn_address, &msg);
-                }
-            }
+        if old_master_id == s.master_id {
+            return None;
+        }
+        let master_elevator = s.known_elevators.iter().find(|cab| cab.id == s.master_id)?.clone();
+        return Some((master_elevator, s.known_elevators.clone()));
+    });
+
+    // Tell everyone about the new master
+    if let Some((master_elevator, known_elevators)) = new_master {
+        let msg = make_udp_msg(
+            state.me_id,
+            MessageType::NewMaster,
+            UdpData::Cab(master_elevator),
+        );
+        for elevator in known_elevators.iter() {
+            udp_handler.send(&elevator.inn_address, &msg);
         }
-       
-        
-        // Both locks (master_id and known_elevators) are released here.
     }
 
     // Ensure our own role is correct.
@@ -0,0 +1,192 @@
+//! ## Parking Module
+//! This module decides where idle cabs should wait for the next call.
+//! The master runs the policy over the known cabs and sends each idle cab a Park message,
+//! the cab drives there only while its queue is empty, see go_next_floor.
+//!
+//! ## The structs includes:
+//! - **ParkingPolicy**: Home floors, lobby during the morning peak, or spread over the shaft.
+//!
+//! ## The functions includes:
+//! - 'parse'            reads one "key: value" line from "boot.txt" into a `ParkingPolicy`.
+//! - 'parking_targets'  the floor every idle cab should park at right now.
+//! - 'hour_of_day'      the hour used to find the morning peak.
+//!
+//! A parking move is not an order: it is not in the queue, it lights no lamp and any real order cancels it.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! parking: home                   off, home, lobby or spread
+//! parking_home: 1=0 2=2 3=3       home floor of every cab, "id=floor"
+//! parking_lobby: 0                floor the cabs wait at during the morning peak
+//! parking_peak: 7-10              the morning peak, hours of the day (UTC), idle cabs spread out outside it
+//! ```
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::modules::cab_object::cab::Cab;
+use crate::modules::cab_object::elevator_status_functions::Status;
+
+/// How idle cabs are parked, off by default
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ParkingPolicy {
+    #[default]
+    Off,
+    HomeFloors(Vec<(u8, u8)>),                          // (cab id, home floor), cabs not listed stay where they are
+    MorningPeak { lobby: u8, start_hour: u8, end_hour: u8 }, // Everyone to the lobby in the peak, spread out outside it
+    Spread,                                             // Idle cabs spread evenly from the bottom to the top floor
+}
+
+impl ParkingPolicy {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "parking" are ignored.
+    /// * `value` - &str - the text after ':'.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a parking key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        match key.trim() {
+            "parking" => {
+                let policy = match value {
+                    "off" => ParkingPolicy::Off,
+                    "home" => ParkingPolicy::HomeFloors(Vec::new()),
+                    "lobby" => ParkingPolicy::MorningPeak { lobby: 0, start_hour: 7, end_hour: 10 },
+                    "spread" => ParkingPolicy::Spread,
+                    _ => return false,
+                };
+                // Keep what the other keys have set if they came first
+                match (&*self, policy) {
+                    (ParkingPolicy::HomeFloors(_), ParkingPolicy::HomeFloors(_)) => {}
+                    (ParkingPolicy::MorningPeak { .. }, ParkingPolicy::MorningPeak { .. }) => {}
+                    (_, policy) => *self = policy,
+                }
+                return true;
+            }
+            "parking_home" => {
+                let homes: Option<Vec<(u8, u8)>> = value
+                    .split_whitespace()
+                    .map(|pair| {
+                        let (id, floor) = pair.split_once('=')?;
+                        Some((id.trim().parse().ok()?, floor.trim().parse().ok()?))
+                    })
+                    .collect();
+                match homes {
+                    Some(homes) => {
+                        *self = ParkingPolicy::HomeFloors(homes);
+                        return true;
+                    }
+                    None => return false,
+                }
+            }
+            "parking_lobby" => {
+                let floor = match value.parse::<u8>() {
+                    Ok(floor) => floor,
+                    Err(_) => return false,
+                };
+                match self {
+                    ParkingPolicy::MorningPeak { lobby, .. } => *lobby = floor,
+                    _ => *self = ParkingPolicy::MorningPeak { lobby: floor, start_hour: 7, end_hour: 10 },
+                }
+                return true;
+            }
+            "parking_peak" => {
+                let hours = value.split_once('-').and_then(|(start, end)| {
+                    Some((start.trim().parse::<u8>().ok()?, end.trim().parse::<u8>().ok()?))
+                });
+                let (start, end) = match hours {
+                    Some(hours) => hours,
+                    None => return false,
+                };
+                match self {
+                    ParkingPolicy::MorningPeak { start_hour, end_hour, .. } => {
+                        *start_hour = start;
+                        *end_hour = end;
+                    }
+                    _ => *self = ParkingPolicy::MorningPeak { lobby: 0, start_hour: start, end_hour: end },
+                }
+                return true;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// True if the cab is free to be parked: alive, idle, nothing to do and not in maintenance
+pub fn can_park(cab: &Cab) -> bool {
+    return cab.alive && cab.status == Status::Idle && cab.queue.is_empty() && !cab.maintenance;
+}
+
+/// parking_targets
+/// Find where every idle cab should wait with the given policy
+///
+/// # Arguments:
+///
+/// * `policy` - &ParkingPolicy - the policy of the master.
+/// * `cabs` - &Vec<Cab> - the known cabs, only those that can park are given a floor.
+/// * `num_floors` - u8 - floors in the shaft.
+/// * `hour` - u8 - hour of the day, see hour_of_day.
+///
+/// # Returns:
+///
+/// Returns - Vec<(u8, u8)> - (cab id, floor) for every idle cab that has a parking floor, also if it is there already.
+/// Floors the cab does not stop at are left out.
+///
+pub fn parking_targets(policy: &ParkingPolicy, cabs: &Vec<Cab>, num_floors: u8, hour: u8) -> Vec<(u8, u8)> {
+    let targets = policy_targets(policy, cabs, num_floors, hour);
+    return targets
+        .into_iter()
+        .filter(|(id, floor)| cabs.iter().any(|cab| cab.id == *id && cab.serves_floor(*floor)))
+        .collect();
+}
+
+/// Where the policy wants every idle cab, before the floors a cab can't reach are taken out
+fn policy_targets(policy: &ParkingPolicy, cabs: &Vec<Cab>, num_floors: u8, hour: u8) -> Vec<(u8, u8)> {
+    let top_floor = num_floors.saturating_sub(1);
+    let idle: Vec<&Cab> = cabs.iter().filter(|cab| can_park(cab)).collect();
+
+    match policy {
+        ParkingPolicy::Off => return Vec::new(),
+        ParkingPolicy::HomeFloors(homes) => {
+            return idle
+                .iter()
+                .filter_map(|cab| homes.iter().find(|(id, _)| *id == cab.id).map(|(_, floor)| (cab.id, (*floor).min(top_floor))))
+                .collect();
+        }
+        ParkingPolicy::MorningPeak { lobby, start_hour, end_hour } => {
+            if hour >= *start_hour && hour < *end_hour {
+                return idle.iter().map(|cab| (cab.id, (*lobby).min(top_floor))).collect();
+            }
+            return policy_targets(&ParkingPolicy::Spread, cabs, num_floors, hour);
+        }
+        ParkingPolicy::Spread => {
+            // The lowest cab takes the lowest spot and so on, so no two cabs cross on the way
+            let mut idle = idle;
+            idle.sort_by_key(|cab| (cab.current_floor, cab.id));
+            let count = idle.len() as u32;
+            return idle
+                .iter()
+                .enumerate()
+                .map(|(i, cab)| {
+                    let floor = if count == 1 {
+                        top_floor as u32 / 2
+                    } else {
+                        (i as u32 * top_floor as u32 + (count - 1) / 2) / (count - 1)
+                    };
+                    (cab.id, floor as u8)
+                })
+                .collect();
+        }
+    }
+}
+
+/// The hour of the day (UTC) at `time`
+pub fn hour_of_day(time: SystemTime) -> u8 {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    return ((seconds / 3600) % 24) as u8;
+}
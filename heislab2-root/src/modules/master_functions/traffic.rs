@@ -0,0 +1,189 @@
+//! ## Traffic Module
+//! This module follows the recent hall calls and picks the dispatch mode the master uses in best_to_worst_elevator.
+//!
+//! ## The structs includes:
+//! - **DispatchMode**: Balanced, up-peak or down-peak.
+//! - **TrafficConfig**: The lobby, how far back calls are counted, when traffic is a peak, and a forced mode.
+//!
+//! ## The functions includes:
+//! - 'parse'                   reads one "key: value" line from "boot.txt" into a `TrafficConfig`.
+//! - 'classify'                the mode that fits a list of hall calls.
+//! - 'record_hall_call'        remembers when a hall call came in, called on the state thread.
+//! - 'refresh_dispatch_mode'   forgets old calls and sets the mode of the node.
+//!
+//! - Up-peak: most calls are going up from the lobby, cabs waiting at the lobby are kept for the lobby.
+//! - Down-peak: most calls are going down from the upper floors, every cab gets its own zone of upper floors.
+//! - Balanced: inter-floor traffic, the plain cost function.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! dispatch_mode: auto             auto, balanced, up_peak or down_peak
+//! dispatch_lobby: 0               the floor people come in at
+//! dispatch_window: 300            seconds of hall calls looked at
+//! dispatch_min_calls: 10          fewer calls than this in the window is balanced traffic
+//! dispatch_peak_share: 60         percent of the calls that makes a peak
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use crate::modules::order_object::order_init::Order;
+use crate::modules::system_clock::now;
+use crate::modules::system_status::{NodeState, SystemState};
+
+/// How the master weighs the cabs for a hall call
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DispatchMode {
+    #[default]
+    Balanced,
+    UpPeak { lobby: u8 },
+    DownPeak { lobby: u8 },
+}
+
+/// How the dispatch mode is found, see the keys above
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrafficConfig {
+    pub lobby: u8,
+    pub window: Duration,
+    pub min_calls: usize,
+    pub peak_share: f64,                // 0.0 to 1.0
+    pub forced: Option<DispatchMode>,   // Set to use this mode whatever the traffic is
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        TrafficConfig {
+            lobby: 0,
+            window: Duration::from_secs(300),
+            min_calls: 10,
+            peak_share: 0.6,
+            forced: None,
+        }
+    }
+}
+
+impl DispatchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DispatchMode::Balanced => "balanced",
+            DispatchMode::UpPeak { .. } => "up-peak",
+            DispatchMode::DownPeak { .. } => "down-peak",
+        }
+    }
+
+    /// Read "auto", "balanced", "up_peak" or "down_peak", auto is Some(None)
+    pub fn parse(text: &str, lobby: u8) -> Option<Option<DispatchMode>> {
+        match text.trim() {
+            "auto" => Some(None),
+            "balanced" => Some(Some(DispatchMode::Balanced)),
+            "up_peak" => Some(Some(DispatchMode::UpPeak { lobby })),
+            "down_peak" => Some(Some(DispatchMode::DownPeak { lobby })),
+            _ => None,
+        }
+    }
+}
+
+impl TrafficConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "dispatch_" are ignored.
+    /// * `value` - &str - the text after ':'.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a dispatch key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        match key.trim() {
+            "dispatch_mode" => DispatchMode::parse(value, self.lobby).map(|mode| self.forced = mode).is_some(),
+            "dispatch_lobby" => value.parse().ok().map(|lobby| self.set_lobby(lobby)).is_some(),
+            "dispatch_window" => value.parse::<f64>().ok().filter(|s| s.is_finite()).and_then(|s| Duration::try_from_secs_f64(s).ok()).map(|window| self.window = window).is_some(),
+            "dispatch_min_calls" => value.parse().ok().map(|calls| self.min_calls = calls).is_some(),
+            "dispatch_peak_share" => value.parse::<f64>().ok().filter(|p| p.is_finite()).map(|p| self.peak_share = p.clamp(0.0, 100.0) / 100.0).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Change the lobby, also in a forced mode read before it
+    pub fn set_lobby(&mut self, lobby: u8) {
+        self.lobby = lobby;
+        self.forced = match self.forced {
+            Some(DispatchMode::UpPeak { .. }) => Some(DispatchMode::UpPeak { lobby }),
+            Some(DispatchMode::DownPeak { .. }) => Some(DispatchMode::DownPeak { lobby }),
+            other => other,
+        };
+    }
+}
+
+/// classify
+/// Find the mode that fits the hall calls
+///
+/// # Arguments:
+///
+/// * `calls` - &[Order] - the hall calls in the window, cab calls are not counted.
+/// * `config` - &TrafficConfig - the lobby and when traffic is a peak.
+///
+/// # Returns:
+///
+/// Returns - DispatchMode - up-peak or down-peak if enough calls share that pattern, else balanced.
+///
+pub fn classify(calls: &[Order], config: &TrafficConfig) -> DispatchMode {
+    let hall_calls: Vec<&Order> = calls.iter().filter(|order| order.order_type != CAB).collect();
+    if hall_calls.is_empty() || hall_calls.len() < config.min_calls {
+        return DispatchMode::Balanced;
+    }
+
+    let total = hall_calls.len() as f64;
+    let up_from_lobby = hall_calls.iter().filter(|order| order.order_type == HALL_UP && order.floor == config.lobby).count() as f64;
+    let down_from_above = hall_calls.iter().filter(|order| order.order_type == HALL_DOWN && order.floor > config.lobby).count() as f64;
+
+    if up_from_lobby / total >= config.peak_share {
+        return DispatchMode::UpPeak { lobby: config.lobby };
+    }
+    if down_from_above / total >= config.peak_share {
+        return DispatchMode::DownPeak { lobby: config.lobby };
+    }
+    return DispatchMode::Balanced;
+}
+
+/// Remember a new hall call, must be called from a state job
+pub fn record_hall_call(s: &mut NodeState, order: &Order) {
+    if order.order_type != CAB {
+        s.hall_calls.push((now(), order.clone()));
+    }
+}
+
+/// refresh_dispatch_mode
+/// Forget the hall calls that are older than the window and set the dispatch mode of the node
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+///
+/// # Returns:
+///
+/// Returns - DispatchMode - the mode to use now.
+///
+pub fn refresh_dispatch_mode(state: &Arc<SystemState>) -> DispatchMode {
+    let (mode, changed) = state.update(|s| {
+        let window = s.traffic.window;
+        let now = now();
+        s.hall_calls.retain(|(at, _)| now.saturating_duration_since(*at) <= window);
+
+        let calls: Vec<Order> = s.hall_calls.iter().map(|(_, order)| order.clone()).collect();
+        let mode = s.traffic.forced.unwrap_or_else(|| classify(&calls, &s.traffic));
+        let changed = mode != s.dispatch_mode;
+        s.dispatch_mode = mode;
+        return (mode, changed);
+    });
+
+    if changed {
+        println!("Dispatch mode is now {}", mode.as_str());
+    }
+    return mode;
+}
@@ -0,0 +1,280 @@
+//! ## Worldview Delta Module
+//! The master no longer sends all known cabs every round. Every worldview update has a sequence number:
+//! most are deltas with only what changed since the update before, every `FULL_SNAPSHOT_EVERY` update is
+//! a full snapshot. A snapshot is sent as one message per cab, so a large system never hits the datagram size.
+//! A receiver that misses an update, or gets one from a new master, asks the master for a snapshot with ResyncRequest.
+//!
+//! ## The structs includes:
+//! - **CabChange**: One change to one cab, e.g. it moved to a floor or got an order.
+//! - **WorldviewUpdate**: A part of a snapshot or a delta, with its sequence number.
+//! - **WorldviewLog**: The worldview as it was at the last update sent (master) or received (slave).
+//!
+//! ## The functions includes:
+//! - 'diff_worldviews'             the changes from one worldview to another.
+//! - 'apply_changes'               applies a delta to a worldview.
+//! - 'snapshot_parts'              splits a worldview into snapshot messages.
+//! - 'next_worldview_update'       the updates the master sends this round, called on the state thread.
+//! - 'receive_worldview_update'    puts an update into the log of a receiver, called on the state thread.
+//!
+//! - Nodes on a protocol older than WorldviewUpdate still get the whole worldview as before, see master_worldview.
+//! - `last_lifesign` is not sent in deltas, the receivers keep their own.
+//! - A delta that would not fit in a datagram is sent as a snapshot instead.
+
+use serde::{Deserialize, Serialize};
+
+use crate::modules::cab_object::cab::Cab;
+use crate::modules::cab_object::elevator_status_functions::Status;
+use crate::modules::order_object::order_init::Order;
+use crate::modules::system_status::NodeState;
+use crate::modules::udp_functions::transport::MAX_DATAGRAM;
+use crate::modules::udp_functions::udp::{encode_msg, make_udp_msg, MessageType, UdpData, PROTOCOL_VERSION};
+
+pub const FULL_SNAPSHOT_EVERY: u32 = 10;    // Updates between two snapshots, a worldview goes out about every 2 s
+
+/// One change to one cab in the worldview
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CabChange {
+    Moved { id: u8, floor: u8, status: Status, direction: u8 },
+    OrderAdded { id: u8, order: Order },
+    OrderRemoved { id: u8, order: Order },
+    Alive { id: u8, alive: bool },
+    Cab(Cab),   // A new cab, or a change the others don't cover
+}
+
+/// What the master sends instead of the whole worldview
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WorldviewUpdate {
+    Snapshot { seq: u32, part: u8, parts: u8, cab: Cab },  // One cab of the worldview at `seq`, `parts` cabs in all
+    Delta { seq: u32, changes: Vec<CabChange> },           // The changes since `seq - 1`
+}
+
+/// The worldview at the last update, sent by the master or received by a slave
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldviewLog {
+    pub sender: u8,                 // The master that sent the updates, 0 before the first
+    pub seq: u32,
+    pub cabs: Vec<Cab>,             // The worldview at `seq`
+    pub synced: bool,               // A receiver has every update up to `seq`
+    pub since_snapshot: u32,        // Deltas the master has sent since its last snapshot
+    pub snapshot: Vec<(u8, Cab)>,   // The parts of a snapshot being received, with their number
+    pub snapshot_seq: u32,
+    pub snapshot_sender: u8,
+}
+
+/// diff_worldviews
+/// The changes that turn `old` into `new`, `apply_changes(old, changes)` gives `new` except for `last_lifesign`
+///
+/// # Arguments:
+///
+/// * `old` - &[Cab] - the worldview the receivers have.
+/// * `new` - &[Cab] - the worldview of the master now.
+///
+/// # Returns:
+///
+/// Returns - Vec<CabChange> - empty if nothing changed.
+///
+pub fn diff_worldviews(old: &[Cab], new: &[Cab]) -> Vec<CabChange> {
+    let mut changes = Vec::new();
+
+    for cab in new {
+        let before = match old.iter().find(|before| before.id == cab.id) {
+            Some(before) => before,
+            None => {
+                changes.push(CabChange::Cab(cab.clone()));
+                continue;
+            }
+        };
+
+        let id = cab.id;
+        let mut cab_changes = Vec::new();
+        if before.current_floor != cab.current_floor || before.status != cab.status || before.direction != cab.direction {
+            cab_changes.push(CabChange::Moved { id, floor: cab.current_floor, status: cab.status.clone(), direction: cab.direction });
+        }
+        if before.alive != cab.alive {
+            cab_changes.push(CabChange::Alive { id, alive: cab.alive });
+        }
+        for order in before.queue.iter().filter(|order| !cab.queue.contains(order)) {
+            cab_changes.push(CabChange::OrderRemoved { id, order: order.clone() });
+        }
+        for order in cab.queue.iter().filter(|order| !before.queue.contains(order)) {
+            cab_changes.push(CabChange::OrderAdded { id, order: order.clone() });
+        }
+
+        // Anything else, or a queue the small changes can't rebuild, sends the whole cab
+        let mut rebuilt = vec![before.clone()];
+        apply_changes(&mut rebuilt, &cab_changes);
+        rebuilt[0].last_lifesign = cab.last_lifesign;
+        if rebuilt[0] == *cab {
+            changes.extend(cab_changes);
+        } else {
+            changes.push(CabChange::Cab(cab.clone()));
+        }
+    }
+    return changes;
+}
+
+/// apply_changes
+/// Apply a delta to a worldview
+///
+/// # Arguments:
+///
+/// * `cabs` - &mut Vec<Cab> - the worldview at the update before the delta.
+/// * `changes` - &[CabChange] - the delta.
+///
+/// # Returns:
+///
+/// Returns - bool - false if a change is for a cab that is not in the worldview, the worldview is then out of sync.
+///
+pub fn apply_changes(cabs: &mut Vec<Cab>, changes: &[CabChange]) -> bool {
+    for change in changes {
+        if let CabChange::Cab(new_cab) = change {
+            match cabs.iter_mut().find(|cab| cab.id == new_cab.id) {
+                Some(cab) => *cab = new_cab.clone(),
+                None => cabs.push(new_cab.clone()),
+            }
+            continue;
+        }
+
+        let id = match change {
+            CabChange::Moved { id, .. } | CabChange::OrderAdded { id, .. } | CabChange::OrderRemoved { id, .. } | CabChange::Alive { id, .. } => *id,
+            CabChange::Cab(_) => continue,
+        };
+        let cab = match cabs.iter_mut().find(|cab| cab.id == id) {
+            Some(cab) => cab,
+            None => return false,
+        };
+        match change {
+            CabChange::Moved { floor, status, direction, .. } => {
+                cab.current_floor = *floor;
+                cab.status = status.clone();
+                cab.direction = *direction;
+            }
+            CabChange::OrderAdded { order, .. } => cab.queue.push(order.clone()),
+            CabChange::OrderRemoved { order, .. } => {
+                if let Some(index) = cab.queue.iter().position(|queued| queued == order) {
+                    cab.queue.remove(index);
+                }
+            }
+            CabChange::Alive { alive, .. } => cab.alive = *alive,
+            CabChange::Cab(_) => {}
+        }
+    }
+    return true;
+}
+
+/// snapshot_parts
+/// Split the worldview at `seq` into snapshot messages, one per cab
+///
+/// # Arguments:
+///
+/// * `seq` - u32 - the sequence number of the snapshot.
+/// * `cabs` - &[Cab] - the worldview.
+///
+/// # Returns:
+///
+/// Returns - Vec<WorldviewUpdate> - one Snapshot per cab.
+///
+pub fn snapshot_parts(seq: u32, cabs: &[Cab]) -> Vec<WorldviewUpdate> {
+    let parts = cabs.len() as u8;
+    return cabs
+        .iter()
+        .enumerate()
+        .map(|(part, cab)| WorldviewUpdate::Snapshot { seq, part: part as u8, parts, cab: cab.clone() })
+        .collect();
+}
+
+/// next_worldview_update
+/// The updates the master sends this round: a snapshot if it is time for one or the receivers follow another master, else a delta
+///
+/// # Arguments:
+///
+/// * `s` - &mut NodeState - the state of the master.
+///
+/// # Returns:
+///
+/// Returns - Vec<WorldviewUpdate> - the parts of a snapshot, or one delta.
+///
+pub fn next_worldview_update(s: &mut NodeState) -> Vec<WorldviewUpdate> {
+    let cabs = s.known_elevators.clone();
+    let me_id = s.me_id;
+    let log = &mut s.worldview_log;
+
+    let seq = log.seq.wrapping_add(1);
+    let mut delta = None;
+    if log.sender == me_id && log.since_snapshot < FULL_SNAPSHOT_EVERY {
+        let update = WorldviewUpdate::Delta { seq, changes: diff_worldviews(&log.cabs, &cabs) };
+        let msg = make_udp_msg(me_id, MessageType::WorldviewUpdate, UdpData::WorldviewUpdate(update.clone()));
+        if encode_msg(&msg, PROTOCOL_VERSION).is_ok_and(|datagram| datagram.len() <= MAX_DATAGRAM) {
+            delta = Some(update);
+        }
+    }
+
+    log.sender = me_id;
+    log.seq = seq;
+    log.synced = true;
+    let updates = match delta {
+        Some(delta) => {
+            log.since_snapshot += 1;
+            vec![delta]
+        }
+        None => {
+            log.since_snapshot = 0;
+            snapshot_parts(seq, &cabs)
+        }
+    };
+    log.cabs = cabs;
+    return updates;
+}
+
+/// receive_worldview_update
+/// Put an update from the master into the log of this node
+///
+/// # Arguments:
+///
+/// * `log` - &mut WorldviewLog - the log of this node.
+/// * `sender_id` - u8 - the master that sent the update.
+/// * `update` - &WorldviewUpdate - the update.
+///
+/// # Returns:
+///
+/// Returns - (Option<Vec<Cab>>, bool) - the whole worldview if the update completed it, and true if an update is missing and a snapshot is needed.
+///
+pub fn receive_worldview_update(log: &mut WorldviewLog, sender_id: u8, update: &WorldviewUpdate) -> (Option<Vec<Cab>>, bool) {
+    match update {
+        WorldviewUpdate::Snapshot { seq, part, parts, cab } => {
+            if log.snapshot_seq != *seq || log.snapshot_sender != sender_id {
+                log.snapshot.clear();
+                log.snapshot_seq = *seq;
+                log.snapshot_sender = sender_id;
+            }
+            log.snapshot.retain(|(number, _)| number != part);
+            log.snapshot.push((*part, cab.clone()));
+
+            if log.snapshot.len() < *parts as usize {
+                return (None, false);
+            }
+            log.sender = sender_id;
+            log.seq = *seq;
+            log.synced = true;
+            // In the order of the master, parts may come in any order
+            let mut snapshot = std::mem::take(&mut log.snapshot);
+            snapshot.sort_by_key(|(number, _)| *number);
+            log.cabs = snapshot.into_iter().map(|(_, cab)| cab).collect();
+            return (Some(log.cabs.clone()), false);
+        }
+        WorldviewUpdate::Delta { seq, changes } => {
+            let in_order = log.synced && log.sender == sender_id;
+            if in_order && *seq == log.seq.wrapping_add(1) && apply_changes(&mut log.cabs, changes) {
+                log.seq = *seq;
+                return (Some(log.cabs.clone()), false);
+            }
+            // A duplicate or a late one
+            if in_order && *seq <= log.seq {
+                return (None, false);
+            }
+            println!("Worldview update {} from elevator {} does not follow {}, asking for a snapshot", seq, sender_id, log.seq);
+            log.synced = false;
+            return (None, true);
+        }
+    }
+}
@@ -8,78 +8,298 @@ use std::{
 
 use crate::modules::{
     cab_object::elevator_status_functions::Status,
+    cab_object::elevator_door::DoorTimer,
     slave_functions::slave::*,
     master_functions::master::*,
+    master_functions::parking::{ParkingPolicy, parking_targets, hour_of_day},
+    master_functions::traffic::refresh_dispatch_mode,
+    master_functions::lease::{LeaseConfig, renew_lease},
     udp_functions::udp_handler_init::*,
     udp_functions::udp::*,
     system_status::*,
+    system_clock::{clock_stopped, elapsed_since, now, sleep, system_now, track},
+    elevator_object::alias_lib::{CAB, DIRN_DOWN, DIRN_STOP},
+    order_object::order_init::Order,
+    node::{NodeCommand, StateCommand},
 };
 
-pub fn spawn_master_monitor(system_state_clone: Arc<SystemState>, udp_handler_clone: Arc<UdpHandler>){
-    spawn(move|| {
+pub fn spawn_master_monitor(system_state_clone: Arc<SystemState>, udp_handler_clone: Arc<UdpHandler>, lease: LeaseConfig, order_update_tx: cbc::Sender<Vec<Order>>){
+    track(spawn(move|| {
         loop{
-            fix_master_issues(&system_state_clone, &udp_handler_clone);
+            // A fenced master has stepped down, it must not pick itself again, see lease.rs
+            if !system_state_clone.read(|s| s.lease.fenced) {
+                fix_master_issues(&system_state_clone, &udp_handler_clone);
+            }
 
             sleep(Duration::from_secs(1));
-            let now = SystemTime::now();
+            if clock_stopped() {
+                return;
+            }
+            let now = system_now();
             
-            // Iterate in reverse order so that removing elements doesn't affect things
-            {
-                let  known_elevators_locked = system_state_clone.known_elevators.lock().unwrap();
-                for i in (0..known_elevators_locked.len()).rev() {
-                    let elevator = &known_elevators_locked[i];
-                    // Only check elevators that are Moving or DoorOpen.
-                    if elevator.status == Status::Moving || elevator.status == Status::DoorOpen {
-                        if let Ok(elapsed) = now.duration_since(elevator.last_lifesign) {
-                            if elapsed >= Duration::from_secs(10) {
-                                let dead_elevator = known_elevators_locked.get(i).unwrap();
-                                println!("Elevator {} is dead (elapsed: {:?})", dead_elevator.id, elapsed);
-                                let error_offline_msg = make_udp_msg(system_state_clone.me_id, MessageType::ErrorOffline, UdpData::Cab(dead_elevator.clone()));
-                                for elevator in known_elevators_locked.iter(){
-                                    udp_handler_clone.send(&elevator.inn_address, &error_offline_msg);
-                                }
+            // Copy the elevators and the master id, then do the checks without holding up the state
+            let (known_elevators, master_id) = system_state_clone.read(|s| (s.known_elevators.clone(), s.master_id));
+
+            // Old hall calls leave the statistics even when no new ones come in
+            if master_id == system_state_clone.me_id {
+                refresh_dispatch_mode(&system_state_clone);
+
+                // An order set aside as unservable is given out once an alive elevator stops at its floor again,
+                // and the lease is back so it can be given to others, see lease.rs
+                let servable_again = system_state_clone.read(|s| {
+                    !s.lease.fenced && unservable_orders(&s.unservable, &s.known_elevators).len() < s.unservable.len()
+                });
+                if servable_again {
+                    reassign_orders(&Vec::new(), &system_state_clone, &udp_handler_clone, order_update_tx.clone());
+                }
+            }
+
+            for elevator in known_elevators.iter() {
+                // Only check elevators that are Moving or DoorOpen.
+                if elevator.status == Status::Moving || elevator.status == Status::DoorOpen {
+                    if let Ok(elapsed) = now.duration_since(elevator.last_lifesign) {
+                        if elapsed >= Duration::from_secs(10) {
+                            println!("Elevator {} is dead (elapsed: {:?})", elevator.id, elapsed);
+                            let error_offline_msg = make_udp_msg(system_state_clone.me_id, MessageType::ErrorOffline, UdpData::Cab(elevator.clone()));
+                            for receiver in known_elevators.iter(){
+                                udp_handler_clone.send(&receiver.inn_address, &error_offline_msg);
                             }
                         }
                     }
                 }
             }
-            
 
-            {   
-                let locked_master_id = system_state_clone.master_id.lock().unwrap();
-                let worldview_system_state=Arc::clone(&system_state_clone);
-                if system_state_clone.me_id == *locked_master_id{
-                    drop(locked_master_id);
-                    print!("BROADCASTING WORLDVIEW _____________________");
-                    //MASTER WORLDVIEW BROADCAST
-                    master_worldview(&worldview_system_state, &udp_handler_clone.clone());
-                }
+            if system_state_clone.me_id == master_id{
+                // Sent also while fenced, the acks are how the lease comes back
+                renew_lease(&system_state_clone, &lease);
+                print!("BROADCASTING WORLDVIEW _____________________");
+                //MASTER WORLDVIEW BROADCAST
+                master_worldview(&system_state_clone, &udp_handler_clone);
             }
             sleep(Duration::from_secs(1));
+            if clock_stopped() {
+                return;
+            }
             check_master_failure(&system_state_clone, &udp_handler_clone);
         }
-});
+}));
 
 }
 
-pub fn spawn_queue_finisher(elevator_clone: Elevator,system_state_clone: Arc<SystemState>,  door_tx_clone: cbc::Sender<bool>,obstruction_rx_clone: cbc::Receiver<bool>){
-    spawn(move|| {
+pub fn spawn_queue_finisher(elevator_clone: Elevator,system_state_clone: Arc<SystemState>,  door_clone: DoorTimer){
+    track(spawn(move|| {
         loop{
             sleep(Duration::from_millis(300));
+            if clock_stopped() {
+                return;
+            }
             
-            let mut known_elevators_locked = system_state_clone.known_elevators.lock().unwrap();
-            if !known_elevators_locked.get_mut(0).unwrap().queue.is_empty(){
-                known_elevators_locked.get_mut(0).unwrap().go_next_floor(door_tx_clone.clone(), obstruction_rx_clone.clone() ,elevator_clone.clone());
-                
+            // Move the cab on the state thread so no other change to the queue is lost, then drive it here
+            for command in system_state_clone.apply(StateCommand::QueueTick) {
+                if let NodeCommand::Elevator(command) = command {
+                    command.run(&elevator_clone, &door_clone);
+                }
             }
-            drop(known_elevators_locked);
+            let mut me = match system_state_clone.my_cab() {
+                Some(me) => me,
+                None => continue,
+            };
 
-            let mut known_elevators_locked = system_state_clone.known_elevators.lock().unwrap().clone();
-            known_elevators_locked.get_mut(0).unwrap().lights(&system_state_clone.clone(), elevator_clone.clone());
-            known_elevators_locked.get_mut(0).unwrap().print_status();
-            elevator_clone.floor_indicator(known_elevators_locked.get_mut(0).unwrap().current_floor);
-            
+            me.lights(&system_state_clone, elevator_clone.clone());
+            me.print_status();
+            elevator_clone.floor_indicator(me.current_floor);
+        }
+    }));
+}
 
+/// spawn_travel_watchdog
+/// Watches the cab while it moves. If no new floor is reached within `travel_timeout` the motor or the
+/// floor sensor has failed: the cab goes to Status::Error, gives its hall orders to the others with
+/// ErrorOffline and keeps its cab orders. The motor is retried every `travel_timeout` until a floor is
+/// reached, then the main loop puts the cab back in service with NewOnline.
+///
+/// # Arguments:
+///
+/// * `elevator_clone` - Elevator - the elevator to retry the motor on.
+/// * `system_state_clone` - Arc<SystemState> - the state of this node.
+/// * `udp_handler_clone` - Arc<UdpHandler> - sends ErrorOffline.
+/// * `travel_timeout` - Duration - longest time allowed between two floors.
+///
+pub fn spawn_travel_watchdog(elevator_clone: Elevator, system_state_clone: Arc<SystemState>, udp_handler_clone: Arc<UdpHandler>, travel_timeout: Duration){
+    track(spawn(move|| {
+        // Floor the cab was at when the current trip was first seen, and when
+        let mut trip: Option<(u8, Instant)> = None;
+        let mut last_retry = now();
+
+        loop{
+            sleep(Duration::from_millis(100));
+            if clock_stopped() {
+                return;
+            }
+
+            let me = match system_state_clone.my_cab() {
+                Some(me) => me,
+                None => continue,
+            };
+
+            match me.status {
+                Status::Moving => {
+                    // A new floor starts a new trip
+                    let started = match trip {
+                        Some((floor, started)) if floor == me.current_floor => started,
+                        _ => now(),
+                    };
+                    trip = Some((me.current_floor, started));
+
+                    if elapsed_since(started) > travel_timeout {
+                        println!("No floor reached in {:?} after floor {}, motor or floor sensor has failed", travel_timeout, me.current_floor);
+                        system_state_clone.update(|s| {
+                            if let Some(me) = s.known_elevators.get_mut(0) {
+                                me.status = Status::Error;
+                            }
+                        });
+                        send_error_offline(&system_state_clone, &udp_handler_clone);
+                        trip = None;
+                        last_retry = now();
+                    }
+                }
+                Status::Error => {
+                    trip = None;
+                    if elapsed_since(last_retry) > travel_timeout {
+                        println!("Cab in error, retrying the motor");
+                        let direction = if me.direction == DIRN_STOP { DIRN_DOWN } else { me.direction };
+                        elevator_clone.motor_direction(direction);
+                        last_retry = now();
+                    }
+                }
+                _ => trip = None,
+            }
         }
-    });
-}
\ No newline at end of file
+    }));
+}
+
+/// spawn_obstruction_watchdog
+/// Watches the door. If it has been held open by the obstruction for longer than `obstruction_timeout`
+/// the cab stops taking hall calls: its hall orders are given to the master with RemoveOrder and it keeps
+/// its cab orders. The cab takes hall calls again when the obstruction clears. Other nodes see the policy
+/// in `takes_hall_calls` of the cab, in lifesigns and worldviews. Hall orders that come back to a cab
+/// not serving hall calls, obstructed or in maintenance, are given away again.
+///
+/// # Arguments:
+///
+/// * `elevator_clone` - Elevator - the elevator to read the obstruction switch on.
+/// * `system_state_clone` - Arc<SystemState> - the state of this node.
+/// * `udp_handler_clone` - Arc<UdpHandler> - sends RemoveOrder and ImAlive.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - passed on to reassign_orders if this node is master.
+/// * `obstruction_timeout` - Duration - longest time the door can be obstructed before the hall orders are given away.
+///
+pub fn spawn_obstruction_watchdog(elevator_clone: Elevator, system_state_clone: Arc<SystemState>, udp_handler_clone: Arc<UdpHandler>, order_update_tx: cbc::Sender<Vec<Order>>, obstruction_timeout: Duration){
+    track(spawn(move|| {
+        // When the door was first seen held open by the obstruction
+        let mut obstructed_since: Option<Instant> = None;
+
+        loop{
+            sleep(Duration::from_millis(100));
+            if clock_stopped() {
+                return;
+            }
+
+            let me = match system_state_clone.my_cab() {
+                Some(me) => me,
+                None => continue,
+            };
+            let obstructed = elevator_clone.obstruction();
+            let door_open = me.status == Status::DoorOpen || me.status == Status::Obstruction;
+
+            if obstructed && door_open {
+                obstructed_since.get_or_insert_with(now);
+            } else {
+                obstructed_since = None;
+            }
+
+            if !me.takes_hall_calls {
+                if !obstructed {
+                    println!("Obstruction cleared, taking hall calls again");
+                    let me = system_state_clone.update(|s| {
+                        let me = s.known_elevators.get_mut(0)?;
+                        me.takes_hall_calls = true;
+                        return Some(me.clone());
+                    });
+                    if let Some(me) = me {
+                        send_to_all(&system_state_clone, &udp_handler_clone, &make_udp_msg(me.id, MessageType::ImAlive, UdpData::Cab(me)));
+                    }
+                    continue;
+                }
+            } else if let Some(since) = obstructed_since {
+                if elapsed_since(since) > obstruction_timeout {
+                    println!("Door obstructed for {:?}, giving away hall orders", obstruction_timeout);
+                    let me = system_state_clone.update(|s| {
+                        let me = s.known_elevators.get_mut(0)?;
+                        me.takes_hall_calls = false;
+                        me.status = Status::Obstruction;
+                        return Some(me.clone());
+                    });
+                    if let Some(me) = me {
+                        send_to_all(&system_state_clone, &udp_handler_clone, &make_udp_msg(me.id, MessageType::ImAlive, UdpData::Cab(me)));
+                    }
+                    give_away_hall_orders(&system_state_clone, &udp_handler_clone, order_update_tx.clone());
+                    continue;
+                }
+            }
+
+            if !me.serves_hall_calls() && me.status != Status::Init && me.queue.iter().any(|order| order.order_type != CAB) {
+                // A worldview sent before the master heard of it gave the orders back
+                give_away_hall_orders(&system_state_clone, &udp_handler_clone, order_update_tx.clone());
+            }
+        }
+    }));
+}
+
+/// spawn_parking_planner
+/// While this node is master, looks at the known cabs every second and sends every idle cab that is not
+/// at its parking floor a Park message. The cab clears the parking floor itself when it gets there or
+/// gets a real order, see go_next_floor.
+///
+/// # Arguments:
+///
+/// * `system_state_clone` - Arc<SystemState> - the state of this node.
+/// * `udp_handler_clone` - Arc<UdpHandler> - sends Park.
+/// * `policy` - ParkingPolicy - where idle cabs should wait, nothing is started if it is off.
+/// * `num_floors` - u8 - floors in the shaft.
+///
+pub fn spawn_parking_planner(system_state_clone: Arc<SystemState>, udp_handler_clone: Arc<UdpHandler>, policy: ParkingPolicy, num_floors: u8){
+    if policy == ParkingPolicy::Off {
+        return;
+    }
+    track(spawn(move|| {
+        loop{
+            sleep(Duration::from_secs(1));
+            if clock_stopped() {
+                return;
+            }
+
+            // A fenced master parks no one, see lease.rs
+            if system_state_clone.read(|s| s.master_id != s.me_id || s.lease.fenced) {
+                continue;
+            }
+
+            let known_elevators = system_state_clone.known_elevators();
+            for (id, floor) in parking_targets(&policy, &known_elevators, num_floors, hour_of_day(system_now())) {
+                let cab = match known_elevators.iter().find(|cab| cab.id == id) {
+                    Some(cab) => cab,
+                    None => continue,
+                };
+                if cab.current_floor == floor || cab.parking == Some(floor) {
+                    continue;
+                }
+
+                // The cab in the message names the elevator and the floor
+                let mut request = cab.clone();
+                request.parking = Some(floor);
+                request.queue.clear();
+                println!("Parking idle cab {} at floor {}", id, floor);
+                udp_handler_clone.send(&cab.inn_address, &make_udp_msg(system_state_clone.me_id, MessageType::Park, UdpData::Cab(request)));
+            }
+        }
+    }));
+}
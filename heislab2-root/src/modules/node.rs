@@ -0,0 +1,799 @@
+//! ## Node Module
+//! This module runs one complete node: the elevator, the cab, the UDP handler and all the monitoring threads.
+//! The main program runs a single node, the simulator runs several of them in the same process.
+//!
+//! ## The structs includes:
+//! - **NodeConfig**: Where the node finds its elevator and which addresses it communicates on.
+//! - **NodeEvent**: Something that happened at the elevator, the main loop handles one at a time.
+//! - **NodeCommand**: Something the node must do on the network, the panel or the elevator after the state has changed.
+//! - **StateCommand**: A change to the state, the main loop turns every event into one.
+//! - **OperatorCommand**: Something asked for at the keyboard of the node.
+//!
+//! ## The functions includes:
+//! - 'run_node'       starts the node and runs the main loop, returns when the node is shut down, the simulation ends or the elevator can't be reached.
+//! - 'apply_command'  the state machine of the node, one match over StateCommand run by the state thread.
+//!
+//! The main loop reads what it needs from the elevator, turns the event into a StateCommand and sends it to the
+//! state thread (see system_status.rs), which gets back a list of NodeCommands. The commands are run afterwards
+//! by the main loop, so no message is sent and no call is made to the elevator while the state is busy.
+
+use crossbeam_channel as cbc;
+use std::{
+    thread::*,
+    sync::Arc,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use crate::modules::{
+    cab_object::elevator_status_functions::Status,
+    order_object::order_init::Order,
+    slave_functions::slave::*,
+    elevator_object::elevator_init::Elevator,
+    udp_functions::udp_handler_init::*,
+    udp_functions::message_handlers::*,
+    udp_functions::udp::*,
+    udp_functions::virtual_network::VirtualNic,
+    udp_functions::fault_injection::FaultConfig,
+    udp_functions::transport::{Transport, TransportConfig, UdpTransport},
+    udp_functions::tcp_transport::TcpTransport,
+    udp_functions::codec::CodecConfig,
+    io::io_init::*,
+    system_clock::{clock_stopped, track},
+    elevator_object::alias_lib::{DIRN_DOWN, DIRN_STOP},
+    elevator_object::poll::CallButton,
+    system_status::{NodeState, SystemState},
+    cab_object::cab::{Cab, floor_mask},
+    cab_object::elevator_load::LoadConfig,
+    cab_object::elevator_door::{DoorConfig, DoorRequest, DoorTimer},
+    cab_object::elevator_command::ElevatorCommand,
+    monitoring_threads::*,
+    master_functions::master::reassign_orders,
+    master_functions::parking::ParkingPolicy,
+    master_functions::lease::LeaseConfig,
+    master_functions::traffic::{DispatchMode, TrafficConfig, refresh_dispatch_mode},
+    shutdown::leave,
+    isolation::{IsolationConfig, spawn_isolation_watch},
+    peer_functions::peer::{Coordination, PeerConfig, NO_MASTER, complete_hall_request, spawn_gossip},
+};
+
+/// Everything a node needs to know before it starts
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub elevator_address: String,               // Address of the elevator server or simulator
+    pub num_floors: u8,
+    pub served_floors: Option<Vec<u8>>,         // Floors this cab stops at, all floors if None
+    pub inn_address: SocketAddr,                // UDP address for receiving messages
+    pub out_address: SocketAddr,                // UDP address for sending messages
+    pub virtual_nic: Option<Arc<VirtualNic>>,   // Set to run on a virtual network instead of real sockets
+    pub faults: Option<FaultConfig>,            // Set to inject network faults, see fault_injection.rs
+    pub transport: TransportConfig,             // How messages to every node are sent and on which interface, see transport.rs
+    pub travel_timeout: Duration,               // Longest time between two floors before the motor is taken as dead
+    pub obstruction_timeout: Duration,          // Longest time the door can be obstructed before the hall orders are given away
+    pub maintenance: bool,                      // Start out of service for hall calls, see set_maintenance
+    pub maintenance_cab_calls: bool,            // Keep serving new cab calls while in maintenance
+    pub operator_rx: Option<cbc::Receiver<OperatorCommand>>, // Commands from the keyboard, see OperatorCommand
+    pub parking: ParkingPolicy,                 // Where idle cabs wait, used while this node is master
+    pub traffic: TrafficConfig,                 // How the dispatch mode is picked while this node is master
+    pub load: LoadConfig,                       // The load sensor and when the cab is full, see elevator_load.rs
+    pub door: DoorConfig,                       // How long the door stays open, see elevator_door.rs
+    pub peer: PeerConfig,                       // Run with a master or as a peer, see peer.rs
+    pub lease: LeaseConfig,                     // How long this node stays master without acks, see lease.rs
+    pub isolation: IsolationConfig,             // How long without another elevator before hall calls are served here, see isolation.rs
+    pub codec: CodecConfig,                     // Bincode, or JSON to read the messages in Wireshark, see codec.rs
+}
+
+/// Something the operator asked for at the keyboard of the node
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperatorCommand {
+    Maintenance(u8, bool),              // Elevator id, on or off
+    DispatchMode(Option<DispatchMode>), // Force a dispatch mode, None goes back to following the traffic
+    Door(DoorRequest),                  // The door-open or door-close button inside the cab
+    AccessibleCall(u8),                 // Cab call made with the accessibility button, the door stays open longer there
+    Shutdown,                           // Hand off the work of the node and stop it, also sent on SIGINT/SIGTERM
+}
+
+/// Default travel_timeout, a bit more than the slowest trip between two floors
+pub const DEFAULT_TRAVEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default obstruction_timeout
+pub const DEFAULT_OBSTRUCTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened at the elevator or in the node
+#[derive(Debug)]
+pub enum NodeEvent {
+    LightUpdate,            // The queue changed, the lights may be wrong
+    OrderUpdate,            // New orders, the cab may have to move
+    Door(bool),             // true when the door has closed
+    Call(CallButton),
+    Floor(u8),
+    Stop(bool),
+    Obstruction(bool),
+    Load(u16),              // New reading of the load sensor
+    Operator(OperatorCommand),
+    OperatorGone,           // Every sender of operator commands is dropped
+}
+
+/// What the node must do after an event has changed the state
+#[derive(Debug)]
+pub enum NodeCommand {
+    SendToAll(UdpMsg),      // Send to every known elevator one by one
+    Broadcast(UdpMsg),
+    NewRequest(UdpMsg),     // Send to every known elevator, handle it here if a send fails
+    Lights,                 // Set the button lights from the queues
+    ClearCallLight(Order),
+    SendNewOnline,
+    SendErrorOffline,
+    ReassignWaitingOrders,  // Give out the hall orders no cab could take, if this node is master
+    SetMaintenance(bool),
+    RequestMaintenance(u8, bool), // Ask another elevator to change mode
+    Elevator(ElevatorCommand),    // Drive the motor, set a lamp or arm the door timer, see elevator_command.rs
+}
+
+/// A change to the state of the node, every one is handled by the match in apply_command on the state thread
+#[derive(Debug)]
+pub enum StateCommand {
+    OrderUpdate,                                // New orders, the cab may have to move
+    DoorClosed { obstructed: bool },            // The door timer ran out, with the obstruction switch read before
+    Call { order: Order, accessible: bool, maintenance_cab_calls: bool }, // A button was pressed, see take_call
+    StartIfIdle,                                // Move an idle cab if its queue says so
+    Floor(u8),
+    Stop { pressed: bool, at_floor: Option<u8> }, // The stop button, with the floor sensor read before
+    Obstruction(bool),
+    Load { load: u16, config: LoadConfig },
+    DoorButton { request: DoorRequest, at_floor: Option<u8> }, // The door buttons inside the cab, with the floor sensor read before
+    ForceDispatchMode(Option<DispatchMode>),
+    QueueTick,                                  // The queue finisher looks for orders the cab is not driving to yet
+}
+
+/// The parts of a node the main loop works with
+struct Node {
+    state: Arc<SystemState>,
+    udphandler: Arc<UdpHandler>,
+    elevator: Elevator,
+    io_channels: IoChannels,
+    door: DoorTimer,
+    operator_rx: cbc::Receiver<OperatorCommand>,
+    maintenance_cab_calls: bool,
+    load: LoadConfig,
+}
+
+/// run_node
+/// Starts all threads of a node and runs its main loop
+///
+/// # Arguments:
+///
+/// * `system_state` - Arc<SystemState> - the state of this node, see system_init.rs
+/// * `config` - NodeConfig - where to find the elevator and the network
+///
+/// # Returns:
+///
+/// Returns - std::io::Result<()> - Ok when the node is shut down or the simulation ends, an error if the elevator could not be reached.
+///
+pub fn run_node(system_state: Arc<SystemState>, config: NodeConfig) -> std::io::Result<()> {
+
+    //--------------INIT ELEVATOR------------
+
+    let elev_num_floors = config.num_floors;
+   
+    let elevator = Elevator::init(&config.elevator_address, elev_num_floors)?;
+
+    println!("Elevator started:\n{:#?}", elevator);
+
+    //--------------INIT ELEVATOR FINISH------------
+
+    // --------------INIT CAB---------------
+    let inn_addr = config.inn_address;
+    let out_addr = config.out_address;
+    
+    let set_id = system_state.me_id;
+    println!("me id is {}",system_state.me_id);
+   
+    let mut cab = Cab::init(&inn_addr, &out_addr, elev_num_floors, set_id, &system_state)?;
+    cab.turn_off_lights(elevator.clone());
+    if let Some(floors) = &config.served_floors {
+        println!("Stopping only at floors {:?}", floors);
+        cab.served_floors = floor_mask(floors);
+    }
+    if config.maintenance {
+        println!("Starting in maintenance");
+        cab.maintenance = true;
+    }
+
+    //---------------INIT UDP HANDLER-------------------
+    let transport: Arc<dyn Transport> = match config.virtual_nic {
+        Some(nic) => nic,
+        None if config.transport.tcp => Arc::new(TcpTransport::open(cab.inn_address, cab.out_address, config.transport.clone())?),
+        None => Arc::new(UdpTransport::open(cab.inn_address, cab.out_address, config.transport.clone())?),
+    };
+    let mut udphandler = init_handler(transport).with_codec(config.codec.codec);
+    if let Some(faults) = config.faults {
+        udphandler = udphandler.with_faults(faults);
+    }
+    let udphandler = Arc::new(udphandler);
+    //-------------INIT UDP HANDLER FINISH-----------------
+
+    let traffic = config.traffic.clone();
+    let coordination = config.peer.coordination;
+    system_state.update(move |s| {
+        s.known_elevators.push(cab);
+        s.traffic = traffic;
+        s.coordination = coordination;
+    });
+
+    println!("Cab initialized:\n{:#?}", elevator);
+    // --------------INIT CAB FINISH---------------
+    
+    // --------------INIT CHANNELS---------------
+    let mut io_channels = IoChannels::new(&elevator);
+    if config.load.sensor && elevator.load().is_none() {
+        println!("The elevator does not answer the load command, running without the load sensor");
+    } else if config.load.sensor {
+        println!("Reading the load sensor, full at {}% of {}", config.load.full_percent, config.load.capacity);
+        io_channels = io_channels.with_load_sensor(&elevator);
+    }
+    let door = DoorTimer::start(io_channels.door_tx.clone(), config.door);
+    // --------------INIT CHANNELS FINISHED---------------
+
+    // --------------INIT RECIEVER THREAD------------------
+    let system_state_clone = Arc::clone(&system_state);
+    
+    // -------------------SET MASTER ID------------------
+    // Peers have no master, see peer.rs
+    if coordination == Coordination::Peer {
+        println!("Running as a peer, gossiping every {:?}", config.peer.gossip_period);
+        system_state.update(|s| s.master_id = NO_MASTER);
+    } else {
+        let mut cab_clone = system_state.my_cab().unwrap();
+        set_new_master(&mut cab_clone, &system_state);
+    }
+    
+    // -------------------SET MASTER ID FINISHED------------------
+
+
+    // -------------INIT RECIEVER-----------------
+    let udphandler_clone = Arc::clone(&udphandler);
+    let order_update_clone = io_channels.order_update_tx.clone();
+    let light_update_clone = io_channels.light_update_tx.clone();
+    track(spawn(move||{
+        while !clock_stopped() {
+            let handler = Arc::clone(&udphandler_clone); 
+            handler.receive(60000, &system_state_clone, order_update_clone.clone(), light_update_clone.clone());
+        }
+    }));
+    // -------------INIT RECIEVER FINISHED-----------------
+    
+    // -------------INIT PHASE-----------------
+    // The cab is in Status::Init until it reaches a floor, the Floor event then stops it and sends NewOnline.
+    // A cab starting at a floor gets that floor from the floor sensor thread at once.
+    if elevator.floor_sensor().is_none() {
+        println!("Started between floors, driving down to find a floor");
+        elevator.motor_direction(DIRN_DOWN);
+    }
+    println!("The master is assigned as: {}",system_state.master_id());
+
+   
+
+    /* ---- -- - ----- -----INIT ELEVATOR MONITOR - Can be found in monitoring_threads ---- - --------- */
+    let system_state_clone = Arc::clone(&system_state);
+    let udp_handler_clone = Arc::clone(&udphandler);
+
+
+    // Peers watch each other in the gossip thread instead, see peer.rs
+    if coordination == Coordination::Peer {
+        spawn_gossip(system_state_clone,
+                    udp_handler_clone,
+                    config.peer,
+                    io_channels.order_update_tx.clone());
+    } else {
+        spawn_master_monitor(system_state_clone, 
+                            udp_handler_clone,
+                            config.lease,
+                            io_channels.order_update_tx.clone());
+    }
+
+    /* ---- -- - ------ -----INIT ISOLATION WATCH - Can be found in isolation.rs ---- - --------- */
+    spawn_isolation_watch(Arc::clone(&system_state),
+                Arc::clone(&udphandler),
+                config.isolation);
+
+
+     /* ---- -- - ------ -----INIT QUEUE FINISHER - Can be found in monitoring_threads ---- - --------- */
+    let system_state_clone = Arc::clone(&system_state);
+    let elevator_clone = elevator.clone();
+
+
+    spawn_queue_finisher(elevator_clone.clone(),
+                system_state_clone,
+                door.clone());
+
+    /* ---- -- - ------ -----INIT TRAVEL WATCHDOG - Can be found in monitoring_threads ---- - --------- */
+    spawn_travel_watchdog(elevator.clone(),
+                Arc::clone(&system_state),
+                Arc::clone(&udphandler),
+                config.travel_timeout);
+
+    /* ---- -- - ------ -----INIT OBSTRUCTION WATCHDOG - Can be found in monitoring_threads ---- - --------- */
+    spawn_obstruction_watchdog(elevator.clone(),
+                Arc::clone(&system_state),
+                Arc::clone(&udphandler),
+                io_channels.order_update_tx.clone(),
+                config.obstruction_timeout);
+
+    /* ---- -- - ------ -----INIT PARKING PLANNER - Can be found in monitoring_threads ---- - --------- */
+    spawn_parking_planner(Arc::clone(&system_state),
+                Arc::clone(&udphandler),
+                config.parking.clone(),
+                elev_num_floors);
+
+
+
+    // ------------------ MAIN LOOP ---------------------
+    let mut node = Node {
+        state: system_state,
+        udphandler,
+        elevator,
+        io_channels,
+        door,
+        operator_rx: config.operator_rx.unwrap_or_else(cbc::never),
+        maintenance_cab_calls: config.maintenance_cab_calls,
+        load: config.load,
+    };
+    loop {
+        let event = match node.next_event() {
+            Some(event) => event,
+            // The simulation has ended and stopped the poll threads, see system_clock.rs
+            None if clock_stopped() => return Ok(()),
+            None => return Err(std::io::Error::other("A poll thread of the elevator has stopped")),
+        };
+        match event {
+            NodeEvent::Operator(OperatorCommand::Shutdown) => {
+                node.shut_down();
+                return Ok(());
+            }
+            NodeEvent::OperatorGone if clock_stopped() => return Ok(()),
+            NodeEvent::OperatorGone => {
+                println!("The keyboard is gone, running without operator commands");
+                node.operator_rx = cbc::never();
+            }
+            event => node.handle(event),
+        }
+    }
+}
+
+impl Node {
+    /// Wait for the next event from the elevator or the other threads of the node, None if a poll thread has stopped
+    fn next_event(&self) -> Option<NodeEvent> {
+        let io = &self.io_channels;
+        cbc::select! {
+            recv(io.light_update_rx) -> _ => Some(NodeEvent::LightUpdate),
+            recv(io.order_update_rx) -> _ => Some(NodeEvent::OrderUpdate),
+            recv(io.door_rx) -> a => a.ok().map(NodeEvent::Door),
+            recv(io.call_rx) -> a => a.ok().map(NodeEvent::Call),
+            recv(io.floor_rx) -> a => a.ok().map(NodeEvent::Floor),
+            recv(io.stop_rx) -> a => a.ok().map(NodeEvent::Stop),
+            recv(io.obstruction_rx) -> a => a.ok().map(NodeEvent::Obstruction),
+            recv(io.load_rx) -> a => a.ok().map(NodeEvent::Load),
+            recv(self.operator_rx) -> a => Some(a.map_or(NodeEvent::OperatorGone, NodeEvent::Operator)),
+        }
+    }
+
+    fn handle(&self, event: NodeEvent) {
+        // Readings of the elevator are taken here, the state thread does not wait on it
+        let command = match event {
+            /* ------- --- -- NEW LIGHT UPDATE  -- ----  ------*/
+            NodeEvent::LightUpdate => return self.execute(vec![NodeCommand::Lights]),
+
+            /* ------- --- -- NEW ORDER UPDATE  -- ----  ------*/
+            NodeEvent::OrderUpdate => StateCommand::OrderUpdate,
+
+            /* ------- --- -- NEW DOOR UPDATE  -- ----  ------*/
+            /* If door is open do nothing*/
+            NodeEvent::Door(false) => return,
+            NodeEvent::Door(true) => StateCommand::DoorClosed { obstructed: self.elevator.obstruction() },
+
+            NodeEvent::Call(call_button) => return self.handle_call(call_button, false),
+
+            NodeEvent::Floor(floor) => {
+                println!("Floor: {:#?}", floor);
+                StateCommand::Floor(floor)
+            },
+
+            NodeEvent::Stop(pressed) => {
+                println!("Stop button: {:#?}", pressed);
+                StateCommand::Stop { pressed, at_floor: self.elevator.floor_sensor() }
+            },
+
+            NodeEvent::Obstruction(obstructed) => {
+                println!("Obstruction: {:#?}", obstructed);
+                StateCommand::Obstruction(obstructed)
+            },
+
+            NodeEvent::Load(load) => StateCommand::Load { load, config: self.load },
+
+            NodeEvent::Operator(OperatorCommand::Maintenance(id, on)) => {
+                if id == self.state.me_id {
+                    return self.execute(vec![NodeCommand::SetMaintenance(on)]);
+                }
+                return self.execute(vec![NodeCommand::RequestMaintenance(id, on)]);
+            },
+
+            NodeEvent::Operator(OperatorCommand::DispatchMode(forced)) => {
+                self.state.apply(StateCommand::ForceDispatchMode(forced));
+                println!("Dispatch mode {}", forced.map_or("follows the traffic", |mode| mode.as_str()));
+                refresh_dispatch_mode(&self.state);
+                return;
+            },
+
+            NodeEvent::Operator(OperatorCommand::Door(request)) => {
+                println!("Door button: {:?}", request);
+                StateCommand::DoorButton { request, at_floor: self.elevator.floor_sensor() }
+            },
+
+            NodeEvent::Operator(OperatorCommand::AccessibleCall(floor)) => return self.handle_call(CallButton { floor, call: CAB }, true),
+
+            // Stop or change the main loop, see run_node
+            NodeEvent::Operator(OperatorCommand::Shutdown) | NodeEvent::OperatorGone => return,
+        };
+
+        let commands = self.state.apply(command);
+        self.execute(commands);
+    }
+
+    /// A button was pressed, or the accessibility button with a cab call if `accessible`
+    fn handle_call(&self, call_button: CallButton, accessible: bool) {
+        println!("{:#?}", call_button);
+        //Make new order and add that order to elevators queue
+        let order = Order::init(call_button.floor, call_button.call);
+
+        // Checked and queued in one command so the cab can't change in between, see take_call
+        let commands = self.state.apply(StateCommand::Call { order, accessible, maintenance_cab_calls: self.maintenance_cab_calls });
+        if commands.is_empty() {
+            return;
+        }
+        self.execute(commands);
+
+        //Safety if elevator is idle to double check if its going to correct floor
+        let commands = self.state.apply(StateCommand::StartIfIdle);
+        self.execute(commands);
+    }
+
+    /// Hand off the work of this node, tell the others it is leaving and stop the cab, see shutdown.rs
+    fn shut_down(&self) {
+        println!("Shutting down elevator {}", self.state.me_id);
+        leave(&self.state, &self.udphandler, self.io_channels.order_update_tx.clone());
+        self.elevator.motor_direction(DIRN_STOP);
+
+        if let Some(me) = self.state.my_cab() {
+            let cab_orders: Vec<u8> = me.queue.iter().filter(|order| order.order_type == CAB).map(|order| order.floor).collect();
+            if !cab_orders.is_empty() {
+                println!("Cab calls to floors {:?} were not served", cab_orders);
+            }
+        }
+    }
+
+    /// Run the commands of an event, outside the state thread
+    fn execute(&self, commands: Vec<NodeCommand>) {
+        for command in commands {
+            match command {
+                NodeCommand::SendToAll(msg) => {
+                    for elevator in self.state.known_elevators().iter() {
+                        self.udphandler.send(&elevator.inn_address, &msg);
+                    }
+                }
+                NodeCommand::Broadcast(msg) => {
+                    self.udphandler.broadcast(&msg);
+                }
+                NodeCommand::NewRequest(msg) => {
+                    for elevator in self.state.known_elevators().iter() {
+                        let send_successfull = self.udphandler.send(&elevator.inn_address, &msg);
+                        if !send_successfull {
+                            handle_new_request(&msg,
+                                               Arc::clone(&self.state),
+                                               Arc::clone(&self.udphandler),
+                                               self.io_channels.order_update_tx.clone(),
+                                               self.io_channels.light_update_tx.clone());
+                        }
+                    }
+                }
+                NodeCommand::Lights => {
+                    if let Some(mut me) = self.state.my_cab() {
+                        me.lights(&self.state, self.elevator.clone());
+                    }
+                }
+                NodeCommand::ClearCallLight(order) => {
+                    self.elevator.call_button_light(order.floor, order.order_type, false);
+                }
+                NodeCommand::SendNewOnline => {
+                    send_new_online(&self.state, &self.udphandler);
+                }
+                NodeCommand::SendErrorOffline => {
+                    send_error_offline(&self.state, &self.udphandler);
+                }
+                NodeCommand::ReassignWaitingOrders => {
+                    if self.state.master_id() == self.state.me_id && !self.state.all_orders().is_empty() {
+                        reassign_orders(&Vec::new(), &self.state, &self.udphandler, self.io_channels.order_update_tx.clone());
+                    }
+                }
+                NodeCommand::SetMaintenance(on) => {
+                    if set_maintenance(&self.state, &self.udphandler, on, self.io_channels.order_update_tx.clone()) {
+                        self.execute(vec![NodeCommand::Lights]);
+                    }
+                }
+                NodeCommand::RequestMaintenance(id, on) => {
+                    request_maintenance(&self.state, &self.udphandler, id, on);
+                }
+                NodeCommand::Elevator(command) => {
+                    command.run(&self.elevator, &self.door);
+                }
+            }
+        }
+    }
+}
+
+/// apply_command
+/// The state machine of the node, changes the state for one command. Runs on the state thread, see SystemState::apply
+///
+/// # Arguments:
+///
+/// * `s` - &mut NodeState - the state of this node.
+/// * `command` - StateCommand - the change, made by the main loop or the queue finisher from an event.
+///
+/// # Returns:
+///
+/// Returns - Vec<NodeCommand> - what the node must do afterwards, in order, outside the state thread.
+///
+pub fn apply_command(s: &mut NodeState, command: StateCommand) -> Vec<NodeCommand> {
+    let me_id = s.me_id;
+    if s.known_elevators.is_empty() {
+        println!("There are no elevators in the system");
+        return Vec::new();
+    }
+
+    match command {
+        StateCommand::OrderUpdate => {
+            let me = &mut s.known_elevators[0];
+            println!("Current queue: {:?}", me.queue);
+
+            /* IF ELEVATOR STATUS IDLE SEND AN "IM ALIVE" MESSAGE TO SYSTEM TO UPDATE SYSTEM OF CURRENT STATE */
+            let mut commands = Vec::new();
+            if me.status == Status::Idle {
+                commands.push(NodeCommand::SendToAll(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(me.clone()))));
+            }
+            commands.extend(elevator_commands(me.go_next_floor()));
+            return commands;
+        },
+
+        StateCommand::DoorClosed { obstructed } => door_closed(s, obstructed),
+
+        StateCommand::Call { order, accessible, maintenance_cab_calls } => {
+            let taken_alone = match take_call(s, order.clone(), accessible, maintenance_cab_calls) {
+                Some(taken_alone) => taken_alone,
+                None => return Vec::new(),
+            };
+            let mut commands = Vec::new();
+            if taken_alone {
+                commands.push(NodeCommand::Lights);
+            }
+            commands.push(NodeCommand::NewRequest(make_udp_msg(me_id, MessageType::NewRequest, UdpData::Order(order))));
+            return commands;
+        },
+
+        StateCommand::StartIfIdle => {
+            let me = &mut s.known_elevators[0];
+            if me.status != Status::Idle {
+                return Vec::new();
+            }
+            let mut commands = elevator_commands(me.go_next_floor());
+            if me.status == Status::Moving {
+                commands.push(NodeCommand::SendToAll(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(me.clone()))));
+            }
+            return commands;
+        },
+
+        StateCommand::Floor(floor) => floor_reached(s, floor),
+
+        StateCommand::Stop { pressed, at_floor } => {
+            let me = &mut s.known_elevators[0];
+            // Held: stop at once and let the others take the hall orders
+            if pressed {
+                if me.status == Status::Stop {
+                    return Vec::new();
+                }
+                let mut commands = elevator_commands(me.toggle_stop(at_floor));
+                commands.extend([NodeCommand::SendErrorOffline, NodeCommand::Lights]);
+                return commands;
+            }
+
+            // Released: close the door the normal way if at a floor, else drive on to the cab orders
+            if me.status != Status::Stop {
+                return Vec::new();
+            }
+            me.alive = true;
+            let mut commands = elevator_commands(me.toggle_stop(at_floor));
+            if at_floor.is_some() {
+                commands.extend(elevator_commands(me.try_close_door()));
+            } else {
+                commands.extend(elevator_commands(me.go_next_floor()));
+            }
+            commands.extend([NodeCommand::SendNewOnline, NodeCommand::Lights]);
+            return commands;
+        },
+
+        StateCommand::Obstruction(obstructed) => {
+            let me = &mut s.known_elevators[0];
+            if obstructed {
+                return elevator_commands(me.set_status(Status::Obstruction));
+            }
+            let mut commands = elevator_commands(me.set_status(Status::Idle));
+            commands.extend(elevator_commands(me.go_next_floor()));
+            commands.push(NodeCommand::Lights);
+            return commands;
+        },
+
+        // Everyone must see a change of the full-load flag before the next hall order is given out
+        StateCommand::Load { load, config } => {
+            let me = &mut s.known_elevators[0];
+            if me.set_load(load, &config) {
+                return vec![NodeCommand::SendToAll(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(me.clone())))];
+            }
+            return Vec::new();
+        },
+
+        StateCommand::DoorButton { request, at_floor } => {
+            let me = &mut s.known_elevators[0];
+            let commands = match request {
+                // Hold an open door, or open it again if the cab is standing at a floor
+                DoorRequest::Open if me.status == Status::DoorOpen || me.status == Status::Obstruction => vec![ElevatorCommand::HoldDoor],
+                DoorRequest::Open if me.status == Status::Idle && at_floor == Some(me.current_floor) => me.try_close_door(),
+                DoorRequest::Close if me.status == Status::DoorOpen => vec![ElevatorCommand::CloseDoor],
+                _ => Vec::new(),
+            };
+            return elevator_commands(commands);
+        },
+
+        StateCommand::ForceDispatchMode(forced) => {
+            s.traffic.forced = forced;
+            return Vec::new();
+        },
+
+        // Move the cab here so no other change to the queue is lost
+        StateCommand::QueueTick => {
+            let me = &mut s.known_elevators[0];
+            if me.queue.is_empty() {
+                return Vec::new();
+            }
+            return elevator_commands(me.go_next_floor());
+        },
+    }
+}
+
+// The commands of the cab as commands of the node
+fn elevator_commands(commands: Vec<ElevatorCommand>) -> Vec<NodeCommand> {
+    return commands.into_iter().map(NodeCommand::Elevator).collect();
+}
+
+// The door timer ran out: keep an obstructed door open, else close it and complete the order the cab stopped for
+fn door_closed(s: &mut NodeState, obstructed: bool) -> Vec<NodeCommand> {
+    let me_id = s.me_id;
+    let me = &mut s.known_elevators[0];
+    // The door stays open during an emergency stop at a floor
+    if me.status == Status::Stop {
+        return Vec::new();
+    }
+    // An obstructed door stays open, the timer looks again after another open time
+    if me.status == Status::Obstruction || obstructed {
+        let mut commands = elevator_commands(me.set_status(Status::Obstruction));
+        commands.push(NodeCommand::Elevator(ElevatorCommand::OpenDoor(false)));
+        return commands;
+    }
+    let mut commands = vec![NodeCommand::Elevator(ElevatorCommand::DoorLight(false))];
+    let cab_clone = me.clone();
+    commands.extend(elevator_commands(me.set_status(Status::Idle)));
+
+    // The order the cab stopped for is first, unless it was given away while the door was obstructed
+    let current_floor = me.current_floor;
+    let completed_order = match me.queue.iter().position(|order| order.floor == current_floor) {
+        Some(index) => me.queue.remove(index),
+        None => {
+            commands.push(NodeCommand::SendToAll(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(me.clone()))));
+            return commands;
+        }
+    };
+    let cab_clone_removed = me.clone();
+
+    /*       FIRST REMOVE FROM OWN ALL ORDERS      */
+    if completed_order.order_type != CAB && s.coordination == Coordination::Peer {
+        complete_hall_request(&mut s.hall_requests, &completed_order);
+    }
+    if completed_order.order_type == CAB {
+        if let Some(index) = s.all_orders.iter().position(|order| (order.floor == completed_order.floor)&& (order.order_type == CAB)) {
+            s.all_orders.remove(index);
+        }
+    } else {
+        s.all_orders.retain(|order| {
+            !((order.floor == completed_order.floor )&& (order.order_type == completed_order.order_type))
+        });
+    }
+
+    commands.extend([
+        NodeCommand::Broadcast(make_udp_msg(me_id, MessageType::OrderComplete, UdpData::Cab(cab_clone))),
+        NodeCommand::Broadcast(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(cab_clone_removed))),
+        NodeCommand::ClearCallLight(completed_order),
+    ]);
+    return commands;
+}
+
+// The cab reached a floor: the first one after start or after a stall puts the cab in service, then it moves on
+fn floor_reached(s: &mut NodeState, floor: u8) -> Vec<NodeCommand> {
+    let me_id = s.me_id;
+    let me = &mut s.known_elevators[0];
+    me.current_floor = floor;
+
+    // The first floor after start ends the init phase, the position is known and the cab goes online
+    let mut commands = Vec::new();
+    if me.status == Status::Init {
+        println!("Found floor {} after start, going online", floor);
+        commands.push(NodeCommand::Elevator(ElevatorCommand::Motor(DIRN_STOP)));
+        me.last_served_floor = floor;
+        me.status = Status::Idle;
+        commands.push(NodeCommand::SendNewOnline);
+        commands.push(NodeCommand::ReassignWaitingOrders);
+    }
+
+    // Reaching a floor after a stall means the motor works again, see spawn_travel_watchdog
+    if me.status == Status::Error {
+        println!("Floor {} reached after motor failure, back in service", floor);
+        me.status = Status::Idle;
+        commands.push(NodeCommand::SendNewOnline);
+    }
+
+    if me.queue.is_empty() && me.parking.is_none() {
+        commands.push(NodeCommand::Elevator(ElevatorCommand::Motor(DIRN_STOP)));
+    }
+    commands.extend(elevator_commands(me.go_next_floor()));
+
+    //Broadcast new state
+    commands.push(NodeCommand::Lights);
+    commands.push(NodeCommand::SendToAll(make_udp_msg(me_id, MessageType::ImAlive, UdpData::Cab(me.clone()))));
+    return commands;
+}
+
+// Put a pressed call in the queue of this cab, runs on the state thread.
+// Cab calls go first in the queue, hall calls only while isolated, see isolation.rs. An accessible cab call is marked
+// in the same job, so the door can't open at the floor before the mark is there.
+// Returns None if the call is refused, else true if a hall call was taken without the master.
+fn take_call(s: &mut NodeState, order: Order, accessible: bool, maintenance_cab_calls: bool) -> Option<bool> {
+    let isolated = s.isolation.isolated;
+    let me = s.known_elevators.get_mut(0)?;
+
+    if order.order_type != CAB {
+        if !isolated || !me.serves_floor(order.floor) || me.queue.contains(&order) {
+            return Some(false);
+        }
+        println!("Isolated, taking hall call to floor {}", order.floor);
+        me.queue.push(order);
+        return Some(true);
+    }
+
+    // The cab can't drive to a floor outside its shaft or a floor it skips
+    if !me.serves_floor(order.floor) {
+        println!("This elevator does not stop at floor {}, not taking cab call", order.floor);
+        return None;
+    }
+    // A cab in maintenance may be set up to refuse new cab calls
+    if !maintenance_cab_calls && me.maintenance {
+        println!("In maintenance, not taking cab call to floor {}", order.floor);
+        return None;
+    }
+
+    if accessible && !me.accessible_stops.contains(&order.floor) {
+        me.accessible_stops.push(order.floor);
+    }
+    if me.queue.len()>1{
+        me.queue.insert(1,order);
+    }else {
+        me.queue.insert(0,order);
+    }
+    return Some(false);
+}
@@ -0,0 +1,368 @@
+//! ## Peer Module
+//! A masterless way to run the system, chosen at startup with "coordination: peer" in "boot.txt".
+//! No master is elected and no worldview is sent. Every node gossips its own Cab and the hall requests it knows of,
+//! and every node runs the same assignment over the merged state, so they agree on who serves a hall call
+//! without asking anyone. A node that goes quiet is dropped by every node after `peer_timeout` and its hall calls
+//! are assigned again. All nodes in a system must use the same coordination.
+//!
+//! ## The structs includes:
+//! - **Coordination**: Master and slaves, or peers.
+//! - **PeerConfig**: The coordination and how often peers gossip.
+//! - **HallRequest**: One hall button in the shared set, with a version so every node ends up with the same set.
+//! - **Gossip**: What a peer sends every gossip period, its Cab and its hall requests.
+//!
+//! ## The functions includes:
+//! - 'parse'                  reads one "key: value" line from "boot.txt" into a `PeerConfig`.
+//! - 'add_hall_request'       a hall button was pressed at this node.
+//! - 'complete_hall_request'  this cab has served a hall call.
+//! - 'merge_hall_requests'    merges the hall requests of another peer.
+//! - 'assign_hall_requests'   who serves every hall request, the same answer on every node with the same state.
+//! - 'apply_assignment'       puts the hall requests assigned to this node in its queue.
+//! - 'gossip'                 assigns and sends the gossip of this node to the other peers.
+//! - 'spawn_gossip'           drops quiet peers and gossips every gossip period.
+//!
+//! - A hall request has a version, raised by the node that presses or serves it. The higher version wins a merge,
+//!   a press wins over a completion with the same version so no call is lost. Served requests are kept as inactive.
+//! - A hall order stays with the cab that has it in its queue while that cab is alive and takes hall calls,
+//!   if several have it the lowest id keeps it. Only requests no cab has are scored, with `best_to_worst_elevator`
+//!   in the balanced mode, one at a time by floor, so the cabs do not swap calls back and forth as they move.
+//! - Parking and the traffic statistics need a master and are off.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! coordination: peer              "master" (default) or "peer"
+//! peer_gossip_period: 0.2         seconds between two gossips of a node
+//! peer_timeout: 2                 seconds without a word from a peer before its hall calls are assigned again
+//! ```
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel as cbc;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::cab_object::cab::Cab;
+use crate::modules::cab_object::elevator_status_functions::Status;
+use crate::modules::elevator_object::alias_lib::CAB;
+use crate::modules::master_functions::master::best_to_worst_elevator;
+use crate::modules::master_functions::traffic::DispatchMode;
+use crate::modules::order_object::order_init::Order;
+use crate::modules::system_clock::{clock_stopped, sleep, system_now, track};
+use crate::modules::system_status::{NodeState, SystemState};
+use crate::modules::udp_functions::udp::{make_udp_msg, MessageType, UdpData};
+use crate::modules::udp_functions::udp_handler_init::UdpHandler;
+
+/// Master id of a node running as a peer, no node has id 0
+pub const NO_MASTER: u8 = 0;
+
+/// How the nodes agree on who serves a hall call
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Coordination {
+    #[default]
+    Master,     // One node is elected master and gives out the hall orders
+    Peer,       // Every node assigns the hall orders itself from the gossiped state
+}
+
+/// The coordination and how often peers gossip, see the keys above
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerConfig {
+    pub coordination: Coordination,
+    pub gossip_period: Duration,
+    pub peer_timeout: Duration,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        PeerConfig {
+            coordination: Coordination::Master,
+            gossip_period: Duration::from_millis(200),
+            peer_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// One hall button in the set every peer keeps
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HallRequest {
+    pub order: Order,
+    pub active: bool,       // Pressed and not served yet
+    pub version: u32,       // Raised on every press and completion, the higher version wins a merge
+}
+
+/// The gossip of one peer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gossip {
+    pub cab: Cab,
+    pub hall_requests: Vec<HallRequest>,
+}
+
+impl PeerConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', "coordination" or a key starting with "peer_".
+    /// * `value` - &str - the text after ':', "master" or "peer" for the coordination, seconds for the others.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a peer key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        if key.trim() == "coordination" {
+            match value {
+                "master" => self.coordination = Coordination::Master,
+                "peer" => self.coordination = Coordination::Peer,
+                _ => return false,
+            }
+            return true;
+        }
+
+        let seconds = match value.parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+            Some(seconds) => seconds,
+            None => return false,
+        };
+        match key.trim() {
+            "peer_gossip_period" => self.gossip_period = seconds,
+            "peer_timeout" => self.peer_timeout = seconds,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+/// add_hall_request
+/// A hall button was pressed at this node, make the request active with a new version
+///
+/// # Arguments:
+///
+/// * `requests` - &mut Vec<HallRequest> - the hall requests of this node.
+/// * `order` - &Order - the hall order of the button.
+///
+/// # Returns:
+///
+/// Returns - bool - false if the request was already active.
+///
+pub fn add_hall_request(requests: &mut Vec<HallRequest>, order: &Order) -> bool {
+    match requests.iter_mut().find(|request| request.order == *order) {
+        Some(request) if request.active => return false,
+        Some(request) => {
+            request.active = true;
+            request.version += 1;
+        }
+        None => requests.push(HallRequest { order: order.clone(), active: true, version: 1 }),
+    }
+    return true;
+}
+
+/// complete_hall_request
+/// This cab has served a hall call, make the request inactive with a new version
+///
+/// # Arguments:
+///
+/// * `requests` - &mut Vec<HallRequest> - the hall requests of this node.
+/// * `order` - &Order - the hall order that was served.
+///
+/// # Returns:
+///
+/// Returns - bool - false if the request was not active.
+///
+pub fn complete_hall_request(requests: &mut [HallRequest], order: &Order) -> bool {
+    match requests.iter_mut().find(|request| request.order == *order && request.active) {
+        Some(request) => {
+            request.active = false;
+            request.version += 1;
+            return true;
+        }
+        None => return false,
+    }
+}
+
+/// merge_hall_requests
+/// Merge the hall requests of another peer into the ones of this node, the same result in any order
+///
+/// # Arguments:
+///
+/// * `requests` - &mut Vec<HallRequest> - the hall requests of this node.
+/// * `other` - &[HallRequest] - the hall requests from the gossip of another peer.
+///
+/// # Returns:
+///
+/// Returns - bool - true if a request of this node changed.
+///
+pub fn merge_hall_requests(requests: &mut Vec<HallRequest>, other: &[HallRequest]) -> bool {
+    let mut changed = false;
+    for theirs in other {
+        match requests.iter_mut().find(|request| request.order == theirs.order) {
+            Some(mine) => {
+                let newer = theirs.version > mine.version || (theirs.version == mine.version && theirs.active && !mine.active);
+                if newer {
+                    *mine = theirs.clone();
+                    changed = true;
+                }
+            }
+            None => {
+                requests.push(theirs.clone());
+                changed = true;
+            }
+        }
+    }
+    return changed;
+}
+
+/// assign_hall_requests
+/// Decide which cab serves every active hall request. Only the requests and the cabs are used,
+/// so every node with the same merged state gets the same answer.
+///
+/// # Arguments:
+///
+/// * `requests` - &[HallRequest] - the shared hall requests.
+/// * `cabs` - &[Cab] - every known cab, with the queues they gossiped.
+///
+/// # Returns:
+///
+/// Returns - Vec<(u8, Order)> - the id of the cab serving each active request, requests no cab can serve are left out.
+///
+pub fn assign_hall_requests(requests: &[HallRequest], cabs: &[Cab]) -> Vec<(u8, Order)> {
+    let mut active: Vec<Order> = requests.iter().filter(|request| request.active).map(|request| request.order.clone()).collect();
+    active.sort_by_key(|order| (order.floor, order.order_type));
+
+    // The cabs that can take hall calls, with their cab orders, by id so ties go to the lowest id
+    let mut candidates: Vec<Cab> = cabs
+        .iter()
+        .filter(|cab| cab.alive && cab.serves_hall_calls() && cab.status != Status::Error)
+        .cloned()
+        .collect();
+    candidates.sort_by_key(|cab| cab.id);
+    for candidate in candidates.iter_mut() {
+        candidate.queue.retain(|order| order.order_type == CAB);
+    }
+
+    let mut assigned = Vec::new();
+    let mut unowned = Vec::new();
+    for order in active {
+        // Kept by the lowest id that already has it
+        let holder = candidates.iter().map(|cab| cab.id).find(|id| {
+            cabs.iter().any(|cab| cab.id == *id && cab.queue.contains(&order))
+        });
+        match holder {
+            Some(id) => {
+                if let Some(candidate) = candidates.iter_mut().find(|cab| cab.id == id) {
+                    candidate.queue.push(order.clone());
+                }
+                assigned.push((id, order));
+            }
+            None => unowned.push(order),
+        }
+    }
+
+    for order in unowned {
+        if let Some(best) = best_to_worst_elevator(&order, &candidates, DispatchMode::Balanced).first() {
+            if let Some(candidate) = candidates.iter_mut().find(|cab| cab.id == *best) {
+                candidate.queue.push(order.clone());
+            }
+            assigned.push((*best, order));
+        }
+    }
+    return assigned;
+}
+
+/// apply_assignment
+/// Put the hall requests assigned to this node in its queue and take out the ones assigned to others
+///
+/// # Arguments:
+///
+/// * `s` - &mut NodeState - the state of this node, run it on the state thread.
+///
+/// # Returns:
+///
+/// Returns - bool - true if the queue of this node changed.
+///
+pub fn apply_assignment(s: &mut NodeState) -> bool {
+    let me_id = s.me_id;
+    let mine: Vec<Order> = assign_hall_requests(&s.hall_requests, &s.known_elevators)
+        .into_iter()
+        .filter(|(id, _)| *id == me_id)
+        .map(|(_, order)| order)
+        .collect();
+
+    let me = match s.known_elevators.get_mut(0) {
+        Some(me) => me,
+        None => return false,
+    };
+    let before = me.queue.clone();
+    me.queue.retain(|order| order.order_type == CAB || mine.contains(order));
+    for order in mine {
+        if !me.queue.contains(&order) {
+            println!("Taking hall call {:?}", order);
+            me.queue.push(order);
+        }
+    }
+    return me.queue != before;
+}
+
+/// gossip
+/// Assign the hall requests, then send the cab and the hall requests of this node to every other peer
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends the gossip.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop if the queue changed.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn gossip(state: &Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: &cbc::Sender<Vec<Order>>) {
+    let found = state.update(|s| {
+        let changed = apply_assignment(s);
+        let gossip = Gossip { cab: s.known_elevators.first()?.clone(), hall_requests: s.hall_requests.clone() };
+        return Some((changed, gossip, s.known_elevators.clone()));
+    });
+    let (changed, gossip, known_elevators) = match found {
+        Some(found) => found,
+        None => return,
+    };
+    if changed {
+        let _ = order_update_tx.send(Vec::new());
+    }
+
+    let msg = make_udp_msg(state.me_id, MessageType::Gossip, UdpData::Gossip(gossip));
+    for elevator in known_elevators.iter().skip(1) {
+        udp_handler.send(&elevator.inn_address, &msg);
+    }
+}
+
+/// spawn_gossip
+/// Every gossip period: drop the peers that have been quiet for `peer_timeout`, then gossip
+///
+/// # Arguments:
+///
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - Arc<UdpHandler> - sends the gossip.
+/// * `config` - PeerConfig - the gossip period and the peer timeout.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop if the queue changed.
+///
+pub fn spawn_gossip(state: Arc<SystemState>, udp_handler: Arc<UdpHandler>, config: PeerConfig, order_update_tx: cbc::Sender<Vec<Order>>) {
+    track(thread::spawn(move || loop {
+        sleep(config.gossip_period);
+        if clock_stopped() {
+            return;
+        }
+
+        let timeout = config.peer_timeout;
+        state.update(move |s| {
+            let now = system_now();
+            for cab in s.known_elevators.iter_mut().skip(1).filter(|cab| cab.alive) {
+                if now.duration_since(cab.last_lifesign).unwrap_or_default() > timeout {
+                    println!("No word from elevator {} in {:?}, its hall calls are assigned again", cab.id, timeout);
+                    cab.alive = false;
+                }
+            }
+        });
+        gossip(&state, &udp_handler, &order_update_tx);
+    }));
+}
@@ -0,0 +1,203 @@
+//! ## Shutdown Module
+//! Stops a node on purpose without the rest of the system having to wait out the lifesign timeouts
+//! (3 s for the master, 10 s for a moving cab). A shutdown is asked for with SIGINT/SIGTERM or with the
+//! "shutdown" command at the keyboard, both end up as `OperatorCommand::Shutdown` in the main loop.
+//!
+//! ## The structs includes:
+//! - **Handover**: Everything a master knows that its successor needs, sent with MessageType::Handover.
+//!
+//! ## The functions includes:
+//! - 'watch_signals'   turns SIGINT and SIGTERM into `OperatorCommand::Shutdown`.
+//! - 'leave'           hands off the work of this node and tells the others it is leaving.
+//!
+//! - A master sends its worldview, `all_orders` and the messages still waiting for acks to the alive elevator
+//!   with the lowest id, the one every node would elect, and waits for its ack. Its hall orders go along in `all_orders`.
+//! - Any other node gives its hall orders back to the master with RemoveOrder, see give_away_hall_orders.
+//! - Then every node sends Leaving to the others, they take it offline at once as with ErrorOffline.
+//!   A node on the first protocol version gets ErrorOffline instead.
+//! - If the successor never acks, the hall orders stay in the Leaving message and are reassigned from there.
+//! - Cab orders can't be served by another cab, they are lost with the node.
+//!
+//! ## Dependencies
+//!
+//! ```toml
+//! [dependencies]
+//! libc = "0.2"
+//! ```
+//! Used to install the signal handlers.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel as cbc;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::cab_object::cab::Cab;
+use crate::modules::elevator_object::alias_lib::CAB;
+use crate::modules::master_functions::master::Role;
+use crate::modules::node::OperatorCommand;
+use crate::modules::order_object::order_init::Order;
+use crate::modules::slave_functions::slave::give_away_hall_orders;
+use crate::modules::system_status::{SystemState, WaitingConfirmation};
+use crate::modules::udp_functions::udp::{make_udp_msg, MessageType, UdpData};
+use crate::modules::udp_functions::udp_handler_init::UdpHandler;
+
+pub const HANDOVER_RETRIES: u8 = 10;    // Times the Handover is sent again, 50 ms apart, before the master leaves anyway
+
+// Set by the signal handler, a handler may do nothing more than this
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+/// The state a master hands to its successor
+#[derive(Debug, Serialize, PartialEq, Deserialize, Clone)]
+pub struct Handover {
+    pub known_elevators: Vec<Cab>,          // Worldview of the old master, its own cab is marked as not alive
+    pub all_orders: Vec<Order>,             // All orders, with the hall orders the old master had in its queue
+    pub pending: Vec<WaitingConfirmation>,  // Messages the old master sent that are still waiting for acks
+}
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// watch_signals
+/// Install handlers for SIGINT and SIGTERM and send `OperatorCommand::Shutdown` to the main loop when one comes.
+/// A second signal while the node is leaving does nothing, kill the process with SIGKILL if it hangs.
+///
+/// # Arguments:
+///
+/// * `operator_tx` - cbc::Sender<OperatorCommand> - the same channel as the keyboard commands.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn watch_signals(operator_tx: cbc::Sender<OperatorCommand>) {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: on_signal only stores to an atomic, which is allowed in a signal handler
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+
+    thread::spawn(move || {
+        while !SIGNALLED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        println!("Signal received, shutting down");
+        let _ = operator_tx.send(OperatorCommand::Shutdown);
+    });
+}
+
+/// leave
+/// Hand off the work of this node and tell the other nodes it is leaving, see the module description.
+/// The node must stop sending after this, the caller stops the main loop.
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - the receiver must still run, the Handover is acked.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - used if the hall orders are reassigned here.
+///
+/// # Returns:
+///
+/// Returns - bool - 'false' if this node was master and no successor took over the state.
+///
+pub fn leave(state: &Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) -> bool {
+    let me_id = state.me_id;
+
+    // No new hall orders from now on, the master sees this in the RemoveOrder or the Handover
+    let is_master = state.update(move |s| {
+        if let Some(me) = s.known_elevators.get_mut(0) {
+            me.takes_hall_calls = false;
+        }
+        return s.master_id == me_id;
+    });
+
+    let handed_over = if is_master {
+        hand_over_master(state, udp_handler)
+    } else {
+        give_away_hall_orders(state, udp_handler, order_update_tx);
+        true
+    };
+
+    announce_leaving(state, udp_handler);
+    return handed_over;
+}
+
+// Send the state of this master to its successor, true if it acked or there is no one to take over
+fn hand_over_master(state: &Arc<SystemState>, udp_handler: &UdpHandler) -> bool {
+    let me_id = state.me_id;
+    let handover = state.read(move |s| {
+        // The same choice every node makes when the master goes offline, see handle_error_offline
+        let successor = s.known_elevators.iter().filter(|e| e.alive && e.id != me_id).min_by_key(|e| e.id)?.clone();
+
+        let mut known_elevators = s.known_elevators.clone();
+        let mut all_orders = s.all_orders.clone();
+        let me = known_elevators.get_mut(0)?;
+        for order in me.queue.iter().filter(|order| order.order_type != CAB) {
+            if !all_orders.contains(order) {
+                all_orders.push(order.clone());
+            }
+        }
+        me.queue.retain(|order| order.order_type == CAB);
+        me.alive = false;
+        me.role = Role::Slave;
+
+        let handover = Handover { known_elevators, all_orders, pending: s.sent_messages.clone() };
+        return Some((successor, handover));
+    });
+
+    let (successor, handover) = match handover {
+        Some(found) => found,
+        None => {
+            println!("No other elevator alive, nothing to hand over");
+            return true;
+        }
+    };
+
+    println!("Handing the master role over to elevator {}", successor.id);
+    let msg = make_udp_msg(me_id, MessageType::Handover, UdpData::Handover(handover));
+    if !udp_handler.ensure_send(&successor, &msg, state, HANDOVER_RETRIES) {
+        println!("Elevator {} did not ack the handover, the hall orders go with the Leaving message", successor.id);
+        return false;
+    }
+
+    // The successor has the hall orders now
+    let successor_id = successor.id;
+    state.update(move |s| {
+        s.master_id = successor_id;
+        for elevator in s.known_elevators.iter_mut() {
+            if elevator.id == me_id {
+                elevator.role = Role::Slave;
+                elevator.queue.retain(|order| order.order_type == CAB);
+            } else if elevator.id == successor_id {
+                elevator.role = Role::Master;
+            }
+        }
+    });
+    return true;
+}
+
+// Tell every other node this one is leaving, with Leaving or ErrorOffline for nodes that don't know Leaving
+fn announce_leaving(state: &Arc<SystemState>, udp_handler: &UdpHandler) {
+    let me_id = state.me_id;
+    let me = state.update(move |s| {
+        let me = s.known_elevators.get_mut(0)?;
+        me.alive = false;
+        return Some(me.clone());
+    });
+    let me = match me {
+        Some(me) => me,
+        None => return,
+    };
+
+    for elevator in state.known_elevators().iter().filter(|e| e.id != me_id) {
+        let message_type = match udp_handler.version_for(&elevator.inn_address) {
+            Ok(version) if version >= MessageType::Leaving.since_version() => MessageType::Leaving,
+            _ => MessageType::ErrorOffline,
+        };
+        udp_handler.send(&elevator.inn_address, &make_udp_msg(me_id, message_type, UdpData::Cab(me.clone())));
+    }
+    println!("Elevator {} has left the system", me_id);
+}
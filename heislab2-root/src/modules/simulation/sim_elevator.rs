@@ -0,0 +1,276 @@
+//! ## Simulated Elevator Module
+//! This module provides an elevator that lives inside the program, used by the simulator instead of the elevator server.
+//!
+//! The simulated elevator listens on a local TCP port and speaks the same 4 byte protocol as the elevator server,
+//! so `Elevator::init` can connect to it without knowing the difference. The car moves in the time of the
+//! system clock, so it follows the virtual clock when the simulator runs.
+//!
+//! ## The structs includes:
+//! - **SimElevator**: Handle to a running simulated elevator, used to press buttons and inspect lights and position.
+//!
+//! ## The functions includes:
+//! - 'start'            starts the TCP server and the physics thread.
+//! - 'press_button'     presses a call button for a short moment.
+//! - 'set_obstruction'  sets the obstruction switch.
+//! - 'set_stop_button'  holds or releases the stop button.
+//! - 'set_motor_power'  cuts or restores the power to the motor.
+//! - 'set_load'         sets what the load sensor reads, see elevator_load.rs.
+//! - 'set_load_sensor'  false makes the elevator ignore the load command, like the elevator server.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::modules::elevator_object::alias_lib::{DIRN_DOWN, DIRN_STOP, DIRN_UP};
+use crate::modules::system_clock::{clock_stopped, now, sleep, track};
+
+// How often the car position is updated
+const PHYSICS_PERIOD: Duration = Duration::from_millis(10);
+// How far from a floor, in floors, the floor sensor is still active
+const SENSOR_WIDTH: f32 = 0.05;
+// How long a pressed button is held down
+const BUTTON_HOLD: Duration = Duration::from_millis(150);
+
+#[derive(Debug)]
+struct SimElevatorState {
+    num_floors: u8,
+    position: f32,                          // Floor number, between floors while moving
+    motor_direction: u8,
+    motor_powered: bool,
+    travel_time: Duration,                  // Time to move one floor
+    buttons_released_at: Vec<[Option<Instant>; 3]>,
+    call_lights: Vec<[bool; 3]>,
+    floor_indicator: u8,
+    door_light: bool,
+    stop_light: bool,
+    stop_button: bool,
+    obstruction: bool,
+    load: u16,                              // What the load sensor reads
+    load_sensor: bool,                      // False to not answer the load command
+    door_log: Vec<(Instant, u8)>,           // Every time the door light was turned on, and at which floor
+}
+
+/// Handle to a simulated elevator running in this process
+#[derive(Clone, Debug)]
+pub struct SimElevator {
+    state: Arc<Mutex<SimElevatorState>>,
+    address: SocketAddr,
+}
+
+impl SimElevatorState {
+    fn floor_sensor(&self) -> Option<u8> {
+        let nearest = self.position.round();
+        if (self.position - nearest).abs() <= SENSOR_WIDTH {
+            Some(nearest as u8)
+        } else {
+            None
+        }
+    }
+
+    fn button(&self, floor: u8, call: u8) -> bool {
+        match self.buttons_released_at.get(floor as usize).and_then(|b| b.get(call as usize)) {
+            Some(Some(released_at)) => now() < *released_at,
+            _ => false,
+        }
+    }
+
+    // Handle one 4 byte command, returns the reply for the commands that expect one
+    fn command(&mut self, buf: [u8; 4]) -> Option<[u8; 4]> {
+        match buf[0] {
+            1 => {
+                self.motor_direction = buf[1];
+                None
+            }
+            2 => {
+                if let Some(lights) = self.call_lights.get_mut(buf[2] as usize) {
+                    if let Some(light) = lights.get_mut(buf[1] as usize) {
+                        *light = buf[3] != 0;
+                    }
+                }
+                None
+            }
+            3 => {
+                self.floor_indicator = buf[1];
+                None
+            }
+            4 => {
+                let on = buf[1] != 0;
+                if on && !self.door_light {
+                    let floor = self.floor_sensor().unwrap_or(self.position.round() as u8);
+                    self.door_log.push((now(), floor));
+                }
+                self.door_light = on;
+                None
+            }
+            5 => {
+                self.stop_light = buf[1] != 0;
+                None
+            }
+            6 => Some([6, self.button(buf[2], buf[1]) as u8, 0, 0]),
+            7 => match self.floor_sensor() {
+                Some(floor) => Some([7, 1, floor, 0]),
+                None => Some([7, 0, 0, 0]),
+            },
+            8 => Some([8, self.stop_button as u8, 0, 0]),
+            9 => Some([9, self.obstruction as u8, 0, 0]),
+            10 if self.load_sensor => {
+                let [high, low] = self.load.to_be_bytes();
+                Some([10, high, low, 0])
+            }
+            _ => None,
+        }
+    }
+
+    fn step(&mut self, dt: Duration) {
+        if !self.motor_powered {
+            return;
+        }
+        let distance = dt.as_secs_f32() / self.travel_time.as_secs_f32();
+        let top = (self.num_floors - 1) as f32;
+        match self.motor_direction {
+            DIRN_UP => self.position = (self.position + distance).min(top),
+            DIRN_DOWN => self.position = (self.position - distance).max(0.0),
+            _ => {}
+        }
+    }
+}
+
+impl SimElevator {
+    /// Start a simulated elevator on a free local port
+    ///
+    /// # Arguments:
+    ///
+    /// * `num_floors` - u8 - number of floors in the shaft.
+    /// * `start_position` - f32 - where the car starts, use a fraction to start between floors.
+    /// * `travel_time` - Duration - time the car uses to move one floor.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - std::io::Result<SimElevator> - handle to the elevator, or an error if no port could be bound.
+    ///
+    pub fn start(num_floors: u8, start_position: f32, travel_time: Duration) -> std::io::Result<SimElevator> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(Mutex::new(SimElevatorState {
+            num_floors,
+            position: start_position,
+            motor_direction: DIRN_STOP,
+            motor_powered: true,
+            travel_time,
+            buttons_released_at: vec![[None; 3]; num_floors as usize],
+            call_lights: vec![[false; 3]; num_floors as usize],
+            floor_indicator: 0,
+            door_light: false,
+            stop_light: false,
+            stop_button: false,
+            obstruction: false,
+            load: 0,
+            load_sensor: true,
+            door_log: Vec::new(),
+        }));
+
+        // Move the car in system clock time and serve every new connection, the node shares one stream
+        // between all its clones of Elevator. Stops with the virtual clock, the connections when the node closes them
+        listener.set_nonblocking(true)?;
+        let physics_state = Arc::clone(&state);
+        track(thread::spawn(move || {
+            let mut last = now();
+            loop {
+                sleep(PHYSICS_PERIOD);
+                if clock_stopped() {
+                    return;
+                }
+                while let Ok((stream, _)) = listener.accept() {
+                    let connection_state = Arc::clone(&physics_state);
+                    track(thread::spawn(move || serve_connection(stream, connection_state)));
+                }
+                let current = now();
+                physics_state.lock().unwrap().step(current.saturating_duration_since(last));
+                last = current;
+            }
+        }));
+
+        Ok(SimElevator { state, address })
+    }
+
+    /// Address to give to `Elevator::init`
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    pub fn press_button(&self, floor: u8, call: u8) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(buttons) = state.buttons_released_at.get_mut(floor as usize) {
+            buttons[call as usize] = Some(now() + BUTTON_HOLD);
+        }
+    }
+
+    pub fn set_obstruction(&self, obstruction: bool) {
+        self.state.lock().unwrap().obstruction = obstruction;
+    }
+
+    pub fn set_stop_button(&self, pressed: bool) {
+        self.state.lock().unwrap().stop_button = pressed;
+    }
+
+    /// Set what the load sensor reads, in the unit the node is configured with
+    pub fn set_load(&self, load: u16) {
+        self.state.lock().unwrap().load = load;
+    }
+
+    /// Answer the load command or not, the elevator server does not
+    pub fn set_load_sensor(&self, answers: bool) {
+        self.state.lock().unwrap().load_sensor = answers;
+    }
+
+    /// Cut or restore the motor power, without power the car stays where it is whatever the direction is
+    pub fn set_motor_power(&self, powered: bool) {
+        self.state.lock().unwrap().motor_powered = powered;
+    }
+
+    pub fn position(&self) -> f32 {
+        self.state.lock().unwrap().position
+    }
+
+    pub fn floor_sensor(&self) -> Option<u8> {
+        self.state.lock().unwrap().floor_sensor()
+    }
+
+    pub fn motor_direction(&self) -> u8 {
+        self.state.lock().unwrap().motor_direction
+    }
+
+    pub fn call_light(&self, floor: u8, call: u8) -> bool {
+        self.state.lock().unwrap().call_lights[floor as usize][call as usize]
+    }
+
+    pub fn door_open(&self) -> bool {
+        self.state.lock().unwrap().door_light
+    }
+
+    pub fn stop_light(&self) -> bool {
+        self.state.lock().unwrap().stop_light
+    }
+
+    /// True if the door has been opened at `floor` at or after `since`
+    pub fn door_opened_at(&self, floor: u8, since: Instant) -> bool {
+        self.state.lock().unwrap().door_log.iter().any(|(at, f)| *f == floor && *at >= since)
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, state: Arc<Mutex<SimElevatorState>>) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_nodelay(true);
+    let mut buf = [0u8; 4];
+    while stream.read_exact(&mut buf).is_ok() {
+        let reply = state.lock().unwrap().command(buf);
+        if let Some(reply) = reply {
+            if stream.write_all(&reply).is_err() {
+                return;
+            }
+        }
+    }
+}
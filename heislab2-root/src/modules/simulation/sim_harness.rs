@@ -0,0 +1,399 @@
+//! ## Simulation Harness Module
+//! This module runs several complete nodes in one process, on a virtual network and a virtual clock,
+//! each with its own simulated elevator. Used by the scenario tests in `tests/`.
+//!
+//! ## The structs includes:
+//! - **SimConfig**: Size of the simulated system and how fast time moves.
+//! - **SimNode**: One running node, with its state, elevator and network card.
+//! - **SimCall**: A button press made by a scenario, used to check that it was served.
+//! - **Simulation**: The running system, advances time and lets the scenario press buttons, kill and shut down nodes.
+//!
+//! Only one simulation can run at a time since the virtual clock is shared by the whole process,
+//! `Simulation::start` waits until the previous one has been dropped.
+//! The seed decides every random choice a scenario makes, in a seeded StdRng like the fault injection uses.
+//! The threads of the nodes are still scheduled by the operating system.
+//!
+//! Every sleep and receive timeout of the nodes runs on the virtual clock. Dropping the simulation stops the clock
+//! and joins the threads of the nodes, so nothing of one scenario runs on into the next.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel as cbc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::modules::cab_object::elevator_door::{DoorConfig, DoorRequest};
+use crate::modules::cab_object::elevator_load::LoadConfig;
+use crate::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use crate::modules::master_functions::parking::ParkingPolicy;
+use crate::modules::master_functions::traffic::{DispatchMode, TrafficConfig};
+use crate::modules::node::{run_node, NodeConfig, OperatorCommand};
+use crate::modules::peer_functions::peer::PeerConfig;
+use crate::modules::master_functions::lease::LeaseConfig;
+use crate::modules::isolation::IsolationConfig;
+use crate::modules::order_object::order_init::Order;
+use crate::modules::simulation::sim_elevator::SimElevator;
+use crate::modules::system_clock::{install_virtual_clock, track, uninstall_virtual_clock, VirtualClock};
+use crate::modules::system_init::init_system_state;
+use crate::modules::system_status::SystemState;
+use crate::modules::udp_functions::udp::BROADCAST_PORT;
+use crate::modules::udp_functions::fault_injection::FaultConfig;
+use crate::modules::udp_functions::transport::TransportConfig;
+use crate::modules::udp_functions::codec::CodecConfig;
+use crate::modules::udp_functions::virtual_network::{VirtualNetwork, VirtualNic};
+
+// Only one simulation at a time, the virtual clock is global
+static SIMULATION_LOCK: Mutex<()> = Mutex::new(());
+
+// Real time the threads of the nodes get to return when the simulation ends
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the simulated system and how fast time moves
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub num_nodes: u8,
+    pub num_floors: u8,
+    pub seed: u64,
+    pub start_position: f32,    // Where every car starts, in floors
+    pub travel_time: Duration,  // Time a car uses between two floors
+    pub time_step: Duration,    // Virtual time added each step
+    pub real_step: Duration,    // Real time waited each step so the node threads can keep up
+    pub boot_time: Duration,    // Virtual time given to the nodes to find each other before the scenario starts
+    pub faults: Option<FaultConfig>, // Network faults injected on every node, partitions count from start
+    pub travel_timeout: Duration,   // Time without a new floor before a moving node takes its motor as dead
+    pub obstruction_timeout: Duration, // Time the door can be obstructed before a node gives away its hall orders
+    pub maintenance_cab_calls: bool,   // Nodes in maintenance keep taking new cab calls
+    pub parking: ParkingPolicy,        // Where the master parks idle cabs
+    pub traffic: TrafficConfig,        // How the master picks its dispatch mode
+    pub served_floors: Vec<(u8, Vec<u8>)>, // (node id, floors it stops at), nodes not listed stop everywhere
+    pub load: LoadConfig,              // Load sensor of every node, set the load with set_load
+    pub door: DoorConfig,              // Door open times of every node
+    pub peer: PeerConfig,              // Run every node with a master or as peers
+    pub lease: LeaseConfig,            // Master lease of every node
+    pub isolation: IsolationConfig,    // How long a node waits for the others before it serves its own hall calls
+    pub codec: CodecConfig,            // What every node sends its messages in
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            num_nodes: 3,
+            num_floors: 4,
+            seed: 1,
+            start_position: 0.0,
+            travel_time: Duration::from_millis(2000),
+            time_step: Duration::from_millis(5),
+            real_step: Duration::from_millis(1),
+            boot_time: Duration::from_secs(5),
+            faults: None,
+            travel_timeout: Duration::from_secs(5),
+            obstruction_timeout: Duration::from_secs(10),
+            maintenance_cab_calls: true,
+            parking: ParkingPolicy::Off,
+            traffic: TrafficConfig::default(),
+            served_floors: Vec::new(),
+            load: LoadConfig { sensor: true, ..LoadConfig::default() },
+            door: DoorConfig::default(),
+            peer: PeerConfig::default(),
+            lease: LeaseConfig::default(),
+            isolation: IsolationConfig::default(),
+            codec: CodecConfig::default(),
+        }
+    }
+}
+
+/// One node running inside the simulation
+#[derive(Debug)]
+pub struct SimNode {
+    pub id: u8,
+    pub state: Arc<SystemState>,
+    pub elevator: SimElevator,
+    pub nic: Arc<VirtualNic>,
+    pub alive: bool,
+    pub operator_tx: cbc::Sender<OperatorCommand>, // The keyboard of the node
+    pub stopped: cbc::Receiver<()>,                 // Gets a message when run_node has returned
+}
+
+/// A button press made by the scenario
+#[derive(Clone, Debug)]
+pub struct SimCall {
+    pub node_id: u8,        // Panel the button was pressed on
+    pub order: Order,
+    pub pressed_at: Instant,
+}
+
+/// A running simulated system
+pub struct Simulation {
+    pub config: SimConfig,
+    pub clock: Arc<VirtualClock>,
+    pub network: Arc<VirtualNetwork>,
+    pub nodes: Vec<SimNode>,
+    pub rng: StdRng,        // Seeded with SimConfig::seed, the same seed gives the same scenario
+    pub calls: Vec<SimCall>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+/// Virtual address of a node, every node gets its own ip on the virtual subnet
+pub fn sim_address(id: u8, port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, id)), port)
+}
+
+impl Simulation {
+    /// Start `config.num_nodes` nodes with ids 1, 2, 3... and let them boot
+    pub fn start(config: SimConfig) -> Simulation {
+        let lock = SIMULATION_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let clock = install_virtual_clock();
+        let network = VirtualNetwork::new();
+        let mut nodes = Vec::new();
+
+        for id in 1..=config.num_nodes {
+            let elevator = SimElevator::start(config.num_floors, config.start_position, config.travel_time)
+                .expect("Failed to start simulated elevator");
+            let inn_address = sim_address(id, BROADCAST_PORT);
+            let out_address = sim_address(id, 3800);
+            let nic = network.attach(inn_address, out_address);
+            let state = Arc::new(init_system_state(id, 1));
+            let (operator_tx, operator_rx) = cbc::unbounded();
+
+            let node_config = NodeConfig {
+                elevator_address: elevator.address(),
+                num_floors: config.num_floors,
+                served_floors: config.served_floors.iter().find(|(node_id, _)| *node_id == id).map(|(_, floors)| floors.clone()),
+                inn_address,
+                out_address,
+                virtual_nic: Some(Arc::clone(&nic)),
+                // Every node draws its own faults, but from the seed of the scenario
+                faults: config.faults.clone().map(|mut faults| {
+                    faults.seed = faults.seed.wrapping_add(config.seed).wrapping_add(id as u64);
+                    faults
+                }),
+                travel_timeout: config.travel_timeout,
+                obstruction_timeout: config.obstruction_timeout,
+                maintenance: false,
+                maintenance_cab_calls: config.maintenance_cab_calls,
+                operator_rx: Some(operator_rx),
+                parking: config.parking.clone(),
+                traffic: config.traffic.clone(),
+                load: config.load,
+                door: config.door,
+                peer: config.peer,
+                lease: config.lease,
+                isolation: config.isolation,
+                codec: config.codec,
+                transport: TransportConfig::default(),
+            };
+            let node_state = Arc::clone(&state);
+            let (stopped_tx, stopped) = cbc::bounded(1);
+            track(thread::spawn(move || {
+                if let Err(e) = run_node(node_state, node_config) {
+                    println!("Simulated node {} stopped: {}", id, e);
+                }
+                let _ = stopped_tx.send(());
+            }));
+
+            nodes.push(SimNode { id, state, elevator, nic, alive: true, operator_tx, stopped });
+        }
+
+        let mut simulation = Simulation {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            clock,
+            network,
+            nodes,
+            calls: Vec::new(),
+            _lock: lock,
+        };
+        let boot_time = simulation.config.boot_time;
+        simulation.run_for(boot_time);
+        simulation
+    }
+
+    /// Advance the virtual clock by one step
+    pub fn step(&mut self) {
+        self.clock.advance(self.config.time_step);
+        thread::sleep(self.config.real_step);
+    }
+
+    /// Let the system run for `duration` of virtual time
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.clock.elapsed() + duration;
+        while self.clock.elapsed() < until {
+            self.step();
+        }
+    }
+
+    /// Run until `done` returns true or `timeout` of virtual time has passed, returns the last result of `done`
+    pub fn run_until<F>(&mut self, timeout: Duration, mut done: F) -> bool
+    where
+        F: FnMut(&Simulation) -> bool,
+    {
+        let until = self.clock.elapsed() + timeout;
+        while self.clock.elapsed() < until {
+            if done(self) {
+                return true;
+            }
+            self.step();
+        }
+        done(self)
+    }
+
+    pub fn node(&self, id: u8) -> &SimNode {
+        self.nodes.iter().find(|n| n.id == id).expect("No simulated node with that id")
+    }
+
+    /// Press a call button on the panel of node `node_id`
+    pub fn press(&mut self, node_id: u8, floor: u8, call: u8) {
+        let pressed_at = self.clock.now();
+        self.node(node_id).elevator.press_button(floor, call);
+        self.calls.push(SimCall { node_id, order: Order::init(floor, call), pressed_at });
+    }
+
+    /// Make a cab call on node `node_id` with the accessibility button
+    pub fn press_accessible(&mut self, node_id: u8, floor: u8) {
+        let pressed_at = self.clock.now();
+        self.node(node_id).operator_tx.send(OperatorCommand::AccessibleCall(floor)).unwrap();
+        self.calls.push(SimCall { node_id, order: Order::init(floor, CAB), pressed_at });
+    }
+
+    /// Press the door-open or door-close button inside the cab of node `node_id`
+    pub fn press_door_button(&mut self, node_id: u8, request: DoorRequest) {
+        self.node(node_id).operator_tx.send(OperatorCommand::Door(request)).unwrap();
+    }
+
+    /// Press a random hall button on a random alive node, returns the node and the order
+    pub fn press_random_hall_call(&mut self) -> (u8, Order) {
+        let alive: Vec<u8> = self.nodes.iter().filter(|n| n.alive).map(|n| n.id).collect();
+        let node_id = alive[self.rng.gen_range(0..alive.len())];
+        let floor = self.rng.gen_range(0..self.config.num_floors);
+        let call = if floor == 0 {
+            HALL_UP
+        } else if floor == self.config.num_floors - 1 {
+            HALL_DOWN
+        } else {
+            self.rng.gen_range(0..2)
+        };
+        self.press(node_id, floor, call);
+        (node_id, Order::init(floor, call))
+    }
+
+    /// Kill a node: unplug it from the network and cut the power to its motor
+    pub fn kill_node(&mut self, id: u8) {
+        let node = self.nodes.iter_mut().find(|n| n.id == id).expect("No simulated node with that id");
+        node.alive = false;
+        node.elevator.set_motor_power(false);
+        self.network.set_connected(node.nic.inn_address.ip(), false);
+    }
+
+    /// Shut node `id` down gracefully, as the "shutdown" command or SIGTERM would, see shutdown.rs.
+    /// The threads of the node keep running until the simulation ends, so it is unplugged once it has left.
+    /// Returns false if the node had not left after `timeout` of virtual time, it is unplugged anyway
+    pub fn shutdown_node(&mut self, id: u8, timeout: Duration) -> bool {
+        self.node(id).operator_tx.send(OperatorCommand::Shutdown).unwrap();
+        let left = self.run_until(timeout, |sim| !sim.node(id).stopped.is_empty());
+
+        let node = self.nodes.iter_mut().find(|n| n.id == id).expect("No simulated node with that id");
+        node.alive = false;
+        self.network.set_connected(node.nic.inn_address.ip(), false);
+        left
+    }
+
+    /// Cut or restore the power to the motor of a node, the node stays on the network
+    pub fn set_motor_power(&mut self, id: u8, powered: bool) {
+        self.node(id).elevator.set_motor_power(powered);
+    }
+
+    /// Turn the obstruction switch of a node on or off
+    pub fn set_obstruction(&mut self, id: u8, obstructed: bool) {
+        self.node(id).elevator.set_obstruction(obstructed);
+    }
+
+    /// Ask for maintenance on or off at the keyboard of node `node_id`, for itself or for elevator `cab_id`
+    pub fn set_maintenance(&mut self, node_id: u8, cab_id: u8, on: bool) {
+        self.node(node_id).operator_tx.send(OperatorCommand::Maintenance(cab_id, on)).unwrap();
+    }
+
+    /// Force a dispatch mode at the keyboard of node `node_id`, None goes back to following the traffic
+    pub fn set_dispatch_mode(&mut self, node_id: u8, mode: Option<DispatchMode>) {
+        self.node(node_id).operator_tx.send(OperatorCommand::DispatchMode(mode)).unwrap();
+    }
+
+    /// Set what the load sensor of a node reads
+    pub fn set_load(&mut self, id: u8, load: u16) {
+        self.node(id).elevator.set_load(load);
+    }
+
+    /// Hold or release the stop button of a node
+    pub fn set_stop_button(&mut self, id: u8, pressed: bool) {
+        self.node(id).elevator.set_stop_button(pressed);
+    }
+
+    /// Bring a killed node back on the network with a working motor
+    pub fn revive_node(&mut self, id: u8) {
+        let node = self.nodes.iter_mut().find(|n| n.id == id).expect("No simulated node with that id");
+        node.alive = true;
+        node.elevator.set_motor_power(true);
+        self.network.set_connected(node.nic.inn_address.ip(), true);
+    }
+
+    /// Ids of the nodes that have `order` in their own queue
+    pub fn nodes_serving(&self, order: &Order) -> Vec<u8> {
+        self.nodes
+            .iter()
+            .filter(|node| {
+                node.state.known_elevators().iter().any(|cab| cab.id == node.id && cab.queue.contains(order))
+            })
+            .map(|node| node.id)
+            .collect()
+    }
+
+    /// True if an alive cab has opened its door for the call after it was pressed.
+    /// Cab calls must be served by the cab they were pressed in.
+    pub fn call_served(&self, call: &SimCall) -> bool {
+        self.nodes
+            .iter()
+            .filter(|node| node.alive)
+            .filter(|node| call.order.order_type != CAB || node.id == call.node_id)
+            .any(|node| node.elevator.door_opened_at(call.order.floor, call.pressed_at))
+    }
+
+    /// Calls pressed by the scenario that are not served yet
+    pub fn unserved_calls(&self) -> Vec<SimCall> {
+        self.calls.iter().filter(|call| !self.call_served(call)).cloned().collect()
+    }
+
+    pub fn all_calls_served(&self) -> bool {
+        self.unserved_calls().is_empty()
+    }
+
+    /// End the simulation like dropping it does, returns how many threads of the nodes did not return in time
+    pub fn stop(mut self) -> usize {
+        self.stop_nodes()
+    }
+
+    // Stop the clock, drop the handles to the nodes and join their threads, then go back to real time
+    fn stop_nodes(&mut self) -> usize {
+        // Every sleep and receive on the clock returns at once, the loops of the nodes see it and return
+        self.clock.stop();
+        for node in self.nodes.iter() {
+            self.network.set_connected(node.nic.inn_address.ip(), false);
+        }
+        // The main loops return when their keyboard is gone, the state threads when their last handle is
+        self.nodes.clear();
+
+        let running = self.clock.join_threads(STOP_TIMEOUT);
+        if running > 0 {
+            println!("{} threads of the simulation did not return in {:?}", running, STOP_TIMEOUT);
+        }
+        uninstall_virtual_clock();
+        running
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.stop_nodes();
+    }
+}
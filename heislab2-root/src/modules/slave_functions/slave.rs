@@ -8,9 +8,13 @@
 //! - 'cancel_order'
 //! - 'update_from_worldview'
 //! - 'notify_wordview_error'
+//! - 'send_own_orders'
 //! - 'check_master_failure'
 //! - 'set_new_master'
 //! - 'reboot_program'
+//! - 'set_maintenance'
+//! - 'request_maintenance'
+//! - 'give_away_hall_orders'
 //! 
 //! ## Dependencies
 //! 
@@ -27,14 +31,16 @@
 //-----------------------IMPORTS------------------------------------------------------------
 
 use crate::modules::cab_object::cab::Cab; //Import for cab struct
-use crate::modules::udp_functions::udp::{UdpMsg, UdpData, MessageType, udp_broadcast, make_udp_msg,udp_ack};
+use crate::modules::udp_functions::udp::{UdpMsg, UdpData, MessageType, make_udp_msg,udp_ack};
 use crate::modules::udp_functions::udp_handler_init::UdpHandler;
 use crate::modules::order_object::order_init::Order;
-use crate::modules::master_functions::master::Role;
+use crate::modules::master_functions::master::{Role, reassign_orders};
 use crate::modules::elevator_object::elevator_init::SystemState;
 use crate::modules::elevator_object::alias_lib::CAB;
+use crate::modules::system_clock::elapsed_since;
 
 
+use crossbeam_channel as cbc;
 use std::net::SocketAddr;
 use std::time::Duration; //https://doc.rust-lang.org/std/time/struct.Instant.html
 use std::env; // Used for reboot function
@@ -83,31 +89,31 @@ pub fn receive_order(slave: &mut Cab, new_order: Order, master_address: SocketAd
 /// 
 /// * `completed_order` - Order - the order that was completed.
 /// * `status` - &SystemState - refrence to the system state.
+/// * `udp_handler` - &UdpHandler - broadcasts in the codec and protocol version of this node.
 /// 
 /// # Returns:
 ///
 /// Returns - bool - 'true' if succsessful broadcast, 'false' if failed to broadcast.
 ///
-pub fn notify_completed(completed_order: Order, state: &SystemState) -> bool {
-
-    //Lock active elevators
-    let known_elevators_locked = state.known_elevators.lock().unwrap();
+pub fn notify_completed(completed_order: Order, state: &SystemState, udp_handler: &UdpHandler) -> bool {
+
+    // Take this elevator and remove the order from all orders
+    let me_id = state.me_id;
+    let removed_order = completed_order.clone();
+    let me = state.update(move |s| {
+        if let Some(index) = s.all_orders.iter().position(|o| *o == removed_order) {
+            s.all_orders.remove(index);
+        }
+        return s.known_elevators.iter().find(|e| e.id == me_id).cloned();
+    });
 
     // Send message with order to remove
-    if let Some(elevator) = known_elevators_locked.iter().find(|e| e.id == state.me_id) {
-        let mut responsible_elevator = elevator.clone();
-        responsible_elevator.queue = vec![completed_order.clone()];
-
-        let message = make_udp_msg(state.me_id,MessageType::OrderComplete, UdpData::Cab(responsible_elevator.clone()));
-        drop(known_elevators_locked);
+    if let Some(elevator) = me {
+        let mut responsible_elevator = elevator;
+        responsible_elevator.queue = vec![completed_order];
 
-        //remove it from all orders
-        let mut all_orders_locked = state.all_orders.lock().unwrap();
-        if let Some(index) = all_orders_locked.iter().position(|o| *o == completed_order) {
-            all_orders_locked.remove(index);
-        }
-
-        return udp_broadcast(&message);
+        let message = make_udp_msg(state.me_id,MessageType::OrderComplete, UdpData::Cab(responsible_elevator));
+        return udp_handler.broadcast(&message);
 
     }else{
         println!("Error:Elevator  {} is missing from active", state.me_id);
@@ -143,6 +149,7 @@ pub fn cancel_order(slave: &mut Cab, order: Order) -> bool {
 /// Checks for discrepancies between the elevators worldview and the masters worldview
 /// if there are orders in the worldview that do not exist in the queue , it updates the elevator's order queue based on a received worldview.
 /// if there are missing orders in the worldview, it notifies the master that there are missing orders.
+/// If this cab has orders the worldview lacks, e.g. hall calls taken while isolated, only this cab is sent to the master.
 /// 
 /// # Arguments:
 /// 
@@ -156,61 +163,63 @@ pub fn cancel_order(slave: &mut Cab, order: Order) -> bool {
 ///
 pub fn update_from_worldview(state: &Arc<SystemState>, new_worldview: &Vec<Cab>,udp_handler: Arc<UdpHandler>) -> bool {
 
-    let mut worldview_missing_orders = false;
-
     // Compare recived worldview to known elevators
-    for wv_elevator in new_worldview{
-
-        //Lock known elevators
-        let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-
-        if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == wv_elevator.id){
-
-
-            let known_queue=elevator.queue.clone();
-
-            //No new orders
-
-            //Check if elevator is alive or dead
-            if elevator.alive != wv_elevator.alive{
+    let new_worldview = new_worldview.clone();
+    let (worldview_missing_orders, own_orders_missing, master_address) = state.update(move |s| {
+        let mut worldview_missing_orders = false;
+        let mut own_orders_missing = false;
+        let me_id = s.me_id;
+
+        for wv_elevator in new_worldview{
+            if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == wv_elevator.id){
+
+                let known_queue=elevator.queue.clone();
+
+                // This node knows its own queue best, the master is missing what this cab has and the worldview lacks
+                if elevator.id == me_id && known_queue.iter().any(|order| !wv_elevator.queue.contains(order)) {
+                    println!("Worldview lacks orders of this cab, sending them to the master");
+                    own_orders_missing = true;
+                }
+
+                //Check if elevator is alive or dead
+                if elevator.alive != wv_elevator.alive{
+                    worldview_missing_orders = true;
+                }
+
+                //No new orders
+                if known_queue == wv_elevator.queue{
+                    println!("Worldview matches for ID:{}", elevator.id);
+                    continue;
+                }
+
+                //Found missing order, add them to queue
+                let missing_orders: Vec<Order> = wv_elevator.queue.iter().filter(|&order| !known_queue.contains(order)).cloned().collect();
+                if !missing_orders.is_empty() {
+                    println!("Elevator {} is missing orders {:?}. Adding...", elevator.id, missing_orders);
+                    elevator.queue.extend(missing_orders);
+                }
+
+            } else{
+                // Add missing worldview elevator to active elevators
+                println!("Found missing elevator, Adding new elevator ID {} from worldview.", wv_elevator.id);
+                s.known_elevators.push(wv_elevator);
                 worldview_missing_orders = true;
             }
-
-            if known_queue == wv_elevator.queue{
-                println!("Worldview matches for ID:{}", elevator.id);
-                continue;
-            }
-
-            //Found missing order, add them to queue
-            let missing_orders: Vec<Order> = wv_elevator.queue.iter().filter(|&order| !known_queue.contains(order)).cloned().collect();
-            if !missing_orders.is_empty() {
-                println!("Elevator {} is missing orders {:?}. Adding...", elevator.id, missing_orders);
-                elevator.queue.extend(missing_orders);
-            }
-
-        } else{
-            // Add missing worldview elevator to active elevators
-            println!("Found missing elevator, Adding new elevator ID {} from worldview.", wv_elevator.id);
-            known_elevators_locked.push(wv_elevator.clone());
-            worldview_missing_orders = true;
         }
-    
-        drop(known_elevators_locked);
-
-    } 
-    
-    if worldview_missing_orders{
 
-        let master_id = state.master_id.lock().unwrap().clone();
-        let known_elevators_locked = state.known_elevators.lock().unwrap().clone();
+        let master_address = s.known_elevators.iter().find(|e| e.id == s.master_id).map(|e| e.inn_address);
+        return (worldview_missing_orders, own_orders_missing, master_address);
+    });
 
-        if let Some(master_elevator) = known_elevators_locked.iter().find(|e| e.id == master_id) {
-            let master_address = master_elevator.inn_address.clone();
+    if worldview_missing_orders{
+        if let Some(master_address) = master_address {
             notify_worldview_error(state.me_id,master_address,state,udp_handler);
         }
+    } else if own_orders_missing {
+        send_own_orders(state, &udp_handler, master_address);
     }
 
-    return worldview_missing_orders;
+    return worldview_missing_orders || own_orders_missing;
     
 }
 
@@ -230,13 +239,39 @@ pub fn update_from_worldview(state: &Arc<SystemState>, new_worldview: &Vec<Cab>,
 ///
 pub fn notify_worldview_error(sender_id: u8 ,master_adress: SocketAddr, state: &Arc<SystemState> ,udp_handler: Arc<UdpHandler>) {
 
-    let all_cabs = state.known_elevators.lock().unwrap().clone();
+    let all_cabs = state.known_elevators();
 
     let message = make_udp_msg(sender_id,MessageType::ErrorWorldview, UdpData::Cabs(all_cabs));
     udp_handler.send(&master_adress, &message);
 }
 
 
+/// send_own_orders
+/// Send the cab of this node as ErrorWorldview, so the master adds the orders it lacks to this cab, see correct_master_worldview.
+/// Only this cab is sent, the copies of the other cabs here may hold orders they have served.
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends the message.
+/// * `master_address` - Option<SocketAddr> - the master, None broadcasts when it is not known who the master is.
+///
+/// # Returns:
+///
+/// Returns - bool - true if the message was sent.
+///
+pub fn send_own_orders(state: &Arc<SystemState>, udp_handler: &UdpHandler, master_address: Option<SocketAddr>) -> bool {
+    let me = match state.my_cab() {
+        Some(me) => me,
+        None => return false,
+    };
+    let message = make_udp_msg(me.id, MessageType::ErrorWorldview, UdpData::Cabs(vec![me]));
+    return match master_address {
+        Some(master_address) => udp_handler.send(&master_address, &message),
+        None => udp_handler.broadcast(&message),
+    };
+}
+
 /// Check for worldview, no update in given time 5s?, assumes dead master and starts master election
 /// 
 /// # Arguments:
@@ -250,37 +285,32 @@ pub fn notify_worldview_error(sender_id: u8 ,master_adress: SocketAddr, state: &
 ///
 pub fn check_master_failure(state: &Arc<SystemState>, udp_handler: &UdpHandler) {
     
+    //Get master and time of last lifesign in one look, so they belong together
+    let (master_id, last_lifesign, known_elevators) = state.read(|s| (s.master_id, s.lifesign_master, s.known_elevators.clone()));
+
     //If i am the master, i am alive
-    if state.me_id == state.master_id.lock().unwrap().clone(){
+    if state.me_id == master_id{
         return;
-    }      
-
-    //Get time of last lifesign
-    let last_lifesign_locked = state.lifesign_master.lock().unwrap();
-    
+    }
 
     //Check age of new lifesign
-    if  last_lifesign_locked.elapsed() > Duration::from_millis(3000) {
+    if  elapsed_since(last_lifesign) > Duration::from_millis(3000) {
         println!("No lifesign from master recived from Master in last 3sec, electing new master");
         //ITERATE THROUGH ELEVATORS, SET OLD MASTER TO DEAD AND RETRIEVE THE CAB STRUCT THAT WAS THE MASTER
 
-        let master_id = state.master_id.lock().unwrap();
-
         //BROADCAST DEATH OF THE MASTER
-        let known_elevators = state.known_elevators.lock().unwrap();
-        let dead_elevator: Vec<Cab> = known_elevators.iter().filter(|cab| cab.id == *master_id).cloned().collect();
-        let msg = make_udp_msg(state.me_id, MessageType::ErrorOffline, UdpData::Cab(dead_elevator.get(0).unwrap().clone()));
-        for elevator in known_elevators.iter(){
-            udp_handler.send(&elevator.inn_address, &msg);
+        if let Some(dead_elevator) = known_elevators.iter().find(|cab| cab.id == master_id) {
+            let msg = make_udp_msg(state.me_id, MessageType::ErrorOffline, UdpData::Cab(dead_elevator.clone()));
+            for elevator in known_elevators.iter(){
+                udp_handler.send(&elevator.inn_address, &msg);
+            }
         }
-
     }
-    drop(last_lifesign_locked);
 
-    let last_lifesign_locked = state.lifesign_master.lock().unwrap();
-    if  last_lifesign_locked.elapsed() > Duration::from_millis(10000){
-        let known_elevators_cloned = state.known_elevators.lock().unwrap().clone();
-        set_new_master(&mut known_elevators_cloned.get(0).unwrap().clone(), &state);
+    if  elapsed_since(last_lifesign) > Duration::from_millis(10000){
+        if let Some(me) = known_elevators.first() {
+            set_new_master(&mut me.clone(), state);
+        }
     }
 }
 
@@ -299,28 +329,24 @@ pub fn check_master_failure(state: &Arc<SystemState>, udp_handler: &UdpHandler)
 pub fn set_new_master(new_master: &mut Cab, state: &Arc<SystemState>){
     println!("Entered set new master");
 
-    let old_master_id = state.master_id.lock().unwrap().clone();
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-
-    //MAKE SET OLD MASTER SLAVE
-    if let Some(old_master) = known_elevators_locked.iter_mut().find(|e| e.id == old_master_id) {
-        old_master.role = Role::Slave;
-        println!("Old master (ID: {}) set to Slave.", old_master.id);
-    }
+    let new_master_id = new_master.id;
+    state.update(move |s| {
+        //MAKE SET OLD MASTER SLAVE
+        let old_master_id = s.master_id;
+        if let Some(old_master) = s.known_elevators.iter_mut().find(|e| e.id == old_master_id) {
+            old_master.role = Role::Slave;
+            println!("Old master (ID: {}) set to Slave.", old_master.id);
+        }
 
-    //Find master id
-    let mut master_id_locked = state.master_id.lock().unwrap();
-    *master_id_locked =new_master.id;
-    drop(master_id_locked);
+        s.master_id = new_master_id;
 
-    if let Some(new_master_cab) = known_elevators_locked.iter_mut().find(|e|e.id == new_master.id){
-        new_master_cab.role = Role::Master;
-        println!("New master (ID: {}).", new_master_cab.id);
-        drop(known_elevators_locked);
-    } else {
-        println!("ERROR: New master is not an active elevator");
-    }  
-    
+        if let Some(new_master_cab) = s.known_elevators.iter_mut().find(|e|e.id == new_master_id){
+            new_master_cab.role = Role::Master;
+            println!("New master (ID: {}).", new_master_cab.id);
+        } else {
+            println!("ERROR: New master is not an active elevator");
+        }
+    });
 }
 
 /// Starts a new instance and kills the old instance of the program
@@ -341,45 +367,46 @@ pub fn reboot_program(){
 }
 
 
-pub fn send_new_online(state: &Arc<SystemState>) -> bool {
-
-    // Lock 
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
+pub fn send_new_online(state: &Arc<SystemState>, udp_handler: &UdpHandler) -> bool {
 
-    //Find this elevator in systemstate
-    if let Some(this_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == state.me_id) {
-        //ensure alive
+    //Find this elevator in systemstate and ensure alive
+    let me_id = state.me_id;
+    let this_elevator = state.update(move |s| {
+        let this_elevator = s.known_elevators.iter_mut().find(|e| e.id == me_id)?;
         this_elevator.alive = true;
+        return Some(this_elevator.clone());
+    });
+
+    if let Some(this_elevator) = this_elevator {
         // Create UdpMsg
         let data = UdpData::Cab(this_elevator.clone());
         let msg = make_udp_msg(this_elevator.id, MessageType::NewOnline, data);
         println!("Creating NewOnline from function");
         // Broadcast the message to notify others that this elevator is online
-        return udp_broadcast(&msg);
+        return udp_handler.broadcast(&msg);
     } 
     return false;
 }
 
-pub fn send_error_offline(state: &Arc<SystemState>) -> bool {
-
-    // Lock 
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-
-    //Find this elevator in systemstate
-    if let Some(my_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == state.me_id) {
+pub fn send_error_offline(state: &Arc<SystemState>, udp_handler: &UdpHandler) -> bool {
 
-        //Set dead
+    //Find this elevator in systemstate, set it dead and empty my queue of all orders that are not cab
+    let me_id = state.me_id;
+    let my_elevator = state.update(move |s| {
+        let my_elevator = s.known_elevators.iter_mut().find(|e| e.id == me_id)?;
         my_elevator.alive = false;
+        let reported = my_elevator.clone();
+        my_elevator.queue.retain(|o| o.order_type == CAB);
+        return Some(reported);
+    });
+
+    if let Some(my_elevator) = my_elevator {
         // Create UdpMsg
         let data = UdpData::Cab(my_elevator.clone());
         let msg = make_udp_msg(my_elevator.id, MessageType::ErrorOffline, data);
-        //Empty my queue of all orders taht are not cab
-        if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == state.me_id) {
-            elevator.queue.retain(|o| o.order_type == CAB);
-        }
 
         // Broadcast the message to notify others that this elevator is going offline
-        return udp_broadcast(&msg);
+        return udp_handler.broadcast(&msg);
     } else {
         println!(
             "ERROR: Elevator with ID {} not found in known_elevators. Cannot send ErrorOffline, Rebooting",
@@ -389,4 +416,114 @@ pub fn send_error_offline(state: &Arc<SystemState>) -> bool {
         reboot_program();
         return false;
     }
-}
\ No newline at end of file
+}
+
+/// set_maintenance
+/// Takes this cab out of service for hall calls or puts it back in service. Going into maintenance the hall
+/// orders are handed off with RemoveOrder and the master finds new elevators for them, the cab orders are kept.
+/// The other nodes see the mode in `maintenance` of the cab, in lifesigns and worldviews.
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends ImAlive and RemoveOrder.
+/// * `on` - bool - 'true' to go into maintenance, 'false' to leave it.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - passed on to reassign_orders if this node is master.
+///
+/// # Returns:
+///
+/// Returns - bool - returns 'true' if the mode changed, 'false' if the cab already was in that mode.
+///
+pub fn set_maintenance(state: &Arc<SystemState>, udp_handler: &UdpHandler, on: bool, order_update_tx: cbc::Sender<Vec<Order>>) -> bool {
+    let me = state.update(move |s| {
+        let me = s.known_elevators.get_mut(0)?;
+        if me.maintenance == on {
+            return None;
+        }
+        me.maintenance = on;
+        return Some(me.clone());
+    });
+
+    let me = match me {
+        Some(me) => me,
+        None => return false,
+    };
+
+    if on {
+        println!("Going into maintenance, handing off hall orders");
+    } else {
+        println!("Leaving maintenance, taking hall calls again");
+    }
+    send_to_all(state, udp_handler, &make_udp_msg(me.id, MessageType::ImAlive, UdpData::Cab(me)));
+
+    if on {
+        give_away_hall_orders(state, udp_handler, order_update_tx);
+    }
+    return true;
+}
+
+/// request_maintenance
+/// Asks another elevator to go into or leave maintenance with a Maintenance message.
+///
+/// # Arguments:
+///
+/// * `state` - &Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends the message.
+/// * `target_id` - u8 - ID of the elevator that should change mode.
+/// * `on` - bool - 'true' for maintenance, 'false' to put it back in service.
+///
+/// # Returns:
+///
+/// Returns - bool - returns 'true' if the message was sent, 'false' if the elevator is unknown or sending failed.
+///
+pub fn request_maintenance(state: &Arc<SystemState>, udp_handler: &UdpHandler, target_id: u8, on: bool) -> bool {
+    let target = match state.known_elevators().into_iter().find(|e| e.id == target_id) {
+        Some(target) => target,
+        None => {
+            println!("ERROR: Elevator ID:{} not known, cannot request maintenance", target_id);
+            return false;
+        }
+    };
+
+    // The cab in the message names the elevator and the wanted mode
+    let mut request = target.clone();
+    request.maintenance = on;
+    request.queue.clear();
+
+    let msg = make_udp_msg(state.me_id, MessageType::Maintenance, UdpData::Cab(request));
+    return udp_handler.send(&target.inn_address, &msg);
+}
+
+/// Take the hall orders out of the own queue and tell every elevator with RemoveOrder, the master reassigns them
+pub fn give_away_hall_orders(state: &Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let given_away = state.update(|s| {
+        let me = s.known_elevators.get_mut(0)?;
+        let hall_orders: Vec<Order> = me.queue.iter().filter(|order| order.order_type != CAB).cloned().collect();
+        me.queue.retain(|order| order.order_type == CAB);
+
+        // The queue of the message holds the orders given away
+        let mut given_away = me.clone();
+        given_away.queue = hall_orders;
+        return Some((given_away, s.master_id));
+    });
+
+    let (given_away, master_id) = match given_away {
+        Some(found) if !found.0.queue.is_empty() => found,
+        _ => return,
+    };
+    let hall_orders = given_away.queue.clone();
+
+    send_to_all(state, udp_handler, &make_udp_msg(state.me_id, MessageType::RemoveOrder, UdpData::Cab(given_away)));
+
+    // The master does not reassign its own RemoveOrder, see handle_remove_order
+    if master_id == state.me_id {
+        reassign_orders(&hall_orders, state, udp_handler, order_update_tx);
+    }
+}
+
+/// Send a message to every known elevator one by one
+pub fn send_to_all(state: &Arc<SystemState>, udp_handler: &UdpHandler, msg: &UdpMsg) {
+    for elevator in state.known_elevators().iter() {
+        udp_handler.send(&elevator.inn_address, msg);
+    }
+}
@@ -0,0 +1,215 @@
+//! ## System Clock Module
+//! This module wraps the time sources used by the rest of the system, so the simulator can replace them with a virtual clock.
+//!
+//! ## The structs includes:
+//! - **VirtualClock**: A clock that only moves when it is advanced, shared by every thread in the process.
+//!
+//! ## The functions includes:
+//! - 'now'                      returns the current `Instant`, real or virtual.
+//! - 'system_now'               returns the current `SystemTime`, real or virtual.
+//! - 'elapsed_since'            time passed since an `Instant` taken from 'now'.
+//! - 'sleep'                    sleeps the thread in real or virtual time.
+//! - 'recv_timeout'             waits for a message on a channel, the timeout in real or virtual time.
+//! - 'track'                    hands a thread to the virtual clock so it is joined when the clock stops.
+//! - 'clock_stopped'            tells a thread of a simulated node to return.
+//! - 'install_virtual_clock'    makes every function above use a new virtual clock.
+//! - 'uninstall_virtual_clock'  goes back to the real clock.
+//!
+//! When no virtual clock is installed every function just calls the standard library,
+//! so the running system behaves exactly as if it used `std::time` directly.
+//!
+//! A virtual clock is stopped when the simulation ends: every sleep and receive on it returns at once,
+//! the looping threads see `clock_stopped` and return, and `join_threads` waits for the tracked ones.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam_channel as cbc;
+
+// The clock currently in use, None means real time
+static VIRTUAL_CLOCK: RwLock<Option<Arc<VirtualClock>>> = RwLock::new(None);
+
+// Real time between two looks at the virtual clock while waiting for a message
+const RECV_POLL: Duration = Duration::from_millis(1);
+
+/// A clock that is moved forward manually, used to run the system in simulated time
+#[derive(Debug)]
+pub struct VirtualClock {
+    start_instant: Instant,     // Real instant when the clock was made, virtual time is counted from here
+    start_system: SystemTime,   // Real system time when the clock was made
+    elapsed: Mutex<Duration>,   // Virtual time passed since start
+    tick: Condvar,              // Wakes sleeping threads when the clock is advanced
+    stopped: AtomicBool,        // The simulation has ended, nothing waits on the clock any more
+    threads: Mutex<Vec<JoinHandle<()>>>, // Threads of the simulated nodes, joined when the clock stops
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock {
+            start_instant: Instant::now(),
+            start_system: SystemTime::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+            tick: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Move the clock forward and wake every thread sleeping on it
+    pub fn advance(&self, step: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed += step;
+        drop(elapsed);
+        self.tick.notify_all();
+    }
+
+    /// Virtual time passed since the clock was made
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+
+    pub fn system_now(&self) -> SystemTime {
+        self.start_system + self.elapsed()
+    }
+
+    /// Block until the clock has been advanced by at least `duration` or has been stopped
+    pub fn sleep(&self, duration: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        let wake_at = *elapsed + duration;
+        while *elapsed < wake_at && !self.is_stopped() {
+            elapsed = self.tick.wait(elapsed).unwrap();
+        }
+    }
+
+    /// Stop the clock, every thread sleeping on it wakes and every later sleep returns at once
+    pub fn stop(&self) {
+        let elapsed = self.elapsed.lock().unwrap();
+        self.stopped.store(true, Ordering::SeqCst);
+        drop(elapsed);
+        self.tick.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// join_threads
+    /// Wait for the tracked threads to return, call it after `stop`
+    ///
+    /// # Arguments:
+    ///
+    /// * `timeout` - Duration - real time to wait before giving up on the threads that are left.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - usize - the number of threads still running, 0 if every thread was joined.
+    ///
+    pub fn join_threads(&self, timeout: Duration) -> usize {
+        let give_up_at = Instant::now() + timeout;
+        loop {
+            // Threads may start others while they stop, so the list is looked at again every round
+            let mut threads = self.threads.lock().unwrap();
+            let (finished, running): (Vec<_>, Vec<_>) = threads.drain(..).partition(|handle| handle.is_finished());
+            *threads = running;
+            let left = threads.len();
+            drop(threads);
+
+            for handle in finished {
+                let _ = handle.join();
+            }
+            if left == 0 || Instant::now() >= give_up_at {
+                return left;
+            }
+            thread::sleep(RECV_POLL);
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replace the real clock with a new virtual clock, returns the clock so it can be advanced
+pub fn install_virtual_clock() -> Arc<VirtualClock> {
+    let clock = Arc::new(VirtualClock::new());
+    *VIRTUAL_CLOCK.write().unwrap() = Some(Arc::clone(&clock));
+    clock
+}
+
+/// Go back to real time
+pub fn uninstall_virtual_clock() {
+    *VIRTUAL_CLOCK.write().unwrap() = None;
+}
+
+fn virtual_clock() -> Option<Arc<VirtualClock>> {
+    VIRTUAL_CLOCK.read().unwrap().clone()
+}
+
+/// Current instant, use this instead of `Instant::now()`
+pub fn now() -> Instant {
+    match virtual_clock() {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    }
+}
+
+/// Current system time, use this instead of `SystemTime::now()`
+pub fn system_now() -> SystemTime {
+    match virtual_clock() {
+        Some(clock) => clock.system_now(),
+        None => SystemTime::now(),
+    }
+}
+
+/// Time passed since `earlier`, use this instead of `Instant::elapsed()`
+pub fn elapsed_since(earlier: Instant) -> Duration {
+    now().saturating_duration_since(earlier)
+}
+
+/// Sleep the current thread, use this instead of `thread::sleep()`
+pub fn sleep(duration: Duration) {
+    match virtual_clock() {
+        Some(clock) => clock.sleep(duration),
+        None => thread::sleep(duration),
+    }
+}
+
+/// Wait at most `max_wait` for a message on `rx`, use this instead of `Receiver::recv_timeout()`.
+/// Returns None on a timeout, when every sender is gone or when the virtual clock has been stopped
+pub fn recv_timeout<T>(rx: &cbc::Receiver<T>, max_wait: Duration) -> Option<T> {
+    let clock = match virtual_clock() {
+        Some(clock) => clock,
+        None => return rx.recv_timeout(max_wait).ok(),
+    };
+
+    let wake_at = clock.elapsed() + max_wait;
+    loop {
+        match rx.recv_timeout(RECV_POLL) {
+            Ok(message) => return Some(message),
+            Err(cbc::RecvTimeoutError::Disconnected) => return None,
+            Err(cbc::RecvTimeoutError::Timeout) => {}
+        }
+        if clock.is_stopped() || clock.elapsed() >= wake_at {
+            return None;
+        }
+    }
+}
+
+/// Hand a thread of a node to the virtual clock so the simulation can join it, the real clock lets it run on its own
+pub fn track(thread: JoinHandle<()>) {
+    if let Some(clock) = virtual_clock() {
+        clock.threads.lock().unwrap().push(thread);
+    }
+}
+
+/// True once the virtual clock has been stopped, a looping thread of a node then returns. Always false in real time
+pub fn clock_stopped() -> bool {
+    virtual_clock().is_some_and(|clock| clock.is_stopped())
+}
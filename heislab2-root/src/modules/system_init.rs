@@ -4,18 +4,36 @@ use std::env;                                           //https://doc.rust-lang.
 use std::fs::File;                                      //https://doc.rust-lang.org/std/fs/struct.File.html
 use std::io::{BufWriter, Write, BufRead, BufReader};    //https://doc.rust-lang.org/std/io/trait.BufRead.html
 use std::path::PathBuf;                                 //https://doc.rust-lang.org/std/path/struct.PathBuf.html
-use std::sync::{Mutex,Arc};
-use std::time::{Duration,Instant};
+use std::time::Duration;
+use crate::modules::system_clock::now;
 
-use crate::modules::system_status::SystemState;
+use crate::modules::system_status::{NodeState, SystemState};
 use crate::modules::udp_functions::udp::{UdpMsg,UdpHeader,UdpData,MessageType};
 use crate::modules::udp_functions::udp::calc_checksum;
+use crate::modules::udp_functions::fault_injection::FaultConfig;
+use crate::modules::udp_functions::transport::TransportConfig;
+use crate::modules::udp_functions::codec::CodecConfig;
+use crate::modules::master_functions::parking::ParkingPolicy;
+use crate::modules::master_functions::traffic::{DispatchMode, TrafficConfig};
+use crate::modules::master_functions::lease::{LeaseConfig, MasterLease};
+use crate::modules::isolation::{Isolation, IsolationConfig};
+use crate::modules::master_functions::worldview_delta::WorldviewLog;
+use crate::modules::cab_object::cab::parse_floors;
+use crate::modules::cab_object::elevator_load::LoadConfig;
+use crate::modules::cab_object::elevator_door::DoorConfig;
+use crate::modules::peer_functions::peer::{Coordination, PeerConfig};
 
 pub fn boot() -> SystemState {
 
     //Get config from "boot.txt"
     let (me_id_value, default_master_id) = load_config();
 
+    init_system_state(me_id_value, default_master_id)
+}
+
+/// Generate the system state of a node without reading "boot.txt", used by boot() and the simulator
+pub fn init_system_state(me_id_value: u8, default_master_id: u8) -> SystemState {
+
     //Just a dummy/filler message
     let dummy_data = UdpData::Checksum(1);
     let checksum=calc_checksum(&dummy_data);
@@ -29,66 +47,281 @@ pub fn boot() -> SystemState {
     };
 
     // Set an old lifesign, this will trigger update of master
-    let old_lifesign = Instant::now() - Duration::from_secs(10);
+    let old_lifesign = now() - Duration::from_secs(10);
 
-    // Generate a system state
-    SystemState {
+    // Generate a system state and hand it to its own thread
+    SystemState::spawn(NodeState {
         me_id: me_id_value,
-        master_id: Arc::new(Mutex::new(default_master_id)),
-        lifesign_master: Arc::new(Mutex::new(old_lifesign)), 
-        last_worldview: Arc::new(Mutex::new(starting_udpmsg)),
-        known_elevators: Arc::new(Mutex::new(Vec::new())),
-        all_orders: Arc::new(Mutex::new(Vec::new())),
-        sent_messages: Arc::new(Mutex::new(Vec::new())),
-    }
+        master_id: default_master_id,
+        lifesign_master: old_lifesign,
+        last_worldview: starting_udpmsg,
+        known_elevators: Vec::new(),
+        all_orders: Vec::new(),
+        unservable: Vec::new(),
+        sent_messages: Vec::new(),
+        hall_calls: Vec::new(),
+        dispatch_mode: DispatchMode::Balanced,
+        traffic: TrafficConfig::default(),
+        coordination: Coordination::Master,
+        hall_requests: Vec::new(),
+        lease: MasterLease::default(),
+        isolation: Isolation::init(),
+        worldview_log: WorldviewLog::default(),
+    })
 }
 
-pub fn load_config() -> (u8, u8) {
-
-    // Find "boot.txt" in the parentfolder of the program
+// Find "boot.txt" in the parentfolder of the program
+fn config_path() -> PathBuf {
     let exe_path = env::current_exe().expect("Failed to find path");
     let exe_dir = exe_path.parent().expect("Failed to get directory");
-    let config_path: PathBuf = exe_dir.join("boot.txt");
-
-    if config_path.exists() {
-        let file = File::open(&config_path).expect("Couldn't open boot.txt");
-        let reader = BufReader::new(file);
-
-        let mut me_id = 0;
-        let mut master_id = 0;
-
-        for line in reader.lines() {
-            let line = line.expect("Failed to read line");
-            if let Some((key, value)) = line.split_once(':') {
-                // match key to variable 
-                match key.trim() {
-                    "me_id" => me_id = value.trim().parse().unwrap_or(0),
-                    "master_id" => master_id = value.trim().parse().unwrap_or(0),
-                    _ => {}
-                }
+    return exe_dir.join("boot.txt");
+}
+
+/// for_each_key
+/// Read "boot.txt" once and hand every "key: value" line with one of the given keys to `parse`
+///
+/// # Arguments:
+///
+/// * `keys` - &[&str] - whole keys, a key ending in '_' like "door_" stands for every key starting with it.
+/// * `parse` - FnMut(&str, &str) -> bool - gets the trimmed key and value, false if the value could not be read.
+///
+/// # Returns:
+///
+/// Returns - bool - false if there is no "boot.txt" to read.
+///
+fn for_each_key(keys: &[&str], mut parse: impl FnMut(&str, &str) -> bool) -> bool {
+
+    let file = match File::open(config_path()) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim();
+            let wanted = keys.iter().any(|wanted| *wanted == key || (wanted.ends_with('_') && key.starts_with(wanted)));
+            if wanted && !parse(key, value.trim()) {
+                println!("Could not read {} in boot.txt", line);
             }
         }
+    }
+    return true;
+}
 
-    return (me_id, master_id);
-    }else {
+pub fn load_config() -> (u8, u8) {
+
+    let mut me_id = 0;
+    let mut master_id = 0;
+    let found = for_each_key(&["me_id", "master_id"], |key, value| {
+        // match key to variable 
+        match key {
+            "me_id" => me_id = value.parse().unwrap_or(0),
+            "master_id" => master_id = value.parse().unwrap_or(0),
+            _ => {}
+        }
+        return true;
+    });
 
-        println!("boot.txt not found, creating one with default values");
+    if found {
+        return (me_id, master_id);
+    }
 
-        let file = File::create(&config_path).expect("Failed to create boot.txt");
-        let mut info = BufWriter::new(file);
+    println!("boot.txt not found, creating one with default values");
 
-        // Set default values
-        let me_id = 5;
-        let master_id = 6;
+    let file = File::create(config_path()).expect("Failed to create boot.txt");
+    let mut info = BufWriter::new(file);
 
-        writeln!(info, "me_id: {}", me_id).expect("Failed to write to boot.txt");
-        writeln!(info, "master_id: {}", master_id).expect("Failed to write to boot.txt");
+    // Set default values
+    let me_id = 5;
+    let master_id = 6;
 
-        return (me_id, master_id);
+    writeln!(info, "me_id: {}", me_id).expect("Failed to write to boot.txt");
+    writeln!(info, "master_id: {}", master_id).expect("Failed to write to boot.txt");
+
+    return (me_id, master_id);
+}
+
+/// load_fault_config
+/// Read the "fault_" keys from "boot.txt", see fault_injection.rs
+///
+/// # Returns:
+///
+/// Returns - Option<FaultConfig> - the faults to inject, None if no fault is turned on.
+///
+pub fn load_fault_config() -> Option<FaultConfig> {
+
+    let mut faults = FaultConfig::default();
+    for_each_key(&["fault_"], |key, value| faults.parse(key, value));
+
+    if faults.is_active() {
+        return Some(faults);
     }
+    return None;
+}
+
+/// load_duration
+/// Read a time in seconds from "boot.txt", like "travel_timeout: 4.5"
+///
+/// # Arguments:
+///
+/// * `key` - &str - the text before ':'.
+/// * `default` - Duration - used if "boot.txt" or the key is missing or can't be read.
+///
+/// # Returns:
+///
+/// Returns - Duration - the time found in "boot.txt" or `default`.
+///
+pub fn load_duration(key: &str, default: Duration) -> Duration {
+
+    // The first line that can be read counts
+    let mut duration = None;
+    for_each_key(&[key], |_, value| {
+        if duration.is_none() {
+            // Negative, infinite and too large times can't be read, from_secs_f64 would panic on them
+            duration = value.parse::<f64>().ok()
+                .filter(|seconds| seconds.is_finite())
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+        }
+        return duration.is_some();
+    });
+    return duration.unwrap_or(default);
+}
+
+/// load_flag
+/// Read a yes/no setting from "boot.txt", like "maintenance: true"
+///
+/// # Arguments:
+///
+/// * `key` - &str - the text before ':'.
+/// * `default` - bool - used if "boot.txt" or the key is missing or can't be read.
+///
+/// # Returns:
+///
+/// Returns - bool - the setting found in "boot.txt" or `default`.
+///
+pub fn load_flag(key: &str, default: bool) -> bool {
 
+    // The first line that can be read counts
+    let mut flag = None;
+    for_each_key(&[key], |_, value| {
+        if flag.is_none() {
+            flag = value.parse().ok();
+        }
+        return flag.is_some();
+    });
+    return flag.unwrap_or(default);
+}
+
+/// Read the parking policy from "boot.txt", see parking.rs for the keys. Off if there are none.
+pub fn load_parking_policy() -> ParkingPolicy {
+
+    let mut policy = ParkingPolicy::default();
+    for_each_key(&["parking", "parking_"], |key, value| policy.parse(key, value));
+    return policy;
+}
+
+/// Read how the master picks its dispatch mode from "boot.txt", see traffic.rs for the keys
+pub fn load_traffic_config() -> TrafficConfig {
+
+    let mut traffic = TrafficConfig::default();
+    for_each_key(&["dispatch_"], |key, value| traffic.parse(key, value));
+    return traffic;
+}
+
+/// Read the load sensor settings from "boot.txt", see elevator_load.rs for the keys. No sensor if there are none.
+pub fn load_load_config() -> LoadConfig {
+
+    let mut load = LoadConfig::default();
+    for_each_key(&["load_"], |key, value| load.parse(key, value));
+    return load;
+}
+
+/// Read how long the door stays open from "boot.txt", see elevator_door.rs for the keys
+pub fn load_door_config() -> DoorConfig {
+
+    let mut door = DoorConfig::default();
+    for_each_key(&["door_"], |key, value| door.parse(key, value));
+    return door;
+}
+
+/// Read how messages to every node are sent from "boot.txt", see transport.rs for the keys. Broadcast if there are none.
+pub fn load_transport_config() -> TransportConfig {
+
+    let mut transport = TransportConfig::default();
+    for_each_key(&["transport", "transport_"], |key, value| transport.parse(key, value));
+    return transport;
+}
+
+/// Read the coordination and the gossip settings from "boot.txt", see peer.rs for the keys. A master if there are none.
+pub fn load_peer_config() -> PeerConfig {
+
+    let mut peer = PeerConfig::default();
+    for_each_key(&["coordination", "peer_"], |key, value| peer.parse(key, value));
+    return peer;
+}
+
+/// load_number
+/// Read a whole number from "boot.txt", like "num_floors: 6"
+///
+/// # Arguments:
+///
+/// * `key` - &str - the text before ':'.
+/// * `default` - u8 - used if "boot.txt" or the key is missing or can't be read.
+///
+/// # Returns:
+///
+/// Returns - u8 - the number found in "boot.txt" or `default`.
+///
+pub fn load_number(key: &str, default: u8) -> u8 {
+
+    // The first line that can be read counts
+    let mut number = None;
+    for_each_key(&[key], |_, value| {
+        if number.is_none() {
+            number = value.parse().ok();
+        }
+        return number.is_some();
+    });
+    return number.unwrap_or(default);
+}
 
+/// Read the floors this cab stops at from "boot.txt", like "served_floors: 0,2-5". None means every floor.
+pub fn load_served_floors(num_floors: u8) -> Option<Vec<u8>> {
+
+    // Only the first line counts, every floor if it can't be read
+    let mut floors = None;
+    let mut seen = false;
+    for_each_key(&["served_floors"], |_, value| {
+        if seen {
+            return true;
+        }
+        seen = true;
+        floors = parse_floors(value, num_floors);
+        return floors.is_some();
+    });
+    return floors;
+}
+
+/// Read the quorum and the duration of the master lease from "boot.txt", see lease.rs for the keys. The defaults if there are none.
+pub fn load_lease_config() -> LeaseConfig {
+
+    let mut lease = LeaseConfig::default();
+    for_each_key(&["lease_"], |key, value| lease.parse(key, value));
+    return lease;
 }
 
+/// Read how long the node waits for another elevator from "boot.txt", see isolation.rs for the key. 3 s if it is not there.
+pub fn load_isolation_config() -> IsolationConfig {
+
+    let mut isolation = IsolationConfig::default();
+    for_each_key(&["isolation_"], |key, value| isolation.parse(key, value));
+    return isolation;
+}
 
+/// Read the codec messages are sent in from "boot.txt", see codec.rs for the key. Bincode if it is not there.
+pub fn load_codec_config() -> CodecConfig {
 
+    let mut codec = CodecConfig::default();
+    for_each_key(&["wire_codec"], |key, value| codec.parse(key, value));
+    return codec;
+}
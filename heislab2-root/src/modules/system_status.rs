@@ -1,25 +1,225 @@
+//! ## System State Module
+//! The state of a node is owned by one thread, the state thread. Every other thread talks
+//! to it by sending a job over a channel and waiting for the answer, so no locks are shared
+//! between threads and the order locks are taken in can't deadlock the node.
+//!
+//! ## The structs includes:
+//! - **NodeState**: Everything a node knows about itself and the other elevators.
+//! - **SystemState**: Handle to the state thread, cheap to clone and pass to other threads.
+//! - **WaitingConfirmation**: A sent message waiting for acks.
+//!
+//! ## The functions includes:
+//! - 'spawn'    starts the state thread owning a `NodeState`.
+//! - 'update'   runs a job with write access on the state thread and returns its result.
+//! - 'read'     runs a job with read access on the state thread and returns its result.
+//! - 'apply'    changes the state for one `StateCommand` of the main loop, see apply_command in node.rs.
+//!
+//! The state thread matches every piece of work it gets: a job, or a command of the state machine of the node.
+//! The state machine returns what the node must do afterwards, the elevator and the network are used by the caller.
+//! Jobs must not touch the handle themselves, that would wait on the thread running them.
+//! Do the network work after the job returns, with the values it gave back.
+//! A check and the change it leads to go in one job, other jobs can run between two jobs in a row,
+//! see take_call in node.rs, handle_new_request and give_order.
+
 use crate::modules::order_object::order_init::Order;
 use crate::modules::cab_object::cab::Cab;
 use crate::modules::udp_functions::udp::UdpMsg;
+use crate::modules::master_functions::traffic::{DispatchMode, TrafficConfig};
+use crate::modules::peer_functions::peer::{Coordination, HallRequest};
+use crate::modules::master_functions::lease::MasterLease;
+use crate::modules::master_functions::worldview_delta::WorldviewLog;
+use crate::modules::isolation::Isolation;
+use crate::modules::system_clock::track;
+use crate::modules::node::{apply_command, NodeCommand, StateCommand};
 
-use std::sync::{Arc, Mutex};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
 use std::time::Instant;
 
+use crossbeam_channel as cbc;
+use serde::{Deserialize, Serialize};
+
+/// Everything a node knows, only the state thread can touch it
+#[derive(Clone, Debug)]
+pub struct NodeState {
+    pub me_id: u8,
+    pub master_id: u8,
+    pub lifesign_master: Instant,
+    pub last_worldview: UdpMsg,
+    pub known_elevators: Vec<Cab>,              // Index 0 is always this node
+    pub all_orders: Vec<Order>,
+    pub unservable: Vec<Order>,                 // Hall orders in all_orders no alive cab stops for, see set_aside_unservable
+    pub sent_messages: Vec<WaitingConfirmation>,
+    pub hall_calls: Vec<(Instant, Order)>,      // Recent hall calls and when they came in, see traffic.rs
+    pub dispatch_mode: DispatchMode,
+    pub traffic: TrafficConfig,
+    pub coordination: Coordination,             // Master and slaves, or peers, see peer.rs
+    pub hall_requests: Vec<HallRequest>,        // The hall requests shared by the peers, empty with a master
+    pub lease: MasterLease,                     // The lease of this node while it is master, see lease.rs
+    pub isolation: Isolation,                   // When another elevator was last heard, see isolation.rs
+    pub worldview_log: WorldviewLog,            // The last worldview update sent or received, see worldview_delta.rs
+}
+
+type StateJob = Box<dyn FnOnce(&mut NodeState) + Send>;
+
+// One piece of work for the state thread
+enum Work {
+    Job(StateJob),
+    Command(StateCommand, cbc::Sender<thread::Result<Vec<NodeCommand>>>),  // With where to send the result
+}
+
+/// Handle to the state thread of a node
 #[derive(Clone, Debug)]
 pub struct SystemState {
     pub me_id : u8,
-    pub master_id: Arc<Mutex<u8>>,
-    pub lifesign_master: Arc<Mutex<Instant>>,
-    pub last_worldview: Arc<Mutex<UdpMsg>>,
-    pub known_elevators: Arc<Mutex<Vec<Cab>>>,
-    pub all_orders: Arc<Mutex<Vec<Order>>>,
-    pub sent_messages: Arc<Mutex<Vec<WaitingConfirmation>>>,
+    jobs: cbc::Sender<Work>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WaitingConfirmation {
     pub message_hash: u32,
     pub responded_ids: Vec<u8>,
     pub all_confirmed: bool,
 }
 
+thread_local! {
+    static ON_STATE_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+impl SystemState {
+    /// spawn
+    /// Start the state thread, it lives until the last handle is dropped
+    ///
+    /// # Arguments:
+    ///
+    /// * `state` - NodeState - the starting state of the node.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - SystemState - a handle to the state thread.
+    ///
+    pub fn spawn(mut state: NodeState) -> SystemState {
+        let me_id = state.me_id;
+        let (jobs_tx, jobs_rx) = cbc::unbounded::<Work>();
+
+        let state_thread = thread::Builder::new()
+            .name(format!("state-{}", me_id))
+            .spawn(move || {
+                ON_STATE_THREAD.with(|flag| flag.set(true));
+                for work in jobs_rx {
+                    match work {
+                        Work::Job(job) => job(&mut state),
+                        Work::Command(command, reply_tx) => {
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| apply_command(&mut state, command)));
+                            let _ = reply_tx.send(result);
+                        }
+                    }
+                }
+            })
+            .expect("Failed to start state thread");
+        track(state_thread);
+
+        return SystemState { me_id, jobs: jobs_tx };
+    }
+
+    /// update
+    /// Run a job on the state thread with write access, the job runs alone so it sees and leaves a consistent state
+    ///
+    /// # Arguments:
+    ///
+    /// * `job` - FnOnce(&mut NodeState) -> R - the work to do, it must not use a `SystemState` itself.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - R - what the job returned, a panic in the job is passed on to the caller.
+    ///
+    pub fn update<R, F>(&self, job: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut NodeState) -> R + Send + 'static,
+    {
+        if ON_STATE_THREAD.with(|flag| flag.get()) {
+            panic!("SystemState used from inside a state job, this would deadlock");
+        }
+
+        let (reply_tx, reply_rx) = cbc::bounded(1);
+        let wrapped: StateJob = Box::new(move |state: &mut NodeState| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| job(state)));
+            let _ = reply_tx.send(result);
+        });
+        self.jobs.send(Work::Job(wrapped)).expect("State thread has stopped");
+
+        match reply_rx.recv().expect("State thread has stopped") {
+            Ok(result) => return result,
+            Err(cause) => panic::resume_unwind(cause),
+        }
+    }
+
+    /// apply
+    /// Change the state for one command of the state machine of the node, on the state thread
+    ///
+    /// # Arguments:
+    ///
+    /// * `command` - StateCommand - the change, made from an event of the elevator, the door or the network.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Vec<NodeCommand> - what the node must do afterwards, in order, a panic in the state machine is passed on to the caller.
+    ///
+    pub fn apply(&self, command: StateCommand) -> Vec<NodeCommand> {
+        if ON_STATE_THREAD.with(|flag| flag.get()) {
+            panic!("SystemState used from inside a state job, this would deadlock");
+        }
+
+        let (reply_tx, reply_rx) = cbc::bounded(1);
+        self.jobs.send(Work::Command(command, reply_tx)).expect("State thread has stopped");
+
+        match reply_rx.recv().expect("State thread has stopped") {
+            Ok(commands) => return commands,
+            Err(cause) => panic::resume_unwind(cause),
+        }
+    }
+
+    /// Run a job on the state thread with read access
+    pub fn read<R, F>(&self, job: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&NodeState) -> R + Send + 'static,
+    {
+        return self.update(move |state| job(state));
+    }
+
+    /// A copy of the whole state
+    pub fn snapshot(&self) -> NodeState {
+        return self.read(|state| state.clone());
+    }
+
+    pub fn master_id(&self) -> u8 {
+        return self.read(|state| state.master_id);
+    }
+
+    /// A copy of every known elevator, this node first
+    pub fn known_elevators(&self) -> Vec<Cab> {
+        return self.read(|state| state.known_elevators.clone());
+    }
+
+    /// A copy of the cab of this node, None before the elevator is connected
+    pub fn my_cab(&self) -> Option<Cab> {
+        return self.read(|state| state.known_elevators.first().cloned());
+    }
+
+    pub fn all_orders(&self) -> Vec<Order> {
+        return self.read(|state| state.all_orders.clone());
+    }
+
+    /// The hall orders set aside because no alive cab stops at their floor
+    pub fn unservable(&self) -> Vec<Order> {
+        return self.read(|state| state.unservable.clone());
+    }
+
+    /// The dispatch mode set by the last refresh_dispatch_mode
+    pub fn dispatch_mode(&self) -> DispatchMode {
+        return self.read(|state| state.dispatch_mode);
+    }
+}
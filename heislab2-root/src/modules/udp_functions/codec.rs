@@ -0,0 +1,135 @@
+//! ## Codec Module
+//! How the UdpMsg after the header of a datagram is written. Bincode is small and is the default,
+//! JSON can be read in Wireshark or tcpdump and is meant for a debug cluster where every node sets it.
+//! The codec is a flag in the version byte of the header, so a node reads datagrams in both codecs
+//! whatever it sends in itself.
+//!
+//! ## The structs includes:
+//! - **Codec**: Bincode or JSON, encodes and decodes any serde type.
+//! - **CodecConfig**: The codec this node sends in.
+//!
+//! ## The functions includes:
+//! - 'encode'          writes a value in the codec.
+//! - 'decode'          reads a value written in the codec.
+//! - 'flag'            the bits the codec sets in the version byte.
+//! - 'split_version'   the codec and the protocol version in a version byte.
+//! - 'parse'           reads one "key: value" line from "boot.txt" into a `CodecConfig`.
+//!
+//! - Checksums are always calculated on the bincode of the data, `CHECKSUM_CODEC`, so they are the same in both codecs.
+//! - Nodes older than the codec take a JSON datagram as an unknown protocol version and drop it.
+//! - A JSON message is several times larger than its bincode, keep a debug cluster small so worldviews fit in a datagram.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! wire_codec: bincode             "bincode" (default) or "json"
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const CODEC_FLAG_JSON: u8 = 0x80;           // Set in the version byte of a JSON datagram
+pub const CHECKSUM_CODEC: Codec = Codec::Bincode; // The codec checksums are calculated on
+
+/// How the message after the header is written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Codec {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// The codec this node sends in, see the keys above
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CodecConfig {
+    pub codec: Codec,
+}
+
+impl Codec {
+    /// encode
+    /// Write a value in this codec
+    ///
+    /// # Arguments:
+    ///
+    /// * `value` - &T - any serde type, a UdpMsg or its data.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Result<Vec<u8>, String> - the bytes, or why the value could not be written.
+    ///
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        return match self {
+            Codec::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            Codec::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        };
+    }
+
+    /// decode
+    /// Read a value written by encode in this codec
+    ///
+    /// # Arguments:
+    ///
+    /// * `bytes` - &[u8] - the bytes.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Result<T, String> - the value, or why the bytes could not be read.
+    ///
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+        return match self {
+            Codec::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+        };
+    }
+
+    /// The bits this codec sets in the version byte, none for bincode so its datagrams are as before
+    pub fn flag(&self) -> u8 {
+        return match self {
+            Codec::Bincode => 0,
+            Codec::Json => CODEC_FLAG_JSON,
+        };
+    }
+
+    /// split_version
+    /// Split the version byte of a datagram into its codec and its protocol version
+    ///
+    /// # Arguments:
+    ///
+    /// * `byte` - u8 - the byte after the magic bytes.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - (Codec, u8) - the codec the message is written in and the protocol version.
+    ///
+    pub fn split_version(byte: u8) -> (Codec, u8) {
+        if byte & CODEC_FLAG_JSON != 0 {
+            return (Codec::Json, byte & !CODEC_FLAG_JSON);
+        }
+        return (Codec::Bincode, byte);
+    }
+}
+
+impl CodecConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', "wire_codec".
+    /// * `value` - &str - the text after ':', "bincode" or "json".
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was the codec key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        if key.trim() != "wire_codec" {
+            return false;
+        }
+        match value.trim() {
+            "bincode" => self.codec = Codec::Bincode,
+            "json" => self.codec = Codec::Json,
+            _ => return false,
+        }
+        return true;
+    }
+}
@@ -0,0 +1,201 @@
+//! ## Dispatcher Module
+//! Runs the handlers of received messages on a fixed set of worker threads instead of one new thread per message.
+//!
+//! ## The structs includes:
+//! - **Lane**: A group of message types that are handled by the same workers, the messages of one sender in the order they arrived.
+//! - **LaneStats**: How many messages a lane has handled, waited for and dropped.
+//! - **MessageDispatcher**: One bounded queue and one worker per lane, per sender shard in the order lane.
+//!
+//! ## The functions includes:
+//! - 'lane_of'    which lane a message type belongs to.
+//! - 'dispatch'   queue a handler on the lane of its message type, on the worker of its sender.
+//! - 'stats'      the counters of one lane.
+//!
+//! Acks have a lane of their own, the handlers on the other lanes may be waiting for them.
+//! A handler that waits for acks (give_order and ensure_broadcast, ensure_send) holds its worker until they come
+//! or the retries run out, 50 ms per retry, about 250 ms for a NewOrder to a cab that does not answer.
+//! The order lane has `ORDER_WORKERS` workers and the messages are shared out by sender id, so such a handler
+//! only holds the messages from its own sender (and the senders with the same shard), the others keep going.
+//! The messages of one sender are still handled in the order they arrived, e.g. a Leaving after its RemoveOrder.
+//! When a queue is full lifesigns are dropped at once, a newer one is on its way.
+//! Other messages make the receiver wait a little (back-pressure) before they are dropped.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+
+use crate::modules::system_clock::track;
+use crate::modules::udp_functions::udp::MessageType;
+
+pub const QUEUE_CAPACITY: usize = 64;                           // Messages waiting per lane
+pub const BACKPRESSURE_WAIT: Duration = Duration::from_millis(50); // How long the receiver waits for room before dropping
+pub const ORDER_WORKERS: usize = 4;                             // Workers of the order lane, one per sender shard
+
+type HandlerJob = Box<dyn FnOnce() + Send>;
+
+/// Message types that share workers, the messages of one sender in a lane are handled one at a time in arrival order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lane {
+    Acks,           // Ack, Nak
+    Lifesigns,      // Worldview, WorldviewUpdate, ImAlive, Gossip
+    Orders,         // NewOrder, NewRequest, OrderComplete, RemoveOrder, Maintenance, Park, Leaving...
+    Membership,     // NewOnline, NewMaster, ErrorWorldview, ErrorOffline, Handover, ResyncRequest
+}
+
+pub const LANES: [Lane; 4] = [Lane::Acks, Lane::Lifesigns, Lane::Orders, Lane::Membership];
+
+/// Counters of one lane
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LaneStats {
+    pub handled: u64,       // Handlers that have finished
+    pub waited: u64,        // Times the receiver had to wait for room in the queue
+    pub dropped: u64,       // Messages thrown away because the queue stayed full
+}
+
+#[derive(Debug, Default)]
+struct LaneCounters {
+    handled: AtomicU64,
+    waited: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// A bounded queue and a worker for every shard of every lane
+#[derive(Debug)]
+pub struct MessageDispatcher {
+    queues: Vec<Vec<cbc::Sender<HandlerJob>>>,    // By lane, then by shard
+    counters: Vec<Arc<LaneCounters>>,             // By lane, shared by its shards
+}
+
+impl Lane {
+    fn index(self) -> usize {
+        return LANES.iter().position(|lane| *lane == self).unwrap();
+    }
+
+    /// True if a newer message of the lane makes an old one useless
+    fn droppable(self) -> bool {
+        return self == Lane::Lifesigns;
+    }
+
+    /// How many workers the lane has, only order handlers wait long enough to need more than one
+    fn workers(self) -> usize {
+        if self == Lane::Orders {
+            return ORDER_WORKERS;
+        }
+        return 1;
+    }
+}
+
+/// Which lane handles a message type
+pub fn lane_of(message_type: &MessageType) -> Lane {
+    match message_type {
+        MessageType::Ack | MessageType::Nak => Lane::Acks,
+        MessageType::Worldview | MessageType::WorldviewUpdate | MessageType::ImAlive | MessageType::Gossip => Lane::Lifesigns,
+        MessageType::NewOnline
+        | MessageType::NewMaster
+        | MessageType::ErrorWorldview
+        | MessageType::ErrorOffline
+        | MessageType::Handover
+        | MessageType::ResyncRequest => Lane::Membership,
+        // Leaving stays in the order lane, after the RemoveOrder the leaving node sent before it
+        _ => Lane::Orders,
+    }
+}
+
+impl MessageDispatcher {
+    /// Start the workers of every lane, each with a queue of `capacity` messages
+    pub fn new(capacity: usize) -> MessageDispatcher {
+        let mut queues = Vec::new();
+        let mut counters = Vec::new();
+
+        for lane in LANES {
+            let lane_counters = Arc::new(LaneCounters::default());
+            let mut lane_queues = Vec::new();
+
+            for shard in 0..lane.workers() {
+                let (tx, rx) = cbc::bounded::<HandlerJob>(capacity);
+                let worker_counters = Arc::clone(&lane_counters);
+
+                let worker = thread::Builder::new()
+                    .name(format!("dispatch-{:?}-{}", lane, shard))
+                    .spawn(move || {
+                        // Stops when the dispatcher is dropped
+                        for job in rx {
+                            job();
+                            worker_counters.handled.fetch_add(1, Ordering::Relaxed);
+                        }
+                    })
+                    .expect("Failed to start dispatcher worker");
+                track(worker);
+                lane_queues.push(tx);
+            }
+
+            queues.push(lane_queues);
+            counters.push(lane_counters);
+        }
+
+        return MessageDispatcher { queues, counters };
+    }
+
+    /// dispatch
+    /// Queue the handler of a message on the lane of its type
+    ///
+    /// # Arguments:
+    ///
+    /// * `message_type` - &MessageType - decides the lane.
+    /// * `sender_id` - u8 - decides the worker in a lane with more than one, the messages of a sender keep their order.
+    /// * `handler` - FnOnce() - handles the message on the worker.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the handler was queued, false if it was dropped.
+    ///
+    pub fn dispatch<F>(&self, message_type: &MessageType, sender_id: u8, handler: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let lane = lane_of(message_type);
+        let lane_queues = &self.queues[lane.index()];
+        let queue = &lane_queues[sender_id as usize % lane_queues.len()];
+        let counters = &self.counters[lane.index()];
+
+        let job: HandlerJob = Box::new(handler);
+        let job = match queue.try_send(job) {
+            Ok(()) => return true,
+            Err(cbc::TrySendError::Full(job)) => job,
+            Err(cbc::TrySendError::Disconnected(_)) => {
+                println!("Dispatcher worker for {:?} has stopped", lane);
+                return false;
+            }
+        };
+
+        if !lane.droppable() {
+            counters.waited.fetch_add(1, Ordering::Relaxed);
+            if queue.send_timeout(job, BACKPRESSURE_WAIT).is_ok() {
+                return true;
+            }
+        }
+
+        let dropped = counters.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        println!("Dispatcher queue for {:?} is full, dropped {:?} ({} dropped so far)", lane, message_type, dropped);
+        return false;
+    }
+
+    /// The counters of a lane
+    pub fn stats(&self, lane: Lane) -> LaneStats {
+        let counters = &self.counters[lane.index()];
+        return LaneStats {
+            handled: counters.handled.load(Ordering::Relaxed),
+            waited: counters.waited.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+        };
+    }
+}
+
+impl Default for MessageDispatcher {
+    fn default() -> Self {
+        MessageDispatcher::new(QUEUE_CAPACITY)
+    }
+}
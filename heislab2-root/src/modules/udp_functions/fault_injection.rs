@@ -0,0 +1,295 @@
+//! ## Fault Injection Module
+//! This module makes the network misbehave on purpose, so retries and master failover can be tested.
+//!
+//! ## The structs includes:
+//! - **Partition**: Two groups of node ids that can't reach each other in a time window.
+//! - **FaultConfig**: How much loss, delay, duplication, reordering and which partitions to inject.
+//! - **FaultInjector**: Decides the fate of every outgoing message from a `UdpHandler`.
+//!
+//! ## The functions includes:
+//! - 'parse'        reads one "key: value" line from "boot.txt" into a `FaultConfig`.
+//! - 'inject'       sends a message zero, one or two times, each copy after its own delay.
+//! - 'partitioned'  tells if two nodes are cut off from each other right now.
+//!
+//! Loss, delay, duplication and reordering are applied when a message is sent,
+//! partitions are applied when a message is received since only then the sender id is known.
+//! Delayed copies wait in one heap per injector and are sent by one thread when their time comes.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! fault_loss: 20              percent of messages dropped
+//! fault_delay: 30             delay in ms added to every message
+//! fault_jitter: 20            random extra delay in ms, 0 to this
+//! fault_duplicate: 5          percent of messages sent twice
+//! fault_reorder: 10           percent of messages held back so later messages overtake them
+//! fault_reorder_delay: 100    how long in ms a reordered message is held back
+//! fault_seed: 42              seed for the random choices
+//! fault_partition: 1,2|3@10-30   nodes 1 and 2 can't reach node 3 from 10s to 30s after start
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as cbc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::modules::system_clock::{clock_stopped, elapsed_since, now, sleep, track};
+
+// How often the delay thread looks at the clock while copies are waiting
+const DELAY_PERIOD: Duration = Duration::from_millis(1);
+
+// Sends one copy of a message on the real network
+type Transmit = Arc<dyn Fn() + Send + Sync>;
+
+/// Nodes in `side_a` and `side_b` can't reach each other from `start` until `end` after the node started
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub side_a: Vec<u8>,
+    pub side_b: Vec<u8>,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// What faults to inject, everything is off by default
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultConfig {
+    pub loss_percent: f64,
+    pub delay: Duration,
+    pub jitter: Duration,
+    pub duplicate_percent: f64,
+    pub reorder_percent: f64,
+    pub reorder_delay: Duration,
+    pub partitions: Vec<Partition>,
+    pub seed: u64,
+}
+
+/// Applies a `FaultConfig` to the messages of one node
+#[derive(Debug)]
+pub struct FaultInjector {
+    pub config: FaultConfig,
+    started: Instant,           // Partition windows are counted from here
+    rng: Mutex<StdRng>,
+    delayed: cbc::Sender<(Instant, Transmit)>,  // Copies for the delay thread and when to send them
+}
+
+// A copy waiting in the delay thread, the heap gives out the earliest first
+struct Delayed {
+    send_at: Instant,
+    seq: u64,               // Copies due at the same time go out in the order they came
+    transmit: Transmit,
+}
+
+impl Partition {
+    /// Parse "1,2|3@10-30", returns None if the text is not a partition
+    pub fn parse(text: &str) -> Option<Partition> {
+        let (sides, window) = text.split_once('@')?;
+        let (side_a, side_b) = sides.split_once('|')?;
+        let (start, end) = window.split_once('-')?;
+
+        let ids = |side: &str| -> Option<Vec<u8>> {
+            side.split(',').map(|id| id.trim().parse::<u8>().ok()).collect()
+        };
+        // Negative, infinite and too large times are not a window, from_secs_f64 would panic on them
+        let seconds = |time: &str| -> Option<Duration> {
+            time.trim().parse::<f64>().ok().filter(|s| s.is_finite()).and_then(|s| Duration::try_from_secs_f64(s).ok())
+        };
+
+        return Some(Partition {
+            side_a: ids(side_a)?,
+            side_b: ids(side_b)?,
+            start: seconds(start)?,
+            end: seconds(end)?,
+        });
+    }
+
+    /// True if the partition separates `a` and `b` at time `at` after start
+    pub fn separates(&self, a: u8, b: u8, at: Duration) -> bool {
+        if at < self.start || at >= self.end {
+            return false;
+        }
+        return (self.side_a.contains(&a) && self.side_b.contains(&b))
+            || (self.side_b.contains(&a) && self.side_a.contains(&b));
+    }
+}
+
+impl FaultConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "fault_" are ignored.
+    /// * `value` - &str - the text after ':'.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a fault key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        let millis = |v: &str| v.parse::<u64>().ok().map(Duration::from_millis);
+        let percent = |v: &str| v.parse::<f64>().ok().filter(|p| p.is_finite()).map(|p| p.clamp(0.0, 100.0));
+
+        match key.trim() {
+            "fault_loss" => percent(value).map(|p| self.loss_percent = p).is_some(),
+            "fault_delay" => millis(value).map(|d| self.delay = d).is_some(),
+            "fault_jitter" => millis(value).map(|d| self.jitter = d).is_some(),
+            "fault_duplicate" => percent(value).map(|p| self.duplicate_percent = p).is_some(),
+            "fault_reorder" => percent(value).map(|p| self.reorder_percent = p).is_some(),
+            "fault_reorder_delay" => millis(value).map(|d| self.reorder_delay = d).is_some(),
+            "fault_seed" => value.parse().ok().map(|s| self.seed = s).is_some(),
+            "fault_partition" => Partition::parse(value).map(|p| self.partitions.push(p)).is_some(),
+            _ => false,
+        }
+    }
+
+    /// True if any fault is turned on
+    pub fn is_active(&self) -> bool {
+        return self.loss_percent > 0.0
+            || !self.delay.is_zero()
+            || !self.jitter.is_zero()
+            || self.duplicate_percent > 0.0
+            || self.reorder_percent > 0.0
+            || !self.partitions.is_empty();
+    }
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> FaultInjector {
+        let rng = StdRng::seed_from_u64(config.seed);
+        let (delayed, delayed_rx) = cbc::unbounded();
+        track(thread::spawn(move || run_delay_thread(delayed_rx)));
+        FaultInjector { config, started: now(), rng: Mutex::new(rng), delayed }
+    }
+
+    /// Decide how many copies of a message to send and the delay of each, no copies means the message is lost
+    pub fn plan(&self) -> Vec<Duration> {
+        let mut rng = self.rng.lock().unwrap();
+        let config = &self.config;
+
+        if rng.gen_bool(config.loss_percent / 100.0) {
+            return Vec::new();
+        }
+
+        let copies = if rng.gen_bool(config.duplicate_percent / 100.0) { 2 } else { 1 };
+        let mut delays = Vec::new();
+        for _ in 0..copies {
+            let mut delay = config.delay;
+            if !config.jitter.is_zero() {
+                delay += config.jitter.mul_f64(rng.gen::<f64>());
+            }
+            if rng.gen_bool(config.reorder_percent / 100.0) {
+                delay += config.reorder_delay;
+            }
+            delays.push(delay);
+        }
+        return delays;
+    }
+
+    /// inject
+    /// Send a message through the faulty network
+    ///
+    /// # Arguments:
+    ///
+    /// * `transmit` - F - sends one copy of the message on the real network.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - always true, a lost message looks like a sent one to the sender.
+    ///
+    pub fn inject<F>(&self, transmit: F) -> bool
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let delays = self.plan();
+        if delays.is_empty() {
+            println!("Fault injection: message dropped");
+            return true;
+        }
+        if delays.len() > 1 {
+            println!("Fault injection: message duplicated");
+        }
+
+        let transmit: Transmit = Arc::new(transmit);
+        for delay in delays {
+            if delay.is_zero() {
+                transmit();
+            } else {
+                let _ = self.delayed.send((now() + delay, Arc::clone(&transmit)));
+            }
+        }
+        return true;
+    }
+
+    /// True if a partition currently separates the nodes `a` and `b`
+    pub fn partitioned(&self, a: u8, b: u8) -> bool {
+        let since_start = elapsed_since(self.started);
+        return self.config.partitions.iter().any(|p| p.separates(a, b, since_start));
+    }
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Delayed {
+    // Reversed, BinaryHeap gives out the largest and the earliest copy must come first
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other.send_at.cmp(&self.send_at).then(other.seq.cmp(&self.seq));
+    }
+}
+
+// The delay thread of an injector: sends every copy when its time comes, stops when the injector is gone
+// and nothing is waiting, or when the virtual clock stops
+fn run_delay_thread(delayed_rx: cbc::Receiver<(Instant, Transmit)>) {
+    let mut waiting: BinaryHeap<Delayed> = BinaryHeap::new();
+    let mut seq = 0;
+    let mut injector_gone = false;
+    loop {
+        // Nothing to send, wait for the next copy without looking at the clock
+        if waiting.is_empty() {
+            match delayed_rx.recv() {
+                Ok((send_at, transmit)) => {
+                    waiting.push(Delayed { send_at, seq, transmit });
+                    seq += 1;
+                }
+                Err(_) => return,
+            }
+        }
+        while !injector_gone {
+            match delayed_rx.try_recv() {
+                Ok((send_at, transmit)) => {
+                    waiting.push(Delayed { send_at, seq, transmit });
+                    seq += 1;
+                }
+                Err(cbc::TryRecvError::Empty) => break,
+                Err(cbc::TryRecvError::Disconnected) => injector_gone = true,
+            }
+        }
+
+        while waiting.peek().is_some_and(|copy| copy.send_at <= now()) {
+            if let Some(copy) = waiting.pop() {
+                (copy.transmit)();
+            }
+        }
+        // A stopped clock never brings the copies that are left due
+        if (injector_gone && waiting.is_empty()) || clock_stopped() {
+            return;
+        }
+        sleep(DELAY_PERIOD);
+    }
+}
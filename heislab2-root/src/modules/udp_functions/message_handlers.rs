@@ -4,16 +4,21 @@
 
 //----------------------------------------------Imports
 use std::net::{IpAddr,SocketAddr, UdpSocket};
-use std::time::{Instant, SystemTime};              // https://doc.rust-lang.org/std/time/struct.Duration.html
+use crate::modules::system_clock::{now, system_now};
 use std::sync::Arc;                     // https://doc.rust-lang.org/std/sync/struct.Mutex.html
 use crossbeam_channel as cbc;
 
 
 use crate::modules::order_object::order_init::Order;
 use crate::modules::elevator_object::elevator_init::SystemState;
-use crate::modules::cab_object::cab::Cab;
-use crate::modules::master_functions::master::{give_order, best_to_worst_elevator,Role,correct_master_worldview, reassign_orders};
-use crate::modules::slave_functions::slave::update_from_worldview;
+use crate::modules::cab_object::cab::{Cab, Status};
+use crate::modules::master_functions::master::{give_order, best_to_worst_elevator,Role,correct_master_worldview, reassign_orders, set_aside_unservable};
+use crate::modules::master_functions::parking::can_park;
+use crate::modules::master_functions::traffic::{record_hall_call, refresh_dispatch_mode};
+use crate::modules::master_functions::lease::{record_ack, step_down_to};
+use crate::modules::master_functions::worldview_delta::{receive_worldview_update, snapshot_parts};
+use crate::modules::slave_functions::slave::{update_from_worldview, set_maintenance, send_to_all, send_new_online};
+use crate::modules::peer_functions::peer::{Coordination, add_hall_request, merge_hall_requests, apply_assignment, gossip};
 
 
 
@@ -40,28 +45,28 @@ pub fn handle_order_completed(msg: &UdpMsg, state: Arc<SystemState>, light_updat
         /* THE FIRST ELEMENT IS THE COMPLETED ORDER */
         let completed_order = completed_order.clone();
 
-        let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-        if let Some(cab) = known_elevators_locked.iter_mut().find(|e| e.id == completed_cab.id){
-            cab.queue.retain(|order| {
-                !(order.floor == completed_order.floor && (order.order_type == completed_order.order_type || order.order_type == CAB))});
-        }
-        drop(known_elevators_locked);
-
-        let mut all_orders_locked = state.all_orders.lock().unwrap();  
-        /* FIND CAB ORDERS AND REMOVE THEM FROM ALL ELEVATORS LOCKED MUTEX*/
-        if completed_order.order_type == CAB {
-            if let Some(index) = all_orders_locked.iter().position(|order| {
-                order.floor == completed_order.floor && order.order_type == CAB
-            }) {
-                all_orders_locked.remove(index);
+        let completed_id = completed_cab.id;
+        state.update(move |s| {
+            if let Some(cab) = s.known_elevators.iter_mut().find(|e| e.id == completed_id){
+                cab.queue.retain(|order| {
+                    !(order.floor == completed_order.floor && (order.order_type == completed_order.order_type || order.order_type == CAB))});
             }
 
-        } else { /* REMOVES COMPLETED HALL ORDERS ASWELL */
-            all_orders_locked.retain(|order| {
-                !(order.floor == completed_order.floor &&
-                  (order.order_type == completed_order.order_type || order.order_type == CAB))
-            });
-        }
+            /* FIND CAB ORDERS AND REMOVE THEM FROM ALL ORDERS */
+            if completed_order.order_type == CAB {
+                if let Some(index) = s.all_orders.iter().position(|order| {
+                    order.floor == completed_order.floor && order.order_type == CAB
+                }) {
+                    s.all_orders.remove(index);
+                }
+
+            } else { /* REMOVES COMPLETED HALL ORDERS ASWELL */
+                s.all_orders.retain(|order| {
+                    !(order.floor == completed_order.floor &&
+                      (order.order_type == completed_order.order_type || order.order_type == CAB))
+                });
+            }
+        });
     } 
     
             
@@ -81,80 +86,101 @@ pub fn handle_new_request(msg: &UdpMsg, state: Arc<SystemState>,udp_handler: Arc
 
     println!("New request recived Floor:{}, Type{}",new_order.floor,new_order.order_type);
 
-    //Lock list of all orders
-    let mut all_orders_locked = state.all_orders.lock().unwrap(); 
-    
-    all_orders_locked.push(new_order.clone());
-    drop(all_orders_locked); // This one sounds scary
+    //Store the order, check if this elevator is master and put a CAB order in the queue of the sender, in one job
+    let stored_order = new_order.clone();
+    let sender_id = msg.header.sender_id;
+    let (master_id, is_peer, sender_elevator) = state.update(move |s| {
+        record_hall_call(s, &stored_order);
+        // A peer only adds its own hall calls, the others learn them from its gossip with its version
+        let is_peer = s.coordination == Coordination::Peer;
+        if is_peer && stored_order.order_type != CAB && sender_id == s.me_id {
+            add_hall_request(&mut s.hall_requests, &stored_order);
+        }
+        // A hall call pressed again is the same order, a cab order is one per cab and goes in every time
+        if stored_order.order_type == CAB || !s.all_orders.contains(&stored_order) {
+            s.all_orders.push(stored_order.clone());
+        }
 
-    //Check if this elevator is master 
-    let master_id_clone = state.master_id.lock().unwrap().clone();
-    let is_master = state.me_id == master_id_clone;
+        // Find the elevator that matches the sender id and put a CAB order first in line after the current one
+        let mut sender_elevator = None;
+        if stored_order.order_type == CAB {
+            if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == sender_id) {
+                if elevator.queue.len()>1{
+                    elevator.queue.insert(1,stored_order);
+                }else {
+                    elevator.queue.insert(0,stored_order);
+                }
+                sender_elevator = Some(elevator.clone());
+            }
+        }
+        return (s.master_id, is_peer, sender_elevator);
+    });
+    let is_master = state.me_id == master_id;
 
 
     //If new request is CAB order
     if new_order.order_type == CAB{
-        // Lock the known elevators and find the elevator that matches the sender id.
-        let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-        if let Some(sender_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == msg.header.sender_id) {
-            if sender_elevator.queue.len()>1{
-                sender_elevator.queue.insert(1,new_order.clone());
-            }else {
-                sender_elevator.queue.insert(0,new_order.clone());
-
-            }
+        if let Some(sender_elevator) = sender_elevator {
             if sender_elevator.id == state.me_id{
-                light_update_tx.send(sender_elevator.queue.clone()).unwrap();
+                let _ = light_update_tx.send(sender_elevator.queue.clone());
             }
             println!("Entered call type cab");
             if is_master {
-                // Capture necessary data (elevator id) before dropping the lock.
-                let elevator_id = sender_elevator.id;
-                // Lock is dropped here when the block ends.
-                drop(known_elevators_locked);
-                give_order(elevator_id, vec![&new_order], &state, &udp_handler);
-                println!("Added CAB order to elevator ID: {}", elevator_id);
+                give_order(sender_elevator.id, vec![&new_order], &state, &udp_handler);
+                println!("Added CAB order to elevator ID: {}", sender_elevator.id);
             }
         }
         else{
             println!("Elevator with NewRequest CAB is not active ID:{}", msg.header.sender_id)
         }
-        order_update_tx.send(vec![new_order.clone()]).unwrap();    
+        let _ = order_update_tx.send(vec![new_order.clone()]);    
     
     }else {
         
         if is_master{
-            let known_elevators_locked = state.known_elevators.lock().unwrap();
-            let alive_elevators: Vec<Cab> = known_elevators_locked.iter().filter(|e| e.alive).cloned().collect();
+            let alive_elevators: Vec<Cab> = state.known_elevators().into_iter().filter(|e| e.alive).collect();
+
+            // Kept in all orders, a cab that stops there may come back later
+            let unservable = set_aside_unservable(vec![new_order.clone()], &state).is_empty();
 
-            if !alive_elevators.is_empty() {
-                let best_elevators = best_to_worst_elevator(&new_order, &alive_elevators);
-                drop(known_elevators_locked);
+            if !alive_elevators.is_empty() && !unservable {
+                let mode = refresh_dispatch_mode(&state);
+                let best_elevators = best_to_worst_elevator(&new_order, &alive_elevators, mode);
+                let i_serve_floor = state.my_cab().is_some_and(|me| me.serves_floor(new_order.floor));
 
                 let best_elevator = match best_elevators.first() {
                     Some(elevator) => {
                         println!("Assigning new hallcall to {:?}", elevator);
-                        elevator
+                        Some(*elevator)
                     }
-                    None => {
+                    None if i_serve_floor => {
                         println!("No available elevator to assign the order, assigning to self");
-                        &state.me_id
+                        Some(state.me_id)
+                    }
+                    None => {
+                        println!("No available elevator to assign the order, this one does not stop at floor {}", new_order.floor);
+                        None
                     }
                 };
 
-                let success = give_order(*best_elevator, vec![&new_order], &state, &udp_handler);
-                
-                if !success{
-                    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-                    known_elevators_locked.get_mut(0).unwrap().queue.push(new_order.clone());
+                if let Some(best_elevator) = best_elevator {
+                    let success = give_order(best_elevator, vec![&new_order], &state, &udp_handler);
+
+                    if !success && i_serve_floor {
+                        let own_order = new_order.clone();
+                        state.update(move |s| s.known_elevators.get_mut(0).unwrap().queue.push(own_order));
+                    }
                 }
 
-                order_update_tx.send(vec![new_order.clone()]).unwrap();
+                let _ = order_update_tx.send(vec![new_order.clone()]);
             }   
-        }       
+        } else if is_peer && msg.header.sender_id == state.me_id {
+            // Tell the peers at once instead of at the next gossip
+            gossip(&state, &udp_handler, &order_update_tx);
+        }
     }
     println!("THIS ORDER UPDATE 2 of 4");
-    order_update_tx.send(vec![new_order.clone()]).unwrap();
+    let _ = order_update_tx.send(vec![new_order.clone()]);
 }
 
 
@@ -178,15 +204,6 @@ pub fn handle_worldview(state: Arc<SystemState>, msg: &UdpMsg,udp_handler: Arc<U
 
     println!("Updating worldview...");
 
-    //Update last lifesign and last worldview
-    let mut last_lifesign_locked = state.lifesign_master.lock().unwrap();
-    *last_lifesign_locked = Instant::now();
-    drop(last_lifesign_locked);
-
-    let mut new_worldview = state.last_worldview.lock().unwrap();
-    *new_worldview = msg.clone();
-    drop(new_worldview);
-    
     let worldview = if let UdpData::Cabs(worldview) = &msg.data{
         worldview
     }
@@ -195,15 +212,128 @@ pub fn handle_worldview(state: Arc<SystemState>, msg: &UdpMsg,udp_handler: Arc<U
         return;
     };
 
-    update_from_worldview(&state, &worldview,udp_handler);
-    /* let known_elevators: Vec<Cab> = {
-    let known_elevators_locked = state.known_elevators.lock().unwrap();
-    known_elevators_locked.clone() */ 
-     
+    take_worldview(state, msg, Some(worldview), udp_handler);
     //not used
     //generate_worldview(&known_elevators);
 }
 
+/// handle_worldview_update
+/// A delta or a part of a snapshot from the master, see worldview_delta.rs. It counts as a lifesign of the master
+/// even when an update is missing, the node then asks the master for a snapshot.
+/// 
+/// # Arguments:
+/// 
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `msg` - UdpMsg - Recived message.
+/// * `udp_handler` - Arc<UdpHandler> - sends the ack and the ResyncRequest.
+/// 
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_worldview_update(state: Arc<SystemState>, msg: &UdpMsg, udp_handler: Arc<UdpHandler>) {
+
+    // My worldview
+    if state.me_id == msg.header.sender_id{
+        return;
+    }
+
+    let update = if let UdpData::WorldviewUpdate(update) = &msg.data{
+        update.clone()
+    }
+    else{
+        println!("Wrong data in message for worldview update");
+        return;
+    };
+
+    let sender_id = msg.header.sender_id;
+    let (worldview, resync_address) = state.update(move |s| {
+        let (worldview, resync) = receive_worldview_update(&mut s.worldview_log, sender_id, &update);
+        let sender_address = s.known_elevators.iter().find(|e| e.id == sender_id).map(|e| e.inn_address);
+        return (worldview, sender_address.filter(|_| resync));
+    });
+
+    if let (Some(address), Some(me)) = (resync_address, state.my_cab()) {
+        udp_handler.send(&address, &make_udp_msg(state.me_id, MessageType::ResyncRequest, UdpData::Cab(me)));
+    }
+
+    take_worldview(state, msg, worldview.as_ref(), udp_handler);
+}
+
+// Lifesign of the master, step down if this is a fenced master and ack to renew the lease of the sender,
+// then merge the worldview if there is a whole one
+fn take_worldview(state: Arc<SystemState>, msg: &UdpMsg, worldview: Option<&Vec<Cab>>, udp_handler: Arc<UdpHandler>) {
+
+    // A fenced master has its own cab as Slave, see lease.rs
+    let sender_id = msg.header.sender_id;
+    let sender = worldview.and_then(|worldview| worldview.iter().find(|cab| cab.id == sender_id).cloned());
+    let sender_is_master = sender.as_ref().is_some_and(|cab| cab.role == Role::Master);
+
+    //Update last lifesign and last worldview
+    let new_worldview = msg.clone();
+    let (stepped_down, follows_sender, known_address) = state.update(move |s| {
+        s.lifesign_master = now();
+        s.last_worldview = new_worldview;
+        let stepped_down = step_down_to(s, sender_id, sender_is_master);
+        let known_address = s.known_elevators.iter().find(|e| e.id == sender_id).map(|e| e.inn_address);
+        return (stepped_down, s.master_id == sender_id, known_address);
+    });
+
+    if stepped_down {
+        send_new_online(&state, &udp_handler);
+    }
+
+    // The ack renews the lease of the master
+    if let Some(address) = sender.map(|cab| cab.inn_address).or(known_address).filter(|_| follows_sender) {
+        udp_ack(address, msg, state.me_id, &udp_handler);
+    }
+
+    if let Some(worldview) = worldview {
+        update_from_worldview(&state, worldview, udp_handler);
+    }
+}
+
+/// handle_resync_request
+/// A node has missed a worldview update, send it the last worldview this master sent as a snapshot
+/// 
+/// # Arguments:
+/// 
+/// * `msg` - UdpMsg - Recived message, with the cab of the node that asks.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends the snapshot.
+/// 
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_resync_request(msg: &UdpMsg, state: Arc<SystemState>, udp_handler: &UdpHandler) {
+    let requester = if let UdpData::Cab(cab) = &msg.data {
+        cab.clone()
+    } else {
+        println!("Wrong data in message for resync request");
+        return;
+    };
+
+    // The snapshot of the last update, the next delta follows it
+    let me_id = state.me_id;
+    let snapshot = state.read(move |s| {
+        if s.master_id != me_id || s.worldview_log.sender != me_id {
+            return None;
+        }
+        return Some(snapshot_parts(s.worldview_log.seq, &s.worldview_log.cabs));
+    });
+
+    match snapshot {
+        Some(parts) => {
+            println!("Sending worldview snapshot to elevator {}", requester.id);
+            for part in parts {
+                udp_handler.send(&requester.inn_address, &make_udp_msg(me_id, MessageType::WorldviewUpdate, UdpData::WorldviewUpdate(part)));
+            }
+        }
+        None => println!("Elevator {} asked for a worldview snapshot, but this node sends none", requester.id),
+    }
+}
+
 /// handle_ack
 /// 
 /// # Arguments:
@@ -217,10 +347,6 @@ pub fn handle_worldview(state: Arc<SystemState>, msg: &UdpMsg,udp_handler: Arc<U
 ///
 pub fn handle_ack(msg: &UdpMsg, state: Arc<SystemState>) {
     
-    for elevator in state.known_elevators.lock().unwrap().iter_mut().filter(|e| !e.alive && msg.header.sender_id == e.id) {
-        elevator.alive = true;
-    }
-
     let sender_id = msg.header.sender_id;
     let original_checksum = if let UdpData::Checksum(original_checksum) = &msg.data {
         *original_checksum
@@ -229,47 +355,44 @@ pub fn handle_ack(msg: &UdpMsg, state: Arc<SystemState>) {
         return;
     };
 
-    //Lock mutex for messages awaiting response
-    let mut sent_messages_locked = state.sent_messages.lock().unwrap();
-
-    if let Some(waiting) = sent_messages_locked.iter_mut().find(|e| e.message_hash == original_checksum){
-        // Add sender id if not in responded
-        if !waiting.responded_ids.contains(&sender_id){
-            waiting.responded_ids.push(sender_id);
-            println!("Added Ack from:{:?} for checksum: {:?}", sender_id, original_checksum);
+    state.update(move |s| {
+        // An ack proves the sender is alive, and that it still hears this node
+        for elevator in s.known_elevators.iter_mut().filter(|e| !e.alive && sender_id == e.id) {
+            elevator.alive = true;
         }
+        record_ack(&mut s.lease, sender_id, now());
 
-        // Variable to control if all elevators have acked
-        let mut all_confirmed = true;
+        if let Some(waiting) = s.sent_messages.iter_mut().find(|e| e.message_hash == original_checksum){
+            // Add sender id if not in responded
+            if !waiting.responded_ids.contains(&sender_id){
+                waiting.responded_ids.push(sender_id);
+                println!("Added Ack from:{:?} for checksum: {:?}", sender_id, original_checksum);
+            }
 
-        // Lock mutex for active elevators
-        let known_elevators_locked = state.known_elevators.lock().unwrap();
+            // Variable to control if all elevators have acked
+            let mut all_confirmed = true;
 
-        //Check that all active elevatos have responded 
-        for elevator in known_elevators_locked.iter().filter(|e|e.alive){
-            if !waiting.responded_ids.contains(&elevator.id){
-                println!("Still missing confirmations for elevaotr ID:{}", elevator.id);
-                all_confirmed = false;
+            //Check that all active elevatos have responded 
+            for elevator in s.known_elevators.iter().filter(|e|e.alive){
+                if !waiting.responded_ids.contains(&elevator.id){
+                    println!("Still missing confirmations for elevaotr ID:{}", elevator.id);
+                    all_confirmed = false;
+                }
             }
 
-        }
-        drop(known_elevators_locked);
+            if all_confirmed{
+                waiting.all_confirmed = true;
+                println!("Added Ack from:{:?} for checksum:{:?}", sender_id, original_checksum);
+            }
 
-        if all_confirmed{
-            waiting.all_confirmed = true;
-            println!("Added Ack from:{:?} for checksum:{:?}", sender_id, original_checksum);
+            println!("All elevators have confirmed reciving message with checksum: {:?}",original_checksum);
+            
+        }else {
+            println!("Checksum: {:?} not found in list waiting for confirmation, sender was {:?}", original_checksum, sender_id);
         }
-
-        println!("All elevators have confirmed reciving message with checksum: {:?}",original_checksum);
-        
-    }else {
-
-    println!("Checksum: {:?} not found in list waiting for confirmation, sender was {:?}", original_checksum, sender_id);
-    };
-
+    });
 }
 
-
 ///handle_nack
 /// 
 /// # Arguments:
@@ -294,8 +417,8 @@ pub fn handle_nak(msg: &UdpMsg, state: Arc<SystemState>, target_address: &Socket
     };
 
     // Check if this NAK matches sent message
-    let sent_messages_locked = state.sent_messages.lock().unwrap();
-    if let Some(waiting_response) = sent_messages_locked.iter().position(|m| m.message_hash == original_checksum) {
+    let known = state.read(move |s| s.sent_messages.iter().any(|m| m.message_hash == original_checksum));
+    if known {
         println!("NAK matches message with checksum: {:?}", original_checksum);
     } else {
         println!("ERROR: Received NAK with unknown checksum {:?}", original_checksum);
@@ -332,46 +455,55 @@ pub fn handle_new_order(msg: &UdpMsg, sender_address: &SocketAddr, state: Arc<Sy
 
     let elevator_id = elevator.id;
 
-    //Lock active elevators
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap(); 
-
     //Find elevator with mathcing ID and update queue
-    if let Some(update_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == elevator_id){
-        for order in &elevator.queue {
-            if !update_elevator.queue.contains(&order){
-                update_elevator.queue.push(order.clone());
-                println!("Order {:?} successfully added to elevator {}.", order, elevator.id);
-                light_update_tx.send(update_elevator.queue.clone()).unwrap();
-                order_update_tx.send(vec![order.clone()]).unwrap();
-
-            }else {
-                println!("Order {:?} already in queue for elevator {}.", order, elevator.id);
+    let updated_elevator = elevator.clone();
+    let sender_id = msg.header.sender_id;
+    let (added, ack_port, in_service) = state.update(move |s| {
+        let mut added = Vec::new();
+        if let Some(update_elevator) = s.known_elevators.iter_mut().find(|e| e.id == elevator_id){
+            for order in &updated_elevator.queue {
+                if !update_elevator.queue.contains(order){
+                    update_elevator.queue.push(order.clone());
+                    println!("Order {:?} successfully added to elevator {}.", order, elevator_id);
+                    added.push((order.clone(), update_elevator.queue.clone()));
+                }else {
+                    println!("Order {:?} already in queue for elevator {}.", order, elevator_id);
+                }
             }
         }
+        // The sender listens on its inn port at the address the message came from
+        let ack_port = s.known_elevators.iter().find(|e| e.id == sender_id).map(|e| e.inn_address.port()).unwrap_or(BROADCAST_PORT);
+        let in_service = s.known_elevators.first().is_some_and(|me| me.status != Status::Stop && me.status != Status::Error);
+        return (added, ack_port, in_service);
+    });
+
+    for (order, queue) in added {
+        let _ = light_update_tx.send(queue);
+        let _ = order_update_tx.send(vec![order]);
     }
-    for elevator in known_elevators_locked.iter(){
-        udp_ack(*&elevator.inn_address, &msg, elevator.id, &udp_handler);
+
+    // An ack makes the master take this elevator as alive, none is sent while it is stopped or its motor has failed
+    if !in_service {
+        println!("Out of service, not acking new order");
+        return false;
     }
-    //Send Ack to sender
-    return udp_ack(*&elevator.inn_address, &msg, elevator.id, &udp_handler);
+
+    //Send Ack to sender, only for ourself so a dead elevator is not taken for alive
+    let ack_address = SocketAddr::new(sender_address.ip(), ack_port);
+    return udp_ack(ack_address, &msg, state.me_id, &udp_handler);
 }
 
 /// handle_new_master
 /// # Arguments:
 /// 
 /// * `msg` - UdpMsg - recived message.
-/// * `known_elevators` - &Arc<Mutex<Vec<Cab>>> - Vector of active elevators.
+/// * `state` - Arc<SystemState> - handle to the system state.
 /// 
 /// # Returns:
 ///
 /// Returns -None- .
 ///
 pub fn handle_new_master(msg: &UdpMsg, state: Arc<SystemState>) {
-    let master_id = state.master_id.lock().unwrap().clone();
-    let known_master_id = state.master_id.lock().unwrap().clone();
-    if  !(known_master_id == master_id){
-        println!("New master detected, ID: {}", msg.header.sender_id);
-
     let cab_to_be_master = if let UdpData::Cab(cab) = &msg.data{
         cab.clone()
     }else{
@@ -379,29 +511,12 @@ pub fn handle_new_master(msg: &UdpMsg, state: Arc<SystemState>) {
         return;
     };
 
-    // Set current master's role to Slave
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-    if let Some(current_master) = known_elevators_locked.iter_mut().find(|elevator| elevator.role == Role::Master) {
-        println!("Changing current master (ID: {}) to slave.", current_master.id);
-        current_master.role = Role::Slave;
-    } else {
-        println!("ERROR: No active master found.");
+    // The master is chosen by every node itself in fix_master_issues and check_master_failure,
+    // taking the announced master here makes two nodes hand the role back and forth.
+    let known_master_id = state.master_id();
+    if known_master_id != cab_to_be_master.id {
+        println!("Elevator {} announced {} as master, keeping {}", msg.header.sender_id, cab_to_be_master.id, known_master_id);
     }
-
-    // Set new master
-    if let Some(new_master) = known_elevators_locked.iter_mut().find(|elevator| elevator.id == cab_to_be_master.id) {
-        println!("Updating elevator ID {} to Master.", cab_to_be_master.id);
-        new_master.role = Role::Master;
-
-        let mut master_id = state.master_id.lock().unwrap();
-        *master_id = cab_to_be_master.id;
-        drop(master_id);
-
-    } else {
-        println!("Error: Elevator ID {} not found in active list.", msg.header.sender_id);
-    }
-    }
-    
 }
 
 /// handle_new_online
@@ -418,52 +533,68 @@ pub fn handle_new_master(msg: &UdpMsg, state: Arc<SystemState>) {
 pub fn handle_new_online(msg: &UdpMsg, state: Arc<SystemState>) -> bool {
     println!("New elevator online, ID: {}", msg.header.sender_id);
 
-    //Lock active elevaotrs
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-
-    // Check if elevator is already active
-    if known_elevators_locked.iter().any(|e| e.id == msg.header.sender_id && e.alive) {
-  
-        println!("Elevator ID:{} is already active.", msg.header.sender_id);
-        return true;
-    }else if let Some(cab) = known_elevators_locked.iter_mut().find(|e| e.id == msg.header.sender_id && !e.alive){
-        cab.alive=true;
-        println!("Elevator ID: is set alive, already known elevator");
-        return true;
-    }
-
-    //Release active elevators
-    drop(known_elevators_locked); 
-    
-    let msg_elevator = if let UdpData::Cab(cab) = &msg.data {
-        cab
-    } else {
-        println!("Error: Wrong UdpData for message type");
-        return false;
+    let msg_elevator = match &msg.data {
+        UdpData::Cab(cab) => Some(cab.clone()),
+        _ => None,
     };
 
-    println!("New unknown elevator online ID: {}, adding to known", msg.header.sender_id);
-
-    // Create new elevator
-    let new_elevator = Cab {
-        inn_address: msg_elevator.inn_address,
-        out_address: msg_elevator.out_address,
-        num_floors: msg_elevator.num_floors,
-        id: msg_elevator.id,
-        current_floor: msg_elevator.current_floor,
-        last_served_floor: msg_elevator.last_served_floor,
-        queue: msg_elevator.queue.clone(),
-        status: msg_elevator.status.clone(),
-        direction: msg_elevator.direction.clone(),
-        role: msg_elevator.role.clone(),
-        last_lifesign: SystemTime::now(),
-        alive: true
-    };
+    // Check and add in one step, so two NewOnline messages can't both add the elevator
+    let sender_id = msg.header.sender_id;
+    let added = state.update(move |s| {
+        // Check if elevator is already active
+        if let Some(cab) = s.known_elevators.iter_mut().find(|e| e.id == sender_id) {
+            if cab.alive {
+                println!("Elevator ID:{} is already active.", sender_id);
+            } else {
+                cab.alive=true;
+                println!("Elevator ID: is set alive, already known elevator");
+            }
+            // The message has the true position, e.g. after the first floor was found at start
+            if let Some(msg_elevator) = msg_elevator.as_ref().filter(|_| sender_id != s.me_id) {
+                cab.merge_with(msg_elevator);
+            }
+            return Some(false);
+        }
 
-    // Lock again and add the new elevator
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-    known_elevators_locked.push(new_elevator);
-    drop(known_elevators_locked); 
+        let msg_elevator = msg_elevator?;
+
+        println!("New unknown elevator online ID: {}, adding to known", sender_id);
+
+        // Create new elevator
+        let new_elevator = Cab {
+            inn_address: msg_elevator.inn_address,
+            out_address: msg_elevator.out_address,
+            num_floors: msg_elevator.num_floors,
+            id: msg_elevator.id,
+            current_floor: msg_elevator.current_floor,
+            last_served_floor: msg_elevator.last_served_floor,
+            queue: msg_elevator.queue,
+            status: msg_elevator.status,
+            direction: msg_elevator.direction,
+            role: msg_elevator.role,
+            last_lifesign: system_now(),
+            alive: true,
+            takes_hall_calls: msg_elevator.takes_hall_calls,
+            maintenance: msg_elevator.maintenance,
+            parking: msg_elevator.parking,
+            served_floors: msg_elevator.served_floors,
+            load: msg_elevator.load,
+            full_load: msg_elevator.full_load,
+            accessible_stops: msg_elevator.accessible_stops,
+            protocol_versions: msg_elevator.protocol_versions,
+        };
+        s.known_elevators.push(new_elevator);
+        return Some(true);
+    });
+
+    match added {
+        Some(true) => {}
+        Some(false) => return true,
+        None => {
+            println!("Error: Wrong UdpData for message type");
+            return false;
+        }
+    }
 
     println!("Added new elevator ID {}.", msg.header.sender_id);
     return true;
@@ -519,109 +650,93 @@ pub fn handle_error_offline(
     
 
     if let UdpData::Cab(ref cab) = msg.data {
-        // Update the shared state directly without cloning.
         println!("Elevator {} went offline. Reassigning orders", cab.id);
 
-        let mut known_elevators = state.known_elevators.lock().unwrap();
-        if let Some(elevator) = known_elevators.iter_mut().find(|e| e.id == cab.id) {
+        // Set the elevator offline and pick a new master if it was the master, in one step
+        let offline_id = cab.id;
+        let taken_over = state.update(move |s| {
+            let elevator = s.known_elevators.iter_mut().find(|e| e.id == offline_id)?;
             elevator.alive = false;
             elevator.role = Role::Slave;
-            println!("Elevator ID:{} set to offline.", cab.id);
-        } else {
-            println!("Elevator ID:{} not found in known elevators.", cab.id);
-            return;
-        }
-        drop(known_elevators);
-        //
-
-        // Check if the offline elevator was the master.
-        let was_master = {
-            let known_elevators = state.known_elevators.lock().unwrap();
-            if let Some(elevator) = known_elevators.iter().find(|e| e.id == cab.id) {
-                let master_id = *state.master_id.lock().unwrap();
-                elevator.id == master_id
-            } else {
-                false
-            }
-        };
-
-        // If the offline elevator was the master, choose a new master.
-        if was_master {
-            let new_master_id_opt = {
-                let mut known_elevators = state.known_elevators.lock().unwrap();
-                // Filter the alive elevators.
-                let mut alive_elevators: Vec<&mut Cab> = known_elevators
-                    .iter_mut()
-                    .filter(|e| e.alive)
-                    .collect();
-                // Sort by ID so the one with the lowest ID comes first.
-                alive_elevators.sort_by_key(|e| e.id);
-                alive_elevators.get(0).map(|e| e.id)
-            };
-
-
-            if let Some(new_master_id) = new_master_id_opt {
-                // Update the master id in the shared state.
-                {
-                    let mut master_id_lock = state.master_id.lock().unwrap();
-                    *master_id_lock = new_master_id;
+            println!("Elevator ID:{} set to offline.", offline_id);
+
+            // If the offline elevator was the master, choose the alive elevator with the lowest ID.
+            if offline_id == s.master_id {
+                match s.known_elevators.iter().filter(|e| e.alive).map(|e| e.id).min() {
+                    Some(new_master_id) => {
+                        // Give the new master time to send a worldview before it is checked for lifesigns
+                        s.master_id = new_master_id;
+                        s.lifesign_master = now();
+                        println!("Master offline, set new master to ID: {}", new_master_id);
+                    }
+                    None => println!("No alive elevators found to set as new master."),
                 }
-                println!("Master offline, set new master to ID: {}", new_master_id);
-                // Optionally, broadcast the new master status here.
-            } else {
-                println!("No alive elevators found to set as new master.");
             }
-        }
 
-        // If this elevator is the new master, reassign orders.
-        {
-            let master_id = *state.master_id.lock().unwrap();
-            if state.me_id == master_id {
-                let orders_to_reassign = {
-                    let known_elevators = state.known_elevators.lock().unwrap();
-                    // Find the offline elevator and collect its hall orders.
-                    if let Some(elevator) = known_elevators.iter().find(|e| e.id == cab.id) {
+            // Collect the hall orders of the offline elevator if this elevator is the master.
+            let orders_to_reassign: Vec<Order> = if s.me_id == s.master_id {
+                s.known_elevators
+                    .iter()
+                    .find(|e| e.id == offline_id)
+                    .map(|elevator| {
                         elevator
                             .queue
                             .iter()
                             .filter(|order| order.order_type == HALL_UP || order.order_type == HALL_DOWN)
                             .cloned()
-                            .collect::<Vec<Order>>()
-                    } else {
-                        Vec::new()
-                    }
-                };
-                if !orders_to_reassign.is_empty() {
-                    println!("I am master, reassigning orders: {:?}", orders_to_reassign);
-                    reassign_orders(&orders_to_reassign, &state, udp_handler, order_update_tx);
-                } else {
-                    println!("No hall orders found for reassignment.");
-                }
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            return Some((s.me_id == s.master_id, orders_to_reassign));
+        });
+
+        let (i_am_master, orders_to_reassign) = match taken_over {
+            Some(taken_over) => taken_over,
+            None => {
+                println!("Elevator ID:{} not found in known elevators.", cab.id);
+                return;
+            }
+        };
+
+        // If this elevator is the new master, reassign orders.
+        if i_am_master {
+            if !orders_to_reassign.is_empty() {
+                println!("I am master, reassigning orders: {:?}", orders_to_reassign);
+                reassign_orders(&orders_to_reassign, &state, udp_handler, order_update_tx);
+            } else {
+                println!("No hall orders found for reassignment.");
             }
         }
 
         // Finally, remove all orders except cab orders from the offline elevator.
-        {
-            let mut known_elevators = state.known_elevators.lock().unwrap();
-            if let Some(elevator) = known_elevators.iter_mut().find(|e| e.id == cab.id) {
+        state.update(move |s| {
+            if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == offline_id) {
                 elevator.queue.retain(|order| order.order_type == CAB);
             }
-        }
+        });
     }
 }
 
-/// handle_error_offline
+/// handle_remove_order
+/// An elevator gives away orders from its queue, e.g. the hall orders of a cab whose door has been obstructed too long.
+/// The orders are taken out of its queue here, and the master hands the hall orders to other elevators.
+///
 /// # Arguments:
 /// 
-/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
-/// * `known_elevators` - &mut Arc<Mutex<Vec<Cab>>> - List of active elevators.
-/// * `&mut failed_orders` - &mut Arc<Mutex<Vec<Order>>> - list of orders that couldnt be distributed.
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd, the cab and the orders it gives away.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends the new orders if this elevator is master.
+/// * `light_update_tx` - cbc::Sender<Vec<Order>> - the lights are updated if the orders were this elevators own.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - passed on to reassign_orders.
 /// 
 /// # Returns:
 ///
 /// Returns - None - .
 ///
-pub fn handle_remove_order(msg: &UdpMsg, state: Arc<SystemState>, light_update_tx: cbc::Sender<Vec<Order>>) {
+pub fn handle_remove_order(msg: &UdpMsg, state: Arc<SystemState>, udp_handler: &UdpHandler, light_update_tx: cbc::Sender<Vec<Order>>, order_update_tx: cbc::Sender<Vec<Order>>) {
 
     let elevator_from_msg = if let UdpData::Cab(cab) = &msg.data {
         cab
@@ -636,39 +751,47 @@ pub fn handle_remove_order(msg: &UdpMsg, state: Arc<SystemState>, light_update_t
 
     println!("Removing order from ID: {}", remove_id);
 
-    //Lock active elevators
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-
     //Check for correct elevator in active elevators
-    if let Some(elevator) = known_elevators_locked.iter_mut().find(|e| e.id == remove_id) {
-
-        for order in &elevator_from_msg.queue {
-    
-            if let Some(index) = elevator.queue.iter().position(|o| o == order) {
-                            
-                elevator.queue.remove(index);
-                println!("Order {:?} removed from elevator ID: {}", order, elevator.id);
-                if elevator.id == state.me_id{
-                    light_update_tx.send(elevator.queue.clone()).unwrap();
+    let removed = elevator_from_msg.clone();
+    let me_id = state.me_id;
+    let (light_updates, is_master) = state.update(move |s| {
+        let mut light_updates = Vec::new();
+        if let Some(elevator) = s.known_elevators.iter_mut().find(|e| e.id == remove_id) {
+            elevator.takes_hall_calls = removed.takes_hall_calls;
+            elevator.maintenance = removed.maintenance;
+            for order in &removed.queue {
+                if let Some(index) = elevator.queue.iter().position(|o| o == order) {
+                    elevator.queue.remove(index);
+                    println!("Order {:?} removed from elevator ID: {}", order, elevator.id);
+                    if elevator.id == me_id{
+                        light_updates.push(elevator.queue.clone());
+                    }
+                } else {
+                    println!("ERROR: Elevator ID:{} does not have order {:?}", elevator.id, order); 
                 }
-                    
-            } else {
-                println!("ERROR: Elevator ID:{} does not have order {:?}", elevator.id, order); 
             }
-                            
+        } else {
+            println!("ERROR: No elevator data found in the message.");
         }
+        return (light_updates, s.master_id == me_id);
+    });
 
-    } else {
-        println!("ERROR: No elevator data found in the message.");
+    for queue in light_updates {
+        let _ = light_update_tx.send(queue);
     }
 
+    // The master finds new elevators for the hall orders, the sender does it itself if it is the master
+    let hall_orders: Vec<Order> = elevator_from_msg.queue.iter().filter(|o| o.order_type != CAB).cloned().collect();
+    if is_master && msg.header.sender_id != me_id && !hall_orders.is_empty() {
+        println!("Reassigning {} hall orders given away by ID:{}", hall_orders.len(), remove_id);
+        reassign_orders(&hall_orders, &state, udp_handler, order_update_tx);
+    }
 }
 
-
 pub fn handle_im_alive(msg: &UdpMsg, state: Arc<SystemState>){
     //Extract updated cab data from messagehandle_ack
 
-     let mut updated_cab = if let UdpData::Cab(cab) = &msg.data{
+     let updated_cab = if let UdpData::Cab(cab) = &msg.data{
         cab.clone()
     }else{
         println!("Couldnt read ImAlive message");
@@ -676,20 +799,287 @@ pub fn handle_im_alive(msg: &UdpMsg, state: Arc<SystemState>){
     };
 
     //Replace the old cab struct with the updated cab struct
-    let mut known_elevators_locked = state.known_elevators.lock().unwrap();
-    if let Some(sender_elevator) = known_elevators_locked.iter_mut().find(|e| e.id == msg.header.sender_id){
+    let sender_id = msg.header.sender_id;
+    let known = state.update(move |s| {
+        let sender_elevator = match s.known_elevators.iter_mut().find(|e| e.id == sender_id) {
+            Some(sender_elevator) => sender_elevator,
+            None => return false,
+        };
+        // A cab that says it is not alive is leaving, a late ImAlive from it must not bring it back
+        if !updated_cab.alive {
+            return true;
+        }
         println!("Updating alive elevator");
         sender_elevator.alive=true;
         sender_elevator.merge_with(&updated_cab);   //------------------------------------------------------------------------------PROBLEM?
-        sender_elevator.last_lifesign = SystemTime::now();
         //Update last lifesign of that elevator
+        sender_elevator.last_lifesign = system_now();
+        return true;
+    });
 
-    } else {
+    if !known {
         //Send a NewOnline message with that cab // ----------------------------------------------------------------------------------This will be corrected in next worldview as there will be a discrepancy
         println!("Elevator not known, running handle_new_online");
-        drop(known_elevators_locked);
-        handle_new_online(&msg, state);
+        handle_new_online(msg, state);
+    }
+}
+
+/// handle_maintenance
+/// Another node asks this elevator to go into or leave maintenance, see request_maintenance.
+/// The cab in the message names the elevator and the wanted mode, requests for other elevators are ignored.
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - sends ImAlive and RemoveOrder.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - passed on to set_maintenance.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_maintenance(msg: &UdpMsg, state: Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let request = if let UdpData::Cab(cab) = &msg.data {
+        cab
+    } else {
+        println!("Couldnt read Maintenance message");
+        return;
+    };
+
+    if request.id != state.me_id {
+        println!("Maintenance request for ID:{} is not for me", request.id);
+        return;
+    }
+
+    println!("ID:{} asks for maintenance {}", msg.header.sender_id, if request.maintenance { "on" } else { "off" });
+    set_maintenance(&state, udp_handler, request.maintenance, order_update_tx);
+}
+
+/// handle_park
+/// The master wants this elevator to wait at another floor, see parking.rs.
+/// The cab in the message names the elevator and the floor. The cab only drives there if it still has nothing to do.
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop so the cab starts moving.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_park(msg: &UdpMsg, state: Arc<SystemState>, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let (target_id, park_floor) = match &msg.data {
+        UdpData::Cab(cab) => match cab.parking {
+            Some(floor) => (cab.id, floor),
+            None => return,
+        },
+        _ => {
+            println!("Couldnt read Park message");
+            return;
+        }
+    };
+
+    if target_id != state.me_id {
+        return;
+    }
+
+    let accepted = state.update(move |s| {
+        let me = match s.known_elevators.get_mut(0) {
+            Some(me) => me,
+            None => return false,
+        };
+        // Real work came in after the master decided
+        if !can_park(me) || !me.serves_floor(park_floor) {
+            return false;
+        }
+        me.parking = Some(park_floor);
+        return true;
+    });
+
+    if accepted {
+        println!("Parking at floor {}", park_floor);
+        let _ = order_update_tx.send(Vec::new());
+    }
+}
+
+/// handle_handover
+/// The master is shutting down and has chosen this elevator to take over, see shutdown.rs.
+/// The state of the old master is merged with what this node knows, the old master is acked,
+/// this node becomes master and hands out the orders no elevator has, then tells everyone with NewMaster.
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - for the ack, the orders and NewMaster.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop if this cab got new orders.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_handover(msg: &UdpMsg, state: Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let handover = if let UdpData::Handover(handover) = &msg.data {
+        handover.clone()
+    } else {
+        println!("Couldnt read Handover message");
+        return;
+    };
+    let old_master_id = msg.header.sender_id;
+
+    // Ack first, the old master is waiting to leave
+    match handover.known_elevators.iter().find(|e| e.id == old_master_id) {
+        Some(old_master) => {
+            udp_ack(old_master.inn_address, msg, state.me_id, udp_handler);
+        }
+        None => println!("Handover from elevator {} does not hold its own cab, not acking", old_master_id),
+    }
+
+    println!("Elevator {} handed over the master role with {} orders", old_master_id, handover.all_orders.len());
+    let me_id = state.me_id;
+    let new_master = state.update(move |s| {
+        for cab in handover.known_elevators.iter().filter(|cab| cab.id != me_id) {
+            match s.known_elevators.iter_mut().find(|e| e.id == cab.id) {
+                Some(known) => {
+                    known.merge_with(cab);
+                    known.queue = cab.queue.clone();
+                    known.alive = cab.alive;
+                }
+                None => s.known_elevators.push(cab.clone()),
+            }
+        }
+
+        // The old master is leaving, its hall orders are in all_orders
+        if let Some(old_master) = s.known_elevators.iter_mut().find(|e| e.id == old_master_id) {
+            old_master.alive = false;
+            old_master.role = Role::Slave;
+            old_master.queue.retain(|order| order.order_type == CAB);
+        }
+        for order in handover.all_orders {
+            if !s.all_orders.contains(&order) {
+                s.all_orders.push(order);
+            }
+        }
+        for waiting in handover.pending {
+            if !s.sent_messages.iter().any(|m| m.message_hash == waiting.message_hash) {
+                s.sent_messages.push(waiting);
+            }
+        }
+
+        s.master_id = me_id;
+        s.lifesign_master = now();
+        let me = s.known_elevators.get_mut(0)?;
+        me.role = Role::Master;
+        return Some(me.clone());
+    });
+
+    let new_master = match new_master {
+        Some(new_master) => new_master,
+        None => return,
+    };
+    println!("Took over as master from elevator {}", old_master_id);
+
+    if !state.all_orders().is_empty() {
+        reassign_orders(&Vec::new(), &state, udp_handler, order_update_tx);
+    }
+    send_to_all(&state, udp_handler, &make_udp_msg(me_id, MessageType::NewMaster, UdpData::Cab(new_master)));
+}
+
+/// handle_leaving
+/// An elevator is shutting down on purpose, see shutdown.rs. Its hall orders were handed off before,
+/// so it is taken offline at once instead of after the lifesign timeouts, in the same way as ErrorOffline.
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `udp_handler` - &UdpHandler - for reassigning orders if this node is master.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop if this cab got new orders.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_leaving(msg: &UdpMsg, state: Arc<SystemState>, udp_handler: &UdpHandler, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let leaving = if let UdpData::Cab(cab) = &msg.data {
+        cab.clone()
+    } else {
+        println!("Couldnt read Leaving message");
+        return;
+    };
+    if leaving.id == state.me_id {
+        return;
+    }
+    println!("Elevator {} is shutting down", leaving.id);
+
+    // The queue in the message is what the elevator still had, hall orders in it were not handed off
+    state.update(move |s| {
+        if let Some(known) = s.known_elevators.iter_mut().find(|e| e.id == leaving.id) {
+            known.queue = leaving.queue;
+            known.takes_hall_calls = false;
+        }
+    });
+    handle_error_offline(msg, state, udp_handler, order_update_tx);
+}
+
+/// handle_gossip
+/// Merge the cab and the hall requests of a peer into the state of this node, then assign the hall requests again,
+/// see peer.rs. Gossip is ignored by a node running with a master.
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - refrence to the UDP message that was recivecd.
+/// * `state` - Arc<SystemState> - the state of this node.
+/// * `order_update_tx` - cbc::Sender<Vec<Order>> - wakes the main loop if this cab got new orders.
+///
+/// # Returns:
+///
+/// Returns - None - .
+///
+pub fn handle_gossip(msg: &UdpMsg, state: Arc<SystemState>, order_update_tx: cbc::Sender<Vec<Order>>) {
+    let gossip = if let UdpData::Gossip(gossip) = &msg.data {
+        gossip.clone()
+    } else {
+        println!("Couldnt read Gossip message");
+        return;
+    };
+    if gossip.cab.id == state.me_id {
+        return;
+    }
+
+    let changed = state.update(move |s| {
+        if s.coordination != Coordination::Peer {
+            println!("Gossip from elevator {}, but this node runs with a master", gossip.cab.id);
+            return false;
+        }
+
+        let mut peer = gossip.cab;
+        peer.alive = true;
+        peer.last_lifesign = system_now();
+        match s.known_elevators.iter_mut().find(|e| e.id == peer.id) {
+            Some(known) => {
+                if !known.alive {
+                    println!("Elevator {} is back", peer.id);
+                }
+                known.merge_with(&peer);
+                known.queue = peer.queue;
+                known.alive = true;
+                known.last_lifesign = peer.last_lifesign;
+            }
+            None => {
+                println!("New peer: elevator {}", peer.id);
+                s.known_elevators.push(peer);
+            }
+        }
+
+        merge_hall_requests(&mut s.hall_requests, &gossip.hall_requests);
+        return apply_assignment(s);
+    });
+
+    if changed {
+        let _ = order_update_tx.send(Vec::new());
     }
-    
-    
 }
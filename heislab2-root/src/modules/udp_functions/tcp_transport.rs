@@ -0,0 +1,218 @@
+//! ## TCP Transport Module
+//! This module sends messages to one node over TCP streams instead of UDP datagrams, so they are not lost or
+//! reordered on a bad network and a node that is gone is noticed when sending. Only a message written just as the
+//! peer died can still be lost, the acks of the protocol cover that. Messages to every node still go on UDP, TCP has no broadcast.
+//! Turn it on with "transport_tcp: true" in "boot.txt", see transport.rs.
+//!
+//! ## The structs includes:
+//! - **TcpTransport**: A UDP transport for messages to every node and one TCP stream to every node talked to.
+//!
+//! ## The functions includes:
+//! - 'open'   binds the UDP sockets and the TCP listener and starts the threads reading from them.
+//!
+//! - Every node listens for streams on the port of its inn address, the same number as the UDP port.
+//! - A message is the length as 4 bytes (big endian) and then the encoded message.
+//! - A stream the peer has closed, or that fails, is connected again once before the message is given up,
+//!   the peer may have restarted.
+//! - Datagrams from UDP and messages from every stream end up in one queue read by recv_timeout.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel as cbc;
+
+use crate::modules::udp_functions::transport::{Datagram, Transport, TransportConfig, UdpTransport};
+
+// Longest wait for a node to answer a new stream, the sending thread waits this long for a dead node
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+// Longest message read from a stream, a longer length means the stream is broken
+const MAX_FRAME: usize = 64 * 1024;
+// How often the listener looks for new streams and the UDP socket is read
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+/// Messages to one node on TCP, messages to every node on UDP
+#[derive(Debug)]
+pub struct TcpTransport {
+    udp: Arc<UdpTransport>,
+    streams: Mutex<HashMap<SocketAddr, TcpStream>>,   // Inn address of the peer -> stream this node opened to it
+    inbox: cbc::Receiver<Datagram>,
+    closed: Arc<AtomicBool>,                          // Stops the threads when the transport is dropped
+}
+
+impl TcpTransport {
+    /// open
+    /// Bind the UDP sockets and the TCP listener and start reading from them
+    ///
+    /// # Arguments:
+    ///
+    /// * `inn_address` - SocketAddr - the port is listened on for datagrams and streams.
+    /// * `out_address` - SocketAddr - datagrams are sent from here.
+    /// * `config` - TransportConfig - how messages to every node are sent.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - std::io::Result<TcpTransport> - an error if a socket could not be bound.
+    ///
+    pub fn open(inn_address: SocketAddr, out_address: SocketAddr, config: TransportConfig) -> std::io::Result<TcpTransport> {
+        let udp = Arc::new(UdpTransport::open(inn_address, out_address, config)?);
+        let listen_address = match inn_address.ip() {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), inn_address.port()),
+            IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), inn_address.port()),
+        };
+        let listener = TcpListener::bind(listen_address)?;
+        listener.set_nonblocking(true)?;
+        println!("Listening for TCP streams on {}", listen_address);
+
+        let (inbox_tx, inbox) = cbc::unbounded();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let (udp_clone, tx, closed_clone) = (Arc::clone(&udp), inbox_tx.clone(), Arc::clone(&closed));
+        thread::spawn(move || {
+            while !closed_clone.load(Ordering::Relaxed) {
+                if let Some(datagram) = udp_clone.recv_timeout(POLL_PERIOD) {
+                    if tx.send(datagram).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        let closed_clone = Arc::clone(&closed);
+        thread::spawn(move || {
+            while !closed_clone.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, from)) => {
+                        let tx = inbox_tx.clone();
+                        let closed = Arc::clone(&closed_clone);
+                        thread::spawn(move || read_stream(stream, from, tx, closed));
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(POLL_PERIOD),
+                    Err(e) => {
+                        eprintln!("Failed to accept TCP stream: {}", e);
+                        thread::sleep(POLL_PERIOD);
+                    }
+                }
+            }
+        });
+
+        return Ok(TcpTransport { udp, streams: Mutex::new(HashMap::new()), inbox, closed });
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_to(&self, peer: &SocketAddr, datagram: &[u8]) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        for _attempt in 0..2 {
+            if streams.get(peer).is_some_and(peer_closed) {
+                streams.remove(peer);
+            }
+            if !streams.contains_key(peer) {
+                match connect(peer) {
+                    Ok(stream) => {
+                        streams.insert(*peer, stream);
+                    }
+                    Err(e) => {
+                        eprintln!("Could not open TCP stream to {}: {}", peer, e);
+                        return false;
+                    }
+                }
+            }
+            let stream = streams.get_mut(peer).unwrap();
+            let length = (datagram.len() as u32).to_be_bytes();
+            if stream.write_all(&length).and_then(|_| stream.write_all(datagram)).is_ok() {
+                return true;
+            }
+            // The peer may have restarted, try a new stream
+            streams.remove(peer);
+        }
+        eprintln!("Error sending to {} on TCP", peer);
+        return false;
+    }
+
+    fn broadcast(&self, datagram: &[u8]) -> bool {
+        return self.udp.broadcast(datagram);
+    }
+
+    fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram> {
+        return self.inbox.recv_timeout(max_wait).ok();
+    }
+
+    fn accepts(&self, sender: IpAddr) -> bool {
+        return self.udp.accepts(sender);
+    }
+
+    fn local_address(&self) -> SocketAddr {
+        return self.udp.local_address();
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+fn connect(peer: &SocketAddr) -> std::io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(peer, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    return Ok(stream);
+}
+
+// True if the peer has closed the stream, a write would seem to work but the message would be lost
+fn peer_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => false,
+        Err(_) => true,
+    };
+    return closed || stream.set_nonblocking(false).is_err();
+}
+
+// Put every message of a stream in the inbox until the stream closes or the transport is dropped
+fn read_stream(mut stream: TcpStream, from: SocketAddr, inbox_tx: cbc::Sender<Datagram>, closed: Arc<AtomicBool>) {
+    // Wake up now and then to see if the transport is dropped
+    if stream.set_nonblocking(false).and_then(|_| stream.set_read_timeout(Some(POLL_PERIOD * 10))).is_err() {
+        return;
+    }
+    loop {
+        let mut length = [0; 4];
+        if !read_full(&mut stream, &mut length, &closed) {
+            return;
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME {
+            println!("TCP stream from {} sent a message of {} bytes, closing it", from, length);
+            return;
+        }
+        let mut message = vec![0; length];
+        if !read_full(&mut stream, &mut message, &closed) || inbox_tx.send((message, from)).is_err() {
+            return;
+        }
+    }
+}
+
+// Fill `buffer` from the stream, false if the stream closed, failed or the transport is dropped first
+fn read_full(stream: &mut TcpStream, buffer: &mut [u8], closed: &AtomicBool) -> bool {
+    let mut read = 0;
+    while read < buffer.len() {
+        if closed.load(Ordering::Relaxed) {
+            return false;
+        }
+        match stream.read(&mut buffer[read..]) {
+            Ok(0) => return false,
+            Ok(size) => read += size,
+            Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => {}
+            Err(_) => return false,
+        }
+    }
+    return true;
+}
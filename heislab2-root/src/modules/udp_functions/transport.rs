@@ -0,0 +1,515 @@
+//! ## Transport Module
+//! This module moves encoded messages between the nodes. The UdpHandler only knows the `Transport` trait,
+//! so the protocol runs the same over UDP sockets, the in-memory network of the simulator (virtual_network.rs)
+//! or TCP streams (tcp_transport.rs). Peers are known by their inn address, the address in their Cab.
+//!
+//! The UDP transport decides how messages meant for every node leave this node, on which network interface,
+//! and which senders are listened to. It keeps one sender socket for its whole life and sends both
+//! unicasts and these "broadcasts" on it. Both IPv4 and IPv6 are supported.
+//!
+//! ## The traits includes:
+//! - **Transport**: Send to one peer, send to every node, wait for the next datagram.
+//!
+//! ## The structs includes:
+//! - **UdpTransport**: The transport on UDP sockets, set up by a TransportConfig.
+//! - **TransportMode**: Broadcast to the subnet, send to a multicast group, or send to a fixed list of nodes.
+//! - **TransportConfig**: The mode, the interface to use and the settings of the mode.
+//! - **Cidr**: A block of addresses like "10.100.23.0/24" or "fe80::/64", used for the list of allowed senders.
+//!
+//! ## The functions includes:
+//! - 'open'             binds the sockets of a UdpTransport.
+//! - 'parse'            reads one "key: value" line from "boot.txt" into a `TransportConfig`.
+//! - 'interface_ip'     the address of the configured interface, given as an address or a name like "eno1".
+//! - 'interface_index'  the number the OS gives the interface, needed for IPv6 link-local addresses.
+//! - 'local_ip'         the address this node sends from, the configured interface or the one picked by the OS.
+//! - 'local_address'    the address and port other nodes reach this node on.
+//! - 'any_address'      the address the receiver socket listens on, every interface of the right family.
+//! - 'destinations'     where a message to every node is sent.
+//! - 'setup'            turns on broadcast or joins the multicast group on the sockets of a UdpHandler.
+//! - 'accepts'          true if a datagram from an address should be read.
+//!
+//! On a PC with several network cards the OS may pick the wrong one, and messages from the other nodes are then
+//! rejected as coming from another subnet. Set "transport_interface" to the card on the elevator network:
+//! the sockets are bound to its address, so broadcasts and multicasts leave on it and the subnet check uses it.
+//!
+//! IPv6 has no broadcast, a broadcast is sent to the link-local all-nodes group ff02::1 instead.
+//! Link-local addresses (fe80::) are only unique on one link, so they are sent on the configured interface.
+//! The TTL is not used for IPv6, a link-local group never leaves the link.
+//!
+//! Without "transport_allow" a sender is read if it is on the same subnet as this node, /24 for IPv4 and /64 for IPv6.
+//!
+//! ## Keys in "boot.txt":
+//! ```text
+//! transport: broadcast            broadcast, multicast or unicast
+//! transport_ipv6: false           true to talk IPv6 instead of IPv4
+//! transport_interface: eno1       name or address of the interface to use, the OS picks one if missing
+//! transport_group: 239.255.0.20   multicast group, every node must use the same, ff02::4c48 for IPv6
+//! transport_ttl: 1                how many routers an IPv4 multicast may pass, 1 stays on the subnet
+//! transport_peers: 10.100.23.21, 10.100.23.22:20000   the nodes for unicast, the port is 20000 if missing
+//! transport_allow: 10.100.23.0/24, fe80::/64          senders that are read, instead of the subnet of this node
+//! transport_tcp: false            true to send messages to one node on TCP, messages to every node still go on UDP
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::time::Duration;
+
+use local_ip_address::{list_afinet_netifas, local_ip, local_ipv6};
+
+use crate::modules::udp_functions::udp::{same_subnet, BROADCAST_PORT};
+
+/// Longest datagram read, longer ones are cut like by a real socket with a small buffer
+pub const MAX_DATAGRAM: usize = 1024;
+
+/// A datagram and the address it came from
+pub type Datagram = (Vec<u8>, SocketAddr);
+
+/// What the UdpHandler needs from the network, every method can be called from several threads
+pub trait Transport: Send + Sync + fmt::Debug {
+    /// Send a datagram to the node with inn address `peer`, false if it could not be sent
+    fn send_to(&self, peer: &SocketAddr, datagram: &[u8]) -> bool;
+
+    /// Send a datagram to every node, false if it could not be sent
+    fn broadcast(&self, datagram: &[u8]) -> bool;
+
+    /// Wait for the next datagram, None if nothing arrived within `max_wait`
+    fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram>;
+
+    /// True if a datagram from `sender` should be read
+    fn accepts(&self, sender: IpAddr) -> bool;
+
+    /// The address datagrams from this node are sent from
+    fn local_address(&self) -> SocketAddr;
+}
+
+/// The transport on UDP sockets, one to send on and one to receive on
+#[derive(Debug)]
+pub struct UdpTransport {
+    sender: UdpSocket,
+    receiver: UdpSocket,
+    local_ip: IpAddr,               // Senders are checked against the subnet of this address
+    pub config: TransportConfig,
+}
+
+/// How a message to every node is sent
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TransportMode {
+    #[default]
+    Broadcast,      // To 255.255.255.255 or ff02::1, every node on the subnet
+    Multicast,      // To the multicast group, every node that has joined it
+    Unicast,        // One copy to every node in the peer list, for networks that drop broadcasts
+}
+
+/// A block of addresses, the first `prefix` bits of `network`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    pub network: IpAddr,
+    pub prefix: u8,
+}
+
+/// The transport of a node, see the keys above
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportConfig {
+    pub mode: TransportMode,
+    pub ipv6: bool,
+    pub interface: Option<String>,
+    pub group: Option<IpAddr>,      // None uses the default group of the family
+    pub ttl: u32,
+    pub peers: Vec<SocketAddr>,
+    pub allowed: Vec<Cidr>,         // Empty reads every sender on the subnet of this node
+    pub tcp: bool,                  // Messages to one node go on TCP, see tcp_transport.rs
+}
+
+// Groups used when "transport_group" is missing
+const DEFAULT_GROUP_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 20);
+const DEFAULT_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x4c48);
+// Every IPv6 node on the link listens to this group
+const ALL_NODES_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            mode: TransportMode::Broadcast,
+            ipv6: false,
+            interface: None,
+            group: None,
+            ttl: 1,
+            peers: Vec::new(),
+            allowed: Vec::new(),
+            tcp: false,
+        }
+    }
+}
+
+impl Cidr {
+    /// parse
+    /// Read a block like "10.100.23.0/24" or "fe80::/64", an address without "/" is a block of one address
+    ///
+    /// # Arguments:
+    ///
+    /// * `text` - &str - the block.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Option<Cidr> - None if the address can't be read or the prefix is too long for the family.
+    ///
+    pub fn parse(text: &str) -> Option<Cidr> {
+        let (address, prefix) = match text.trim().split_once('/') {
+            Some((address, prefix)) => (address.trim(), Some(prefix.trim().parse::<u8>().ok()?)),
+            None => (text.trim(), None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return None;
+        }
+        return Some(Cidr { network, prefix });
+    }
+
+    /// True if `address` is in the block, IPv4 addresses written as IPv6 (::ffff:10.0.0.1) count as IPv4
+    pub fn contains(&self, address: IpAddr) -> bool {
+        return match (self.network.to_canonical(), address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                network.to_bits() & mask == address.to_bits() & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                network.to_bits() & mask == address.to_bits() & mask
+            }
+            _ => false,
+        };
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}/{}", self.network, self.prefix);
+    }
+}
+
+impl TransportConfig {
+    /// parse
+    /// Read one line of "boot.txt"
+    ///
+    /// # Arguments:
+    ///
+    /// * `key` - &str - the text before ':', keys that don't start with "transport" are ignored.
+    /// * `value` - &str - the text after ':'.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - true if the key was a transport key and the value could be read.
+    ///
+    pub fn parse(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim();
+        match key.trim() {
+            "transport" => {
+                self.mode = match value {
+                    "broadcast" => TransportMode::Broadcast,
+                    "multicast" => TransportMode::Multicast,
+                    "unicast" => TransportMode::Unicast,
+                    _ => return false,
+                };
+            }
+            "transport_ipv6" => match value.parse() {
+                Ok(ipv6) => self.ipv6 = ipv6,
+                _ => return false,
+            },
+            "transport_tcp" => match value.parse() {
+                Ok(tcp) => self.tcp = tcp,
+                _ => return false,
+            },
+            "transport_interface" => {
+                if value.is_empty() {
+                    return false;
+                }
+                self.interface = Some(value.to_string());
+            }
+            "transport_group" => match value.parse::<IpAddr>() {
+                Ok(group) if group.is_multicast() => self.group = Some(group),
+                _ => return false,
+            },
+            "transport_ttl" => match value.parse() {
+                Ok(ttl) => self.ttl = ttl,
+                _ => return false,
+            },
+            "transport_peers" => {
+                let mut peers = Vec::new();
+                for peer in value.split(',').map(str::trim).filter(|peer| !peer.is_empty()) {
+                    match parse_peer(peer) {
+                        Some(address) => peers.push(address),
+                        None => return false,
+                    }
+                }
+                self.peers = peers;
+            }
+            "transport_allow" => {
+                let mut allowed = Vec::new();
+                for block in value.split(',').map(str::trim).filter(|block| !block.is_empty()) {
+                    match Cidr::parse(block) {
+                        Some(block) => allowed.push(block),
+                        None => return false,
+                    }
+                }
+                self.allowed = allowed;
+            }
+            _ => return false,
+        }
+        return true;
+    }
+
+    /// The multicast group of the node, the one in "boot.txt" or the default of the family
+    pub fn group(&self) -> IpAddr {
+        if let Some(group) = self.group {
+            return group;
+        }
+        if self.ipv6 {
+            return IpAddr::V6(DEFAULT_GROUP_V6);
+        }
+        return IpAddr::V4(DEFAULT_GROUP_V4);
+    }
+
+    /// The address of the configured interface in the family of the node, None if no interface is set or it can't be found
+    pub fn interface_ip(&self) -> Option<IpAddr> {
+        let interface = self.interface.as_deref()?;
+        if let Ok(address) = interface.parse::<IpAddr>() {
+            return Some(address);
+        }
+        let found = list_afinet_netifas().ok()?.into_iter().find_map(|(name, address)| {
+            if name == interface && address.is_ipv6() == self.ipv6 {
+                return Some(address);
+            }
+            return None;
+        });
+        if found.is_none() {
+            println!("Network interface {} has no {} address, letting the OS pick one", interface, if self.ipv6 { "IPv6" } else { "IPv4" });
+        }
+        return found;
+    }
+
+    /// interface_index
+    /// The number the OS gives the configured interface, used as the scope of IPv6 link-local addresses
+    ///
+    /// # Returns:
+    ///
+    /// Returns - u32 - the index, 0 lets the OS pick the interface.
+    ///
+    pub fn interface_index(&self) -> u32 {
+        let interface = match self.interface.as_deref() {
+            Some(interface) => interface,
+            None => return 0,
+        };
+        // An address is looked up to find the name of its interface
+        let name = match interface.parse::<IpAddr>() {
+            Ok(address) => match list_afinet_netifas().ok().and_then(|list| list.into_iter().find(|(_, found)| *found == address)) {
+                Some((name, _)) => name,
+                None => return 0,
+            },
+            Err(_) => interface.to_string(),
+        };
+        return fs::read_to_string(format!("/sys/class/net/{}/ifindex", name))
+            .ok()
+            .and_then(|index| index.trim().parse().ok())
+            .unwrap_or(0);
+    }
+
+    /// The address this node sends from, the configured interface if it is found, else the one the OS picks
+    pub fn local_ip(&self) -> IpAddr {
+        if let Some(address) = self.interface_ip() {
+            return address;
+        }
+        if self.ipv6 {
+            return local_ipv6().unwrap_or(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        }
+        return local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    /// The address other nodes reach this node on at `port`, with the scope of the interface if it is link-local
+    pub fn local_address(&self, port: u16) -> SocketAddr {
+        return self.scoped(SocketAddr::new(self.local_ip(), port));
+    }
+
+    /// Every address of the family of the node at `port`, what the receiver socket listens on
+    pub fn any_address(&self, port: u16) -> SocketAddr {
+        if self.ipv6 {
+            return SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+        }
+        return SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    }
+
+    /// destinations
+    /// Where a message to every node is sent
+    ///
+    /// # Returns:
+    ///
+    /// Returns - Vec<SocketAddr> - the broadcast address, the multicast group or the peer list, all on the node port.
+    ///
+    pub fn destinations(&self) -> Vec<SocketAddr> {
+        let destinations = match self.mode {
+            TransportMode::Broadcast if self.ipv6 => vec![SocketAddr::new(IpAddr::V6(ALL_NODES_V6), BROADCAST_PORT)],
+            TransportMode::Broadcast => vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), BROADCAST_PORT)],
+            TransportMode::Multicast => vec![SocketAddr::new(self.group(), BROADCAST_PORT)],
+            TransportMode::Unicast => self.peers.clone(),
+        };
+        return destinations.into_iter().map(|destination| self.scoped(destination)).collect();
+    }
+
+    /// setup
+    /// Prepare the sockets of a UdpHandler for the mode
+    ///
+    /// # Arguments:
+    ///
+    /// * `sender` - &UdpSocket - the socket everything is sent on, bound to the interface.
+    /// * `receiver` - &UdpSocket - the socket messages are received on.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - std::io::Result<()> - an error if the OS refused an option or the group could not be joined.
+    ///
+    pub fn setup(&self, sender: &UdpSocket, receiver: &UdpSocket) -> std::io::Result<()> {
+        if !self.ipv6 {
+            sender.set_broadcast(self.mode == TransportMode::Broadcast)?;
+        }
+        if self.mode != TransportMode::Multicast {
+            return Ok(());
+        }
+        match self.group() {
+            IpAddr::V4(group) => {
+                sender.set_multicast_ttl_v4(self.ttl)?;
+                // Other nodes on the same PC must get the messages too
+                sender.set_multicast_loop_v4(true)?;
+                let interface = match self.interface_ip() {
+                    Some(IpAddr::V4(interface)) => interface,
+                    _ => Ipv4Addr::UNSPECIFIED,
+                };
+                receiver.join_multicast_v4(&group, &interface)?;
+                println!("Joined multicast group {} on {}", group, interface);
+            }
+            IpAddr::V6(group) => {
+                sender.set_multicast_loop_v6(true)?;
+                let index = self.interface_index();
+                receiver.join_multicast_v6(&group, index)?;
+                println!("Joined multicast group {} on interface {}", group, index);
+            }
+        }
+        return Ok(());
+    }
+
+    /// True if a datagram from `sender` should be read: from a node in the peer list, from an allowed block,
+    /// or from the subnet of `local` if no blocks are allowed
+    pub fn accepts(&self, local: IpAddr, sender: IpAddr) -> bool {
+        let sender = sender.to_canonical();
+        if self.peers.iter().any(|peer| peer.ip().to_canonical() == sender) {
+            return true;
+        }
+        if self.allowed.is_empty() {
+            return same_subnet(local, sender);
+        }
+        return self.allowed.iter().any(|block| block.contains(sender));
+    }
+
+    // Link-local IPv6 addresses and groups only mean something together with an interface
+    fn scoped(&self, address: SocketAddr) -> SocketAddr {
+        if let SocketAddr::V6(address) = address {
+            let ip = *address.ip();
+            let link_local = ip.is_unicast_link_local() || (ip.is_multicast() && ip.segments()[0] & 0x000f == 0x2);
+            if link_local && address.scope_id() == 0 {
+                return SocketAddr::V6(SocketAddrV6::new(ip, address.port(), 0, self.interface_index()));
+            }
+        }
+        return address;
+    }
+}
+
+impl UdpTransport {
+    /// open
+    /// Bind the sockets and set them up for the mode in `config`
+    ///
+    /// # Arguments:
+    ///
+    /// * `inn_address` - SocketAddr - the port is listened on, on every interface of the family.
+    /// * `out_address` - SocketAddr - the sender socket is bound to it, this picks the interface messages leave on.
+    /// * `config` - TransportConfig - how messages to every node are sent.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - std::io::Result<UdpTransport> - an error if a socket could not be bound.
+    ///
+    pub fn open(inn_address: SocketAddr, out_address: SocketAddr, config: TransportConfig) -> std::io::Result<UdpTransport> {
+        let sender = UdpSocket::bind(out_address)?;
+        // Listen on every interface, broadcasts and multicasts are not delivered to a socket bound to one address
+        let receiver_address = match inn_address.ip() {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), inn_address.port()),
+            IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), inn_address.port()),
+        };
+        let receiver = UdpSocket::bind(receiver_address)?;
+        sender.set_nonblocking(true)?;
+
+        if let Err(e) = config.setup(&sender, &receiver) {
+            eprintln!("Could not set up {:?} transport: {}", config.mode, e);
+        }
+        let local_ip = match out_address.ip().is_unspecified() {
+            true => config.local_ip(),
+            false => out_address.ip(),
+        };
+        return Ok(UdpTransport { sender, receiver, local_ip, config });
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&self, peer: &SocketAddr, datagram: &[u8]) -> bool {
+        match self.sender.send_to(datagram, peer) {
+            Ok(_) => return true,
+            Err(e) => {
+                eprintln!("Error sending to {}: {}", peer, e);
+                return false;
+            }
+        }
+    }
+
+    fn broadcast(&self, datagram: &[u8]) -> bool {
+        let mut sent = false;
+        for destination in self.config.destinations() {
+            sent |= self.send_to(&destination, datagram);
+        }
+        return sent;
+    }
+
+    fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram> {
+        // A timeout of zero is not allowed, it would wait forever
+        let max_wait = max_wait.max(Duration::from_millis(1));
+        self.receiver.set_read_timeout(Some(max_wait)).expect("Failed to set timeout for socket");
+        let mut buffer = [0; MAX_DATAGRAM];
+        match self.receiver.recv_from(&mut buffer) {
+            Ok((size, sender)) => return Some((buffer[..size].to_vec(), sender)),
+            Err(ref e) if (e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut) => {
+                // Ignore the error if it's just a timeout
+                return None;
+            }
+            Err(e) => {
+                println!("Failed to receive message: {}", e);
+                return None;
+            }
+        }
+    }
+
+    fn accepts(&self, sender: IpAddr) -> bool {
+        return self.config.accepts(self.local_ip, sender);
+    }
+
+    fn local_address(&self) -> SocketAddr {
+        return self.sender.local_addr().expect("Sender socket has no address");
+    }
+}
+
+// "10.100.23.21", "10.100.23.21:20001", "fe80::1" or "[fe80::1]:20001"
+fn parse_peer(peer: &str) -> Option<SocketAddr> {
+    if let Ok(address) = peer.parse() {
+        return Some(address);
+    }
+    let ip: IpAddr = peer.parse().ok()?;
+    return Some(SocketAddr::new(ip, BROADCAST_PORT));
+}
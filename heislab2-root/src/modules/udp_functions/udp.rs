@@ -4,6 +4,7 @@
 //! ## The structs includes:
 //! - **UdpMsg**: Contains the data that is being sendt aswell as a header that describes the message
 //! - **UdpHeader**: Contains information about the message such as sender, checksum and message type
+//! - **ProtocolError**: Why a datagram could not be read or a message could not be sent in a version
 //! 
 //! ## Message Types:
 //! - **Wordview:** Synchronizes the system's state across the diffrent nodes, only sent by master node.
@@ -13,6 +14,11 @@
 //! - **New_Online:** Informs that a new elevator has joined the system/gone online.
 //! - **Error_Worldview:** Reports inconsistencies in worldview synchronization.
 //! - **Error_Offline:** Handles elevator disconnections.
+//! - **Handover:** The state a master hands to its successor before it shuts down, see shutdown.rs.
+//! - **Leaving:** A node announces it is shutting down, after its hall orders are handed off.
+//! - **Gossip:** The cab and the hall requests of a node running as a peer, see peer.rs.
+//! - **WorldviewUpdate:** A delta or a part of a snapshot of the worldview of the master, see worldview_delta.rs.
+//! - **ResyncRequest:** A node missed a worldview update and asks the master for a snapshot.
 //! 
 //! ## The functions includes:
 //! - 'make_udp_msg'  Formats a UDP message.
@@ -20,10 +26,13 @@
 //! - 'handle_"Message_type"' handels each spesific mesesage type.
 //! - 'msg_serialize'     serializes UDP messages for transmission.           
 //! - 'msg_deserialize'    deserializes transmitted udp messages.
+//! - 'encode_msg'     serializes a message in a given protocol version, with the magic bytes and the version first.
+//! - 'encode_msg_with'  the same in a given codec, see codec.rs.
+//! - 'decode_msg'     checks the magic bytes and the version and deserializes the rest in the codec the header names.
+//! - 'negotiate'      the newest protocol version two nodes both speak.
 //! - 'calc_checksum'  calculates checksum to ensure message integrity.
 //! - 'comp_checksum'  compares checksum of recived message to the calculated checksum.
 //! - 'udp_send'       sending of udp messages without requirement for acknowledment.
-//! - 'udp_broadcast'  broadcasts UDP messages.
 //! - 'udp_recive_ensure'  recives UDP messages and responds with ACK if message is accepted/correct.
 //! - 'udp_send_ensure'  sends UDP messages and waits for ACK,if not recvied within timeout, it resends untill it runs out of retries.
 //!
@@ -34,20 +43,32 @@
 //! [dependencies]
 //! serde = { version = "1", features = ["derive"] }
 //! bincode = "1"
+//! serde_json = "1"
 //! sha2 = { version = "0.11.0-pre.4" }
 //! ```
 //! these are primarily used for serialization/deserialization and calculation hash for checksum. 
+//!
+//! ## Wire format
+//! Every datagram starts with the magic bytes "HL" and one byte with the protocol version, then the UdpMsg in bincode.
+//! In a debug cluster the UdpMsg is JSON instead, the version byte then has CODEC_FLAG_JSON set, see codec.rs.
+//! A node speaks every version from OLDEST_PROTOCOL_VERSION to PROTOCOL_VERSION and says so in its Cab (see NewOnline).
+//! Messages to a known node are sent in the newest version both speak, everything else in the oldest version,
+//! so nodes can be upgraded one at a time. A message type that is new in a version is never sent in an older one.
+//! Every message, broadcasts too, goes through the UdpHandler and its transport, see udp_handler_init.rs and transport.rs.
+//! Version 1 is the first framed version and its Cab already has every field up to protocol_versions. A node from before
+//! the framing sends no magic bytes and is rejected with BadMagic, it can't take part. A new field in the Cab or in any
+//! other data changes the layout: bump PROTOCOL_VERSION and leave the field out when sending in an older version.
 
 #[allow(unused_imports)]
 #[allow(unused_variables)]
 #[allow(non_camel_case_types)]                  // https://doc.rust-lang.org/std/net/struct.UdpSocket.html       
 use serde::{Deserialize, Serialize};            // https://serde.rs/impl-serialize.html         //Add to Cargo.toml file, Check comment above
                                                 // https://docs.rs/serde/latest/serde/ser/trait.Serialize.html#tymethod.serialize
-use bincode;                                    // https://docs.rs/bincode/latest/bincode/      //Add to Cargo.toml file, Check comment above
 use crc32fast::Hasher;                          // Add to Cargo.toml file, Check comment above  //Add to Cargo,toml Smaller but less secure hash than Sha256, this is 4Bytes while Sha256 is 32Bytes
 use std::sync::Arc;                             // https://doc.rust-lang.org/std/sync/struct.Mutex.html
 
-use std::net::{SocketAddr,IpAddr,UdpSocket};
+use std::fmt;
+use std::net::{SocketAddr,IpAddr};
 
 
 use crate::modules::order_object::order_init::Order;
@@ -55,6 +76,11 @@ use crate::modules::elevator_object::elevator_init::SystemState;
 use crate::modules::cab_object::cab::Cab;
 
 use crate::modules::udp_functions::udp_handler_init::*;
+use crate::modules::udp_functions::transport::Cidr;
+use crate::modules::shutdown::Handover;
+use crate::modules::peer_functions::peer::Gossip;
+use crate::modules::master_functions::worldview_delta::WorldviewUpdate;
+use crate::modules::udp_functions::codec::{Codec, CHECKSUM_CODEC};
 
 
 pub use crate::modules::elevator_object::*;
@@ -62,6 +88,12 @@ pub use elevator_init::Elevator;
 pub use alias_lib::{HALL_DOWN, HALL_UP,CAB, DIRN_DOWN, DIRN_UP, DIRN_STOP};
 
 
+//----------------------------------------------Constants
+pub const BROADCAST_PORT: u16 = 20000;     // Port every node listens for broadcasts on
+pub const PROTOCOL_MAGIC: [u8; 2] = *b"HL"; // First bytes of every datagram
+pub const PROTOCOL_VERSION: u8 = 4;        // Newest protocol version this node speaks, bump when the wire format changes
+pub const OLDEST_PROTOCOL_VERSION: u8 = 1; // Oldest protocol version this node can still read and write
+
 //----------------------------------------------Enum
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub enum MessageType {
@@ -81,9 +113,53 @@ pub enum MessageType {
     RemoveOrder,
     NewRequest,
     ImAlive,
+    Maintenance,
+    Park,
+    Handover,
+    Leaving,
+    Gossip,
+    WorldviewUpdate,
+    ResyncRequest,
+}
+
+impl MessageType {
+    /// The protocol version the message type came with, it is not sent to nodes speaking an older version
+    pub fn since_version(&self) -> u8 {
+        // Give a new type the version it comes with
+        match self {
+            MessageType::Handover | MessageType::Leaving => 2,
+            MessageType::Gossip => 3,
+            MessageType::WorldviewUpdate | MessageType::ResyncRequest => 4,
+            _ => OLDEST_PROTOCOL_VERSION,
+        }
+    }
 }
 
 //----------------------------------------------Structs
+/// Why a datagram could not be read, or a message could not be sent in a version
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProtocolError {
+    BadMagic,                                       // Not from this system, or from a node older than the versioned protocol
+    UnsupportedVersion(u8),                         // Framed in a version this node does not speak
+    NotInVersion(MessageType, u8),                  // The message type is newer than the version it was to be sent in
+    Incompatible { ours: (u8, u8), theirs: (u8, u8) }, // The two nodes have no version in common
+    Malformed(String),                              // The message could not be read in its codec
+    WrongData(MessageType),                         // The data does not fit the message type
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::BadMagic => write!(f, "no magic bytes, not a message of this protocol"),
+            ProtocolError::UnsupportedVersion(version) => write!(f, "protocol version {} not supported, this node speaks {}-{}", version, OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            ProtocolError::NotInVersion(message_type, version) => write!(f, "{:?} is not in protocol version {}", message_type, version),
+            ProtocolError::Incompatible { ours, theirs } => write!(f, "no common protocol version, this node speaks {}-{} and the other {}-{}", ours.0, ours.1, theirs.0, theirs.1),
+            ProtocolError::Malformed(e) => write!(f, "could not deserialize message: {}", e),
+            ProtocolError::WrongData(message_type) => write!(f, "wrong data for {:?}", message_type),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, PartialEq, Deserialize, Clone)] // this is needed to serialize message
 //UDP Header
 pub struct UdpHeader {
@@ -107,6 +183,9 @@ pub enum UdpData {
     Cab(Cab),
     Orders(Vec<Order>),
     Order(Order),
+    Handover(Handover),
+    Gossip(Gossip),
+    WorldviewUpdate(WorldviewUpdate),
 }
 
 
@@ -147,8 +226,7 @@ pub fn make_udp_msg(sender_id: u8,message_type: MessageType, message: UdpData) -
 /// Returns - Vec<u8>- a string of the serialized message.
 ///
 pub fn msg_serialize(msg: &UdpMsg) -> Vec<u8> {
-    let serialized_msg = bincode::serialize(msg).expect("Failed to serialize message");
-    return serialized_msg;
+    return encode_msg(msg, PROTOCOL_VERSION).expect("Failed to serialize message");
 }
 
 /// deserialize
@@ -162,23 +240,111 @@ pub fn msg_serialize(msg: &UdpMsg) -> Vec<u8> {
 /// Returns - Option<UdpMsg>- .returns either the deserialized message or none
 ///
 pub fn msg_deserialize(buffer: &[u8]) -> Option<UdpMsg> {
-    match bincode::deserialize::<UdpMsg>(buffer) {
-        Ok(msg) => {
-            // println!("Deserialized msg type: {:?},Deserialized msg data: {:?}", msg.header.message_type, msg.data);
-            if data_valid_for_type(&msg) {
-                return Some(msg);
-            } else {
-                println!("Invalid data messagetype");
-                return None;
-            }
-        }
+    match decode_msg(buffer) {
+        Ok((_, msg)) => return Some(msg),
         Err(e) => {
-            println!("Failed to deserialize message: {}", e);
+            println!("Rejected message: {}", e);
             return None;
         }
     }
 }
 
+/// encode_msg
+/// Serialize a message in a protocol version, with the magic bytes and the version first. Written in bincode
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - the message.
+/// * `version` - u8 - the version to write, see negotiate.
+///
+/// # Returns:
+///
+/// Returns - Result<Vec<u8>, ProtocolError> - the datagram, or why the message can't be sent in that version.
+///
+pub fn encode_msg(msg: &UdpMsg, version: u8) -> Result<Vec<u8>, ProtocolError> {
+    return encode_msg_with(msg, version, Codec::Bincode);
+}
+
+/// encode_msg_with
+/// Serialize a message in a protocol version and a codec, the codec is flagged in the version byte
+///
+/// # Arguments:
+///
+/// * `msg` - &UdpMsg - the message.
+/// * `version` - u8 - the version to write, see negotiate.
+/// * `codec` - Codec - bincode or JSON, see codec.rs.
+///
+/// # Returns:
+///
+/// Returns - Result<Vec<u8>, ProtocolError> - the datagram, or why the message can't be sent in that version.
+///
+pub fn encode_msg_with(msg: &UdpMsg, version: u8, codec: Codec) -> Result<Vec<u8>, ProtocolError> {
+    if !(OLDEST_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    if msg.header.message_type.since_version() > version {
+        return Err(ProtocolError::NotInVersion(msg.header.message_type.clone(), version));
+    }
+    let payload = codec.encode(msg).map_err(ProtocolError::Malformed)?;
+
+    let mut datagram = Vec::with_capacity(PROTOCOL_MAGIC.len() + 1 + payload.len());
+    datagram.extend_from_slice(&PROTOCOL_MAGIC);
+    datagram.push(version | codec.flag());
+    datagram.extend_from_slice(&payload);
+    return Ok(datagram);
+}
+
+/// decode_msg
+/// Read a datagram written by encode_msg or encode_msg_with, in the codec flagged in its version byte
+///
+/// # Arguments:
+///
+/// * `buffer` - &[u8] - the datagram.
+///
+/// # Returns:
+///
+/// Returns - Result<(u8, UdpMsg), ProtocolError> - the version it was written in and the message, or why it was rejected.
+///
+pub fn decode_msg(buffer: &[u8]) -> Result<(u8, UdpMsg), ProtocolError> {
+    let header_len = PROTOCOL_MAGIC.len() + 1;
+    if buffer.len() < header_len || buffer[..PROTOCOL_MAGIC.len()] != PROTOCOL_MAGIC {
+        return Err(ProtocolError::BadMagic);
+    }
+    let (codec, version) = Codec::split_version(buffer[PROTOCOL_MAGIC.len()]);
+    if !(OLDEST_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+
+    let msg = codec.decode::<UdpMsg>(&buffer[header_len..]).map_err(ProtocolError::Malformed)?;
+    if msg.header.message_type.since_version() > version {
+        return Err(ProtocolError::NotInVersion(msg.header.message_type, version));
+    }
+    if !data_valid_for_type(&msg) {
+        return Err(ProtocolError::WrongData(msg.header.message_type));
+    }
+    return Ok((version, msg));
+}
+
+/// negotiate
+/// Find the newest protocol version two nodes both speak
+///
+/// # Arguments:
+///
+/// * `ours` - (u8, u8) - oldest and newest version of this node.
+/// * `theirs` - (u8, u8) - oldest and newest version of the other node, from its Cab.
+///
+/// # Returns:
+///
+/// Returns - Result<u8, ProtocolError> - the version to send in, or Incompatible if there is none.
+///
+pub fn negotiate(ours: (u8, u8), theirs: (u8, u8)) -> Result<u8, ProtocolError> {
+    let newest = ours.1.min(theirs.1);
+    if newest < ours.0.max(theirs.0) {
+        return Err(ProtocolError::Incompatible { ours, theirs });
+    }
+    return Ok(newest);
+}
+
 /// data_valid_for_type
 /// Checks that the message contains correct data structure for message type to ensure correct deserialization.
 /// used primarily in derserialization()
@@ -204,13 +370,20 @@ fn data_valid_for_type(msg: &UdpMsg) -> bool {
         (MessageType::NewOnline, UdpData::Cab(_)) => true,
         (MessageType::Ack, UdpData::Checksum(_)) => true,
         (MessageType::Nak, UdpData::Checksum(_)) => true,
-        (MessageType::RemoveOrder, UdpData::Cabs(_)) => true,
+        (MessageType::RemoveOrder, UdpData::Cab(_)) => true,
+        (MessageType::Maintenance, UdpData::Cab(_)) => true,
+        (MessageType::Park, UdpData::Cab(_)) => true,
+        (MessageType::Handover, UdpData::Handover(_)) => true,
+        (MessageType::Leaving, UdpData::Cab(_)) => true,
+        (MessageType::Gossip, UdpData::Gossip(_)) => true,
+        (MessageType::WorldviewUpdate, UdpData::WorldviewUpdate(_)) => true,
+        (MessageType::ResyncRequest, UdpData::Cab(_)) => true,
         _ => false,
     }
 }
 
 /// calc_checksum
-/// Calculate Checksum.
+/// Calculate Checksum, on the data in CHECKSUM_CODEC whatever codec the message is sent in.
 /// 
 /// # Arguments:
 /// 
@@ -221,7 +394,7 @@ fn data_valid_for_type(msg: &UdpMsg) -> bool {
 /// Returns - Vec<u8>- returns the hashed string .
 ///
 pub fn calc_checksum(data: &UdpData) -> u32 {
-    let serialized_data = bincode::serialize(data).expect("Failed to serialize data");
+    let serialized_data = CHECKSUM_CODEC.encode(data).expect("Failed to serialize data");
     let mut hasher = Hasher::new();
     hasher.update(&serialized_data);
     return hasher.finalize();
@@ -309,41 +482,9 @@ pub fn udp_nak(target_address: SocketAddr, original_msg: &UdpMsg, sender_id: u8,
 }
 
 
-///udp_broadcast
-///Broadcast
-/// 
-/// # Arguments:
-/// 
-/// * `msg` - &UdpMsg - .
-/// 
-/// # Returns:
-///
-/// Returns - bool - `true` if succesful else `false`.
-///
-pub fn udp_broadcast(msg: &UdpMsg) -> bool {
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind socket for broadcast");
-    socket
-        .set_broadcast(true)
-        .expect("failed to activate broadcast");
-
-    let msg = msg_serialize(msg);
-    let target_address = "255.255.255.255:20000";
-
-    match socket.send_to(&msg, target_address) {
-        Ok(_) => {
-            println!("Broadcast successful");
-            return true;
-        }
-        Err(e) => {
-            eprintln!("Error sending data: {}", e);
-            
-        }
-    }
-    return false;
-}  
-
 //same subnet
-//Check if the sender is from the same subnet.
+//Check if the sender is from the same subnet, /24 for IPv4 and /64 for IPv6.
+//A list of allowed blocks can be set instead, see transport.rs
 /// 
 /// # Arguments:
 /// 
@@ -356,18 +497,11 @@ pub fn udp_broadcast(msg: &UdpMsg) -> bool {
 ///
 pub fn same_subnet(local: IpAddr, remote: IpAddr) -> bool {
 
-    match (local, remote) {
-        // Check that both are IPv4
-        (IpAddr::V4(local_ip), IpAddr::V4(remote_ip)) => {
-            //Split IP adress into array of bytes
-            let local = local_ip.octets();
-            let remote = remote_ip.octets();
-            
-            // Check that each byte in the subnet matches its corresponding byte in the other adress
-            return local[0] == remote[0] && local[1] == remote[1] && local[2] == remote[2];
-        }
-        _ => return false
-    }
+    let prefix = match local.to_canonical() {
+        IpAddr::V4(_) => 24,
+        IpAddr::V6(_) => 64,
+    };
+    return Cidr { network: local, prefix }.contains(remote);
 }
 
 
@@ -387,18 +521,26 @@ pub fn confirm_recived(msg:&UdpMsg, state: &Arc<SystemState>) -> bool {
     
     let checksum = msg.header.checksum;
 
-    let mut sent_messages_locked = state.sent_messages.lock().unwrap();
-    if let Some(waiting_for_confirmation) = sent_messages_locked.iter().find(|m|m.message_hash==checksum){
+    let confirmed = state.update(move |s| {
+        let waiting_for_confirmation = s.sent_messages.iter().find(|m| m.message_hash == checksum)?;
+        if waiting_for_confirmation.all_confirmed {
+            //Remove messages
+            s.sent_messages.retain(|m| m.message_hash != checksum);
+            return Some(true);
+        }
+        return Some(false);
+    });
 
-        if waiting_for_confirmation.all_confirmed{
+    match confirmed {
+        Some(true) => {
             println!("Confirmation checked message with checksum {}, all acks recvied", checksum);
-            //Remove messages 
-            sent_messages_locked.retain(|m| m.message_hash != checksum);
             return true;
-        }else{
+        }
+        Some(false) => {
             println!("Confirmation checked message with checksum {}, Not recived all acks", checksum);
             return false;
         }
+        None => {}
     }
     println!("Checksum:{} not in sent messages", checksum);
     return false;
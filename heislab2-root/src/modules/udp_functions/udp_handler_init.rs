@@ -3,54 +3,82 @@
 #[allow(non_camel_case_types)]
 
 //----------------------------------------------Imports
-use std::net::{IpAddr,SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 use std::time::Duration;              // https://doc.rust-lang.org/std/time/struct.Duration.html
 use std::sync::{Mutex,Arc};                     // https://doc.rust-lang.org/std/sync/struct.Mutex.html
+use std::collections::HashMap;
 use crossbeam_channel as cbc;
-use std::thread;
 
 use crate::modules::udp_functions::message_handlers::*;
 use crate::modules::udp_functions::udp::*;
+use crate::modules::udp_functions::virtual_network::VirtualNic;
+use crate::modules::udp_functions::fault_injection::{FaultConfig, FaultInjector};
+use crate::modules::udp_functions::dispatcher::MessageDispatcher;
+use crate::modules::udp_functions::transport::{Transport, TransportConfig, UdpTransport};
+use crate::modules::udp_functions::codec::Codec;
+use crate::modules::system_clock::sleep;
 
 use crate::modules::order_object::order_init::Order;
 use crate::modules::elevator_object::elevator_init::SystemState;
 use crate::modules::cab_object::cab::Cab;
 use crate::modules::system_status::WaitingConfirmation;
+use crate::modules::isolation::heard_from;
+use crate::modules::master_functions::worldview_delta::WorldviewUpdate;
 
 pub use crate::modules::elevator_object::*;
 pub use elevator_init::Elevator;
 pub use alias_lib::{HALL_DOWN, HALL_UP,CAB, DIRN_DOWN, DIRN_UP, DIRN_STOP};
-use local_ip_address::local_ip;
 
 #[derive (Clone, Debug)]
 pub struct UdpHandler {
-    pub sender_socket: Arc<Mutex<UdpSocket>>,
-    pub receiver_socket: Arc<Mutex<UdpSocket>>,
+    pub transport: Arc<dyn Transport>,                    // UDP sockets, TCP or the virtual network of the simulator, see transport.rs
+    pub fault_injector: Option<Arc<FaultInjector>>,       // Set to test the system on a bad network
+    pub dispatcher: Arc<MessageDispatcher>,               // Workers running the handlers of received messages
+    pub peer_versions: Arc<Mutex<HashMap<SocketAddr, (u8, u8)>>>, // Inn address -> protocol versions the node speaks, from its Cab
+    pub codec: Codec,                                     // How sent messages are written, any codec is read, see codec.rs
 }
 
 
+/// Make a handler on UDP sockets bound to the addresses of `me`, sending to every node with broadcasts
 pub fn init_udp_handler(me: Cab) -> UdpHandler {
+    let transport = UdpTransport::open(me.inn_address, me.out_address, TransportConfig::default()).expect("Could not bind UDP sockets");
+    return init_handler(Arc::new(transport));
+}
+
+/// Make a handler that sends and receives on a virtual network instead of real sockets, used by the simulator
+pub fn init_virtual_udp_handler(nic: Arc<VirtualNic>) -> UdpHandler {
+    return init_handler(nic);
+}
 
-    let sender_socket = UdpSocket::bind(me.out_address).expect("Could not bind UDP socket");
-    let receiver_socket = UdpSocket::bind(me.inn_address).expect("Could not bind UDP receiver socket");
-    /* 
-    //Linjen under er det som jeg har tullet med som burde settes tilbake
-    let receiver_addr = format!("0.0.0.0:20000");
-    let receiver_socket = UdpSocket::bind(receiver_addr).expect("Could not bind UDP receiver socket");
-    */
-    
-    sender_socket.set_nonblocking(true).expect("Failed to set non-blocking mode");
-    receiver_socket.set_nonblocking(true).expect("Failed to set non-blocking mode");
-
-    //Turn sockets into mutexes
-    let sender_socket = Arc::new(Mutex::new(sender_socket));
-    let receiver_socket = Arc::new(Mutex::new(receiver_socket));
-    return UdpHandler{sender_socket,receiver_socket};
+/// Make a handler that sends and receives on any transport
+pub fn init_handler(transport: Arc<dyn Transport>) -> UdpHandler {
+    return UdpHandler{transport, fault_injector: None, dispatcher: Arc::new(MessageDispatcher::default()), peer_versions: Arc::default(), codec: Codec::default()};
 }
 
 
 impl UdpHandler {
 
+    /// Inject the faults in `config` on every message this handler sends and receives
+    pub fn with_faults(mut self, config: FaultConfig) -> UdpHandler {
+        println!("Fault injection enabled: {:?}", config);
+        self.fault_injector = Some(Arc::new(FaultInjector::new(config)));
+        return self;
+    }
+
+    /// Send every message in `codec`, JSON to read the traffic in Wireshark
+    pub fn with_codec(mut self, codec: Codec) -> UdpHandler {
+        println!("Sending messages in {:?}", codec);
+        self.codec = codec;
+        return self;
+    }
+
+    // Same handler talking to the network directly, used to send the copies made by the fault injector
+    fn without_faults(&self) -> UdpHandler {
+        let mut handler = self.clone();
+        handler.fault_injector = None;
+        return handler;
+    }
+
     /// receive
     /// 
     /// # Arguments:
@@ -64,50 +92,41 @@ impl UdpHandler {
     ///
     pub fn receive(self: Arc<Self>, max_wait: u32, state: &Arc<SystemState>, order_update_tx: cbc::Sender<Vec<Order>>, light_update_tx: cbc::Sender<Vec<Order>>) -> Option<UdpMsg> {
 
-        //Lock socket from udp.handler
-        let sock = self.receiver_socket.lock().expect("Failed to lock receiver socket");
-        //Set timeout for reciving messages (How long are you willing to wait)
-        sock.set_read_timeout(Some(Duration::from_millis(max_wait as u64))).expect("Failed to set timeout for socket");
-        let mut buffer = [0; 1024];
-
-
-        //Find IP
-        let local_ip = local_ip().unwrap();
-        drop(sock); 
-        
-
         loop{
 
-            let sock = self.receiver_socket.lock().expect("Failed to lock receiver socket");
             // Receive data
-            let (size, sender) = match sock.recv_from(&mut buffer) {
-                Ok(res) => res,
-                Err(ref e) if (e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut) => {
-                    // Ignore the error if it's just a timeout
-                    return None;
-                }
-                Err(e) => {
-                    println!("Failed to receive message: {}", e);
-                    return None;
-                }
-            };
-            drop(sock); 
-
-            let sender_ip = sender.ip();
+            let (datagram, sender) = self.transport.recv_timeout(Duration::from_millis(max_wait as u64))?;
 
             //Check that the sender is from the same subnet, we dont want any outside messages
-            //UNCOMMENT THIS
-            
-            if !same_subnet(local_ip, sender_ip) {
-                println!("Message from rejected {}(sender not in same subnet)",sender_ip);
+            if !self.transport.accepts(sender.ip()) {
+                println!("Message from rejected {}(sender not in same subnet)",sender.ip());
                 return None;
             }
             
 
-            println!("Received message of size {} from {}", size, sender);
+            println!("Received message of size {} from {}", datagram.len(), sender);
             // Identify Messagetype and handle appropriatly
-            if let Some(msg) = msg_deserialize(&buffer[..size]) {
+            let msg = match decode_msg(&datagram) {
+                Ok((_, msg)) => Some(msg),
+                Err(e) => {
+                    println!("Rejected message from {}: {}", sender, e);
+                    None
+                }
+            };
+            if let Some(msg) = msg {
                 println!("Message type: {:?}", msg.header.message_type);
+                self.learn_peer_versions(&msg);
+
+                if let Some(injector) = &self.fault_injector {
+                    if injector.partitioned(msg.header.sender_id, state.me_id) {
+                        println!("Fault injection: message from {} dropped by partition", msg.header.sender_id);
+                        continue;
+                    }
+                }
+
+                // Any message from another elevator shows this node is not isolated, see isolation.rs
+                let sender_id = msg.header.sender_id;
+                state.update(move |s| heard_from(s, sender_id));
 
                 let passable_state = Arc::clone(state);
                 let udp_handler_clone = Arc::clone(&self);
@@ -116,24 +135,32 @@ impl UdpHandler {
                 let light_update_tx_clone = light_update_tx.clone();
 
 
+                // Handlers run on the worker of their lane and sender, see dispatcher.rs
+                let dispatcher = &self.dispatcher;
+                let message_type = &msg.header.message_type;
                 match msg.header.message_type{
-                    MessageType::Worldview => {thread::spawn(move || {handle_worldview(passable_state, &msg_clone, udp_handler_clone)});},
-                    MessageType::Ack => {thread::spawn(move || {handle_ack(&msg_clone, passable_state)});},
-                    MessageType::Nak => {thread::spawn(move || {handle_nak(&msg_clone, passable_state, &sender, udp_handler_clone)});},
-                    MessageType::NewOrder => {thread::spawn(move || {handle_new_order(&msg_clone, &sender, passable_state, udp_handler_clone, light_update_tx_clone,tx_clone)});},
-                    MessageType::NewOnline => {thread::spawn(move ||{handle_new_online(&msg_clone, passable_state)});},
-                    MessageType::ErrorWorldview => {thread::spawn(move || {handle_error_worldview(&msg_clone, passable_state)});},
-                    MessageType::ErrorOffline => {handle_error_offline(&msg, passable_state, &self, tx_clone);},  // Some Error here, not sure what channel should be passed compiler says: "argument #4 of type `crossbeam_channel::Sender<Vec<Order>>` is missing"
-                    MessageType::OrderComplete => {thread::spawn(move || {if !(&msg_clone.header.sender_id == &passable_state.me_id){handle_order_completed(&msg_clone, passable_state, light_update_tx_clone);}});},
-                    MessageType::NewRequest => {thread::spawn(move || {handle_new_request(&msg_clone,passable_state, udp_handler_clone,tx_clone, light_update_tx_clone)});},
-                    MessageType::NewMaster => {thread::spawn(move ||{ handle_new_master(&msg_clone, passable_state)});},
-                    MessageType::ImAlive => {thread::spawn(move ||{ handle_im_alive(&msg_clone, passable_state)});},
+                    MessageType::Worldview => {dispatcher.dispatch(message_type, sender_id, move || {handle_worldview(passable_state, &msg_clone, udp_handler_clone)});},
+                    MessageType::Ack => {dispatcher.dispatch(message_type, sender_id, move || {handle_ack(&msg_clone, passable_state)});},
+                    MessageType::Nak => {dispatcher.dispatch(message_type, sender_id, move || {handle_nak(&msg_clone, passable_state, &sender, udp_handler_clone)});},
+                    MessageType::NewOrder => {dispatcher.dispatch(message_type, sender_id, move || {handle_new_order(&msg_clone, &sender, passable_state, udp_handler_clone, light_update_tx_clone,tx_clone);});},
+                    MessageType::NewOnline => {dispatcher.dispatch(message_type, sender_id, move ||{handle_new_online(&msg_clone, passable_state);});},
+                    MessageType::ErrorWorldview => {dispatcher.dispatch(message_type, sender_id, move || {handle_error_worldview(&msg_clone, passable_state)});},
+                    MessageType::ErrorOffline => {dispatcher.dispatch(message_type, sender_id, move || {handle_error_offline(&msg_clone, passable_state, &udp_handler_clone, tx_clone)});},
+                    MessageType::OrderComplete => {dispatcher.dispatch(message_type, sender_id, move || {if !(&msg_clone.header.sender_id == &passable_state.me_id){handle_order_completed(&msg_clone, passable_state, light_update_tx_clone);}});},
+                    MessageType::NewRequest => {dispatcher.dispatch(message_type, sender_id, move || {handle_new_request(&msg_clone,passable_state, udp_handler_clone,tx_clone, light_update_tx_clone)});},
+                    MessageType::NewMaster => {dispatcher.dispatch(message_type, sender_id, move ||{ handle_new_master(&msg_clone, passable_state)});},
+                    MessageType::ImAlive => {dispatcher.dispatch(message_type, sender_id, move ||{ handle_im_alive(&msg_clone, passable_state)});},
+                    MessageType::RemoveOrder => {dispatcher.dispatch(message_type, sender_id, move || {handle_remove_order(&msg_clone, passable_state, &udp_handler_clone, light_update_tx_clone, tx_clone)});},
+                    MessageType::Maintenance => {dispatcher.dispatch(message_type, sender_id, move || {handle_maintenance(&msg_clone, passable_state, &udp_handler_clone, tx_clone)});},
+                    MessageType::Park => {dispatcher.dispatch(message_type, sender_id, move || {handle_park(&msg_clone, passable_state, tx_clone)});},
+                    MessageType::Handover => {dispatcher.dispatch(message_type, sender_id, move || {handle_handover(&msg_clone, passable_state, &udp_handler_clone, tx_clone)});},
+                    MessageType::Gossip => {dispatcher.dispatch(message_type, sender_id, move || {handle_gossip(&msg_clone, passable_state, tx_clone)});},
+                    MessageType::Leaving => {dispatcher.dispatch(message_type, sender_id, move || {handle_leaving(&msg_clone, passable_state, &udp_handler_clone, tx_clone)});},
+                    MessageType::WorldviewUpdate => {dispatcher.dispatch(message_type, sender_id, move || {handle_worldview_update(passable_state, &msg_clone, udp_handler_clone)});},
+                    MessageType::ResyncRequest => {dispatcher.dispatch(message_type, sender_id, move || {handle_resync_request(&msg_clone, passable_state, &udp_handler_clone)});},
                     _ => println!("Unreadable message received from {}", sender),
                 };
                 //return Some(msg);
-            } else {
-                println!("Failed to deserialize message from {}", sender);
-                //return None;
             }
         }
     }
@@ -143,19 +170,86 @@ impl UdpHandler {
     /// Sends a UDP message
     pub fn send(&self, target_address: &SocketAddr, msg: &UdpMsg) -> bool {
 
-        let data = msg_serialize(msg);
-        let sock = self.sender_socket.lock().expect("Faild to lock socket for sending");
+        if let Some(injector) = &self.fault_injector {
+            let handler = self.without_faults();
+            let target = *target_address;
+            let msg = msg.clone();
+            return injector.inject(move || {handler.send(&target, &msg);});
+        }
 
-        match sock.send_to(&data, target_address) {
-            Ok(_) => {
-                println!("Message type:{:?} sent to: {}", msg.header.message_type, target_address);
-                return true;
+        let data = match self.version_for(target_address).and_then(|version| encode_msg_with(msg, version, self.codec)) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Not sending {:?} to {}: {}", msg.header.message_type, target_address, e);
+                return false;
             }
+        };
+
+        if self.transport.send_to(target_address, &data) {
+            println!("Message type:{:?} sent to: {}", msg.header.message_type, target_address);
+            return true;
+        }
+        return false;
+    }
+
+
+    /// Sends a UDP message to every node, the way the transport does it
+    pub fn broadcast(&self, msg: &UdpMsg) -> bool {
+        if let Some(injector) = &self.fault_injector {
+            let handler = self.without_faults();
+            let msg = msg.clone();
+            return injector.inject(move || {handler.broadcast(&msg);});
+        }
+        let data = match encode_msg_with(msg, self.broadcast_version(), self.codec) {
+            Ok(data) => data,
             Err(e) => {
-                eprintln!("Error sending message: {}", e);
+                eprintln!("Not broadcasting {:?}: {}", msg.header.message_type, e);
                 return false;
             }
         };
+        return self.transport.broadcast(&data);
+    }
+
+    /// The protocol version to send to `target_address` in: the newest both speak, the oldest if the node is not known yet
+    pub fn version_for(&self, target_address: &SocketAddr) -> Result<u8, ProtocolError> {
+        return match self.peer_versions.lock().unwrap().get(target_address) {
+            Some(theirs) => negotiate((OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION), *theirs),
+            None => Ok(OLDEST_PROTOCOL_VERSION),
+        };
+    }
+
+    /// The protocol version broadcasts are sent in: the newest every known node speaks, the oldest before any is known
+    pub fn broadcast_version(&self) -> u8 {
+        let peers = self.peer_versions.lock().unwrap();
+        if peers.is_empty() {
+            return OLDEST_PROTOCOL_VERSION;
+        }
+        return peers
+            .values()
+            .filter_map(|theirs| negotiate((OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION), *theirs).ok())
+            .min()
+            .unwrap_or(OLDEST_PROTOCOL_VERSION);
+    }
+
+    /// Remember the protocol versions every node in a received message speaks, see the Cab of NewOnline and ImAlive
+    pub fn learn_peer_versions(&self, msg: &UdpMsg) {
+        let cabs = match &msg.data {
+            UdpData::Cab(cab) => std::slice::from_ref(cab),
+            UdpData::Cabs(cabs) => cabs.as_slice(),
+            UdpData::Gossip(gossip) => std::slice::from_ref(&gossip.cab),
+            UdpData::WorldviewUpdate(WorldviewUpdate::Snapshot { cab, .. }) => std::slice::from_ref(cab),
+            _ => return,
+        };
+        let mut peers = self.peer_versions.lock().unwrap();
+        for cab in cabs {
+            let known = peers.insert(cab.inn_address, cab.protocol_versions);
+            if known != Some(cab.protocol_versions) {
+                match negotiate((OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION), cab.protocol_versions) {
+                    Ok(version) => println!("Elevator {} speaks protocol {}-{}, using version {}", cab.id, cab.protocol_versions.0, cab.protocol_versions.1, version),
+                    Err(e) => println!("Elevator {} can't be talked to: {}", cab.id, e),
+                }
+            }
+        }
     }
 
 
@@ -164,29 +258,29 @@ impl UdpHandler {
 
         // Add check for acks
         let confirmation = WaitingConfirmation {message_hash: message.header.checksum, responded_ids: vec![state.me_id],   all_confirmed: false,};
-        let mut sent_messages_locked = state.sent_messages.lock().unwrap();
-        sent_messages_locked.push(confirmation);
-        drop(sent_messages_locked);
+        let checksum = message.header.checksum;
+        let me_id = state.me_id;
+        let (known_elevators, only_elevator) = state.update(move |s| {
+            s.sent_messages.push(confirmation);
+
+            let only_elevator = s.known_elevators.len() == 1 && s.known_elevators[0].id == me_id;
+            if only_elevator {
+                //Mark message as recvied
+                if let Some(entry) = s.sent_messages.iter_mut().find(|m| m.message_hash == checksum) {
+                    entry.all_confirmed = true;
+                }
+            }
+            return (s.known_elevators.clone(), only_elevator);
+        });
 
-        let known_elevators_locked = state.known_elevators.lock().unwrap();
-        for elevator in known_elevators_locked.iter(){
-                            self.send(&elevator.inn_address, &message);
+        for elevator in known_elevators.iter(){
+            self.send(&elevator.inn_address, message);
         }
-        drop(known_elevators_locked);
 
-        let known_elevators_locked = state.known_elevators.lock().unwrap();
-        if known_elevators_locked.len() == 1 && known_elevators_locked[0].id == state.me_id {
-
-            //Mark message as recvied 
-            let mut sent_messages_locked = state.sent_messages.lock().unwrap();
-            if let Some(entry) = sent_messages_locked.iter_mut().find(|m| m.message_hash == message.header.checksum) {
-                entry.all_confirmed = true;
-            }
+        if only_elevator {
             println!("This is the only elevator in system, skipping ACK wait.");
             return false;
         }
-        drop(known_elevators_locked);
-        
 
         // Check if we already have all ACKs.
     if !confirm_recived(message, state) {
@@ -196,22 +290,22 @@ impl UdpHandler {
         while retries > 0 {
             println!("Remaining retries: {}", retries);
             retries -= 1;
-            std::thread::sleep(Duration::from_millis(50));
+            sleep(Duration::from_millis(50));
 
-            // Clone the current confirmation list snapshot.
-            let sent_messages_snapshot = state.sent_messages.lock().unwrap().clone();
-            let known_elevators = state.known_elevators.lock().unwrap();
+            // Copy the confirmation and the elevators at the same moment.
+            let (waiting, known_elevators) = state.read(move |s| {
+                let waiting = s.sent_messages.iter().find(|m| m.message_hash == checksum).cloned();
+                return (waiting, s.known_elevators.clone());
+            });
 
-            if let Some(waiting) = sent_messages_snapshot
-                .iter()
-                .find(|m| m.message_hash == message.header.checksum)
+            if let Some(waiting) = waiting
             {
                 // Only consider elevators marked as alive.
                 let expected_ids: Vec<u8> = known_elevators.iter().filter(|e| e.alive).map(|e| e.id).collect();
                 let missing: Vec<u8> = expected_ids.iter().cloned().filter(|id| !waiting.responded_ids.contains(id)).collect();
 
                 last_expected_ids = expected_ids;
-                last_waiting = Some(waiting.clone());
+                last_waiting = Some(waiting);
 
                 // Resend the message only to the ones that have not acked.
                 for elevator_id in missing.iter() {
@@ -231,13 +325,22 @@ impl UdpHandler {
         if let Some(waiting) = last_waiting {
             let missing: Vec<u8> = last_expected_ids.into_iter().filter(|id| !waiting.responded_ids.contains(id)).collect();
             if !missing.is_empty() {
-                let mut known_elevators = state.known_elevators.lock().unwrap();
-                for elevator in known_elevators.iter_mut() {
-                    if missing.contains(&elevator.id) {
-                        elevator.alive = false;
-                        println!("Elevator {} marked as dead after all retries failed.", elevator.id);
+                state.update(move |s| {
+                    for elevator in s.known_elevators.iter_mut() {
+                        if missing.contains(&elevator.id) {
+                            elevator.alive = false;
+                            println!("Elevator {} marked as dead after all retries failed.", elevator.id);
+
+                            // A dead elevator wont serve its hall orders, keep them for reassignment
+                            for order in elevator.queue.iter().filter(|o| o.order_type != CAB) {
+                                if !s.all_orders.contains(order) {
+                                    s.all_orders.push(order.clone());
+                                }
+                            }
+                            elevator.queue.retain(|o| o.order_type == CAB);
+                        }
                     }
-                }
+                });
             }
         }
         return false;
@@ -247,4 +350,46 @@ impl UdpHandler {
         return true;
     }
     }
+
+    /// ensure_send
+    /// Send a message to one elevator and resend it until that elevator acks it
+    ///
+    /// # Arguments:
+    ///
+    /// * `target` - &Cab - the elevator that must ack the message.
+    /// * `message` - &UdpMsg - the message, its checksum is what the ack carries.
+    /// * `state` - &Arc<SystemState> - the acks are collected in `sent_messages`, see handle_ack.
+    /// * `max_retries` - u8 - how many times the message is sent again, 50 ms apart.
+    ///
+    /// # Returns:
+    ///
+    /// Returns - bool - 'true' if the elevator acked the message, 'false' if it never did.
+    ///
+    pub fn ensure_send(&self, target: &Cab, message: &UdpMsg, state: &Arc<SystemState>, max_retries: u8) -> bool {
+        let checksum = message.header.checksum;
+        let target_id = target.id;
+        let confirmation = WaitingConfirmation {message_hash: checksum, responded_ids: vec![state.me_id], all_confirmed: false,};
+        state.update(move |s| s.sent_messages.push(confirmation));
+
+        let mut acked = false;
+        for attempt in 0..=max_retries {
+            if attempt > 0 {
+                println!("Resending {:?} to elevator {}, remaining retries: {}", message.header.message_type, target_id, max_retries - attempt);
+            }
+            self.send(&target.inn_address, message);
+            sleep(Duration::from_millis(50));
+
+            acked = state.read(move |s| {
+                s.sent_messages.iter().any(|m| m.message_hash == checksum && m.responded_ids.contains(&target_id))
+            });
+            if acked {
+                println!("{:?} acknowledged by elevator {}", message.header.message_type, target_id);
+                break;
+            }
+        }
+
+        // Only this call waits for the ack, forget the message either way
+        state.update(move |s| s.sent_messages.retain(|m| m.message_hash != checksum));
+        return acked;
+    }
 }
@@ -0,0 +1,141 @@
+//! ## Virtual Network Module
+//! This module provides an in-process network that can replace the UDP sockets of a `UdpHandler`,
+//! so several nodes can run in the same program. Used by the simulator.
+//!
+//! ## The structs includes:
+//! - **VirtualNetwork**: The shared medium, delivers datagrams between the connected NICs.
+//! - **VirtualNic**: One node's connection to the network, identified by its inn address.
+//!
+//! Datagrams sent to `255.255.255.255` are delivered to every NIC listening on the same port,
+//! including the sender, just like a broadcast on a real subnet.
+//!
+//! A `VirtualNic` is a `Transport`, so a UdpHandler can run on it, see init_virtual_udp_handler.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crossbeam_channel as cbc;
+
+use crate::modules::system_clock;
+use crate::modules::udp_functions::transport::{Transport, MAX_DATAGRAM};
+use crate::modules::udp_functions::udp::{same_subnet, BROADCAST_PORT};
+
+pub use crate::modules::udp_functions::transport::Datagram;
+
+/// The shared medium all virtual NICs are connected to
+#[derive(Debug, Default)]
+pub struct VirtualNetwork {
+    nics: Mutex<HashMap<SocketAddr, cbc::Sender<Datagram>>>,   // Inn address -> queue of the NIC listening there
+    connected: Mutex<HashMap<IpAddr, bool>>,                   // Nodes that are unplugged drop everything in and out
+}
+
+/// One node's connection to the virtual network
+#[derive(Debug)]
+pub struct VirtualNic {
+    pub inn_address: SocketAddr,    // Where this NIC receives datagrams
+    pub out_address: SocketAddr,    // Reported as sender address to the receivers
+    network: Arc<VirtualNetwork>,
+    rx: cbc::Receiver<Datagram>,
+}
+
+impl VirtualNetwork {
+    pub fn new() -> Arc<VirtualNetwork> {
+        Arc::new(VirtualNetwork::default())
+    }
+
+    /// Connect a new NIC receiving on `inn_address` and sending from `out_address`
+    pub fn attach(self: &Arc<Self>, inn_address: SocketAddr, out_address: SocketAddr) -> Arc<VirtualNic> {
+        let (tx, rx) = cbc::unbounded::<Datagram>();
+        self.nics.lock().unwrap().insert(inn_address, tx);
+        self.connected.lock().unwrap().insert(inn_address.ip(), true);
+
+        Arc::new(VirtualNic {
+            inn_address,
+            out_address,
+            network: Arc::clone(self),
+            rx,
+        })
+    }
+
+    /// Plug or unplug the node with this ip, an unplugged node neither sends nor receives
+    pub fn set_connected(&self, ip: IpAddr, connected: bool) {
+        self.connected.lock().unwrap().insert(ip, connected);
+    }
+
+    pub fn is_connected(&self, ip: IpAddr) -> bool {
+        *self.connected.lock().unwrap().get(&ip).unwrap_or(&false)
+    }
+
+    /// Deliver a datagram, returns false if nothing was listening at the target
+    pub fn deliver(&self, data: &[u8], from: SocketAddr, target: &SocketAddr) -> bool {
+        if !self.is_connected(from.ip()) {
+            // The cable is out, the datagram disappears without an error like on a real network
+            return true;
+        }
+
+        let nics = self.nics.lock().unwrap();
+        let receivers: Vec<(&SocketAddr, &cbc::Sender<Datagram>)> = if target.ip() == IpAddr::V4(Ipv4Addr::BROADCAST) {
+            nics.iter().filter(|(addr, _)| addr.port() == target.port()).collect()
+        } else {
+            nics.iter().filter(|(addr, _)| *addr == target).collect()
+        };
+
+        if receivers.is_empty() {
+            return false;
+        }
+
+        for (addr, tx) in receivers {
+            if self.is_connected(addr.ip()) {
+                let _ = tx.send((data.to_vec(), from));
+            }
+        }
+        true
+    }
+}
+
+impl VirtualNic {
+    pub fn send_to(&self, data: &[u8], target: &SocketAddr) -> bool {
+        self.network.deliver(data, self.out_address, target)
+    }
+
+    /// Broadcast to every NIC listening on `port`
+    pub fn broadcast(&self, data: &[u8], port: u16) -> bool {
+        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), port);
+        self.network.deliver(data, self.out_address, &target)
+    }
+
+    /// Wait for the next datagram, None if nothing arrived within `max_wait` of system clock time
+    pub fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram> {
+        system_clock::recv_timeout(&self.rx, max_wait)
+    }
+
+    pub fn network(&self) -> &Arc<VirtualNetwork> {
+        &self.network
+    }
+}
+
+impl Transport for VirtualNic {
+    fn send_to(&self, peer: &SocketAddr, datagram: &[u8]) -> bool {
+        self.network.deliver(datagram, self.out_address, peer)
+    }
+
+    fn broadcast(&self, datagram: &[u8]) -> bool {
+        VirtualNic::broadcast(self, datagram, BROADCAST_PORT)
+    }
+
+    fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram> {
+        let (mut datagram, sender) = VirtualNic::recv_timeout(self, max_wait)?;
+        // Truncate like a real socket would with a too small buffer
+        datagram.truncate(MAX_DATAGRAM);
+        Some((datagram, sender))
+    }
+
+    fn accepts(&self, sender: IpAddr) -> bool {
+        same_subnet(self.inn_address.ip(), sender)
+    }
+
+    fn local_address(&self) -> SocketAddr {
+        self.out_address
+    }
+}
@@ -0,0 +1,106 @@
+//! Tests of the wire codecs
+//!
+//! - the codec key is read from "boot.txt" lines
+//! - JSON datagrams are flagged in the version byte, readable as text and decoded like bincode ones
+//! - checksums are the same in both codecs
+//! - in the simulator a cluster sending JSON serves its calls
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::elevator_object::alias_lib::{HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::worldview_delta::snapshot_parts;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::shutdown::Handover;
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::system_init::init_system_state;
+use heislab2_root::modules::udp_functions::codec::{Codec, CodecConfig, CODEC_FLAG_JSON};
+use heislab2_root::modules::udp_functions::udp::{
+    calc_checksum, comp_checksum, decode_msg, encode_msg, encode_msg_with, make_udp_msg, MessageType, ProtocolError,
+    UdpData, PROTOCOL_MAGIC, PROTOCOL_VERSION,
+};
+
+fn cab(id: u8) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, id)), 20000);
+    return Cab::init(&address, &address, 4, id, &state).unwrap();
+}
+
+#[test]
+fn codec_key_is_read() {
+    let mut config = CodecConfig::default();
+    assert_eq!(config.codec, Codec::Bincode);
+
+    assert!(config.parse("wire_codec", " json"));
+    assert_eq!(config.codec, Codec::Json);
+    assert!(config.parse("wire_codec", "bincode"));
+    assert_eq!(config.codec, Codec::Bincode);
+
+    assert!(!config.parse("wire_codec", "cbor"));
+    assert!(!config.parse("wire_colour", "json"));
+}
+
+#[test]
+fn json_datagrams_are_flagged_and_readable() {
+    let msg = make_udp_msg(1, MessageType::NewRequest, UdpData::Order(Order::init(2, HALL_UP)));
+    let datagram = encode_msg_with(&msg, PROTOCOL_VERSION, Codec::Json).unwrap();
+
+    assert_eq!(datagram[..2], PROTOCOL_MAGIC);
+    assert_eq!(datagram[2], PROTOCOL_VERSION | CODEC_FLAG_JSON);
+    assert_eq!(Codec::split_version(datagram[2]), (Codec::Json, PROTOCOL_VERSION));
+    let text = std::str::from_utf8(&datagram[3..]).expect("JSON payload is not text");
+    assert!(text.contains("\"sender_id\":1") && text.contains("NewRequest"), "{}", text);
+
+    // Bincode datagrams are as before the codec
+    assert_eq!(encode_msg_with(&msg, PROTOCOL_VERSION, Codec::Bincode), encode_msg(&msg, PROTOCOL_VERSION));
+    assert_eq!(decode_msg(&datagram), Ok((PROTOCOL_VERSION, msg)));
+
+    // The version checks still apply to JSON datagrams
+    let mut newer = datagram.clone();
+    newer[2] = (PROTOCOL_VERSION + 1) | CODEC_FLAG_JSON;
+    assert_eq!(decode_msg(&newer), Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+    assert!(matches!(decode_msg(&datagram[..datagram.len() - 2]), Err(ProtocolError::Malformed(_))));
+}
+
+#[test]
+fn every_kind_of_data_survives_json() {
+    let handover = Handover { known_elevators: vec![cab(1), cab(2)], all_orders: vec![Order::init(3, HALL_DOWN)], pending: Vec::new() };
+    let update = snapshot_parts(7, &[cab(1)]).remove(0);
+    let messages = vec![
+        make_udp_msg(1, MessageType::Worldview, UdpData::Cabs(vec![cab(1), cab(2)])),
+        make_udp_msg(2, MessageType::NewOnline, UdpData::Cab(cab(2))),
+        make_udp_msg(1, MessageType::Ack, UdpData::Checksum(12345)),
+        make_udp_msg(1, MessageType::Handover, UdpData::Handover(handover)),
+        make_udp_msg(1, MessageType::WorldviewUpdate, UdpData::WorldviewUpdate(update)),
+    ];
+
+    for msg in messages {
+        let datagram = encode_msg_with(&msg, PROTOCOL_VERSION, Codec::Json).unwrap();
+        let (_, decoded) = decode_msg(&datagram).unwrap();
+        assert_eq!(decoded, msg);
+        assert!(comp_checksum(&decoded), "Checksum of {:?} changed in JSON", msg.header.message_type);
+    }
+}
+
+#[test]
+fn checksum_does_not_depend_on_the_codec() {
+    let data = UdpData::Cab(cab(3));
+    let msg = make_udp_msg(3, MessageType::ImAlive, data.clone());
+    let bincode = decode_msg(&encode_msg_with(&msg, PROTOCOL_VERSION, Codec::Bincode).unwrap()).unwrap().1;
+    let json = decode_msg(&encode_msg_with(&msg, PROTOCOL_VERSION, Codec::Json).unwrap()).unwrap().1;
+    assert_eq!(bincode.header.checksum, calc_checksum(&data));
+    assert_eq!(json.header.checksum, calc_checksum(&data));
+}
+
+#[test]
+fn json_cluster_serves_calls() {
+    let mut sim = Simulation::start(SimConfig { codec: CodecConfig { codec: Codec::Json }, ..SimConfig::default() });
+    assert!([1, 2, 3].iter().all(|id| sim.node(*id).state.master_id() == 1), "The nodes did not agree on a master in JSON");
+
+    sim.press(2, 3, HALL_DOWN);
+    sim.press(3, 1, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(40), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served in JSON: {:?}", sim.unserved_calls());
+}
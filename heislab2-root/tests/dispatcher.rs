@@ -0,0 +1,108 @@
+//! Tests of the worker pool that runs the handlers of received messages
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::udp_functions::dispatcher::{Lane, MessageDispatcher};
+use heislab2_root::modules::udp_functions::udp::MessageType;
+
+#[test]
+fn messages_of_one_type_are_handled_in_order() {
+    let dispatcher = MessageDispatcher::new(16);
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let (done_tx, done_rx) = cbc::unbounded();
+
+    for i in 0..10 {
+        let handled = Arc::clone(&handled);
+        let done_tx = done_tx.clone();
+        assert!(dispatcher.dispatch(&MessageType::NewRequest, 1, move || {
+            handled.lock().unwrap().push(i);
+            done_tx.send(()).unwrap();
+        }));
+    }
+    for _ in 0..10 {
+        done_rx.recv_timeout(Duration::from_secs(5)).expect("Handler never ran");
+    }
+
+    assert_eq!(*handled.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    assert_eq!(dispatcher.stats(Lane::Orders).handled, 10);
+}
+
+#[test]
+fn acks_are_handled_while_an_order_handler_waits_for_them() {
+    let dispatcher = MessageDispatcher::new(16);
+    let (ack_tx, ack_rx) = cbc::unbounded();
+    let (done_tx, done_rx) = cbc::unbounded();
+
+    // Like give_order, the order handler blocks until the ack has been handled
+    dispatcher.dispatch(&MessageType::NewRequest, 1, move || {
+        let acked = ack_rx.recv_timeout(Duration::from_secs(5)).is_ok();
+        done_tx.send(acked).unwrap();
+    });
+    dispatcher.dispatch(&MessageType::Ack, 2, move || ack_tx.send(()).unwrap());
+
+    assert_eq!(done_rx.recv_timeout(Duration::from_secs(10)), Ok(true));
+}
+
+#[test]
+fn full_lifesign_queue_drops_and_counts() {
+    let dispatcher = MessageDispatcher::new(2);
+    let (release_tx, release_rx) = cbc::unbounded::<()>();
+    let (started_tx, started_rx) = cbc::unbounded();
+
+    // Keep the worker busy so the queue fills up
+    dispatcher.dispatch(&MessageType::ImAlive, 2, move || {
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).expect("Worker never started");
+
+    let accepted = (0..5).filter(|_| dispatcher.dispatch(&MessageType::Worldview, 1, || {})).count();
+    release_tx.send(()).unwrap();
+
+    let stats = dispatcher.stats(Lane::Lifesigns);
+    assert_eq!(accepted, 2);
+    assert_eq!(stats.dropped, 3);
+    assert_eq!(stats.waited, 0, "Lifesigns should be dropped without waiting");
+}
+
+#[test]
+fn order_handler_waiting_for_acks_holds_only_its_own_sender() {
+    let dispatcher = MessageDispatcher::new(16);
+    let (release_tx, release_rx) = cbc::unbounded::<()>();
+    let (handled_tx, handled_rx) = cbc::unbounded();
+
+    // Like give_order to a cab that does not answer, the handler of sender 1 waits until it is released
+    let first = handled_tx.clone();
+    dispatcher.dispatch(&MessageType::NewRequest, 1, move || {
+        let _ = release_rx.recv_timeout(Duration::from_secs(5));
+        first.send((1, MessageType::NewRequest)).unwrap();
+    });
+    let others = [
+        (1, MessageType::OrderComplete),
+        (2, MessageType::OrderComplete),
+        (1, MessageType::Ack),
+        (1, MessageType::ImAlive),
+        (1, MessageType::NewOnline),
+    ];
+    for (sender_id, message_type) in others {
+        let handled_tx = handled_tx.clone();
+        let handled_type = message_type.clone();
+        dispatcher.dispatch(&message_type, sender_id, move || handled_tx.send((sender_id, handled_type)).unwrap());
+    }
+
+    // The other lanes and the order messages of other senders go on, the next order message of sender 1 waits
+    let mut handled = Vec::new();
+    while let Ok(message) = handled_rx.recv_timeout(Duration::from_millis(500)) {
+        handled.push(message);
+    }
+    assert_eq!(handled.len(), 4, "Only the other lanes and senders should run: {:?}", handled);
+    assert!(handled.contains(&(2, MessageType::OrderComplete)), "An order message of another sender waited for the blocked handler");
+    assert!(!handled.contains(&(1, MessageType::OrderComplete)), "The order messages of the sender did not wait for the blocked handler");
+
+    release_tx.send(()).unwrap();
+    assert_eq!(handled_rx.recv_timeout(Duration::from_secs(5)), Ok((1, MessageType::NewRequest)));
+    assert_eq!(handled_rx.recv_timeout(Duration::from_secs(5)), Ok((1, MessageType::OrderComplete)));
+}
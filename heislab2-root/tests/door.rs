@@ -0,0 +1,95 @@
+//! Tests of the door timer, on the real clock with short open times
+//!
+//! - the keys in "boot.txt" give the expected config
+//! - the door closes after the open time, or the accessible open time
+//! - the door-open button holds the door, never past the max hold
+//! - the door-close button closes the door at once
+
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::cab_object::elevator_door::{DoorConfig, DoorTimer};
+
+const CONFIG: DoorConfig = DoorConfig {
+    open_time: Duration::from_millis(100),
+    accessible_open_time: Duration::from_millis(300),
+    max_hold: Duration::from_millis(250),
+};
+
+/// Time from `opened` until the timer asks for the door to close
+fn closes_after(door_rx: &cbc::Receiver<bool>, opened: Instant) -> Duration {
+    assert_eq!(door_rx.recv_timeout(Duration::from_secs(2)), Ok(true), "Door timer never fired");
+    return opened.elapsed();
+}
+
+#[test]
+fn config_is_read_from_boot_keys() {
+    let mut config = DoorConfig::default();
+    assert!(config.parse("door_open_time", " 3"));
+    assert!(config.parse("door_accessible_time", "7.5"));
+    assert!(config.parse("door_max_hold", "20"));
+    assert_eq!(config.open_time, Duration::from_secs(3));
+    assert_eq!(config.accessible_open_time, Duration::from_millis(7500));
+    assert_eq!(config.max_hold, Duration::from_secs(20));
+
+    assert!(!config.parse("door_open_time", "-1"));
+    assert!(!config.parse("door_open_time", "inf"));
+    assert!(!config.parse("door_max_hold", "1e30"));
+    assert!(!config.parse("door_color", "2"));
+}
+
+#[test]
+fn door_closes_after_the_open_time() {
+    let (door_tx, door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, CONFIG);
+
+    let opened = Instant::now();
+    door.open(false);
+    let open_for = closes_after(&door_rx, opened);
+    assert!(open_for >= Duration::from_millis(100) && open_for < Duration::from_millis(250), "Open for {:?}", open_for);
+
+    let opened = Instant::now();
+    door.open(true);
+    let open_for = closes_after(&door_rx, opened);
+    assert!(open_for >= Duration::from_millis(300), "Accessible stop open for only {:?}", open_for);
+
+    // Fired once per opening
+    assert!(door_rx.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn door_open_button_holds_until_the_max_hold() {
+    let (door_tx, door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, CONFIG);
+
+    // Held every 50 ms, far past the max hold
+    let opened = Instant::now();
+    door.open(false);
+    let mut closed = false;
+    for _ in 0..20 {
+        if door_rx.recv_timeout(Duration::from_millis(50)).is_ok() {
+            closed = true;
+            break;
+        }
+        door.hold();
+    }
+    let open_for = opened.elapsed();
+    assert!(closed, "Door held open forever");
+    assert!(open_for >= Duration::from_millis(250) && open_for < Duration::from_millis(400), "Held for {:?}", open_for);
+}
+
+#[test]
+fn door_close_button_closes_at_once() {
+    let (door_tx, door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, CONFIG);
+
+    let opened = Instant::now();
+    door.open(true);
+    door.close();
+    assert!(closes_after(&door_rx, opened) < Duration::from_millis(100));
+
+    // Nothing to close with the door shut
+    door.close();
+    assert!(door_rx.recv_timeout(Duration::from_millis(100)).is_err());
+}
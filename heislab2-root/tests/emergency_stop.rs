@@ -0,0 +1,122 @@
+//! Tests of the emergency stop in the state machine of the cab
+//!
+//! Every test drives a `Cab` against a simulated elevator and checks the motor, the door and the stop lamp:
+//! - the motor stops at once and the door only opens at a floor
+//! - the stop lamp is lit while the button is held, other status changes do not end the stop
+//! - hall orders are released and cab orders kept
+//! - after the release the cab moves on to its cab orders
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_command::run_elevator_commands;
+use heislab2_root::modules::cab_object::elevator_door::{DoorConfig, DoorTimer};
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_DOWN, DIRN_STOP, DIRN_UP, HALL_UP};
+use heislab2_root::modules::elevator_object::elevator_init::Elevator;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::simulation::sim_elevator::SimElevator;
+use heislab2_root::modules::system_init::init_system_state;
+
+const NUM_FLOORS: u8 = 4;
+
+/// A cab on a simulated elevator standing at `position`, with a hall order and a cab order
+fn cab_at(position: f32, current_floor: u8) -> (SimElevator, Elevator, DoorTimer, Cab) {
+    let sim = SimElevator::start(NUM_FLOORS, position, Duration::from_secs(2)).unwrap();
+    let elevator = Elevator::init(&sim.address(), NUM_FLOORS).unwrap();
+    let state = Arc::new(init_system_state(1, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+    let mut cab = Cab::init(&address, &address, NUM_FLOORS, 1, &state).unwrap();
+    cab.current_floor = current_floor;
+    cab.status = Status::Idle;
+    cab.queue = vec![Order::init(3, HALL_UP), Order::init(0, CAB)];
+    let (door_tx, _door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, DoorConfig::default());
+    return (sim, elevator, door, cab);
+}
+
+/// Press or release the stop button, as the main loop does
+fn stop_button(cab: &mut Cab, elevator: &Elevator, door: &DoorTimer) {
+    run_elevator_commands(&cab.toggle_stop(elevator.floor_sensor()), elevator, door);
+}
+
+/// A read goes over the same connection as the commands, so every command before it has been carried out
+fn settle(elevator: &Elevator) {
+    elevator.floor_sensor();
+}
+
+#[test]
+fn stop_between_floors_stops_motor_and_keeps_door_closed() {
+    let (sim, elevator, door, mut cab) = cab_at(1.5, 1);
+    cab.status = Status::Moving;
+    cab.direction = DIRN_UP;
+    elevator.motor_direction(DIRN_UP);
+
+    stop_button(&mut cab, &elevator, &door);
+    settle(&elevator);
+
+    assert_eq!(cab.status, Status::Stop);
+    assert_eq!(sim.motor_direction(), DIRN_STOP);
+    assert!(sim.stop_light());
+    assert!(!sim.door_open(), "Door opened between floors");
+}
+
+#[test]
+fn stop_at_floor_opens_door() {
+    let (sim, elevator, door, mut cab) = cab_at(2.0, 2);
+
+    stop_button(&mut cab, &elevator, &door);
+    settle(&elevator);
+
+    assert_eq!(sim.motor_direction(), DIRN_STOP);
+    assert!(sim.stop_light());
+    assert!(sim.door_open(), "Door not opened at a floor");
+}
+
+#[test]
+fn stop_releases_hall_orders_and_keeps_cab_orders() {
+    let (_sim, elevator, door, mut cab) = cab_at(2.0, 2);
+
+    stop_button(&mut cab, &elevator, &door);
+
+    assert_eq!(cab.queue, vec![Order::init(0, CAB)]);
+}
+
+#[test]
+fn stop_holds_until_released() {
+    let (sim, elevator, door, mut cab) = cab_at(1.5, 1);
+
+    stop_button(&mut cab, &elevator, &door);
+
+    // Nothing but the stop button ends the stop, and the cab does not move
+    run_elevator_commands(&cab.set_status(Status::Idle), &elevator, &door);
+    run_elevator_commands(&cab.set_status(Status::Obstruction), &elevator, &door);
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    settle(&elevator);
+    assert_eq!(cab.status, Status::Stop);
+    assert_eq!(sim.motor_direction(), DIRN_STOP);
+    assert!(sim.stop_light(), "Stop lamp went out while held");
+
+    stop_button(&mut cab, &elevator, &door);
+    settle(&elevator);
+    assert_eq!(cab.status, Status::Idle);
+    assert!(!sim.stop_light(), "Stop lamp still lit after release");
+}
+
+#[test]
+fn resume_between_floors_drives_to_cab_order() {
+    let (sim, elevator, door, mut cab) = cab_at(1.5, 1);
+
+    stop_button(&mut cab, &elevator, &door);
+    stop_button(&mut cab, &elevator, &door);
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    settle(&elevator);
+
+    assert_eq!(cab.status, Status::Moving);
+    assert_eq!(sim.motor_direction(), DIRN_DOWN);
+}
@@ -0,0 +1,56 @@
+//! Tests of the fault injection, without a network
+//!
+//! - the "fault_" keys are read and values that would panic later are refused
+//! - delayed copies are sent once their delay has passed, the earliest first
+
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::udp_functions::fault_injection::{FaultConfig, FaultInjector, Partition};
+
+#[test]
+fn fault_keys_are_read() {
+    let mut config = FaultConfig::default();
+    assert!(config.parse("fault_loss", " 20"));
+    assert!(config.parse("fault_delay", "30"));
+    assert!(config.parse("fault_duplicate", "150"));
+    assert!(config.parse("fault_partition", "1,2|3@10-30"));
+    assert_eq!(config.loss_percent, 20.0);
+    assert_eq!(config.delay, Duration::from_millis(30));
+    assert_eq!(config.duplicate_percent, 100.0);
+    assert_eq!(config.partitions, vec![Partition { side_a: vec![1, 2], side_b: vec![3], start: Duration::from_secs(10), end: Duration::from_secs(30) }]);
+
+    assert!(!config.parse("fault_loss", "NaN"));
+    assert!(!config.parse("fault_reorder", "inf"));
+    assert!(!config.parse("fault_colour", "1"));
+    assert_eq!(config.loss_percent, 20.0);
+}
+
+#[test]
+fn partition_window_must_be_a_time() {
+    assert!(Partition::parse("1|2@inf-30").is_none());
+    assert!(Partition::parse("1|2@0-1e30").is_none());
+    assert!(Partition::parse("1|2@NaN-30").is_none());
+    assert!(Partition::parse("1|2@0.5-30").is_some());
+}
+
+#[test]
+fn delayed_copies_are_sent_after_their_delay_earliest_first() {
+    let config = FaultConfig { delay: Duration::from_millis(50), reorder_percent: 50.0, reorder_delay: Duration::from_millis(100), seed: 7, ..FaultConfig::default() };
+    let injector = FaultInjector::new(config);
+    let (sent_tx, sent_rx) = cbc::unbounded();
+
+    let started = Instant::now();
+    for message in 0..200 {
+        let sent_tx = sent_tx.clone();
+        assert!(injector.inject(move || sent_tx.send((message, Instant::now())).unwrap()));
+    }
+
+    let sent: Vec<(i32, Instant)> = (0..200).map(|_| sent_rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+    assert!(sent.iter().all(|(_, at)| at.duration_since(started) >= Duration::from_millis(50)), "A copy was sent before its delay");
+
+    // The held back copies come after the others, within each group the order is kept
+    let order_breaks = sent.windows(2).filter(|pair| pair[0].0 > pair[1].0).count();
+    assert_eq!(order_breaks, 1, "Copies not sent in the order they were due: {:?}", sent.iter().map(|(message, _)| message).collect::<Vec<_>>());
+}
@@ -0,0 +1,96 @@
+//! Tests of the isolated-node fallback
+//!
+//! - the isolation timeout is read from "boot.txt" lines
+//! - in the simulator a slave cut off from the others serves the hall calls pressed on its panel and rejoins afterwards
+//! - the master learns the hall calls the slave took while it was cut off when the partition heals
+
+use std::time::Duration;
+
+use heislab2_root::modules::elevator_object::alias_lib::{HALL_DOWN, HALL_UP};
+use heislab2_root::modules::isolation::IsolationConfig;
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::udp_functions::fault_injection::{FaultConfig, Partition};
+
+#[test]
+fn isolation_key_is_read() {
+    let mut config = IsolationConfig::default();
+    assert_eq!(config.timeout, Duration::from_secs(3));
+
+    assert!(config.parse("isolation_timeout", " 1.5"));
+    assert_eq!(config.timeout, Duration::from_millis(1500));
+
+    assert!(!config.parse("isolation_timeout", "-1"));
+    assert!(!config.parse("isolation_timeout", "inf"));
+    assert!(!config.parse("isolation_timeout", "1e30"));
+    assert!(!config.parse("isolation_colour", "1"));
+}
+
+#[test]
+fn isolated_slave_serves_its_own_hall_calls_and_rejoins() {
+    let faults = FaultConfig {
+        partitions: vec![Partition::parse("3|1,2@8-35").unwrap()],
+        ..FaultConfig::default()
+    };
+    let mut sim = Simulation::start(SimConfig { faults: Some(faults), ..SimConfig::default() });
+    assert!(!sim.node(3).state.read(|s| s.isolation.isolated), "Elevator 3 was isolated before the partition");
+
+    let isolated = sim.run_until(Duration::from_secs(10), |sim| sim.node(3).state.read(|s| s.isolation.isolated));
+    assert!(isolated, "Elevator 3 did not notice it was cut off");
+    assert!(!sim.node(1).state.read(|s| s.isolation.isolated), "Elevator 1 still hears elevator 2");
+
+    // Taken, lit and served here, nobody else hears of it
+    sim.press(3, 2, HALL_UP);
+    let order = sim.calls[0].order.clone();
+    let taken = sim.run_until(Duration::from_secs(1), |sim| {
+        sim.nodes_serving(&order) == vec![3] && sim.node(3).elevator.call_light(2, HALL_UP)
+    });
+    assert!(taken, "The isolated elevator did not take and light its hall call: {:?}", sim.nodes_serving(&order));
+    let served = sim.run_until(Duration::from_secs(20), |sim| sim.all_calls_served());
+    assert!(served, "The isolated elevator did not serve its hall call");
+
+    // After the partition every node takes elevator 3 as alive again
+    let rejoined = sim.run_until(Duration::from_secs(30), |sim| {
+        !sim.node(3).state.read(|s| s.isolation.isolated)
+            && sim.nodes.iter().all(|node| node.state.known_elevators().iter().any(|cab| cab.id == 3 && cab.alive))
+    });
+    assert!(rejoined, "Elevator 3 did not rejoin after the partition");
+
+    sim.press(3, 3, HALL_DOWN);
+    sim.press(1, 1, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(40), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after elevator 3 rejoined: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn master_learns_hall_calls_taken_while_isolated() {
+    // Short partition, the call is still on its way when it heals
+    let faults = FaultConfig {
+        partitions: vec![Partition::parse("3|1,2@8-14").unwrap()],
+        ..FaultConfig::default()
+    };
+    let mut sim = Simulation::start(SimConfig { faults: Some(faults), ..SimConfig::default() });
+    let isolated = sim.run_until(Duration::from_secs(10), |sim| sim.node(3).state.read(|s| s.isolation.isolated));
+    assert!(isolated, "Elevator 3 did not notice it was cut off");
+
+    sim.press(3, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    let taken = sim.run_until(Duration::from_secs(1), |sim| sim.nodes_serving(&order) == vec![3]);
+    assert!(taken, "The isolated elevator did not take its hall call");
+    assert!(!sim.all_calls_served());
+
+    // The worldview of the master has the call in the queue of cab 3 and in no other queue
+    let learned = sim.run_until(Duration::from_secs(10), |sim| {
+        let holders: Vec<u8> = sim.node(1).state.known_elevators().iter()
+            .filter(|cab| cab.queue.contains(&order))
+            .map(|cab| cab.id)
+            .collect();
+        holders == vec![3]
+    });
+    assert!(learned, "The master did not learn the hall call elevator 3 took: {:?}", sim.node(1).state.known_elevators());
+    assert!(!sim.node(3).state.read(|s| s.isolation.isolated), "Elevator 3 is still isolated");
+
+    let served = sim.run_until(Duration::from_secs(20), |sim| sim.all_calls_served());
+    assert!(served, "The hall call was not served after the partition");
+    assert!(!sim.node(1).elevator.door_opened_at(3, sim.calls[0].pressed_at), "Elevator 1 served the call too");
+    assert!(!sim.node(2).elevator.door_opened_at(3, sim.calls[0].pressed_at), "Elevator 2 served the call too");
+}
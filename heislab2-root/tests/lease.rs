@@ -0,0 +1,111 @@
+//! Tests of the master lease
+//!
+//! - the lease keys are read from "boot.txt" lines
+//! - a majority or any other elevator renews the lease, a new master holds it for a while without acks
+//! - in the simulator a partitioned master is fenced, serves its own hall calls and steps down when the partition ends
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::elevator_object::alias_lib::HALL_DOWN;
+use heislab2_root::modules::master_functions::lease::{lease_holds, record_ack, LeaseConfig, MasterLease, Quorum};
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::system_clock::now;
+use heislab2_root::modules::system_init::init_system_state;
+use heislab2_root::modules::udp_functions::fault_injection::{FaultConfig, Partition};
+
+fn cabs(ids: &[u8]) -> Vec<Cab> {
+    let state = Arc::new(init_system_state(ids[0], ids[0]));
+    ids.iter()
+        .map(|id| {
+            let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, *id)), 20000);
+            Cab::init(&address, &address, 4, *id, &state).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn lease_keys_are_read() {
+    let mut config = LeaseConfig::default();
+    assert_eq!(config.quorum, Quorum::Majority);
+
+    assert!(config.parse("lease_quorum", " any"));
+    assert!(config.parse("lease_duration", "2.5"));
+    assert_eq!(config.quorum, Quorum::AnyPeer);
+    assert_eq!(config.duration, Duration::from_millis(2500));
+
+    assert!(!config.parse("lease_quorum", "all"));
+    assert!(!config.parse("lease_duration", "0"));
+    assert!(!config.parse("lease_duration", "inf"));
+    assert!(!config.parse("lease_duration", "1e30"));
+    assert!(!config.parse("lease_colour", "1"));
+}
+
+#[test]
+fn acks_from_a_quorum_renew_the_lease() {
+    let majority = LeaseConfig::default();
+    let any = LeaseConfig { quorum: Quorum::AnyPeer, ..LeaseConfig::default() };
+    let five = cabs(&[1, 2, 3, 4, 5]);
+    let start = now();
+    let later = start + Duration::from_secs(10);
+
+    // A new master holds the lease before anyone could ack
+    let mut lease = MasterLease { since: Some(start), ..MasterLease::default() };
+    assert!(lease_holds(&lease, 1, &five, &majority, start + Duration::from_secs(1)));
+    assert!(!lease_holds(&lease, 1, &five, &majority, later));
+
+    // The master and two others are three of five
+    record_ack(&mut lease, 2, later - Duration::from_secs(1));
+    assert!(!lease_holds(&lease, 1, &five, &majority, later));
+    assert!(lease_holds(&lease, 1, &five, &any, later));
+    record_ack(&mut lease, 3, later);
+    assert!(lease_holds(&lease, 1, &five, &majority, later));
+
+    // Old acks and acks from itself do not count
+    record_ack(&mut lease, 1, later);
+    assert!(!lease_holds(&lease, 1, &five, &majority, later + Duration::from_secs(5)));
+    assert!(!lease_holds(&lease, 1, &five, &any, later + Duration::from_secs(5)));
+
+    // A master alone is a majority of one
+    let alone = cabs(&[1]);
+    assert!(lease_holds(&MasterLease::default(), 1, &alone, &majority, later));
+    assert!(lease_holds(&MasterLease::default(), 1, &alone, &any, later));
+}
+
+#[test]
+fn partitioned_master_serves_its_own_calls_and_steps_down() {
+    let faults = FaultConfig {
+        partitions: vec![Partition::parse("1|2,3@8-40").unwrap()],
+        ..FaultConfig::default()
+    };
+    let mut sim = Simulation::start(SimConfig { faults: Some(faults), ..SimConfig::default() });
+    assert_eq!(sim.node(2).state.master_id(), 1, "Elevator 1 should be master after boot");
+
+    let fenced = sim.run_until(Duration::from_secs(15), |sim| sim.node(1).state.read(|s| s.lease.fenced));
+    assert!(fenced, "The partitioned master kept its lease");
+    assert_eq!(sim.node(1).state.master_id(), 1, "A fenced master keeps its master id");
+
+    // It takes the call itself instead of giving it to a cab it can't reach
+    sim.press(1, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    let taken = sim.run_until(Duration::from_secs(2), |sim| sim.nodes_serving(&order) == vec![1]);
+    assert!(taken, "The fenced master did not take its own hall call: {:?}", sim.nodes_serving(&order));
+    let served = sim.run_until(Duration::from_secs(20), |sim| sim.all_calls_served());
+    assert!(served, "The fenced master did not serve its own hall call");
+    assert!([2, 3].iter().all(|id| sim.node(*id).state.master_id() == 2), "Elevators 2 and 3 did not elect elevator 2");
+
+    // When the partition ends the old master becomes a slave of elevator 2
+    let rejoined = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.nodes.iter().all(|node| {
+            node.state.master_id() == 2 && node.state.known_elevators().iter().any(|cab| cab.id == 1 && cab.alive)
+        })
+    });
+    assert!(rejoined, "Elevator 1 did not rejoin as a slave of elevator 2");
+    assert!(!sim.node(1).state.read(|s| s.lease.fenced), "Elevator 1 is still fenced");
+
+    sim.press(2, 2, HALL_DOWN);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after the partition: {:?}", sim.unserved_calls());
+}
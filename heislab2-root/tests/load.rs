@@ -0,0 +1,129 @@
+//! Tests of the load sensor and of full cabs, without a network
+//!
+//! - the keys in "boot.txt" give the expected config
+//! - a reading over the full share sets the full-load flag
+//! - best_to_worst_elevator puts a full cab behind the others for hall orders
+//! - a full cab drives past hall orders on the way and still stops for cab orders
+//! - an elevator that does not answer the load command gives no reading and does not block the other commands
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_command::run_elevator_commands;
+use heislab2_root::modules::cab_object::elevator_door::{DoorConfig, DoorTimer};
+use heislab2_root::modules::cab_object::elevator_load::LoadConfig;
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_STOP, DIRN_UP, HALL_UP};
+use heislab2_root::modules::elevator_object::elevator_init::{Elevator, LOAD_TIMEOUT};
+use heislab2_root::modules::master_functions::master::best_to_worst_elevator;
+use heislab2_root::modules::master_functions::traffic::DispatchMode;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::simulation::sim_elevator::SimElevator;
+use heislab2_root::modules::system_init::init_system_state;
+
+const NUM_FLOORS: u8 = 4;
+
+fn idle_cab(id: u8, floor: u8) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut cab = Cab::init(&address, &address, NUM_FLOORS, id, &state).unwrap();
+    cab.current_floor = floor;
+    cab.status = Status::Idle;
+    return cab;
+}
+
+/// A cab moving up past floor 1 towards a cab order at the top, with a hall order at floor 1 in its queue
+fn cab_passing_floor_one(full: bool) -> (SimElevator, Elevator, Cab) {
+    let sim = SimElevator::start(NUM_FLOORS, 1.0, Duration::from_secs(2)).unwrap();
+    let elevator = Elevator::init(&sim.address(), NUM_FLOORS).unwrap();
+    let mut cab = idle_cab(1, 1);
+    cab.status = Status::Moving;
+    cab.direction = DIRN_UP;
+    cab.last_served_floor = 0;
+    cab.queue = vec![Order::init(3, CAB), Order::init(1, HALL_UP)];
+    cab.full_load = full;
+    return (sim, elevator, cab);
+}
+
+#[test]
+fn config_is_read_from_boot_keys() {
+    let mut config = LoadConfig::default();
+    assert!(!config.sensor);
+    assert!(config.parse("load_sensor", " true"));
+    assert!(config.parse("load_capacity", "8"));
+    assert!(config.parse("load_full", "75"));
+    assert_eq!(config, LoadConfig { sensor: true, capacity: 8, full_percent: 75 });
+
+    assert!(!config.parse("load_capacity", "0"));
+    assert!(!config.parse("load_full", "most"));
+}
+
+#[test]
+fn reading_over_the_full_share_sets_the_flag() {
+    let config = LoadConfig { sensor: true, capacity: 8, full_percent: 75 };
+    let mut cab = idle_cab(1, 0);
+    assert_eq!(cab.load, None);
+
+    assert!(!cab.set_load(5, &config));
+    assert_eq!(cab.load, Some(5));
+    assert!(!cab.full_load);
+
+    assert!(cab.set_load(6, &config));
+    assert!(cab.full_load);
+    assert!(!cab.set_load(8, &config));
+
+    assert!(cab.set_load(2, &config));
+    assert!(!cab.full_load);
+}
+
+#[test]
+fn full_cab_is_put_behind_the_others_for_hall_orders() {
+    let mut full = idle_cab(1, 1);
+    full.full_load = true;
+    let cabs = vec![full, idle_cab(2, 3)];
+
+    assert_eq!(best_to_worst_elevator(&Order::init(1, HALL_UP), &cabs, DispatchMode::Balanced), vec![2, 1]);
+    // The people inside can still choose where to go
+    assert_eq!(best_to_worst_elevator(&Order::init(1, CAB), &cabs, DispatchMode::Balanced)[0], 1);
+}
+
+#[test]
+fn full_cab_passes_hall_orders_on_the_way() {
+    let (door_tx, _door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, DoorConfig::default());
+
+    let (sim, elevator, mut cab) = cab_passing_floor_one(true);
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    elevator.floor_sensor();
+    assert_eq!(sim.motor_direction(), DIRN_UP);
+    assert!(!sim.door_open(), "Full cab stopped for a hall order");
+
+    // With room inside the same cab stops
+    let (sim, elevator, mut cab) = cab_passing_floor_one(false);
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    elevator.floor_sensor();
+    assert_eq!(sim.motor_direction(), DIRN_STOP);
+    assert!(sim.door_open());
+}
+
+#[test]
+fn elevator_without_load_sensor_gives_no_reading() {
+    let sim = SimElevator::start(NUM_FLOORS, 0.0, Duration::from_secs(2)).unwrap();
+    let elevator = Elevator::init(&sim.address(), NUM_FLOORS).unwrap();
+    sim.set_load(3);
+    assert_eq!(elevator.load(), Some(3));
+
+    // Like the elevator server, no answer at all
+    sim.set_load_sensor(false);
+    let asked_at = std::time::Instant::now();
+    assert_eq!(elevator.load(), None);
+    assert!(asked_at.elapsed() < LOAD_TIMEOUT * 5, "The load command waited for {:?}", asked_at.elapsed());
+
+    // The stream is still in step for the commands that are answered
+    assert_eq!(elevator.floor_sensor(), Some(0));
+    assert!(!elevator.stop_button());
+}
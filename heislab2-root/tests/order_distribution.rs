@@ -0,0 +1,477 @@
+//! Property tests for the order distribution of the master and the slaves
+//!
+//! Every case builds the system state of a master with a few random cabs, runs a random list of steps
+//! through the real functions and checks the invariants after every step:
+//! - no hall order is lost, it is in the queue of an alive cab or waiting in `all_orders`
+//! - no hall order is in the queue of two alive cabs
+//! - no order is twice in `all_orders`
+//! - cab orders never leave the queue of their own cab
+//! - a cab that failed ends with no hall orders
+//!
+//! Messages go over a virtual network, cabs that are "unreachable" never answer so `ensure_broadcast`
+//! runs out of retries. When a case fails proptest shrinks it and prints the smallest scenario it found.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+use proptest::prelude::*;
+
+use heislab2_root::modules::cab_object::cab::{floor_mask, Cab};
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_DOWN, DIRN_STOP, DIRN_UP, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::master::{correct_master_worldview, give_order, reassign_elevator_orders, reassign_orders, Role};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::slave_functions::slave::update_from_worldview;
+use heislab2_root::modules::system_clock::{install_virtual_clock, system_now};
+use heislab2_root::modules::system_init::init_system_state;
+use heislab2_root::modules::system_status::SystemState;
+use heislab2_root::modules::udp_functions::udp::{msg_deserialize, udp_ack, MessageType, BROADCAST_PORT, OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use heislab2_root::modules::udp_functions::udp_handler_init::{init_virtual_udp_handler, UdpHandler};
+use heislab2_root::modules::udp_functions::virtual_network::VirtualNetwork;
+
+const NUM_FLOORS: u8 = 4;
+const MASTER_ID: u8 = 1;
+
+//----------------------------------------------Scenario
+
+/// One cab at the start of a scenario
+#[derive(Clone, Debug)]
+struct CabSpec {
+    id: u8,
+    floor: u8,
+    status: Status,
+    alive: bool,        // What the master believes
+    reachable: bool,    // If the cab answers on the network
+    cab_orders: Vec<u8>,
+}
+
+/// A hall order at the start of a scenario, owned by the cab at `owner % cabs` if it is alive, else waiting in all_orders
+#[derive(Clone, Debug)]
+struct HallSpec {
+    order: Order,
+    owner: u8,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    GiveOrder { cab: u8, order: Order },
+    FailCab(u8),
+    Reassign(Vec<Order>),
+    Worldview(Vec<(u8, Order)>),    // Orders the master has added to the queue of a cab, applied by a slave
+    Correct(Vec<(u8, Order)>),      // Orders a slave reports that the master has not got
+}
+
+#[derive(Clone)]
+struct Scenario {
+    cabs: Vec<CabSpec>,
+    hall_orders: Vec<HallSpec>,
+    steps: Vec<Step>,
+}
+
+fn order_name(order: &Order) -> String {
+    let kind = match order.order_type {
+        HALL_UP => "hall up",
+        HALL_DOWN => "hall down",
+        CAB => "cab",
+        _ => "unknown",
+    };
+    format!("{} at floor {}", kind, order.floor)
+}
+
+fn order_list(orders: &[Order]) -> String {
+    orders.iter().map(order_name).collect::<Vec<_>>().join(", ")
+}
+
+fn assignment_list(assignments: &[(u8, Order)]) -> String {
+    assignments.iter().map(|(cab, order)| format!("{} to cab {}", order_name(order), cab)).collect::<Vec<_>>().join(", ")
+}
+
+// Proptest prints the failing scenario with Debug, so make it read like a story
+impl fmt::Debug for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        for cab in &self.cabs {
+            writeln!(
+                f,
+                "  cab {}{}: floor {}, {:?}, {}, {}, cab orders at floors {:?}",
+                cab.id,
+                if cab.id == MASTER_ID { " (master)" } else { "" },
+                cab.floor,
+                cab.status,
+                if cab.alive { "alive" } else { "dead" },
+                if cab.reachable { "answers" } else { "never answers" },
+                cab.cab_orders,
+            )?;
+        }
+        for hall in &self.hall_orders {
+            writeln!(f, "  {} owned by cab slot {}", order_name(&hall.order), hall.owner)?;
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let text = match step {
+                Step::GiveOrder { cab, order } => format!("give {} to cab {}", order_name(order), cab),
+                Step::FailCab(cab) => format!("cab {} fails, its orders are reassigned", cab),
+                Step::Reassign(orders) => format!("reassign [{}]", order_list(orders)),
+                Step::Worldview(orders) => format!("worldview from master adds [{}]", assignment_list(orders)),
+                Step::Correct(orders) => format!("slave reports missing [{}]", assignment_list(orders)),
+            };
+            writeln!(f, "  step {}: {}", i + 1, text)?;
+        }
+        Ok(())
+    }
+}
+
+//----------------------------------------------Strategies
+
+fn any_order() -> impl Strategy<Value = Order> {
+    (0..NUM_FLOORS, 0..3u8).prop_map(|(floor, order_type)| Order::init(floor, order_type))
+}
+
+fn any_hall_order() -> impl Strategy<Value = Order> {
+    (0..NUM_FLOORS, 0..2u8).prop_map(|(floor, order_type)| Order::init(floor, order_type))
+}
+
+fn any_status() -> impl Strategy<Value = Status> {
+    prop_oneof![Just(Status::Idle), Just(Status::Moving), Just(Status::DoorOpen), Just(Status::Error)]
+}
+
+fn any_cab(id: u8) -> impl Strategy<Value = CabSpec> {
+    (0..NUM_FLOORS, any_status(), any::<bool>(), any::<bool>(), prop::collection::vec(0..NUM_FLOORS, 0..3)).prop_map(
+        move |(floor, status, alive, reachable, cab_orders)| CabSpec {
+            id,
+            floor,
+            status,
+            // The master is always alive and always hears itself
+            alive: alive || id == MASTER_ID,
+            reachable: reachable || id == MASTER_ID,
+            cab_orders,
+        },
+    )
+}
+
+fn any_step(num_cabs: u8) -> BoxedStrategy<Step> {
+    let cab = 1..=num_cabs;
+    let steps = prop_oneof![
+        3 => (cab.clone(), any_order()).prop_map(|(cab, order)| Step::GiveOrder { cab, order }),
+        1 => prop::collection::vec(any_order(), 0..3).prop_map(Step::Reassign),
+        1 => prop::collection::vec((cab.clone(), any_order()), 1..3).prop_map(Step::Worldview),
+        1 => prop::collection::vec((cab, any_order()), 1..3).prop_map(Step::Correct),
+    ];
+    if num_cabs == 1 {
+        return steps.boxed();
+    }
+    // The master never reports its own failure
+    prop_oneof![5 => steps, 1 => (2..=num_cabs).prop_map(Step::FailCab)].boxed()
+}
+
+fn any_scenario() -> impl Strategy<Value = Scenario> {
+    (1..=4u8).prop_flat_map(|num_cabs| {
+        let cabs: Vec<_> = (1..=num_cabs).map(any_cab).collect();
+        let hall_orders = prop::collection::vec((any_hall_order(), 0..num_cabs), 0..4)
+            .prop_map(|halls| halls.into_iter().map(|(order, owner)| HallSpec { order, owner }).collect());
+        let steps = prop::collection::vec(any_step(num_cabs), 1..6);
+        (cabs, hall_orders, steps).prop_map(|(cabs, hall_orders, steps)| Scenario { cabs, hall_orders, steps })
+    })
+}
+
+//----------------------------------------------Harness
+
+/// Virtual time moves 5 times faster than real time, so the retries of `ensure_broadcast` don't slow the tests down
+fn fast_clock() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        let clock = install_virtual_clock();
+        thread::spawn(move || loop {
+            clock.advance(Duration::from_millis(1));
+            thread::sleep(Duration::from_micros(200));
+        });
+    });
+}
+
+fn cab_address(id: u8, port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 200, 0, id)), port)
+}
+
+/// The master of a scenario, running its receive loop on a virtual network with a thread answering for every reachable cab
+struct Harness {
+    state: Arc<SystemState>,
+    udp_handler: Arc<UdpHandler>,
+    order_update_tx: cbc::Sender<Vec<Order>>,
+    stop: Arc<AtomicBool>,
+    hall_orders: Vec<Order>,        // Every hall order that the system has promised to serve
+    cab_orders: Vec<(u8, Order)>,   // Every cab order and the cab it belongs to
+    failed_cabs: Vec<u8>,
+}
+
+impl Harness {
+    fn start(scenario: &Scenario) -> Harness {
+        fast_clock();
+
+        let state = Arc::new(init_system_state(MASTER_ID, MASTER_ID));
+        let network = VirtualNetwork::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut cab_orders = Vec::new();
+
+        let mut cabs: Vec<Cab> = scenario
+            .cabs
+            .iter()
+            .map(|spec| {
+                let queue: Vec<Order> = spec.cab_orders.iter().map(|floor| Order::init(*floor, CAB)).collect();
+                for order in &queue {
+                    cab_orders.push((spec.id, order.clone()));
+                }
+                let direction = match spec.status {
+                    Status::Moving if spec.floor == 0 => DIRN_UP,
+                    Status::Moving => DIRN_DOWN,
+                    _ => DIRN_STOP,
+                };
+                Cab {
+                    inn_address: cab_address(spec.id, BROADCAST_PORT),
+                    out_address: cab_address(spec.id, 3800),
+                    num_floors: NUM_FLOORS,
+                    id: spec.id,
+                    current_floor: spec.floor,
+                    last_served_floor: spec.floor,
+                    queue,
+                    status: spec.status.clone(),
+                    direction,
+                    role: if spec.id == MASTER_ID { Role::Master } else { Role::Slave },
+                    last_lifesign: system_now(),
+                    alive: spec.alive,
+                    takes_hall_calls: true,
+                    maintenance: false,
+                    parking: None,
+                    served_floors: floor_mask(&(0..NUM_FLOORS).collect::<Vec<u8>>()),
+                    load: None,
+                    full_load: false,
+                    accessible_stops: Vec::new(),
+                    protocol_versions: (OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION),
+                }
+            })
+            .collect();
+
+        // Hand out the hall orders, each one only once
+        let mut hall_orders: Vec<Order> = Vec::new();
+        let mut all_orders = Vec::new();
+        for hall in &scenario.hall_orders {
+            if hall_orders.contains(&hall.order) {
+                continue;
+            }
+            hall_orders.push(hall.order.clone());
+            let owner = &mut cabs[hall.owner as usize % scenario.cabs.len()];
+            if owner.alive {
+                owner.queue.push(hall.order.clone());
+            } else {
+                all_orders.push(hall.order.clone());
+            }
+        }
+
+        state.update(move |s| {
+            s.known_elevators = cabs;
+            s.all_orders = all_orders;
+        });
+
+        // The master
+        let nic = network.attach(cab_address(MASTER_ID, BROADCAST_PORT), cab_address(MASTER_ID, 3800));
+        let udp_handler = Arc::new(init_virtual_udp_handler(nic));
+        let (order_update_tx, order_update_rx) = cbc::unbounded::<Vec<Order>>();
+        let (light_update_tx, light_update_rx) = cbc::unbounded::<Vec<Order>>();
+        {
+            let udp_handler = Arc::clone(&udp_handler);
+            let state = Arc::clone(&state);
+            let order_update_tx = order_update_tx.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                // The receivers live as long as the loop, the handlers send updates on them
+                let _updates = (order_update_rx, light_update_rx);
+                while !stop.load(Ordering::Relaxed) {
+                    Arc::clone(&udp_handler).receive(20, &state, order_update_tx.clone(), light_update_tx.clone());
+                }
+                thread::sleep(Duration::from_millis(100));
+            });
+        }
+
+        // The other cabs only answer new orders
+        for spec in scenario.cabs.iter().filter(|c| c.reachable && c.id != MASTER_ID) {
+            let nic = network.attach(cab_address(spec.id, BROADCAST_PORT), cab_address(spec.id, 3800));
+            let peer_handler = init_virtual_udp_handler(Arc::clone(&nic));
+            let stop = Arc::clone(&stop);
+            let id = spec.id;
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Some((data, _)) = nic.recv_timeout(Duration::from_millis(20)) {
+                        if let Some(msg) = msg_deserialize(&data) {
+                            if msg.header.message_type == MessageType::NewOrder {
+                                udp_ack(cab_address(MASTER_ID, BROADCAST_PORT), &msg, id, &peer_handler);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        Harness {
+            state,
+            udp_handler,
+            order_update_tx,
+            stop,
+            hall_orders,
+            cab_orders,
+            failed_cabs: Vec::new(),
+        }
+    }
+
+    /// Let the messages sent during a step arrive before the invariants are checked
+    fn settle(&self) {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    fn promise_hall_order(&mut self, order: &Order) {
+        if order.order_type != CAB && !self.hall_orders.contains(order) {
+            self.hall_orders.push(order.clone());
+        }
+    }
+
+    fn run(&mut self, step: &Step) {
+        match step {
+            Step::GiveOrder { cab, order } => {
+                if give_order(*cab, vec![order], &self.state, &self.udp_handler) {
+                    // An accepted order is a promise, a refused one is still the callers problem
+                    self.promise_hall_order(order);
+                }
+                // A cab order that reached its cab belongs to it, even if not every node acknowledged it
+                self.settle();
+                let landed = self.state.known_elevators().iter().any(|c| c.id == *cab && c.queue.contains(order));
+                if order.order_type == CAB && landed {
+                    self.cab_orders.push((*cab, order.clone()));
+                }
+            }
+            Step::FailCab(cab) => {
+                reassign_elevator_orders(*cab, &self.state, &self.udp_handler, self.order_update_tx.clone());
+                self.failed_cabs.push(*cab);
+            }
+            Step::Reassign(orders) => {
+                // Cab orders are never reassigned, they stay with the cab that has them
+                let orders: Vec<Order> = orders.iter().filter(|o| o.order_type != CAB).cloned().collect();
+                for order in &orders {
+                    self.promise_hall_order(order);
+                }
+                reassign_orders(&orders, &self.state, &self.udp_handler, self.order_update_tx.clone());
+            }
+            Step::Worldview(additions) => {
+                let worldview = self.view_with(additions);
+                update_from_worldview(&self.state, &worldview, Arc::clone(&self.udp_handler));
+            }
+            Step::Correct(additions) => {
+                let report = self.view_with(additions);
+                correct_master_worldview(&report, &self.state);
+            }
+        }
+    }
+
+    // The known cabs with some new orders added, as another node would see them.
+    // Hall orders that someone already has and orders for dead cabs are left out, that view would already be wrong.
+    fn view_with(&mut self, additions: &[(u8, Order)]) -> Vec<Cab> {
+        let mut view = self.state.known_elevators();
+        let all_orders = self.state.all_orders();
+        for (cab_id, order) in additions {
+            let taken = order.order_type != CAB && view.iter().any(|cab| cab.alive && cab.queue.contains(order));
+            let waiting = all_orders.contains(order);
+            if taken || waiting {
+                continue;
+            }
+            if let Some(cab) = view.iter_mut().find(|cab| cab.id == *cab_id && cab.alive) {
+                if !cab.queue.contains(order) {
+                    cab.queue.push(order.clone());
+                }
+                if order.order_type == CAB {
+                    self.cab_orders.push((*cab_id, order.clone()));
+                } else {
+                    self.promise_hall_order(order);
+                }
+            }
+        }
+        view
+    }
+
+    /// Returns a description of the first broken invariant
+    fn check(&self) -> Result<(), String> {
+        let (cabs, all_orders) = self.state.read(|s| (s.known_elevators.clone(), s.all_orders.clone()));
+
+        for order in &self.hall_orders {
+            let serving: Vec<u8> = cabs.iter().filter(|c| c.alive && c.queue.contains(order)).map(|c| c.id).collect();
+            if serving.is_empty() && !all_orders.contains(order) {
+                return Err(format!("{} was lost", order_name(order)));
+            }
+            if serving.len() > 1 {
+                return Err(format!("{} is served by alive cabs {:?}", order_name(order), serving));
+            }
+        }
+
+        for (i, order) in all_orders.iter().enumerate() {
+            if all_orders[..i].contains(order) {
+                return Err(format!("{} is twice in all_orders [{}]", order_name(order), order_list(&all_orders)));
+            }
+        }
+
+        for cab in &cabs {
+            for order in cab.queue.iter().filter(|o| o.order_type == CAB) {
+                let belongs_here = self.cab_orders.iter().any(|(id, o)| *id == cab.id && o == order);
+                if !belongs_here {
+                    return Err(format!("{} ended up in cab {}", order_name(order), cab.id));
+                }
+            }
+        }
+
+        for (id, order) in &self.cab_orders {
+            let still_there = cabs.iter().any(|c| c.id == *id && c.queue.contains(order));
+            if !still_there {
+                return Err(format!("{} left cab {}", order_name(order), id));
+            }
+        }
+
+        for id in &self.failed_cabs {
+            if let Some(cab) = cabs.iter().find(|c| c.id == *id && !c.alive) {
+                let hall: Vec<Order> = cab.queue.iter().filter(|o| o.order_type != CAB).cloned().collect();
+                if !hall.is_empty() {
+                    return Err(format!("failed cab {} still has [{}]", id, order_list(&hall)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+//----------------------------------------------Properties
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 128, ..ProptestConfig::default() })]
+
+    #[test]
+    fn order_distribution_keeps_invariants(scenario in any_scenario()) {
+        let mut harness = Harness::start(&scenario);
+        if let Err(broken) = harness.check() {
+            prop_assert!(false, "broken before the first step: {}", broken);
+        }
+
+        for (i, step) in scenario.steps.iter().enumerate() {
+            harness.run(step);
+            harness.settle();
+            if let Err(broken) = harness.check() {
+                prop_assert!(false, "after step {}: {}", i + 1, broken);
+            }
+        }
+
+    }
+}
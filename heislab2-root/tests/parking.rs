@@ -0,0 +1,130 @@
+//! Tests of the parking policy and of parking moves in the state machine of the cab
+//!
+//! - the policies pick the expected floor for every idle cab, busy cabs are left alone
+//! - the keys in "boot.txt" give the expected policy
+//! - a parked cab drives without opening the door and stops at the floor
+//! - a real order cancels the parking move
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel as cbc;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_command::run_elevator_commands;
+use heislab2_root::modules::cab_object::elevator_door::{DoorConfig, DoorTimer};
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_DOWN, DIRN_STOP, DIRN_UP};
+use heislab2_root::modules::elevator_object::elevator_init::Elevator;
+use heislab2_root::modules::master_functions::parking::{parking_targets, ParkingPolicy};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::simulation::sim_elevator::SimElevator;
+use heislab2_root::modules::system_init::init_system_state;
+
+const NUM_FLOORS: u8 = 4;
+
+/// An idle cab with an empty queue at `floor`
+fn idle_cab(id: u8, floor: u8) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut cab = Cab::init(&address, &address, NUM_FLOORS, id, &state).unwrap();
+    cab.current_floor = floor;
+    cab.status = Status::Idle;
+    return cab;
+}
+
+/// An idle cab on a simulated elevator standing at `floor`
+fn cab_on_elevator(floor: u8) -> (SimElevator, Elevator, Cab) {
+    let sim = SimElevator::start(NUM_FLOORS, floor as f32, Duration::from_secs(2)).unwrap();
+    let elevator = Elevator::init(&sim.address(), NUM_FLOORS).unwrap();
+    return (sim, elevator, idle_cab(1, floor));
+}
+
+/// A read goes over the same connection as the commands, so every command before it has been carried out
+fn settle(elevator: &Elevator) {
+    elevator.floor_sensor();
+}
+
+#[test]
+fn home_floors_park_only_listed_idle_cabs() {
+    let mut busy = idle_cab(2, 1);
+    busy.queue.push(Order::init(3, CAB));
+    let mut in_maintenance = idle_cab(3, 1);
+    in_maintenance.maintenance = true;
+    let cabs = vec![idle_cab(1, 2), busy, in_maintenance, idle_cab(4, 3)];
+    let policy = ParkingPolicy::HomeFloors(vec![(1, 0), (2, 0), (3, 0)]);
+
+    assert_eq!(parking_targets(&policy, &cabs, NUM_FLOORS, 12), vec![(1, 0)]);
+}
+
+#[test]
+fn morning_peak_sends_everyone_to_the_lobby_and_spreads_outside_it() {
+    let cabs = vec![idle_cab(1, 3), idle_cab(2, 3), idle_cab(3, 2)];
+    let policy = ParkingPolicy::MorningPeak { lobby: 0, start_hour: 7, end_hour: 10 };
+
+    assert_eq!(parking_targets(&policy, &cabs, NUM_FLOORS, 8), vec![(1, 0), (2, 0), (3, 0)]);
+    assert_eq!(parking_targets(&policy, &cabs, NUM_FLOORS, 10), parking_targets(&ParkingPolicy::Spread, &cabs, NUM_FLOORS, 10));
+}
+
+#[test]
+fn spread_keeps_the_order_of_the_cabs() {
+    let cabs = vec![idle_cab(1, 3), idle_cab(2, 0), idle_cab(3, 3)];
+
+    assert_eq!(parking_targets(&ParkingPolicy::Spread, &cabs, NUM_FLOORS, 12), vec![(2, 0), (1, 2), (3, 3)]);
+    assert_eq!(parking_targets(&ParkingPolicy::Spread, &cabs[..1].to_vec(), NUM_FLOORS, 12), vec![(1, 1)]);
+}
+
+#[test]
+fn policy_is_read_from_boot_keys() {
+    let mut policy = ParkingPolicy::default();
+    assert!(policy.parse("parking_home", " 1=0 2=3"));
+    assert!(policy.parse("parking", " home"));
+    assert_eq!(policy, ParkingPolicy::HomeFloors(vec![(1, 0), (2, 3)]));
+
+    let mut policy = ParkingPolicy::default();
+    assert!(policy.parse("parking", "lobby"));
+    assert!(policy.parse("parking_lobby", "1"));
+    assert!(policy.parse("parking_peak", "6-9"));
+    assert_eq!(policy, ParkingPolicy::MorningPeak { lobby: 1, start_hour: 6, end_hour: 9 });
+
+    assert!(!policy.parse("parking", "everywhere"));
+    assert!(!policy.parse("parking_home", "1:0"));
+}
+
+#[test]
+fn parking_move_drives_without_opening_the_door() {
+    let (sim, elevator, mut cab) = cab_on_elevator(0);
+    let (door_tx, _door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, DoorConfig::default());
+    cab.parking = Some(2);
+
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    settle(&elevator);
+    assert_eq!(cab.status, Status::Moving);
+    assert_eq!(sim.motor_direction(), DIRN_UP);
+
+    // Arrived, as the main loop does on a new floor
+    cab.current_floor = 2;
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    settle(&elevator);
+    assert_eq!(cab.status, Status::Idle);
+    assert_eq!(cab.parking, None);
+    assert_eq!(sim.motor_direction(), DIRN_STOP);
+    assert!(!sim.door_open(), "Door opened for a parking move");
+}
+
+#[test]
+fn real_order_cancels_parking() {
+    let (sim, elevator, mut cab) = cab_on_elevator(2);
+    let (door_tx, _door_rx) = cbc::unbounded();
+    let door = DoorTimer::start(door_tx, DoorConfig::default());
+    cab.parking = Some(3);
+
+    cab.queue.push(Order::init(0, CAB));
+    run_elevator_commands(&cab.go_next_floor(), &elevator, &door);
+    settle(&elevator);
+
+    assert_eq!(cab.parking, None);
+    assert_eq!(sim.motor_direction(), DIRN_DOWN);
+}
@@ -0,0 +1,168 @@
+//! Tests of the masterless peer mode
+//!
+//! - hall requests merge to the same set in any order, a press wins over a completion of the same version
+//! - every node gets the same assignment from the same state, and a cab keeps the hall calls it has
+//! - in the simulator hall calls are served without a master, also when the serving node dies
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::peer_functions::peer::{
+    add_hall_request, assign_hall_requests, complete_hall_request, merge_hall_requests, Coordination, HallRequest, PeerConfig,
+    NO_MASTER,
+};
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::system_init::init_system_state;
+
+fn cab_at(id: u8, floor: u8) -> Cab {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, id)), 20000);
+    let state = Arc::new(init_system_state(id, 1));
+    let mut cab = Cab::init(&address, &address, 6, id, &state).unwrap();
+    cab.current_floor = floor;
+    cab.alive = true;
+    cab.status = Status::Idle;
+    return cab;
+}
+
+fn peer_config() -> SimConfig {
+    let peer = PeerConfig { coordination: Coordination::Peer, ..PeerConfig::default() };
+    SimConfig { peer, ..SimConfig::default() }
+}
+
+#[test]
+fn peer_keys_are_read() {
+    let mut config = PeerConfig::default();
+    assert_eq!(config.coordination, Coordination::Master);
+
+    assert!(config.parse("coordination", " peer"));
+    assert!(config.parse("peer_gossip_period", "0.5"));
+    assert!(config.parse("peer_timeout", "3"));
+    assert_eq!(config.coordination, Coordination::Peer);
+    assert_eq!(config.gossip_period, Duration::from_millis(500));
+    assert_eq!(config.peer_timeout, Duration::from_secs(3));
+
+    assert!(!config.parse("coordination", "anarchy"));
+    assert!(!config.parse("peer_timeout", "0"));
+    assert!(!config.parse("peer_timeout", "inf"));
+    assert!(!config.parse("peer_gossip_period", "1e30"));
+    assert!(!config.parse("peer_colour", "1"));
+}
+
+#[test]
+fn hall_requests_merge_to_the_same_set() {
+    let up = Order::init(1, HALL_UP);
+    let down = Order::init(3, HALL_DOWN);
+
+    let mut a = Vec::new();
+    assert!(add_hall_request(&mut a, &up));
+    assert!(!add_hall_request(&mut a, &up), "An active request is not pressed again");
+    let mut b = Vec::new();
+    add_hall_request(&mut b, &down);
+
+    let mut ab = a.clone();
+    merge_hall_requests(&mut ab, &b);
+    let mut ba = b.clone();
+    merge_hall_requests(&mut ba, &a);
+    ab.sort_by_key(|request| request.order.floor);
+    ba.sort_by_key(|request| request.order.floor);
+    assert_eq!(ab, ba);
+
+    // The completion has a higher version and wins, in either direction
+    let mut served = ab.clone();
+    assert!(complete_hall_request(&mut served, &up));
+    assert!(merge_hall_requests(&mut ab, &served));
+    assert!(!ab.iter().find(|request| request.order == up).unwrap().active);
+    assert!(!merge_hall_requests(&mut served, &ba));
+
+    // A new press and a completion of the same version, the press wins so the call is not lost
+    let pressed = vec![HallRequest { order: down.clone(), active: true, version: 2 }];
+    let mut completed = vec![HallRequest { order: down.clone(), active: false, version: 2 }];
+    assert!(merge_hall_requests(&mut completed, &pressed));
+    assert!(completed[0].active);
+}
+
+#[test]
+fn every_node_gets_the_same_assignment() {
+    let mut requests = Vec::new();
+    add_hall_request(&mut requests, &Order::init(0, HALL_UP));
+    add_hall_request(&mut requests, &Order::init(5, HALL_DOWN));
+    let cabs = vec![cab_at(1, 0), cab_at(2, 5), cab_at(3, 2)];
+
+    let assignment = assign_hall_requests(&requests, &cabs);
+    assert!(assignment.contains(&(1, Order::init(0, HALL_UP))), "{:?}", assignment);
+    assert!(assignment.contains(&(2, Order::init(5, HALL_DOWN))), "{:?}", assignment);
+
+    // The cabs in another order, and the requests merged the other way
+    let reversed: Vec<Cab> = cabs.iter().rev().cloned().collect();
+    let mut merged = Vec::new();
+    merge_hall_requests(&mut merged, &requests.iter().rev().cloned().collect::<Vec<HallRequest>>());
+    assert_eq!(assign_hall_requests(&merged, &reversed), assignment);
+}
+
+#[test]
+fn a_cab_keeps_its_hall_calls_until_it_dies() {
+    let order = Order::init(4, HALL_DOWN);
+    let mut requests = Vec::new();
+    add_hall_request(&mut requests, &order);
+
+    // Cab 3 is further away but already on its way
+    let mut far = cab_at(3, 0);
+    far.queue = vec![Order::init(1, CAB), order.clone()];
+    let cabs = vec![cab_at(1, 4), cab_at(2, 3), far];
+    assert_eq!(assign_hall_requests(&requests, &cabs), vec![(3, order.clone())]);
+
+    // Two cabs took it at the same time, the lowest id keeps it
+    let mut both = cabs.clone();
+    both[1].queue.push(order.clone());
+    assert_eq!(assign_hall_requests(&requests, &both), vec![(2, order.clone())]);
+
+    let mut dead = cabs.clone();
+    dead[2].alive = false;
+    assert_eq!(assign_hall_requests(&requests, &dead), vec![(1, order.clone())]);
+
+    // Served requests are not assigned
+    complete_hall_request(&mut requests, &order);
+    assert!(assign_hall_requests(&requests, &cabs).is_empty());
+}
+
+#[test]
+fn peers_serve_hall_calls_without_a_master() {
+    let mut sim = Simulation::start(peer_config());
+
+    sim.press(1, 3, HALL_DOWN);
+    sim.press(3, 1, HALL_UP);
+    sim.press(2, 2, CAB);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+
+    for id in 1..=3 {
+        let state = &sim.node(id).state;
+        assert_eq!(state.master_id(), NO_MASTER, "Elevator {} elected a master", id);
+        assert_eq!(state.known_elevators().iter().filter(|cab| cab.alive).count(), 3, "Elevator {} lost a peer", id);
+    }
+}
+
+#[test]
+fn hall_call_of_a_dead_peer_is_taken_over() {
+    let mut sim = Simulation::start(peer_config());
+
+    sim.press(1, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    let assigned = sim.run_until(Duration::from_secs(5), |sim| sim.nodes_serving(&order).len() == 1);
+    assert!(assigned, "Hall call was not assigned to exactly one peer: {:?}", sim.nodes_serving(&order));
+
+    let victim = sim.nodes_serving(&order)[0];
+    sim.kill_node(victim);
+
+    // The peer timeout, not the 10 s of a master
+    let taken_over = sim.run_until(Duration::from_secs(4), |sim| sim.nodes_serving(&order).iter().any(|id| *id != victim));
+    assert!(taken_over, "No peer took over the hall call of elevator {}", victim);
+
+    let served = sim.run_until(Duration::from_secs(60), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after the peer died: {:?}", sim.unserved_calls());
+}
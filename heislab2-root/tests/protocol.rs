@@ -0,0 +1,157 @@
+//! Tests of the versioned wire format, without running a node
+//!
+//! - every datagram starts with the magic bytes and the version
+//! - datagrams from other programs or unknown versions are rejected with their own error
+//! - two nodes use the newest version they both speak, and nothing is sent to a node with no common version
+//! - message types newer than a version are not sent in it
+//! - the Cab of version 1 keeps its layout, a new field needs a new version
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_UP, HALL_UP};
+use heislab2_root::modules::master_functions::master::Role;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::shutdown::Handover;
+use heislab2_root::modules::system_init::init_system_state;
+use heislab2_root::modules::udp_functions::codec::Codec;
+use heislab2_root::modules::udp_functions::udp::{
+    decode_msg, encode_msg, make_udp_msg, negotiate, MessageType, ProtocolError, UdpData, OLDEST_PROTOCOL_VERSION,
+    PROTOCOL_MAGIC, PROTOCOL_VERSION,
+};
+use heislab2_root::modules::udp_functions::udp_handler_init::init_virtual_udp_handler;
+use heislab2_root::modules::udp_functions::virtual_network::VirtualNetwork;
+
+fn address(id: u8) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, id)), 20000)
+}
+
+fn cab_speaking(id: u8, versions: (u8, u8)) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let mut cab = Cab::init(&address(id), &address(id), 4, id, &state).unwrap();
+    cab.protocol_versions = versions;
+    return cab;
+}
+
+#[test]
+fn datagram_starts_with_magic_and_version() {
+    let msg = make_udp_msg(1, MessageType::NewRequest, UdpData::Order(Order::init(2, HALL_UP)));
+    let datagram = encode_msg(&msg, PROTOCOL_VERSION).unwrap();
+
+    assert_eq!(datagram[..2], PROTOCOL_MAGIC);
+    assert_eq!(datagram[2], PROTOCOL_VERSION);
+    assert_eq!(decode_msg(&datagram), Ok((PROTOCOL_VERSION, msg)));
+}
+
+#[test]
+fn foreign_and_unknown_datagrams_are_rejected() {
+    let msg = make_udp_msg(1, MessageType::NewRequest, UdpData::Order(Order::init(2, HALL_UP)));
+    let datagram = encode_msg(&msg, PROTOCOL_VERSION).unwrap();
+
+    // Unframed bincode, as sent before the protocol had a version
+    assert_eq!(decode_msg(&datagram[3..]), Err(ProtocolError::BadMagic));
+    assert_eq!(decode_msg(b"H"), Err(ProtocolError::BadMagic));
+
+    let mut newer = datagram.clone();
+    newer[2] = PROTOCOL_VERSION + 1;
+    assert_eq!(decode_msg(&newer), Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+    assert_eq!(encode_msg(&msg, PROTOCOL_VERSION + 1), Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+
+    assert!(matches!(decode_msg(&datagram[..datagram.len() - 2]), Err(ProtocolError::Malformed(_))));
+
+    let wrong_data = make_udp_msg(1, MessageType::NewRequest, UdpData::Checksum(7));
+    let datagram = encode_msg(&wrong_data, PROTOCOL_VERSION).unwrap();
+    assert_eq!(decode_msg(&datagram), Err(ProtocolError::WrongData(MessageType::NewRequest)));
+}
+
+#[test]
+fn shutdown_messages_are_not_sent_in_the_first_version() {
+    let cab = cab_speaking(1, (OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION));
+    let leaving = make_udp_msg(1, MessageType::Leaving, UdpData::Cab(cab.clone()));
+    assert_eq!(encode_msg(&leaving, 1), Err(ProtocolError::NotInVersion(MessageType::Leaving, 1)));
+
+    let handover = Handover { known_elevators: vec![cab], all_orders: vec![Order::init(2, HALL_UP)], pending: Vec::new() };
+    let msg = make_udp_msg(1, MessageType::Handover, UdpData::Handover(handover));
+    let datagram = encode_msg(&msg, 2).unwrap();
+    assert_eq!(decode_msg(&datagram), Ok((2, msg)));
+}
+
+#[test]
+fn newest_common_version_is_used() {
+    assert_eq!(negotiate((1, 3), (2, 5)), Ok(3));
+    assert_eq!(negotiate((1, 3), (1, 1)), Ok(1));
+    assert_eq!(negotiate((1, 3), (4, 5)), Err(ProtocolError::Incompatible { ours: (1, 3), theirs: (4, 5) }));
+    assert_eq!(negotiate((2, 3), (1, 1)), Err(ProtocolError::Incompatible { ours: (2, 3), theirs: (1, 1) }));
+}
+
+#[test]
+fn handler_talks_to_each_node_in_its_version() {
+    let network = VirtualNetwork::new();
+    let handler = init_virtual_udp_handler(network.attach(address(1), address(1)));
+    let _compatible_nic = network.attach(address(2), address(2));
+    let _newer_nic = network.attach(address(3), address(3));
+
+    // Nodes not heard from yet get the oldest version
+    assert_eq!(handler.version_for(&address(2)), Ok(OLDEST_PROTOCOL_VERSION));
+    assert_eq!(handler.broadcast_version(), OLDEST_PROTOCOL_VERSION);
+
+    let compatible = cab_speaking(2, (OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION + 1));
+    handler.learn_peer_versions(&make_udp_msg(2, MessageType::NewOnline, UdpData::Cab(compatible.clone())));
+    assert_eq!(handler.version_for(&address(2)), Ok(PROTOCOL_VERSION));
+
+    let newer = cab_speaking(3, (PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2));
+    handler.learn_peer_versions(&make_udp_msg(3, MessageType::NewOnline, UdpData::Cab(newer.clone())));
+    assert!(matches!(handler.version_for(&address(3)), Err(ProtocolError::Incompatible { .. })));
+
+    let msg = make_udp_msg(1, MessageType::ImAlive, UdpData::Cab(compatible));
+    assert!(handler.send(&address(2), &msg));
+    assert!(!handler.send(&address(3), &msg), "Sent to a node with no common version");
+}
+
+#[test]
+fn cab_of_the_first_version_keeps_its_layout() {
+    // Version 1 is the first framed version, its Cab has every field up to protocol_versions
+    let cab = Cab {
+        inn_address: address(2),
+        out_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, 2)), 3800),
+        num_floors: 4,
+        id: 2,
+        current_floor: 1,
+        last_served_floor: 1,
+        queue: vec![Order::init(3, CAB)],
+        status: Status::Moving,
+        direction: DIRN_UP,
+        role: Role::Slave,
+        last_lifesign: SystemTime::UNIX_EPOCH,
+        alive: true,
+        takes_hall_calls: true,
+        maintenance: false,
+        parking: Some(0),
+        served_floors: 0b1011,
+        load: Some(300),
+        full_load: false,
+        accessible_stops: vec![3],
+        protocol_versions: (1, 4),
+    };
+    let expected: Vec<u8> = [
+        &[0, 0, 0, 0, 10, 100, 0, 2, 32, 78][..],   // inn_address
+        &[0, 0, 0, 0, 10, 100, 0, 2, 216, 14],      // out_address
+        &[4, 2, 1, 1],                              // num_floors, id, current_floor, last_served_floor
+        &[1, 0, 0, 0, 0, 0, 0, 0, 3, 2],            // queue
+        &[1, 0, 0, 0, 1, 1, 0, 0, 0],               // status, direction, role
+        &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],      // last_lifesign
+        &[1, 1, 0],                                 // alive, takes_hall_calls, maintenance
+        &[1, 0],                                    // parking
+        &[11, 0, 0, 0, 0, 0, 0, 0],                 // served_floors
+        &[1, 44, 1, 0],                             // load, full_load
+        &[1, 0, 0, 0, 0, 0, 0, 0, 3],               // accessible_stops
+        &[1, 4],                                    // protocol_versions
+    ]
+    .concat();
+
+    assert_eq!(Codec::Bincode.encode(&cab).unwrap(), expected, "The Cab layout changed, bump PROTOCOL_VERSION");
+    assert_eq!(Codec::Bincode.decode::<Cab>(&expected), Ok(cab));
+}
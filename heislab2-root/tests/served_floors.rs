@@ -0,0 +1,93 @@
+//! Tests of cabs that only stop at some floors, without a network
+//!
+//! - the floor lists in "boot.txt" are read into masks
+//! - best_to_worst_elevator never puts a cab first for a floor it does not stop at
+//! - orders no alive cab can reach are found
+//! - the master sets them aside once and gives them out when a cab stops there again
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use heislab2_root::modules::cab_object::cab::{floor_mask, parse_floors, Cab};
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::master::{best_to_worst_elevator, set_aside_unservable, unservable_orders};
+use heislab2_root::modules::master_functions::traffic::DispatchMode;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::system_init::init_system_state;
+
+const NUM_FLOORS: u8 = 6;
+
+fn idle_cab(id: u8, floor: u8, floors: &[u8]) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut cab = Cab::init(&address, &address, NUM_FLOORS, id, &state).unwrap();
+    cab.current_floor = floor;
+    cab.status = Status::Idle;
+    cab.served_floors = floor_mask(floors);
+    return cab;
+}
+
+#[test]
+fn floor_lists_are_read() {
+    assert_eq!(parse_floors("0,2-4", NUM_FLOORS), Some(vec![0, 2, 3, 4]));
+    assert_eq!(parse_floors(" 5 ", NUM_FLOORS), Some(vec![5]));
+    assert_eq!(parse_floors("1-6", NUM_FLOORS), None);
+    assert_eq!(parse_floors("3-1", NUM_FLOORS), None);
+    assert_eq!(parse_floors("one", NUM_FLOORS), None);
+}
+
+#[test]
+fn new_cab_stops_at_every_floor_of_its_shaft() {
+    let state = Arc::new(init_system_state(1, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let cab = Cab::init(&address, &address, 4, 1, &state).unwrap();
+
+    assert!((0..4).all(|floor| cab.serves_floor(floor)));
+    assert!(!cab.serves_floor(4));
+}
+
+#[test]
+fn orders_only_go_to_cabs_that_stop_there() {
+    // Cab 1 is an express cab, cab 2 does not reach the basement
+    let cabs = vec![idle_cab(1, 0, &[0, 4, 5]), idle_cab(2, 1, &[1, 2, 3, 4, 5])];
+
+    assert_eq!(best_to_worst_elevator(&Order::init(2, HALL_UP), &cabs, DispatchMode::Balanced), vec![2]);
+    assert_eq!(best_to_worst_elevator(&Order::init(0, HALL_UP), &cabs, DispatchMode::Balanced), vec![1]);
+    assert_eq!(best_to_worst_elevator(&Order::init(3, CAB), &cabs, DispatchMode::Balanced), vec![2]);
+    assert_eq!(best_to_worst_elevator(&Order::init(5, HALL_DOWN), &cabs, DispatchMode::Balanced).len(), 2);
+}
+
+#[test]
+fn orders_no_alive_cab_reaches_are_unservable() {
+    let mut dead = idle_cab(2, 1, &[1, 2]);
+    dead.alive = false;
+    let cabs = vec![idle_cab(1, 0, &[0, 3]), dead];
+    let orders = vec![Order::init(0, HALL_UP), Order::init(2, HALL_UP), Order::init(1, HALL_DOWN), Order::init(2, CAB)];
+
+    assert_eq!(unservable_orders(&orders, &cabs), vec![Order::init(2, HALL_UP), Order::init(1, HALL_DOWN)]);
+}
+
+#[test]
+fn unservable_orders_are_set_aside_until_a_cab_stops_there() {
+    let state = Arc::new(init_system_state(1, 1));
+    let mut dead = idle_cab(2, 1, &[1, 2]);
+    dead.alive = false;
+    let cabs = vec![idle_cab(1, 0, &[0, 3]), dead];
+    state.update(move |s| s.known_elevators = cabs);
+
+    let orders = vec![Order::init(0, HALL_UP), Order::init(2, HALL_UP)];
+    assert_eq!(set_aside_unservable(orders.clone(), &state), vec![Order::init(0, HALL_UP)]);
+    assert_eq!(state.unservable(), vec![Order::init(2, HALL_UP)]);
+    assert_eq!(state.all_orders(), vec![Order::init(2, HALL_UP)]);
+
+    // Asked again nothing is added twice
+    assert_eq!(set_aside_unservable(orders.clone(), &state), vec![Order::init(0, HALL_UP)]);
+    assert_eq!(state.unservable(), vec![Order::init(2, HALL_UP)]);
+    assert_eq!(state.all_orders(), vec![Order::init(2, HALL_UP)]);
+
+    // Cab 2 is back, the order can be given out
+    state.update(|s| s.known_elevators[1].alive = true);
+    assert_eq!(set_aside_unservable(orders.clone(), &state), orders);
+    assert!(state.unservable().is_empty());
+}
@@ -0,0 +1,72 @@
+//! Graceful shutdown in the simulator: the other nodes take over at once instead of after the lifesign timeouts
+
+use std::time::Duration;
+
+use heislab2_root::modules::elevator_object::alias_lib::HALL_DOWN;
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+
+// Make `id` the only cab taking hall calls until `call` is in its queue, then put the others back in service
+fn assign_hall_call_to(sim: &mut Simulation, id: u8, floor: u8, call: u8) {
+    let others: Vec<u8> = (1..=sim.config.num_nodes).filter(|other| *other != id).collect();
+    for other in others.iter() {
+        sim.set_maintenance(*other, *other, true);
+    }
+    let out_of_service = sim.run_until(Duration::from_secs(5), |sim| {
+        others.iter().all(|other| sim.node(id).state.known_elevators().iter().any(|cab| cab.id == *other && cab.maintenance))
+    });
+    assert!(out_of_service, "The other cabs never went into maintenance");
+
+    sim.press(id, floor, call);
+    let order = sim.calls.last().unwrap().order.clone();
+    let assigned = sim.run_until(Duration::from_secs(5), |sim| sim.nodes_serving(&order) == vec![id]);
+    assert!(assigned, "Hall call was not given to elevator {}", id);
+
+    for other in others.iter() {
+        sim.set_maintenance(*other, *other, false);
+    }
+    sim.run_for(Duration::from_millis(500));
+}
+
+#[test]
+fn master_hands_over_its_state_when_shut_down() {
+    let mut sim = Simulation::start(SimConfig::default());
+    assert_eq!(sim.node(2).state.master_id(), 1, "Elevator 1 should be master after boot");
+
+    assign_hall_call_to(&mut sim, 1, 3, HALL_DOWN);
+    assert!(sim.shutdown_node(1, Duration::from_secs(2)), "The master did not finish leaving");
+
+    // Well before the 3 s master timeout
+    let taken_over = sim.run_until(Duration::from_secs(1), |sim| {
+        [2, 3].iter().all(|id| {
+            let state = &sim.node(*id).state;
+            state.master_id() == 2 && state.known_elevators().iter().any(|cab| cab.id == 1 && !cab.alive)
+        })
+    });
+    assert!(taken_over, "Elevator 2 did not take over as master at once");
+
+    let served = sim.run_until(Duration::from_secs(60), |sim| sim.all_calls_served());
+    assert!(served, "The hall call of the old master was not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn slave_gives_away_its_hall_orders_when_shut_down() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    assign_hall_call_to(&mut sim, 2, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    assert!(sim.shutdown_node(2, Duration::from_secs(2)), "Elevator 2 did not finish leaving");
+
+    let reassigned = sim.run_until(Duration::from_secs(1), |sim| {
+        let serving = sim.nodes_serving(&order);
+        !serving.is_empty() && !serving.contains(&2)
+    });
+    assert!(reassigned, "The hall call of elevator 2 was not given to another cab at once");
+    // The master adds the order to its own state before it reads the Leaving message
+    let offline = sim.run_until(Duration::from_secs(1), |sim| {
+        sim.node(1).state.known_elevators().iter().any(|cab| cab.id == 2 && !cab.alive)
+    });
+    assert!(offline, "The master still takes elevator 2 as alive");
+
+    let served = sim.run_until(Duration::from_secs(60), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
@@ -0,0 +1,526 @@
+//! Scenario tests, every test runs a full system of nodes in the simulator
+//! Run with `cargo test -- --nocapture` to see the log of the nodes
+
+use std::time::{Duration, Instant};
+
+use heislab2_root::modules::cab_object::elevator_door::{DoorConfig, DoorRequest};
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, DIRN_STOP, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::master::unservable_orders;
+use heislab2_root::modules::master_functions::parking::ParkingPolicy;
+use heislab2_root::modules::master_functions::traffic::{DispatchMode, TrafficConfig};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::udp_functions::fault_injection::{FaultConfig, Partition};
+
+#[test]
+fn hall_call_is_served() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(1, 3, HALL_DOWN);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn cab_call_is_served_by_own_cab() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(2, 2, CAB);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn hall_call_is_served_when_serving_node_dies() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(1, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&order).is_empty());
+    assert!(assigned, "Hall call was never assigned");
+
+    // Kill the node that took the order before it gets there
+    let victim = sim.nodes_serving(&order)[0];
+    sim.kill_node(victim);
+
+    let served = sim.run_until(Duration::from_secs(60), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after node died: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn every_thread_of_the_nodes_returns_when_the_simulation_stops() {
+    let faults = FaultConfig { delay: Duration::from_millis(20), ..FaultConfig::default() };
+    let mut sim = Simulation::start(SimConfig { faults: Some(faults), ..SimConfig::default() });
+
+    // Stop with a cab moving, a node dead and copies waiting in the fault injection
+    sim.press(1, 3, HALL_DOWN);
+    sim.run_for(Duration::from_secs(1));
+    sim.kill_node(3);
+    sim.run_for(Duration::from_secs(1));
+
+    assert_eq!(sim.stop(), 0, "Threads of the nodes still running after the simulation stopped");
+}
+
+#[test]
+fn random_hall_calls_are_served() {
+    let config = SimConfig { seed: 26, ..SimConfig::default() };
+    let mut sim = Simulation::start(config);
+
+    for _ in 0..6 {
+        sim.press_random_hall_call();
+        sim.run_for(Duration::from_secs(3));
+    }
+    let served = sim.run_until(Duration::from_secs(90), |sim| sim.all_calls_served());
+
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn hall_calls_are_served_on_lossy_network() {
+    let faults = FaultConfig {
+        loss_percent: 20.0,
+        delay: Duration::from_millis(20),
+        jitter: Duration::from_millis(30),
+        duplicate_percent: 10.0,
+        reorder_percent: 10.0,
+        reorder_delay: Duration::from_millis(100),
+        ..FaultConfig::default()
+    };
+    let config = SimConfig { seed: 27, faults: Some(faults), ..SimConfig::default() };
+    let mut sim = Simulation::start(config);
+
+    for _ in 0..4 {
+        sim.press_random_hall_call();
+        sim.run_for(Duration::from_secs(3));
+    }
+    let served = sim.run_until(Duration::from_secs(90), |sim| sim.all_calls_served());
+
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn new_master_is_elected_when_master_is_partitioned() {
+    let faults = FaultConfig {
+        partitions: vec![Partition::parse("1|2,3@8-60").unwrap()],
+        ..FaultConfig::default()
+    };
+    let config = SimConfig { faults: Some(faults), ..SimConfig::default() };
+    let mut sim = Simulation::start(config);
+
+    let master_of = |sim: &Simulation, id: u8| sim.node(id).state.master_id();
+    assert_eq!(master_of(&sim, 2), 1);
+
+    let elected = sim.run_until(Duration::from_secs(30), |sim| {
+        let new_master = master_of(sim, 2);
+        new_master != 1 && new_master == master_of(sim, 3)
+    });
+    assert!(elected, "Nodes 2 and 3 did not agree on a new master");
+
+    sim.press(2, 3, HALL_DOWN);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served by the new master: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn hall_call_is_handed_off_when_motor_stalls() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(1, 3, HALL_DOWN);
+    let order = sim.calls[0].order.clone();
+    let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&order).is_empty());
+    assert!(assigned, "Hall call was never assigned");
+
+    // The node stays on the network, only its motor dies
+    let victim = sim.nodes_serving(&order)[0];
+    sim.set_motor_power(victim, false);
+
+    let served = sim.run_until(Duration::from_secs(60), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after the motor stalled: {:?}", sim.unserved_calls());
+
+    let in_error = |sim: &Simulation| sim.node(victim).state.my_cab().is_some_and(|cab| cab.status == Status::Error);
+    assert!(in_error(&sim), "Node {} did not notice its motor stalled", victim);
+
+    // With power back the node reaches a floor and is trusted again by the others
+    sim.set_motor_power(victim, true);
+    let rejoined = sim.run_until(Duration::from_secs(30), |sim| {
+        !in_error(sim)
+            && sim.nodes.iter().all(|node| {
+                node.state.known_elevators().iter().any(|cab| cab.id == victim && cab.alive)
+            })
+    });
+    assert!(rejoined, "Node {} did not rejoin after the motor came back", victim);
+}
+
+#[test]
+fn hall_orders_are_given_away_when_door_is_obstructed() {
+    let mut sim = Simulation::start(SimConfig { obstruction_timeout: Duration::from_secs(3), ..SimConfig::default() });
+
+    sim.press(1, 3, HALL_DOWN);
+    let hall_call = sim.calls[0].clone();
+    let arrived = sim.run_until(Duration::from_secs(20), |sim| sim.all_calls_served());
+    assert!(arrived, "Hall call was never served");
+
+    // Jam the door of the cab that came, it still has the hall order until the door closes
+    let victim = sim.nodes.iter().find(|node| node.elevator.door_opened_at(3, hall_call.pressed_at)).unwrap().id;
+    sim.set_obstruction(victim, true);
+    sim.press(victim, 0, CAB);
+    let cab_call = sim.calls[1].clone();
+
+    let handed_off = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.nodes.iter().any(|node| node.id != victim && node.elevator.door_opened_at(3, hall_call.pressed_at))
+    });
+    assert!(handed_off, "No other cab took the hall call from the obstructed cab");
+
+    let victim_cab = sim.node(victim).state.my_cab().unwrap();
+    assert!(victim_cab.queue.contains(&cab_call.order), "The obstructed cab lost its cab order");
+    assert!(victim_cab.queue.iter().all(|order| order.order_type == CAB), "The obstructed cab kept hall orders");
+    for node in sim.nodes.iter() {
+        let seen = node.state.known_elevators().into_iter().find(|cab| cab.id == victim).unwrap();
+        assert!(!seen.takes_hall_calls, "Node {} does not know cab {} is obstructed", node.id, victim);
+    }
+
+    // When the obstruction clears the cab serves its cab order and takes hall calls again
+    sim.set_obstruction(victim, false);
+    let recovered = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.call_served(&cab_call)
+            && sim.nodes.iter().all(|node| {
+                node.state.known_elevators().iter().any(|cab| cab.id == victim && cab.takes_hall_calls)
+            })
+    });
+    assert!(recovered, "Cab {} did not recover after the obstruction cleared", victim);
+}
+
+#[test]
+fn emergency_stop_releases_hall_orders_and_resumes() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(1, 3, HALL_DOWN);
+    let hall_call = sim.calls[0].clone();
+    let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&hall_call.order).is_empty());
+    assert!(assigned, "Hall call was never assigned");
+    let victim = sim.nodes_serving(&hall_call.order)[0];
+
+    // Stop the cab between floors on its way up
+    let underway = sim.run_until(Duration::from_secs(10), |sim| sim.node(victim).elevator.position() > 1.3);
+    assert!(underway, "Cab {} never left", victim);
+    sim.set_stop_button(victim, true);
+    sim.press(victim, 0, CAB);
+    let cab_call = sim.calls[1].clone();
+
+    let stopped = sim.run_until(Duration::from_secs(2), |sim| {
+        let elevator = &sim.node(victim).elevator;
+        elevator.stop_light() && elevator.motor_direction() == DIRN_STOP
+    });
+    assert!(stopped, "Cab {} did not stop", victim);
+
+    let handed_off = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.nodes.iter().any(|node| node.id != victim && node.elevator.door_opened_at(3, hall_call.pressed_at))
+    });
+    assert!(handed_off, "No other cab took the hall call from the stopped cab");
+    let stopped_cab = sim.node(victim).state.my_cab().unwrap();
+    assert!(stopped_cab.queue.contains(&cab_call.order), "The stopped cab lost its cab order");
+    assert!(!sim.node(victim).elevator.door_open(), "Door opened between floors");
+    assert!(sim.node(victim).elevator.stop_light(), "Stop lamp went out while held");
+
+    // Released, the cab serves its cab order and is alive again for the others
+    sim.set_stop_button(victim, false);
+    let resumed = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.call_served(&cab_call)
+            && !sim.node(victim).elevator.stop_light()
+            && sim.nodes.iter().all(|node| {
+                node.state.known_elevators().iter().any(|cab| cab.id == victim && cab.alive)
+            })
+    });
+    assert!(resumed, "Cab {} did not resume after the stop was released", victim);
+}
+
+#[test]
+fn nodes_starting_between_floors_find_a_floor_before_going_online() {
+    let mut sim = Simulation::start(SimConfig { start_position: 1.5, boot_time: Duration::ZERO, ..SimConfig::default() });
+
+    // Pressed while the cab is still looking for its first floor, queued until it is online
+    sim.press(1, 3, CAB);
+    let in_init = sim.run_until(Duration::from_millis(200), |sim| {
+        sim.node(1).state.my_cab().is_some_and(|cab| cab.status == Status::Init)
+    });
+    assert!(in_init, "Node 1 did not start in the init phase");
+
+    let online = sim.run_until(Duration::from_secs(10), |sim| {
+        sim.nodes.iter().all(|node| {
+            node.state.known_elevators().len() == sim.nodes.len()
+                && node.state.known_elevators().iter().all(|cab| cab.status != Status::Init && cab.current_floor >= 1)
+        })
+    });
+    assert!(online, "Nodes did not go online with their true floor");
+    for node in sim.nodes.iter().filter(|node| node.id != 1) {
+        assert_eq!(node.elevator.floor_sensor(), Some(1), "Node {} did not stop at the first floor below", node.id);
+        assert_eq!(node.elevator.motor_direction(), DIRN_STOP);
+    }
+
+    sim.press(2, 0, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served after start: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn cab_in_maintenance_hands_off_hall_orders_and_keeps_cab_calls() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    sim.press(1, 3, HALL_DOWN);
+    let hall_call = sim.calls[0].clone();
+    let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&hall_call.order).is_empty());
+    assert!(assigned, "Hall call was never assigned");
+    let victim = sim.nodes_serving(&hall_call.order)[0];
+    let operator = sim.nodes.iter().find(|node| node.id != victim).unwrap().id;
+
+    // Asked for from the keyboard of another node, sent on with a Maintenance message
+    sim.set_maintenance(operator, victim, true);
+    sim.press(victim, 2, CAB);
+    let cab_call = sim.calls[1].clone();
+
+    let handed_off = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.nodes.iter().any(|node| node.id != victim && node.elevator.door_opened_at(3, hall_call.pressed_at))
+    });
+    assert!(handed_off, "No other cab took the hall call from the cab in maintenance");
+    for node in sim.nodes.iter() {
+        let seen = node.state.known_elevators().into_iter().find(|cab| cab.id == victim).unwrap();
+        assert!(seen.maintenance, "Node {} does not know cab {} is in maintenance", node.id, victim);
+    }
+
+    // New hall calls go to the others, the cab call is still served
+    sim.press(victim, 0, HALL_UP);
+    let new_hall_call = sim.calls[2].clone();
+    let served = sim.run_until(Duration::from_secs(30), |sim| {
+        assert!(!sim.nodes_serving(&new_hall_call.order).contains(&victim), "Cab in maintenance got a new hall order");
+        sim.all_calls_served()
+    });
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+    assert!(sim.call_served(&cab_call));
+
+    // Back in service from its own keyboard
+    sim.set_maintenance(victim, victim, false);
+    let back = sim.run_until(Duration::from_secs(5), |sim| {
+        sim.nodes.iter().all(|node| node.state.known_elevators().iter().any(|cab| cab.id == victim && !cab.maintenance))
+    });
+    assert!(back, "Cab {} did not leave maintenance", victim);
+}
+
+#[test]
+fn idle_cabs_return_to_their_home_floors() {
+    let homes = vec![(1, 0), (2, 3), (3, 1)];
+    let mut sim = Simulation::start(SimConfig { parking: ParkingPolicy::HomeFloors(homes.clone()), ..SimConfig::default() });
+
+    let parked = |sim: &Simulation| {
+        homes.iter().all(|(id, floor)| {
+            let node = sim.node(*id);
+            node.elevator.floor_sensor() == Some(*floor)
+                && node.state.my_cab().is_some_and(|cab| cab.status == Status::Idle && cab.parking.is_none())
+        })
+    };
+    let started = sim.clock.now();
+    let at_home = sim.run_until(Duration::from_secs(30), parked);
+    assert!(at_home, "Idle cabs did not park at their home floors");
+    for node in sim.nodes.iter() {
+        for floor in 0..sim.config.num_floors {
+            assert!(!node.elevator.door_opened_at(floor, started), "Node {} opened the door for a parking move", node.id);
+        }
+    }
+
+    // Work first, then home again, without a hall lamp for the parking moves
+    sim.press(2, 0, CAB);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+    for floor in 0..sim.config.num_floors {
+        assert!(!sim.node(1).elevator.call_light(floor, HALL_UP) && !sim.node(1).elevator.call_light(floor, HALL_DOWN));
+    }
+    let back_home = sim.run_until(Duration::from_secs(30), parked);
+    assert!(back_home, "Cab 2 did not return home after its call");
+}
+
+/// Send cab 3 to the top floor, then make a hall call near the lobby and return the cabs serving it
+fn cabs_taking_a_call_near_the_lobby(forced: Option<DispatchMode>) -> Vec<u8> {
+    let traffic = TrafficConfig { forced, ..TrafficConfig::default() };
+    let mut sim = Simulation::start(SimConfig { traffic, ..SimConfig::default() });
+
+    sim.press(3, 3, CAB);
+    let at_top = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.all_calls_served()
+            && sim.node(1).state.known_elevators().iter().any(|cab| cab.id == 3 && cab.current_floor == 3 && cab.status == Status::Idle)
+    });
+    assert!(at_top, "Cab 3 did not reach the top floor");
+
+    sim.press(1, 1, HALL_DOWN);
+    let order = sim.calls[1].order.clone();
+    let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&order).is_empty());
+    assert!(assigned, "Hall call was never assigned");
+    return sim.nodes_serving(&order);
+}
+
+#[test]
+fn balanced_dispatch_sends_the_closest_cab() {
+    let balanced = cabs_taking_a_call_near_the_lobby(Some(DispatchMode::Balanced));
+    assert!(!balanced.contains(&3), "Balanced dispatch sent the cab from the top floor");
+}
+
+#[test]
+fn up_peak_keeps_cabs_at_the_lobby_for_lobby_pickups() {
+    let up_peak = cabs_taking_a_call_near_the_lobby(Some(DispatchMode::UpPeak { lobby: 0 }));
+    assert_eq!(up_peak, vec![3], "Up-peak dispatch took a cab away from the lobby");
+}
+
+#[test]
+fn down_peak_zones_cabs_by_upper_floors() {
+    let traffic = TrafficConfig { forced: Some(DispatchMode::DownPeak { lobby: 0 }), ..TrafficConfig::default() };
+    let mut sim = Simulation::start(SimConfig { traffic, ..SimConfig::default() });
+
+    // Every cab waits at the lobby, each upper floor has its own cab
+    for (i, (floor, zone_cab)) in [(3, 3), (2, 2)].into_iter().enumerate() {
+        sim.press(1, floor, HALL_DOWN);
+        let order = sim.calls[i].order.clone();
+        let assigned = sim.run_until(Duration::from_secs(10), |sim| !sim.nodes_serving(&order).is_empty());
+        assert!(assigned, "Hall call at floor {} was never assigned", floor);
+        assert_eq!(sim.nodes_serving(&order), vec![zone_cab], "Hall call at floor {} left its zone", floor);
+    }
+
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+}
+
+#[test]
+fn dispatch_mode_follows_lobby_traffic_and_can_be_overridden() {
+    let traffic = TrafficConfig { min_calls: 4, ..TrafficConfig::default() };
+    let mut sim = Simulation::start(SimConfig { traffic, ..SimConfig::default() });
+    assert_eq!(sim.node(1).state.dispatch_mode(), DispatchMode::Balanced);
+
+    // Every cab waits at the lobby, so wait for the master to count each call before the next press
+    for (i, node_id) in [1, 2, 3, 1].into_iter().enumerate() {
+        sim.press(node_id, 0, HALL_UP);
+        let counted = sim.run_until(Duration::from_secs(10), |sim| sim.node(1).state.read(|s| s.hall_calls.len()) > i);
+        assert!(counted, "Master did not count hall call {}", i + 1);
+        sim.run_for(Duration::from_secs(4));
+    }
+    let up_peak = sim.run_until(Duration::from_secs(2), |sim| sim.node(1).state.dispatch_mode() == DispatchMode::UpPeak { lobby: 0 });
+    assert!(up_peak, "Master did not switch to up-peak, mode is {:?}", sim.node(1).state.dispatch_mode());
+
+    // The operator forces balanced dispatch on the master
+    sim.set_dispatch_mode(1, Some(DispatchMode::Balanced));
+    let overridden = sim.run_until(Duration::from_secs(2), |sim| sim.node(1).state.dispatch_mode() == DispatchMode::Balanced);
+    assert!(overridden, "Operator could not override the dispatch mode");
+}
+
+#[test]
+fn calls_only_go_to_cabs_that_stop_at_the_floor() {
+    // Cab 1 does not reach the top floor, cab 2 is an express cab skipping floor 2
+    let served_floors = vec![(1, vec![0, 1, 2]), (2, vec![0, 1, 3])];
+    let mut sim = Simulation::start(SimConfig { num_nodes: 2, served_floors, ..SimConfig::default() });
+
+    sim.press(1, 3, HALL_DOWN);
+    sim.press(2, 2, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+    assert!(sim.node(2).elevator.door_opened_at(3, sim.calls[0].pressed_at), "Top floor call not served by cab 2");
+    assert!(sim.node(1).elevator.door_opened_at(2, sim.calls[1].pressed_at), "Floor 2 call not served by cab 1");
+    assert!(!sim.node(2).elevator.door_opened_at(2, sim.calls[0].pressed_at), "Express cab stopped at floor 2");
+
+    // A cab call to a floor the cab skips is refused
+    sim.press(2, 2, CAB);
+    sim.run_for(Duration::from_secs(2));
+    assert!(!sim.node(2).state.my_cab().unwrap().queue.contains(&Order::init(2, CAB)), "Express cab took a cab call to floor 2");
+    sim.calls.pop();
+
+    // Without cab 2 nothing reaches the top floor, the master keeps the call as unservable.
+    // Cab 2 dies on its way down, the master only looks for lifesigns from moving cabs
+    sim.press(2, 0, CAB);
+    sim.calls.pop();
+    let moving = sim.run_until(Duration::from_secs(5), |sim| {
+        sim.node(1).state.known_elevators().iter().any(|cab| cab.id == 2 && cab.status == Status::Moving)
+    });
+    assert!(moving, "Cab 2 did not leave the top floor");
+    sim.kill_node(2);
+    let dead = sim.run_until(Duration::from_secs(30), |sim| {
+        sim.node(1).state.known_elevators().iter().any(|cab| cab.id == 2 && !cab.alive)
+    });
+    assert!(dead, "Master did not notice cab 2 is gone");
+    sim.press(1, 3, HALL_DOWN);
+    let order = Order::init(3, HALL_DOWN);
+    let waiting = sim.run_until(Duration::from_secs(5), |sim| sim.node(1).state.unservable().contains(&order));
+    assert!(waiting, "Top floor call not reported as unservable");
+    assert!(sim.nodes_serving(&order).is_empty(), "Cab 1 was given a floor it does not reach");
+
+    // Pressed again it is still set aside once, not tried again
+    sim.press(1, 3, HALL_DOWN);
+    sim.run_for(Duration::from_secs(2));
+    let state = &sim.node(1).state;
+    assert_eq!(state.unservable(), vec![order.clone()]);
+    assert_eq!(state.all_orders().iter().filter(|o| **o == order).count(), 1, "The call is stored twice");
+    assert_eq!(unservable_orders(&state.all_orders(), &state.known_elevators()), vec![order.clone()]);
+}
+
+#[test]
+fn full_cab_is_passed_over_for_hall_calls() {
+    // Both cabs wait at the ground floor, cab 1 would be first if it had room
+    let mut sim = Simulation::start(SimConfig { num_nodes: 2, ..SimConfig::default() });
+
+    sim.set_load(1, 600);
+    let full = sim.run_until(Duration::from_secs(2), |sim| {
+        sim.nodes.iter().all(|node| node.state.known_elevators().iter().any(|cab| cab.id == 1 && cab.full_load))
+    });
+    assert!(full, "Full-load flag did not reach every node");
+
+    sim.press(1, 2, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+    assert!(sim.node(2).elevator.door_opened_at(2, sim.calls[0].pressed_at), "Hall call not served by cab 2");
+    assert_eq!(sim.node(1).elevator.floor_sensor(), Some(0), "Full cab left for a hall call");
+
+    // Once people get off, cab 1 is first again
+    sim.set_load(1, 100);
+    sim.run_for(Duration::from_secs(2));
+    sim.press(2, 1, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(30), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served: {:?}", sim.unserved_calls());
+    assert!(sim.node(1).elevator.door_opened_at(1, sim.calls[1].pressed_at), "Cab 1 not used after it emptied");
+}
+
+#[test]
+fn door_buttons_and_accessible_calls_change_how_long_the_door_is_open() {
+    let door = DoorConfig { open_time: Duration::from_secs(2), accessible_open_time: Duration::from_secs(6), max_hold: Duration::from_secs(8) };
+    let mut sim = Simulation::start(SimConfig { num_nodes: 1, door, ..SimConfig::default() });
+
+    // Time from the door opening at `floor` until it has closed again
+    fn open_time(sim: &mut Simulation, floor: u8, since: Instant) -> Duration {
+        let opened = sim.run_until(Duration::from_secs(20), |sim| sim.node(1).elevator.door_opened_at(floor, since));
+        assert!(opened, "Door never opened at floor {}", floor);
+        let opened_at = sim.clock.now();
+        let closed = sim.run_until(Duration::from_secs(20), |sim| !sim.node(1).elevator.door_open());
+        assert!(closed, "Door never closed at floor {}", floor);
+        return sim.clock.now() - opened_at;
+    }
+
+    sim.press(1, 1, CAB);
+    let pressed_at = sim.calls[0].pressed_at;
+    let normal = open_time(&mut sim, 1, pressed_at);
+    assert!(normal >= Duration::from_secs(2) && normal < Duration::from_secs(3), "Normal stop open for {:?}", normal);
+
+    sim.press_accessible(1, 2);
+    let pressed_at = sim.calls[1].pressed_at;
+    let accessible = open_time(&mut sim, 2, pressed_at);
+    assert!(accessible >= Duration::from_secs(6), "Accessible stop open for only {:?}", accessible);
+
+    // The door-open button opens the door again and holds it, the door-close button ends it
+    sim.press_door_button(1, DoorRequest::Open);
+    let reopened = sim.run_until(Duration::from_secs(1), |sim| sim.node(1).elevator.door_open());
+    assert!(reopened, "Door-open button did not open the door");
+    for _ in 0..3 {
+        sim.run_for(Duration::from_secs(1));
+        sim.press_door_button(1, DoorRequest::Open);
+    }
+    assert!(sim.node(1).elevator.door_open(), "Door closed while held");
+    sim.press_door_button(1, DoorRequest::Close);
+    let closed = sim.run_until(Duration::from_millis(500), |sim| !sim.node(1).elevator.door_open());
+    assert!(closed, "Door-close button did not close the door");
+    assert!(sim.all_calls_served(), "Calls not served: {:?}", sim.unserved_calls());
+}
@@ -0,0 +1,97 @@
+//! Tests of the dispatch modes without a network
+//!
+//! - the hall calls are classified as up-peak, down-peak or balanced
+//! - the keys in "boot.txt" give the expected config
+//! - every mode changes which cab best_to_worst_elevator puts first
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::master::best_to_worst_elevator;
+use heislab2_root::modules::master_functions::traffic::{classify, DispatchMode, TrafficConfig};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::system_init::init_system_state;
+
+const NUM_FLOORS: u8 = 4;
+
+fn idle_cab(id: u8, floor: u8) -> Cab {
+    let state = Arc::new(init_system_state(id, 1));
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let mut cab = Cab::init(&address, &address, NUM_FLOORS, id, &state).unwrap();
+    cab.current_floor = floor;
+    cab.status = Status::Idle;
+    return cab;
+}
+
+fn calls(list: &[(u8, u8)]) -> Vec<Order> {
+    return list.iter().map(|(floor, call)| Order::init(*floor, *call)).collect();
+}
+
+#[test]
+fn hall_calls_are_classified() {
+    let config = TrafficConfig { min_calls: 4, ..TrafficConfig::default() };
+
+    let up_peak = calls(&[(0, HALL_UP), (0, HALL_UP), (0, HALL_UP), (2, HALL_DOWN), (1, CAB)]);
+    assert_eq!(classify(&up_peak, &config), DispatchMode::UpPeak { lobby: 0 });
+
+    let down_peak = calls(&[(3, HALL_DOWN), (2, HALL_DOWN), (1, HALL_DOWN), (0, HALL_UP)]);
+    assert_eq!(classify(&down_peak, &config), DispatchMode::DownPeak { lobby: 0 });
+
+    let inter_floor = calls(&[(1, HALL_UP), (2, HALL_DOWN), (0, HALL_UP), (3, HALL_DOWN)]);
+    assert_eq!(classify(&inter_floor, &config), DispatchMode::Balanced);
+
+    // Too few calls to tell
+    assert_eq!(classify(&up_peak[..3], &config), DispatchMode::Balanced);
+}
+
+#[test]
+fn config_is_read_from_boot_keys() {
+    let mut config = TrafficConfig::default();
+    assert!(config.parse("dispatch_mode", " up_peak"));
+    assert!(config.parse("dispatch_lobby", "1"));
+    assert!(config.parse("dispatch_window", "60"));
+    assert!(config.parse("dispatch_min_calls", "5"));
+    assert!(config.parse("dispatch_peak_share", "75"));
+
+    assert_eq!(config.forced, Some(DispatchMode::UpPeak { lobby: 1 }));
+    assert_eq!(config.lobby, 1);
+    assert_eq!(config.window.as_secs(), 60);
+    assert_eq!(config.min_calls, 5);
+    assert_eq!(config.peak_share, 0.75);
+
+    assert!(config.parse("dispatch_mode", "auto"));
+    assert_eq!(config.forced, None);
+    assert!(!config.parse("dispatch_mode", "rush"));
+    assert!(!config.parse("dispatch_window", "inf"));
+    assert!(!config.parse("dispatch_window", "1e30"));
+    assert!(!config.parse("dispatch_peak_share", "NaN"));
+    assert_eq!(config.window.as_secs(), 60);
+    assert_eq!(config.peak_share, 0.75);
+}
+
+#[test]
+fn up_peak_keeps_the_lobby_cab_for_the_lobby() {
+    let cabs = vec![idle_cab(1, 0), idle_cab(2, 3)];
+    let away = Order::init(1, HALL_DOWN);
+    let lobby_call = Order::init(0, HALL_UP);
+    let up_peak = DispatchMode::UpPeak { lobby: 0 };
+
+    assert_eq!(best_to_worst_elevator(&away, &cabs, DispatchMode::Balanced)[0], 1);
+    assert_eq!(best_to_worst_elevator(&away, &cabs, up_peak)[0], 2);
+    assert_eq!(best_to_worst_elevator(&lobby_call, &cabs, up_peak)[0], 1);
+}
+
+#[test]
+fn down_peak_gives_every_cab_a_zone() {
+    let cabs = vec![idle_cab(1, 0), idle_cab(2, 0), idle_cab(3, 0)];
+    let down_peak = DispatchMode::DownPeak { lobby: 0 };
+
+    for (floor, zone_cab) in [(1, 1), (2, 2), (3, 3)] {
+        assert_eq!(best_to_worst_elevator(&Order::init(floor, HALL_DOWN), &cabs, down_peak)[0], zone_cab);
+    }
+    // Cab orders are not zoned
+    assert_eq!(best_to_worst_elevator(&Order::init(3, CAB), &cabs, down_peak)[0], 1);
+}
@@ -0,0 +1,243 @@
+//! Tests of how messages to every node are sent
+//!
+//! - the keys in "boot.txt" pick the mode, the interface, the group and the peers
+//! - every mode sends to the right addresses, and peers in the list are accepted from any subnet
+//! - a unicast "broadcast" goes out on the sender socket of the handler to every listed node, over IPv4 and IPv6
+//! - senders are filtered by the subnet of the node, or by a list of allowed blocks of both families
+//! - the protocol runs on any Transport, and the TCP transport delivers in order and survives a restarted peer
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use heislab2_root::modules::elevator_object::alias_lib::HALL_UP;
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::udp_functions::tcp_transport::TcpTransport;
+use heislab2_root::modules::udp_functions::transport::{Cidr, Datagram, Transport, TransportConfig, TransportMode, UdpTransport};
+use heislab2_root::modules::udp_functions::udp::{decode_msg, make_udp_msg, same_subnet, udp_ack, MessageType, UdpData, BROADCAST_PORT};
+use heislab2_root::modules::udp_functions::udp_handler_init::init_handler;
+
+// Remembers everything sent, never receives anything
+#[derive(Debug, Default)]
+struct RecordingTransport {
+    sent: Mutex<Vec<(Option<SocketAddr>, Vec<u8>)>>,   // None for messages to every node
+}
+
+impl Transport for RecordingTransport {
+    fn send_to(&self, peer: &SocketAddr, datagram: &[u8]) -> bool {
+        self.sent.lock().unwrap().push((Some(*peer), datagram.to_vec()));
+        return true;
+    }
+
+    fn broadcast(&self, datagram: &[u8]) -> bool {
+        self.sent.lock().unwrap().push((None, datagram.to_vec()));
+        return true;
+    }
+
+    fn recv_timeout(&self, max_wait: Duration) -> Option<Datagram> {
+        thread::sleep(max_wait);
+        return None;
+    }
+
+    fn accepts(&self, _sender: IpAddr) -> bool {
+        return true;
+    }
+
+    fn local_address(&self) -> SocketAddr {
+        return SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3800);
+    }
+}
+
+// A port nothing listens on right now, for both TCP and UDP
+fn free_port() -> u16 {
+    return TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+fn parse_lines(lines: &[&str]) -> TransportConfig {
+    let mut config = TransportConfig::default();
+    for line in lines {
+        let (key, value) = line.split_once(':').unwrap();
+        assert!(config.parse(key, value), "Could not read {}", line);
+    }
+    return config;
+}
+
+#[test]
+fn keys_set_the_mode_and_its_settings() {
+    let config = parse_lines(&["transport: multicast", "transport_group: 239.1.2.3", "transport_ttl: 4", "transport_interface: 10.100.23.21"]);
+    assert_eq!(config.mode, TransportMode::Multicast);
+    assert_eq!(config.ttl, 4);
+    assert_eq!(config.interface_ip(), Some(IpAddr::V4(Ipv4Addr::new(10, 100, 23, 21))));
+    assert_eq!(config.destinations(), vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 1, 2, 3)), BROADCAST_PORT)]);
+
+    let config = parse_lines(&["transport: unicast", "transport_peers: 10.100.23.22, 192.168.0.7:20001"]);
+    assert_eq!(
+        config.destinations(),
+        vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 23, 22)), BROADCAST_PORT),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 7)), 20001),
+        ]
+    );
+
+    let config = TransportConfig::default();
+    assert_eq!(config.destinations(), vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), BROADCAST_PORT)]);
+}
+
+#[test]
+fn ipv6_broadcasts_go_to_all_nodes_on_the_link() {
+    // Link-local groups get the scope of the interface, 0 lets the OS pick it when no interface is set
+    let config = parse_lines(&["transport_ipv6: true"]);
+    assert_eq!(config.destinations(), vec!["[ff02::1]:20000".parse().unwrap()]);
+    assert_eq!(config.any_address(BROADCAST_PORT), "[::]:20000".parse().unwrap());
+
+    let config = parse_lines(&["transport_ipv6: true", "transport: multicast"]);
+    assert_eq!(config.group(), "ff02::4c48".parse::<IpAddr>().unwrap());
+
+    let config = parse_lines(&["transport_ipv6: true", "transport: multicast", "transport_interface: lo", "transport_group: ff02::1234"]);
+    let index: u32 = std::fs::read_to_string("/sys/class/net/lo/ifindex").unwrap().trim().parse().unwrap();
+    assert_eq!(config.interface_index(), index);
+    assert_eq!(config.destinations(), vec![format!("[ff02::1234%{}]:20000", index).parse().unwrap()]);
+}
+
+#[test]
+fn bad_values_are_not_read() {
+    let mut config = TransportConfig::default();
+    assert!(!config.parse("transport", "carrier pigeon"));
+    assert!(!config.parse("transport_group", "10.0.0.1"), "Not a multicast group");
+    assert!(!config.parse("transport_ttl", "-1"));
+    assert!(!config.parse("transport_peers", "10.0.0.1, nowhere"));
+    assert!(!config.parse("transport_allow", "10.0.0.0/33"));
+    assert!(!config.parse("transport_allow", "fe80::/129"));
+    assert!(!config.parse("transport_ipv6", "maybe"));
+    assert_eq!(config, TransportConfig::default());
+}
+
+#[test]
+fn listed_peers_are_accepted_from_another_subnet() {
+    let config = parse_lines(&["transport: unicast", "transport_peers: 192.168.0.7"]);
+    let local = IpAddr::V4(Ipv4Addr::new(10, 100, 23, 21));
+
+    assert!(config.accepts(local, IpAddr::V4(Ipv4Addr::new(10, 100, 23, 30))));
+    assert!(config.accepts(local, IpAddr::V4(Ipv4Addr::new(192, 168, 0, 7))));
+    assert!(!config.accepts(local, IpAddr::V4(Ipv4Addr::new(192, 168, 0, 8))));
+}
+
+#[test]
+fn blocks_match_both_families() {
+    let block = Cidr::parse("10.100.16.0/20").unwrap();
+    assert!(block.contains("10.100.23.21".parse().unwrap()));
+    assert!(block.contains("::ffff:10.100.31.1".parse().unwrap()), "IPv4 written as IPv6");
+    assert!(!block.contains("10.100.32.1".parse().unwrap()));
+    assert!(!block.contains("fe80::1".parse().unwrap()));
+
+    let block = Cidr::parse("fe80::/10").unwrap();
+    assert!(block.contains("fe80::1".parse().unwrap()));
+    assert!(block.contains("febf::1".parse().unwrap()));
+    assert!(!block.contains("fec0::1".parse().unwrap()));
+
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("192.168.0.1".parse().unwrap()));
+    assert_eq!(Cidr::parse("10.0.0.1").unwrap().prefix, 32);
+    assert_eq!(Cidr::parse("::1").unwrap().prefix, 128);
+    assert_eq!(Cidr::parse("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+}
+
+#[test]
+fn subnet_of_the_node_is_used_without_allowed_blocks() {
+    let local: IpAddr = "fe80::1:2:3:4".parse().unwrap();
+    assert!(same_subnet(local, "fe80::9:9:9:9".parse().unwrap()));
+    assert!(!same_subnet(local, "fe80:0:0:1::1".parse().unwrap()));
+    assert!(!same_subnet(local, "10.100.23.21".parse().unwrap()));
+    assert!(same_subnet("10.100.23.21".parse().unwrap(), "::ffff:10.100.23.30".parse().unwrap()));
+
+    // The blocks replace the subnet of the node
+    let config = parse_lines(&["transport_allow: 10.100.0.0/16, fd00::/8"]);
+    let local = IpAddr::V4(Ipv4Addr::new(10, 100, 23, 21));
+    assert!(config.accepts(local, "10.100.40.1".parse().unwrap()));
+    assert!(config.accepts(local, "fd00::2".parse().unwrap()));
+    assert!(!config.accepts(local, "10.101.23.22".parse().unwrap()));
+}
+
+fn check_unicast_to_every_peer(loopback: IpAddr) {
+    let peers: Vec<UdpSocket> = (0..2).map(|_| UdpSocket::bind(SocketAddr::new(loopback, 0)).unwrap()).collect();
+    let mut config = TransportConfig::default();
+    config.mode = TransportMode::Unicast;
+    config.ipv6 = loopback.is_ipv6();
+    config.peers = peers.iter().map(|peer| peer.local_addr().unwrap()).collect();
+
+    let local = SocketAddr::new(loopback, 0);
+    let handler = init_handler(Arc::new(UdpTransport::open(local, local, config).unwrap()));
+
+    let msg = make_udp_msg(1, MessageType::NewRequest, UdpData::Order(Order::init(2, HALL_UP)));
+    assert!(handler.broadcast(&msg));
+
+    let sender = handler.transport.local_address();
+    for peer in peers {
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buffer = [0; 1024];
+        let (size, from) = peer.recv_from(&mut buffer).expect("Peer got nothing");
+        assert_eq!(from, sender, "Not sent on the sender socket of the handler");
+        assert_eq!(decode_msg(&buffer[..size]).map(|(_, received)| received), Ok(msg.clone()));
+    }
+}
+
+#[test]
+fn unicast_mode_sends_a_copy_to_every_peer() {
+    check_unicast_to_every_peer(IpAddr::V4(Ipv4Addr::LOCALHOST));
+}
+
+#[test]
+fn unicast_mode_works_over_ipv6() {
+    check_unicast_to_every_peer(IpAddr::V6(Ipv6Addr::LOCALHOST));
+}
+
+#[test]
+fn protocol_runs_on_any_transport() {
+    let transport = Arc::new(RecordingTransport::default());
+    let handler = init_handler(transport.clone());
+    let master = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 23, 21)), BROADCAST_PORT);
+
+    let order = make_udp_msg(1, MessageType::NewRequest, UdpData::Order(Order::init(2, HALL_UP)));
+    assert!(udp_ack(master, &order, 3, &handler));
+    assert!(handler.broadcast(&order));
+
+    let sent = transport.sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].0, Some(master));
+    let (_, ack) = decode_msg(&sent[0].1).unwrap();
+    assert_eq!((ack.header.message_type, ack.header.sender_id), (MessageType::Ack, 3));
+    assert_eq!(sent[1].0, None);
+    assert_eq!(decode_msg(&sent[1].1).map(|(_, received)| received), Ok(order));
+}
+
+#[test]
+fn tcp_delivers_in_order_and_reconnects_to_a_restarted_peer() {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let open = |port: u16| TcpTransport::open(SocketAddr::new(localhost, port), SocketAddr::new(localhost, 0), TransportConfig::default()).unwrap();
+    let sender = open(free_port());
+    let port = free_port();
+    let receiver = open(port);
+    let peer = SocketAddr::new(localhost, port);
+
+    for message in [b"first", b"secnd", b"third"] {
+        assert!(sender.send_to(&peer, message));
+    }
+    for message in [b"first", b"secnd", b"third"] {
+        let (received, from) = receiver.recv_timeout(Duration::from_secs(2)).expect("Message lost on TCP");
+        assert_eq!(received, message.to_vec());
+        assert_eq!(from.ip(), localhost);
+    }
+
+    // The peer restarts on the same port, the old stream is closed
+    drop(receiver);
+    thread::sleep(Duration::from_millis(500));
+    let receiver = open(port);
+    assert!(sender.send_to(&peer, b"again"));
+    let (received, _) = receiver.recv_timeout(Duration::from_secs(2)).expect("Message lost after the peer restarted");
+    assert_eq!(received, b"again".to_vec());
+
+    // Nobody listens any more
+    drop(receiver);
+    thread::sleep(Duration::from_millis(500));
+    assert!(!sender.send_to(&peer, b"alone"));
+}
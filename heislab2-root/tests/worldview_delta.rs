@@ -0,0 +1,132 @@
+//! Tests of the delta worldview
+//!
+//! - a delta applied to the old worldview gives the new one
+//! - the master sends a snapshot first, then deltas, and a snapshot again every `FULL_SNAPSHOT_EVERY` updates
+//! - a receiver assembles a snapshot from its parts and asks for a new one when a delta is missing
+//! - in the simulator the slaves keep in sync and hall calls are served
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use heislab2_root::modules::cab_object::cab::Cab;
+use heislab2_root::modules::cab_object::elevator_status_functions::Status;
+use heislab2_root::modules::elevator_object::alias_lib::{CAB, HALL_DOWN, HALL_UP};
+use heislab2_root::modules::master_functions::worldview_delta::{
+    apply_changes, diff_worldviews, next_worldview_update, receive_worldview_update, snapshot_parts, CabChange,
+    WorldviewLog, WorldviewUpdate, FULL_SNAPSHOT_EVERY,
+};
+use heislab2_root::modules::order_object::order_init::Order;
+use heislab2_root::modules::simulation::sim_harness::{SimConfig, Simulation};
+use heislab2_root::modules::system_init::init_system_state;
+
+fn cabs(ids: &[u8]) -> Vec<Cab> {
+    let state = Arc::new(init_system_state(ids[0], ids[0]));
+    ids.iter()
+        .map(|id| {
+            let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 100, 0, *id)), 20000);
+            Cab::init(&address, &address, 4, *id, &state).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn delta_turns_the_old_worldview_into_the_new() {
+    let old = cabs(&[1, 2, 3]);
+    let mut new = old.clone();
+    new[0].current_floor = 2;
+    new[0].status = Status::Moving;
+    new[1].queue.push(Order::init(3, HALL_DOWN));
+    new[2].alive = false;
+    new.extend(cabs(&[4]));
+
+    let changes = diff_worldviews(&old, &new);
+    assert!(changes.iter().any(|change| matches!(change, CabChange::Moved { id: 1, floor: 2, .. })));
+    assert!(changes.iter().any(|change| matches!(change, CabChange::OrderAdded { id: 2, .. })));
+    assert!(changes.iter().any(|change| matches!(change, CabChange::Alive { id: 3, alive: false })));
+    assert!(changes.iter().any(|change| matches!(change, CabChange::Cab(cab) if cab.id == 4)));
+
+    let mut rebuilt = old.clone();
+    assert!(apply_changes(&mut rebuilt, &changes));
+    assert_eq!(rebuilt, new);
+    assert!(diff_worldviews(&new, &new).is_empty());
+
+    // A change to a cab the receiver does not know means it is out of sync
+    let mut unknown = cabs(&[1]);
+    assert!(!apply_changes(&mut unknown, &[CabChange::Alive { id: 7, alive: true }]));
+}
+
+#[test]
+fn master_sends_a_snapshot_then_deltas() {
+    let mut s = init_system_state(1, 1).read(|s| s.clone());
+    s.known_elevators = cabs(&[1, 2, 3]);
+
+    let first = next_worldview_update(&mut s);
+    assert_eq!(first.len(), 3, "The first update is a snapshot with one part per cab");
+    assert!(first.iter().all(|update| matches!(update, WorldviewUpdate::Snapshot { seq: 1, parts: 3, .. })));
+
+    s.known_elevators[1].queue.push(Order::init(1, CAB));
+    let second = next_worldview_update(&mut s);
+    assert!(matches!(&second[..], [WorldviewUpdate::Delta { seq: 2, changes }] if changes.len() == 1));
+
+    for _ in 1..FULL_SNAPSHOT_EVERY {
+        assert!(matches!(&next_worldview_update(&mut s)[..], [WorldviewUpdate::Delta { .. }]));
+    }
+    let snapshot = next_worldview_update(&mut s);
+    assert!(snapshot.iter().all(|update| matches!(update, WorldviewUpdate::Snapshot { .. })), "No snapshot after {} deltas", FULL_SNAPSHOT_EVERY);
+}
+
+#[test]
+fn receiver_assembles_snapshots_and_asks_for_one_on_a_gap() {
+    let worldview = cabs(&[1, 2]);
+    let mut log = WorldviewLog::default();
+
+    // A delta before any snapshot can't be used
+    let (update, resync) = receive_worldview_update(&mut log, 1, &WorldviewUpdate::Delta { seq: 4, changes: Vec::new() });
+    assert!(update.is_none() && resync);
+
+    let parts = snapshot_parts(5, &worldview);
+    assert_eq!(receive_worldview_update(&mut log, 1, &parts[1]), (None, false));
+    let (update, resync) = receive_worldview_update(&mut log, 1, &parts[0]);
+    assert!(!resync && log.synced);
+    assert_eq!(update.map(|cabs| cabs.len()), Some(2));
+
+    // In order, duplicate, then a gap
+    let moved = vec![CabChange::Moved { id: 2, floor: 3, status: Status::Idle, direction: 0 }];
+    let (update, resync) = receive_worldview_update(&mut log, 1, &WorldviewUpdate::Delta { seq: 6, changes: moved.clone() });
+    assert!(!resync);
+    assert_eq!(update.unwrap()[1].current_floor, 3);
+    assert_eq!(receive_worldview_update(&mut log, 1, &WorldviewUpdate::Delta { seq: 6, changes: moved.clone() }), (None, false));
+    assert_eq!(receive_worldview_update(&mut log, 1, &WorldviewUpdate::Delta { seq: 8, changes: moved.clone() }), (None, true));
+    assert!(!log.synced);
+
+    // Deltas from another master need a snapshot of its own
+    let mut log = WorldviewLog::default();
+    for part in snapshot_parts(1, &worldview) {
+        receive_worldview_update(&mut log, 1, &part);
+    }
+    assert_eq!(receive_worldview_update(&mut log, 2, &WorldviewUpdate::Delta { seq: 2, changes: Vec::new() }), (None, true));
+}
+
+#[test]
+fn slaves_keep_in_sync_and_calls_are_served() {
+    let mut sim = Simulation::start(SimConfig::default());
+
+    let synced = sim.run_until(Duration::from_secs(10), |sim| {
+        [2, 3].iter().all(|id| sim.node(*id).state.read(|s| s.worldview_log.synced && s.worldview_log.sender == 1))
+    });
+    assert!(synced, "The slaves did not get the worldview from the master");
+
+    sim.press(2, 3, HALL_DOWN);
+    sim.press(3, 1, HALL_UP);
+    let served = sim.run_until(Duration::from_secs(40), |sim| sim.all_calls_served());
+    assert!(served, "Calls not served with delta worldviews: {:?}", sim.unserved_calls());
+
+    // The slaves have the worldview of the master, apart from the lifesigns
+    let master = sim.node(1).state.read(|s| s.worldview_log.clone());
+    for id in [2, 3] {
+        let log = sim.node(id).state.read(|s| s.worldview_log.clone());
+        assert!(log.synced, "Elevator {} lost the worldview", id);
+        assert!(log.seq <= master.seq);
+    }
+}